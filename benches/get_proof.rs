@@ -0,0 +1,51 @@
+//! Times `get_proof` on a populated tree of depth 256.
+//!
+//! Run with `cargo bench --bench get_proof`.
+
+use binary_merkle_tree::{generate_tree, DBValue, Tree, TreeDBBuilder};
+use hash256_std_hasher::Hash256StdHasher;
+use hash_db::{HashDB, Hasher};
+use std::{hint::black_box, time::Instant};
+
+const DEPTH: usize = 256;
+const KEYS: usize = 1_000;
+const ROUNDS: usize = 20;
+const SEED: u64 = 1;
+
+#[derive(Debug)]
+struct Sha3;
+
+impl Hasher for Sha3 {
+    type Out = [u8; 32];
+
+    type StdHasher = Hash256StdHasher;
+
+    const LENGTH: usize = 32;
+
+    fn hash(x: &[u8]) -> Self::Out {
+        use sha3::Digest;
+        sha3::Sha3_256::digest(x).into()
+    }
+}
+
+fn main() {
+    let (db, root) = generate_tree::<Sha3>(SEED, KEYS, 8, DEPTH).unwrap();
+    let reader: &dyn HashDB<Sha3, DBValue> = &db;
+    let tree = TreeDBBuilder::new(&reader, &root, DEPTH).build();
+    let keys: Vec<Vec<u8>> = tree
+        .iter_from(&[0; DEPTH])
+        .unwrap()
+        .map(|leaf| leaf.unwrap().0)
+        .collect();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for key in keys.iter() {
+            black_box(tree.get_proof(key).unwrap());
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "get_proof at depth {DEPTH}: {:?} per proof",
+        elapsed / (ROUNDS * KEYS) as u32
+    );
+}
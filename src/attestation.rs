@@ -0,0 +1,84 @@
+use crate::{rstd::Vec, Hasher};
+
+/// Sign root statements.
+pub trait RootSigner {
+    /// The signature produced by the signer.
+    type Signature;
+
+    /// Sign the given encoded statement.
+    fn sign(&self, message: &[u8]) -> Self::Signature;
+}
+
+/// Verify signatures over root statements.
+pub trait RootVerifier {
+    /// The signature accepted by the verifier.
+    type Signature;
+
+    /// Return true if `signature` is a valid signature over the given encoded statement.
+    fn verify(&self, message: &[u8], signature: &Self::Signature) -> bool;
+}
+
+/// A statement binding a tree root to a version and arbitrary metadata.
+pub struct RootStatement<H: Hasher> {
+    pub root: H::Out,
+    pub version: u64,
+    pub metadata: Vec<u8>,
+}
+
+impl<H: Hasher> RootStatement<H> {
+    /// Create a new `RootStatement`.
+    pub fn new(root: H::Out, version: u64, metadata: Vec<u8>) -> Self {
+        Self {
+            root,
+            version,
+            metadata,
+        }
+    }
+
+    /// Encode the statement as `root || version (little endian) || metadata`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(H::LENGTH + 8 + self.metadata.len());
+        encoded.extend_from_slice(self.root.as_ref());
+        encoded.extend_from_slice(&self.version.to_le_bytes());
+        encoded.extend_from_slice(&self.metadata);
+        encoded
+    }
+}
+
+impl<H: Hasher> Clone for RootStatement<H> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root,
+            version: self.version,
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+/// A signed `RootStatement`.
+pub struct Attestation<H: Hasher, S> {
+    pub statement: RootStatement<H>,
+    pub signature: S,
+}
+
+impl<H: Hasher, S> Attestation<H, S> {
+    /// Sign a statement for the given root, version and metadata.
+    pub fn sign<T: RootSigner<Signature = S>>(
+        signer: &T,
+        root: H::Out,
+        version: u64,
+        metadata: Vec<u8>,
+    ) -> Self {
+        let statement = RootStatement::new(root, version, metadata);
+        let signature = signer.sign(&statement.encode());
+        Self {
+            statement,
+            signature,
+        }
+    }
+
+    /// Return true if the signature over the statement is valid.
+    pub fn verify<T: RootVerifier<Signature = S>>(&self, verifier: &T) -> bool {
+        verifier.verify(&self.statement.encode(), &self.signature)
+    }
+}
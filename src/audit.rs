@@ -0,0 +1,72 @@
+use crate::{rstd::Vec, Hasher, MutationSink};
+
+/// A structured record of a change applied to a `TreeDBMut`.
+#[derive(Debug)]
+pub enum Mutation<H: Hasher> {
+    /// A value was inserted, resulting in the given uncommitted root.
+    Insert {
+        key: Vec<u8>,
+        old_value_hash: H::Out,
+        new_value_hash: H::Out,
+        root: H::Out,
+    },
+    /// Pending changes were committed to the backing database.
+    Commit { root: H::Out },
+    /// The changes since a checkpoint were discarded, restoring the given uncommitted root.
+    /// Only the first `retained` inserts since the last commit remain in effect.
+    Revert { retained: usize, root: H::Out },
+}
+
+impl<H: Hasher> Clone for Mutation<H> {
+    fn clone(&self) -> Self {
+        match self {
+            Mutation::Insert {
+                key,
+                old_value_hash,
+                new_value_hash,
+                root,
+            } => Mutation::Insert {
+                key: key.clone(),
+                old_value_hash: *old_value_hash,
+                new_value_hash: *new_value_hash,
+                root: *root,
+            },
+            Mutation::Commit { root } => Mutation::Commit { root: *root },
+            Mutation::Revert { retained, root } => Mutation::Revert {
+                retained: *retained,
+                root: *root,
+            },
+        }
+    }
+}
+
+/// Collect mutation records in memory.
+pub struct MutationLog<H: Hasher> {
+    mutations: Vec<Mutation<H>>,
+}
+
+impl<H: Hasher> MutationLog<H> {
+    /// Create a new empty `MutationLog`.
+    pub fn new() -> Self {
+        Self {
+            mutations: Vec::new(),
+        }
+    }
+
+    /// Drain all collected mutation records.
+    pub fn drain(&mut self) -> Vec<Mutation<H>> {
+        core::mem::take(&mut self.mutations)
+    }
+}
+
+impl<H: Hasher> Default for MutationLog<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: Hasher> MutationSink<H> for MutationLog<H> {
+    fn on_mutation(&mut self, mutation: Mutation<H>) {
+        self.mutations.push(mutation);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    rstd::{Arc, HashMap, Vec},
+    DBValue, TreeError,
+};
+use hash_db::{AsHashDB, HashDB, Hasher, Prefix, EMPTY_PREFIX};
+use std::{
+    sync::{mpsc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+};
+
+/// A batch of nodes handed to the worker, numbered in submission order.
+type Batch<H> = (u64, Vec<(<H as Hasher>::Out, DBValue)>);
+
+/// The state shared between a `BackgroundDb`, its worker and its tickets.
+struct Shared<H: Hasher, B> {
+    backend: Mutex<B>,
+    /// Nodes not yet written to the backend, served to reads meanwhile.
+    pending: Mutex<HashMap<H::Out, DBValue>>,
+    progress: Arc<Progress>,
+}
+
+/// The number of the last batch written, and whether the worker has stopped.
+#[derive(Default)]
+struct Progress {
+    state: Mutex<(u64, bool)>,
+    changed: Condvar,
+}
+
+impl Progress {
+    fn update(&self, update: impl FnOnce(&mut (u64, bool))) {
+        update(&mut self.state.lock().expect("lock poisoned"));
+        self.changed.notify_all();
+    }
+
+    fn written(&self) -> u64 {
+        self.state.lock().expect("lock poisoned").0
+    }
+
+    /// Block until batch `number` has been written.  Fails with
+    /// `TreeError::BackgroundCommitFailed` if the worker stopped first.
+    fn wait_for(&self, number: u64) -> Result<(), TreeError> {
+        let mut state = self.state.lock().expect("lock poisoned");
+        loop {
+            if state.0 >= number {
+                return Ok(());
+            }
+            if state.1 {
+                return Err(TreeError::BackgroundCommitFailed);
+            }
+            state = self.changed.wait(state).expect("lock poisoned");
+        }
+    }
+}
+
+/// Marks the worker as stopped when it exits, including by a panic of the backend, so tickets
+/// do not wait forever.
+struct StopGuard(Arc<Progress>);
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.0.update(|state| state.1 = true);
+    }
+}
+
+/// A `HashDB` that writes to its backend on a background thread.
+///
+/// Commits of a `TreeDBMut` over it only buffer the committed nodes.  `submit` then hands the
+/// buffered nodes to a worker thread that writes them to the backend, so the next block can be
+/// computed while the previous one is flushed.  Submitted nodes stay readable until they are
+/// written, so trees over the database see every committed node at any time.
+///
+/// Under sustained load the worker may fall behind, and unwritten nodes accumulate in memory.
+/// `queue_depth` and `pending_nodes` measure the backlog, `flush` drains it, and
+/// `with_max_queue_depth` bounds it by making `submit` block.
+///
+/// Prefixes are ignored.  Removals wait for all submitted nodes to be written.  Dropping the
+/// database submits the buffered nodes and waits for the worker to write them.
+pub struct BackgroundDb<H: Hasher + 'static, B: HashDB<H, DBValue> + 'static> {
+    shared: Arc<Shared<H, B>>,
+    /// Nodes buffered since the last submission, in write order.
+    batch: Vec<(H::Out, DBValue)>,
+    submitted: u64,
+    max_queue_depth: Option<usize>,
+    sender: Option<mpsc::Sender<Batch<H>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<H: Hasher + 'static, B: HashDB<H, DBValue> + 'static> BackgroundDb<H, B> {
+    /// Wrap `backend`, starting the worker thread writing to it.
+    pub fn new(backend: B) -> Self {
+        let shared = Arc::new(Shared {
+            backend: Mutex::new(backend),
+            pending: Mutex::new(HashMap::new()),
+            progress: Arc::default(),
+        });
+        let (sender, receiver) = mpsc::channel::<Batch<H>>();
+
+        let worker_shared = shared.clone();
+        let worker = thread::spawn(move || {
+            let _stopped = StopGuard(worker_shared.progress.clone());
+            for (number, batch) in receiver {
+                let mut backend = worker_shared.backend.lock().expect("lock poisoned");
+                for (hash, node) in batch.iter() {
+                    backend.emplace(*hash, EMPTY_PREFIX, node.clone());
+                }
+                drop(backend);
+
+                // written nodes are read from the backend from now on
+                let mut pending = worker_shared.pending.lock().expect("lock poisoned");
+                for (hash, _) in batch.iter() {
+                    pending.remove(hash);
+                }
+                drop(pending);
+                worker_shared.progress.update(|state| state.0 = number);
+            }
+        });
+
+        Self {
+            shared,
+            batch: Vec::new(),
+            submitted: 0,
+            max_queue_depth: None,
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Bound the number of submitted batches waiting to be written: `submit` blocks until the
+    /// worker has written enough batches for a new one to fit.  A bound of zero makes every
+    /// submission synchronous.
+    pub fn with_max_queue_depth(mut self, max_queue_depth: usize) -> Self {
+        self.max_queue_depth = Some(max_queue_depth);
+        self
+    }
+
+    /// Return the number of submitted batches not yet written.
+    pub fn queue_depth(&self) -> usize {
+        (self.submitted - self.shared.progress.written()) as usize
+    }
+
+    /// Return the number of nodes not yet written, buffered or submitted.
+    pub fn pending_nodes(&self) -> usize {
+        self.shared.pending.lock().expect("lock poisoned").len()
+    }
+
+    /// Submit the buffered nodes and block until every submitted node is written.  Fails with
+    /// `TreeError::BackgroundCommitFailed` if the worker stopped first.
+    pub fn flush(&mut self) -> Result<(), TreeError> {
+        self.submit().wait()
+    }
+
+    /// Hand the nodes buffered since the last submission to the worker.  Returns a ticket
+    /// completed once they, and all nodes submitted before them, are written.  Blocks until the
+    /// queue is within the bound set with `with_max_queue_depth`.
+    pub fn submit(&mut self) -> CommitTicket {
+        if !self.batch.is_empty() {
+            self.submitted += 1;
+            let batch = core::mem::take(&mut self.batch);
+            if let Some(sender) = self.sender.as_ref() {
+                // a stopped worker is reported by the ticket
+                let _ = sender.send((self.submitted, batch));
+            }
+
+            if let Some(max_queue_depth) = self.max_queue_depth {
+                let written = self.submitted.saturating_sub(max_queue_depth as u64);
+                let _ = self.shared.progress.wait_for(written);
+            }
+        }
+
+        CommitTicket {
+            progress: self.shared.progress.clone(),
+            number: self.submitted,
+        }
+    }
+
+    /// Lock and return the backend.  Nodes submitted but not yet written are missing from it,
+    /// wait for their tickets first.
+    pub fn backend(&self) -> MutexGuard<'_, B> {
+        self.shared.backend.lock().expect("lock poisoned")
+    }
+}
+
+impl<H: Hasher + 'static, B: HashDB<H, DBValue> + 'static> Drop for BackgroundDb<H, B> {
+    fn drop(&mut self) {
+        self.submit();
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<H: Hasher + 'static, B: HashDB<H, DBValue> + 'static> HashDB<H, DBValue>
+    for BackgroundDb<H, B>
+{
+    fn get(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+        let pending = self.shared.pending.lock().expect("lock poisoned");
+        if let Some(node) = pending.get(key) {
+            return Some(node.clone());
+        }
+        drop(pending);
+        self.backend().get(key, prefix)
+    }
+
+    fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+        let pending = self.shared.pending.lock().expect("lock poisoned");
+        pending.contains_key(key) || {
+            drop(pending);
+            self.backend().contains(key, prefix)
+        }
+    }
+
+    fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+        let key = H::hash(value);
+        self.emplace(key, prefix, value.to_vec());
+        key
+    }
+
+    fn emplace(&mut self, key: H::Out, _prefix: Prefix, value: DBValue) {
+        let mut pending = self.shared.pending.lock().expect("lock poisoned");
+        pending.insert(key, value.clone());
+        self.batch.push((key, value));
+    }
+
+    fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+        // a stopped worker has nothing left to write
+        let _ = self.flush();
+        self.backend().remove(key, prefix);
+    }
+}
+
+impl<H: Hasher + 'static, B: HashDB<H, DBValue> + 'static> AsHashDB<H, DBValue>
+    for BackgroundDb<H, B>
+{
+    fn as_hash_db(&self) -> &dyn HashDB<H, DBValue> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, DBValue> + 'a) {
+        self
+    }
+}
+
+/// A handle on a batch submitted to a `BackgroundDb`.
+pub struct CommitTicket {
+    progress: Arc<Progress>,
+    number: u64,
+}
+
+impl CommitTicket {
+    /// Return true if the batch has been written.
+    pub fn is_done(&self) -> bool {
+        self.progress.written() >= self.number
+    }
+
+    /// Block until the batch has been written.  Fails with `TreeError::BackgroundCommitFailed`
+    /// if the worker stopped first, e.g. because the backend panicked.
+    pub fn wait(&self) -> Result<(), TreeError> {
+        self.progress.wait_for(self.number)
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    rstd::{BTreeMap, HashMap},
+    DBValue, Hasher, Node, Tree, TreeError,
+};
+use std::sync::Mutex;
+
+/// Memoize inclusion proofs generated from a `Tree`.
+///
+/// Proofs are cached per key for the root the cache was last used with.  When the cache is
+/// queried against a tree with a different root all cached proofs are discarded.
+pub struct ProofCache<H: Hasher> {
+    root: Option<H::Out>,
+    proofs: HashMap<Vec<u8>, Vec<(usize, DBValue)>>,
+}
+
+impl<H: Hasher> ProofCache<H> {
+    /// Create a new empty `ProofCache`.
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            proofs: HashMap::new(),
+        }
+    }
+
+    /// Get an inclusion proof for the leaf at the specified key, generating it from `tree` on a
+    /// cache miss.
+    pub fn get_proof<T: Tree<H> + ?Sized>(
+        &mut self,
+        tree: &T,
+        key: &[u8],
+    ) -> Result<Vec<(usize, DBValue)>, TreeError> {
+        if self.root.as_ref() != Some(tree.root()) {
+            self.proofs.clear();
+            self.root = Some(*tree.root());
+        }
+
+        if let Some(proof) = self.proofs.get(key) {
+            return Ok(proof.clone());
+        }
+
+        let proof = tree.get_proof(key)?;
+        self.proofs.insert(key.to_vec(), proof.clone());
+        Ok(proof)
+    }
+
+    /// Return the root the cached proofs were generated against.
+    pub fn root(&self) -> Option<&H::Out> {
+        self.root.as_ref()
+    }
+
+    /// Discard all cached proofs.
+    pub fn invalidate(&mut self) {
+        self.root = None;
+        self.proofs.clear();
+    }
+
+    /// Return the number of cached proofs.
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    /// Return true if no proofs are cached.
+    pub fn is_empty(&self) -> bool {
+        self.proofs.is_empty()
+    }
+}
+
+impl<H: Hasher> Default for ProofCache<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A cache of decoded nodes read by `TreeDB`, bounded by the bytes it holds.
+///
+/// Nodes are content addressed, so cached nodes stay valid across roots and one cache can serve
+/// every root of a long-running node.  The least recently used nodes are evicted once the cached
+/// bytes exceed the limit.  The cache is safe to share between trees on several threads, see
+/// `TreeDBBuilder::with_node_cache`.
+///
+/// Since a cached node is served to every tree sharing the cache, including trees that verify
+/// hashes, only nodes whose encoding hashes to their key are admitted.
+pub struct NodeCache<H: Hasher> {
+    inner: Mutex<NodeCacheInner<H>>,
+}
+
+struct NodeCacheInner<H: Hasher> {
+    byte_limit: usize,
+    /// The cached nodes with the tick they were last used at.
+    nodes: HashMap<H::Out, (DBValue, u64)>,
+    /// The cached nodes ordered from the least recently used.
+    recency: BTreeMap<u64, H::Out>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+/// Counters describing the use of a `NodeCache`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// The bytes currently held, counting each node's hash and encoding.
+    pub bytes: usize,
+    /// The number of nodes currently held.
+    pub entries: usize,
+}
+
+impl<H: Hasher> NodeCache<H> {
+    /// Create an empty cache holding at most `byte_limit` bytes.
+    pub fn new(byte_limit: usize) -> Self {
+        Self {
+            inner: Mutex::new(NodeCacheInner {
+                byte_limit,
+                nodes: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Return the cached encoding of the node with the given hash.
+    pub fn get(&self, hash: &H::Out) -> Option<DBValue> {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (data, last_used) = match inner.nodes.get_mut(hash) {
+            Some((data, last_used)) => (data.clone(), core::mem::replace(last_used, tick)),
+            None => {
+                inner.stats.misses += 1;
+                return None;
+            }
+        };
+        inner.recency.remove(&last_used);
+        inner.recency.insert(tick, *hash);
+        inner.stats.hits += 1;
+        Some(data)
+    }
+
+    /// Cache the encoding of the node with the given hash, evicting the least recently used
+    /// nodes to stay within the limit.  Nodes larger than the limit and encodings that do not
+    /// decode to a node with the given hash are not cached.
+    pub fn insert(&self, hash: H::Out, data: DBValue) {
+        if !Node::<H>::decode(&data).is_ok_and(|node| node.hash() == hash) {
+            return;
+        }
+        self.insert_verified(hash, data);
+    }
+
+    /// Cache an encoding the caller has already checked against `hash`.
+    pub(crate) fn insert_verified(&self, hash: H::Out, data: DBValue) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        let size = H::LENGTH + data.len();
+        if size > inner.byte_limit || inner.nodes.contains_key(&hash) {
+            return;
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.nodes.insert(hash, (data, tick));
+        inner.recency.insert(tick, hash);
+        inner.stats.bytes += size;
+        inner.stats.entries += 1;
+        inner.evict();
+    }
+
+    /// Return the byte limit.
+    pub fn byte_limit(&self) -> usize {
+        self.inner.lock().expect("lock poisoned").byte_limit
+    }
+
+    /// Change the byte limit, evicting nodes if the cache holds more.
+    pub fn set_byte_limit(&self, byte_limit: usize) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.byte_limit = byte_limit;
+        inner.evict();
+    }
+
+    /// Return the counters of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().expect("lock poisoned").stats
+    }
+
+    /// Discard all cached nodes.  The hit, miss and eviction counters are kept.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.nodes.clear();
+        inner.recency.clear();
+        inner.stats.bytes = 0;
+        inner.stats.entries = 0;
+    }
+}
+
+impl<H: Hasher> NodeCacheInner<H> {
+    fn evict(&mut self) {
+        while self.stats.bytes > self.byte_limit {
+            let (_, hash) = self
+                .recency
+                .pop_first()
+                .expect("bytes are held by cached nodes");
+            let (data, _) = self
+                .nodes
+                .remove(&hash)
+                .expect("recency tracks cached nodes");
+            self.stats.bytes -= H::LENGTH + data.len();
+            self.stats.entries -= 1;
+            self.stats.evictions += 1;
+        }
+    }
+}
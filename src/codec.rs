@@ -0,0 +1,147 @@
+use crate::{rstd::Vec, StorageProof, TreeError};
+
+const FRAME_MAGIC: u8 = 0xb7;
+const HEADER_LEN: usize = 6;
+
+/// A compression algorithm for the wire encoding of proofs.
+///
+/// Implementations wrap a compression library.  `Uncompressed` is provided for peers that do not
+/// support compression, `Zstd` with the `zstd` feature and `Snappy` with the `snappy` feature.
+pub trait ProofCodec {
+    /// Return the identifier written to the frame header.
+    fn id(&self) -> u8;
+
+    /// Compress the given data.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decompress the given data, failing if the output would exceed `max_len` bytes.
+    fn decompress(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, TreeError>;
+}
+
+/// A codec that stores proofs without compression.
+pub struct Uncompressed;
+
+impl ProofCodec for Uncompressed {
+    fn id(&self) -> u8 {
+        0
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, TreeError> {
+        if data.len() > max_len {
+            return Err(TreeError::ProofTooLarge);
+        }
+        Ok(data.to_vec())
+    }
+}
+
+/// A codec compressing proofs with zstd at the given level.
+#[cfg(feature = "zstd")]
+pub struct Zstd {
+    pub level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl Default for Zstd {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl ProofCodec for Zstd {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        zstd::bulk::compress(data, self.level).expect("compressing to a vec cannot fail")
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, TreeError> {
+        // the output buffer is capped at `max_len`, so larger payloads fail to decompress
+        zstd::bulk::decompress(data, max_len).map_err(|_| TreeError::ProofDecodeFailed)
+    }
+}
+
+/// A codec compressing proofs with raw snappy, trading ratio for speed.
+#[cfg(feature = "snappy")]
+pub struct Snappy;
+
+#[cfg(feature = "snappy")]
+impl ProofCodec for Snappy {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        snap::raw::Encoder::new()
+            .compress_vec(data)
+            .expect("compressing to a vec cannot fail")
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, TreeError> {
+        // the payload states its length, so it is checked before any output is allocated
+        let len = snap::raw::decompress_len(data).map_err(|_| TreeError::ProofDecodeFailed)?;
+        if len > max_len {
+            return Err(TreeError::ProofTooLarge);
+        }
+        snap::raw::Decoder::new()
+            .decompress_vec(data)
+            .map_err(|_| TreeError::ProofDecodeFailed)
+    }
+}
+
+impl StorageProof {
+    /// Encode the proof and compress it with the given codec.
+    ///
+    /// The frame is a magic byte, the codec identifier and the uncompressed length as a
+    /// little-endian `u32`, followed by the compressed payload.  Fails with
+    /// `TreeError::ProofTooLarge` if the encoded proof does not fit the length field.
+    pub fn encode_with(&self, codec: &dyn ProofCodec) -> Result<Vec<u8>, TreeError> {
+        let nodes: Vec<&Vec<u8>> = self.nodes().collect();
+        let data = bincode::serialize(&nodes).expect("serializing to a vec cannot fail");
+        let len = u32::try_from(data.len()).map_err(|_| TreeError::ProofTooLarge)?;
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + data.len());
+        frame.push(FRAME_MAGIC);
+        frame.push(codec.id());
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend(codec.compress(&data));
+        Ok(frame)
+    }
+
+    /// Decode a proof produced by `encode_with` using the same codec.  Fails with
+    /// `TreeError::ProofTooLarge` before decompressing if the proof would exceed `max_len`
+    /// bytes.
+    pub fn decode_with(
+        data: &[u8],
+        codec: &dyn ProofCodec,
+        max_len: usize,
+    ) -> Result<Self, TreeError> {
+        if data.len() < HEADER_LEN || data[0] != FRAME_MAGIC || data[1] != codec.id() {
+            return Err(TreeError::ProofDecodeFailed);
+        }
+
+        let mut len = [0; 4];
+        len.copy_from_slice(&data[2..HEADER_LEN]);
+        let len = u32::from_le_bytes(len) as usize;
+        if len > max_len {
+            return Err(TreeError::ProofTooLarge);
+        }
+
+        let decompressed = codec.decompress(&data[HEADER_LEN..], len)?;
+        if decompressed.len() != len {
+            return Err(TreeError::ProofDecodeFailed);
+        }
+        let nodes: Vec<Vec<u8>> =
+            bincode::deserialize(&decompressed).map_err(|_| TreeError::ProofDecodeFailed)?;
+
+        Ok(StorageProof::new(nodes))
+    }
+}
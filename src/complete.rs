@@ -0,0 +1,87 @@
+use crate::{
+    flat::{hash_complete, persist_complete},
+    rstd::{Arc, Vec},
+    DBValue, Hasher, NullHashes, TreeError,
+};
+use hash_db::HashDB;
+use memory_db::{HashKey, MemoryDB};
+
+/// The database `CompleteTreeBuilder::build` stores a tree in.
+pub type CompleteTreeDb<H> = MemoryDB<H, HashKey<H>, DBValue>;
+
+/// Builds a complete tree from the values of all of its leaves.
+///
+/// Nodes are built level by level from the leaves, hashing each node once, which is faster
+/// than inserting every leaf into a `TreeDBMut`.  Empty subtrees are not stored, like in trees
+/// committed by `TreeDBMut`.  Unlike a `FlatTree` the depth is not bounded by
+/// `MAX_FLAT_DEPTH`, but both build and store the tree the same way.
+pub struct CompleteTreeBuilder<H: Hasher> {
+    depth: usize,
+    leaves: Vec<DBValue>,
+    null_hashes: Arc<NullHashes<H>>,
+}
+
+impl<H: Hasher> CompleteTreeBuilder<H> {
+    /// Create a builder for a tree of the given depth holding `leaves` from the leftmost leaf.
+    /// There must be exactly `2^depth` leaves, default leaves for empty leaves.
+    pub fn new(depth: usize, leaves: Vec<DBValue>) -> Self {
+        Self {
+            depth,
+            leaves,
+            null_hashes: Arc::default(),
+        }
+    }
+
+    /// Build a tree with the given null hashes, for trees whose empty leaves hold another value
+    /// than the empty value.
+    pub fn with_null_hashes(mut self, null_hashes: Arc<NullHashes<H>>) -> Self {
+        self.null_hashes = null_hashes;
+        self
+    }
+
+    /// Write the nodes of the tree to `db` and return its root.  Fails with
+    /// `TreeError::IndexOutOfBounds` unless there are `2^depth` leaves.
+    pub fn build_into(self, db: &mut dyn HashDB<H, DBValue>) -> Result<H::Out, TreeError> {
+        let width = 1usize.checked_shl(self.depth as u32);
+        if width != Some(self.leaves.len()) {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let hashes = hash_complete::<H>(&self.leaves);
+        Ok(persist_complete(
+            self.depth,
+            &hashes,
+            &self.leaves,
+            &self.null_hashes,
+            db,
+        ))
+    }
+
+    /// Build the tree in a new database, returning the database and the root.  Fails like
+    /// `build_into`.
+    pub fn build(self) -> Result<(CompleteTreeDb<H>, H::Out), TreeError> {
+        let mut db = CompleteTreeDb::default();
+        let root = self.build_into(&mut db)?;
+        Ok((db, root))
+    }
+}
+
+impl<H: Hasher> Clone for CompleteTreeBuilder<H> {
+    fn clone(&self) -> Self {
+        Self {
+            depth: self.depth,
+            leaves: self.leaves.clone(),
+            null_hashes: self.null_hashes.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> core::fmt::Debug for CompleteTreeBuilder<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CompleteTreeBuilder")
+            .field("depth", &self.depth)
+            .field("leaves", &self.leaves)
+            .field("null_hashes", &self.null_hashes)
+            .finish()
+    }
+}
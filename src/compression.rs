@@ -0,0 +1,60 @@
+use crate::{rstd::Vec, DBValue, Hasher, Node, TreeError};
+
+/// The tag of a value node stored compressed, next to the canonical tags of value (0) and
+/// inner (1) nodes.
+pub(crate) const COMPRESSED_VALUE: u8 = 2;
+
+/// A compression applied to values at rest, such as zstd.
+///
+/// Values are compressed when written to the database on commit and decompressed on read.
+/// Leaves are hashed over the uncompressed value, so roots and proofs do not depend on the
+/// compression, and recorders receive canonical nodes.  Values that do not shrink are stored
+/// uncompressed.
+///
+/// Trees over a database holding compressed values must be opened with the same compression,
+/// and so must `repair`, `apply_diff` and the integrity check of `TreeDB`.  Inner nodes are
+/// never compressed, so readers that only decode inner nodes, such as `stream_diff`,
+/// `EpochDB` and prefetching, work on any store, and `stream_diff` ships values as stored.
+pub trait ValueCompression {
+    /// Compress a value.
+    fn compress(&self, value: &[u8]) -> Vec<u8>;
+
+    /// Decompress a value.
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, TreeError>;
+}
+
+/// Encode a node for the database, compressing its value if it shrinks.
+pub(crate) fn encode_stored<H: Hasher>(
+    compression: Option<&dyn ValueCompression>,
+    node: &Node<H>,
+) -> DBValue {
+    if let (Some(compression), Node::Value(value)) = (compression, node) {
+        let value = value.get();
+        let compressed = compression.compress(value);
+        if compressed.len() < value.len() {
+            let mut encoded = Vec::with_capacity(1 + compressed.len());
+            encoded.push(COMPRESSED_VALUE);
+            encoded.extend_from_slice(&compressed);
+            return encoded;
+        }
+    }
+    node.encode()
+}
+
+/// Return the canonical encoding of a node read from the database.
+pub(crate) fn decode_stored(
+    compression: Option<&dyn ValueCompression>,
+    data: DBValue,
+) -> Result<DBValue, TreeError> {
+    if data.first() != Some(&COMPRESSED_VALUE) {
+        return Ok(data);
+    }
+
+    let value = compression
+        .ok_or(TreeError::NodeDeserializationFailed)?
+        .decompress(&data[1..])?;
+    let mut encoded = Vec::with_capacity(1 + value.len());
+    encoded.push(0);
+    encoded.extend_from_slice(&value);
+    Ok(encoded)
+}
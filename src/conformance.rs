@@ -0,0 +1,178 @@
+//! Reusable property checks for hashers and backends.
+//!
+//! Each check generates pseudo-random operations from a seed, runs them against trees stored in
+//! backends created by `new_backend`, and panics with the seed and the failing case if a
+//! property does not hold.  Call them from a `#[test]` to validate a new `Hasher` or `HashDB`
+//! implementation.
+
+use crate::{
+    compute_null_hashes,
+    rstd::{BTreeMap, Vec},
+    DBValue, Hasher, Tree, TreeDBBuilder, TreeDBMutBuilder, TreeMut,
+};
+use hash_db::HashDB;
+
+/// The number of operations generated per check.
+const OPERATIONS: usize = 64;
+
+/// A xorshift64* generator, enough to spread test cases without a dependency.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub(crate) fn key(&mut self, depth: usize) -> Vec<u8> {
+        (0..depth).map(|_| (self.next() & 1) as u8).collect()
+    }
+
+    /// Return a non-empty value, as the empty value marks an empty leaf.
+    fn value(&mut self) -> DBValue {
+        let len = 1 + (self.next() % 8) as usize;
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    fn operations(&mut self, depth: usize) -> Vec<(Vec<u8>, DBValue)> {
+        (0..OPERATIONS)
+            .map(|_| (self.key(depth), self.value()))
+            .collect()
+    }
+}
+
+/// Insert `operations` into a new tree in `backend`, committing after every `batch` inserts,
+/// and return the root.
+fn build<H: Hasher>(
+    backend: &mut dyn HashDB<H, DBValue>,
+    depth: usize,
+    operations: &[(Vec<u8>, DBValue)],
+    batch: usize,
+) -> H::Out {
+    let mut root = compute_null_hashes::<H>(depth)[0];
+    for chunk in operations.chunks(batch.max(1)) {
+        let mut tree = TreeDBMutBuilder::new(backend, &mut root, depth).build();
+        for (key, value) in chunk {
+            tree.insert(key, value.clone())
+                .unwrap_or_else(|e| panic!("insert of {key:?} failed: {e:?}"));
+        }
+        tree.commit();
+    }
+    root
+}
+
+/// Check that every inserted value reads back, both before and after commit, and that keys
+/// never written read as empty.
+pub fn check_round_trip<H, B>(mut new_backend: impl FnMut() -> B, depth: usize, seed: u64)
+where
+    H: Hasher,
+    B: HashDB<H, DBValue>,
+{
+    let mut rng = Rng::new(seed);
+    let operations = rng.operations(depth);
+    let mut model = BTreeMap::new();
+
+    let mut backend = new_backend();
+    let mut root = compute_null_hashes::<H>(depth)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut backend, &mut root, depth).build();
+    for (key, value) in operations.iter() {
+        let old = tree.insert(key, value.clone()).unwrap();
+        let expected = model.insert(key.clone(), value.clone()).unwrap_or_default();
+        assert_eq!(old, expected, "seed {seed}: wrong old value at {key:?}");
+        assert_eq!(
+            tree.get_value(key).unwrap(),
+            *value,
+            "seed {seed}: uncommitted value at {key:?}"
+        );
+    }
+    tree.commit();
+    drop(tree);
+
+    let reader: &dyn HashDB<H, DBValue> = &backend;
+    let tree = TreeDBBuilder::new(&reader, &root, depth).build();
+    for _ in 0..OPERATIONS {
+        let key = rng.key(depth);
+        let expected = model.get(&key).cloned().unwrap_or_default();
+        assert_eq!(
+            tree.get_value(&key).unwrap(),
+            expected,
+            "seed {seed}: committed value at {key:?}"
+        );
+    }
+}
+
+/// Check that the proof of every key, populated or empty, verifies against the root.
+pub fn check_proofs<H, B>(mut new_backend: impl FnMut() -> B, depth: usize, seed: u64)
+where
+    H: Hasher,
+    B: HashDB<H, DBValue>,
+{
+    let mut rng = Rng::new(seed);
+    let operations = rng.operations(depth);
+
+    let mut backend = new_backend();
+    let root = build::<H>(&mut backend, depth, &operations, OPERATIONS);
+    let reader: &dyn HashDB<H, DBValue> = &backend;
+    let tree = TreeDBBuilder::new(&reader, &root, depth).build();
+
+    let keys = operations.iter().map(|(key, _)| key.clone());
+    let random_keys: Vec<Vec<u8>> = (0..OPERATIONS).map(|_| rng.key(depth)).collect();
+    for key in keys.chain(random_keys) {
+        let (value, proof) = tree.get_value_proof(&key).unwrap();
+        let computed = proof
+            .compute_root(&key, &value.unwrap_or_default())
+            .unwrap();
+        assert_eq!(
+            computed, root,
+            "seed {seed}: proof of {key:?} does not verify"
+        );
+    }
+}
+
+/// Check that the root only depends on the final contents: committing in batches, reopening
+/// the tree between batches and inserting in a different order all yield the same root.
+pub fn check_commit_reopen<H, B>(mut new_backend: impl FnMut() -> B, depth: usize, seed: u64)
+where
+    H: Hasher,
+    B: HashDB<H, DBValue>,
+{
+    let mut rng = Rng::new(seed);
+    let operations = rng.operations(depth);
+    let expected = build::<H>(&mut new_backend(), depth, &operations, OPERATIONS);
+
+    for batch in [1, 7] {
+        let root = build::<H>(&mut new_backend(), depth, &operations, batch);
+        assert_eq!(
+            root, expected,
+            "seed {seed}: root differs with batches of {batch}"
+        );
+    }
+
+    // only the last write to a key counts, in whatever order the keys are written
+    let mut last_writes: BTreeMap<Vec<u8>, DBValue> = BTreeMap::new();
+    last_writes.extend(operations.iter().cloned());
+    let mut reordered: Vec<(Vec<u8>, DBValue)> = last_writes.into_iter().collect();
+    reordered.reverse();
+    let root = build::<H>(&mut new_backend(), depth, &reordered, OPERATIONS);
+    assert_eq!(
+        root, expected,
+        "seed {seed}: root depends on insertion order"
+    );
+}
+
+/// Run every check.
+pub fn check_all<H, B>(mut new_backend: impl FnMut() -> B, depth: usize, seed: u64)
+where
+    H: Hasher,
+    B: HashDB<H, DBValue>,
+{
+    check_round_trip::<H, B>(&mut new_backend, depth, seed);
+    check_proofs::<H, B>(&mut new_backend, depth, seed);
+    check_commit_reopen::<H, B>(&mut new_backend, depth, seed);
+}
@@ -0,0 +1,140 @@
+use crate::{decode_hash, rstd::Vec, DBValue, Hasher, Tree, TreeDB, TreeError};
+
+/// A serializable position in a leaf iteration, used to resume paging through a tree.
+#[derive(Debug)]
+pub struct Cursor<H: Hasher> {
+    root: H::Out,
+    key: Vec<u8>,
+}
+
+impl<H: Hasher> Cursor<H> {
+    /// Return the root of the tree the cursor was created for.
+    pub fn root(&self) -> &H::Out {
+        &self.root
+    }
+
+    /// Return the key iteration resumes from.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Encode the cursor as the root followed by the key.
+    pub fn encode(&self) -> Vec<u8> {
+        [self.root.as_ref(), &self.key].concat()
+    }
+
+    /// Decode a cursor produced by `encode()`.
+    pub fn decode(data: &[u8]) -> Result<Self, TreeError> {
+        if data.len() < H::LENGTH {
+            return Err(TreeError::DecodeHashFailed);
+        }
+
+        let (root, key) = data.split_at(H::LENGTH);
+        Ok(Self {
+            root: decode_hash::<H>(root)?,
+            key: key.to_vec(),
+        })
+    }
+}
+
+impl<H: Hasher> Clone for Cursor<H> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root,
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for Cursor<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root && self.key == other.key
+    }
+}
+
+impl<H: Hasher> Eq for Cursor<H> {}
+
+/// Return the key following `key`, or `None` if `key` is the last key.
+fn next_key(key: &[u8]) -> Option<Vec<u8>> {
+    let position = key.iter().rposition(|bit| *bit == 0)?;
+    let mut next = key[..position].to_vec();
+    next.push(1);
+    next.resize(key.len(), 0);
+    Some(next)
+}
+
+/// An iterator over the populated leaves of a `TreeDB` in key order, starting at a given key.
+///
+/// Created by `TreeDB::iter_from` or `TreeDB::resume`.  Use `cursor()` to obtain a position
+/// that resumes after the last yielded leaf.  Iteration stops after the first error.
+pub struct LeafIter<'t, 'a, H: Hasher> {
+    tree: &'t TreeDB<'a, H>,
+    start: Vec<u8>,
+    next: Option<Vec<u8>>,
+    /// Nodes left to visit as (key prefix, node hash).
+    pending: Vec<(Vec<u8>, H::Out)>,
+}
+
+impl<'t, 'a, H: Hasher> LeafIter<'t, 'a, H> {
+    pub(crate) fn new(tree: &'t TreeDB<'a, H>, start: &[u8]) -> Result<Self, TreeError> {
+        if start.len() != tree.depth() {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        Ok(Self {
+            tree,
+            start: start.to_vec(),
+            next: Some(start.to_vec()),
+            pending: Vec::from([(Vec::new(), *tree.root())]),
+        })
+    }
+
+    /// Return a cursor resuming after the last yielded leaf, or `None` if the iteration reached
+    /// the end of the key space.
+    pub fn cursor(&self) -> Option<Cursor<H>> {
+        self.next.as_ref().map(|key| Cursor {
+            root: *self.tree.root(),
+            key: key.clone(),
+        })
+    }
+
+    fn step(&mut self) -> Result<Option<(Vec<u8>, DBValue)>, TreeError> {
+        while let Some((path, hash)) = self.pending.pop() {
+            let level = path.len();
+            if hash == self.tree.null_hash(level) {
+                continue;
+            }
+
+            let node = self.tree.lookup(&hash, level)?;
+            if level == self.tree.depth() {
+                self.next = next_key(&path);
+                return Ok(Some((path, node.get_value()?.get().clone())));
+            }
+
+            for bit in [true, false] {
+                let mut child = path.clone();
+                child.push(bit as u8);
+                // skip subtrees that lie entirely before the start key
+                if child.as_slice() >= &self.start[..=level] {
+                    self.pending
+                        .push((child, *node.get_child_bool(bit)?.get_hash()));
+                }
+            }
+        }
+
+        self.next = None;
+        Ok(None)
+    }
+}
+
+impl<'t, 'a, H: Hasher> Iterator for LeafIter<'t, 'a, H> {
+    type Item = Result<(Vec<u8>, DBValue), TreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.step();
+        if result.is_err() {
+            self.pending.clear();
+        }
+        result.transpose()
+    }
+}
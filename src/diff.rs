@@ -0,0 +1,169 @@
+use crate::{
+    compression, decode_hash, rstd::Vec, DBValue, HashDBRef, Hasher, Node, NullHashes, TreeError,
+    ValueCompression, EMPTY_PREFIX,
+};
+use hash_db::HashDB;
+use std::io::{Read, Write};
+
+const BEGIN_RECORD: u8 = 0;
+const INSERT_RECORD: u8 = 1;
+const DELETE_RECORD: u8 = 2;
+const END_RECORD: u8 = 3;
+
+/// The number of nodes inserted and deleted by a diff.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiffStats {
+    pub inserted: usize,
+    pub deleted: usize,
+}
+
+/// Decode a hash read from a diff.
+fn diff_hash<H: Hasher>(data: &[u8]) -> Result<H::Out, TreeError> {
+    decode_hash::<H>(data).map_err(|_| TreeError::DiffFailed)
+}
+
+fn write_record(writer: &mut dyn Write, record: (u8, &[u8], &[u8])) -> Result<(), TreeError> {
+    bincode::serialize_into(writer, &record).map_err(|_| TreeError::DiffFailed)
+}
+
+/// Write the node changes turning the tree at `old_root` into the tree at `new_root`, both of
+/// the given depth and null hashes and stored in `db`, for a replica to apply with
+/// `apply_diff`.
+///
+/// Only subtrees whose hashes differ are traversed.  Every node of the new tree missing at its
+/// position in the old tree is inserted, and every node of the old tree missing at its position
+/// in the new tree is deleted, in key order of their paths.  The stream is a bincode record
+/// naming both roots, a record per node and an end record.
+pub fn stream_diff<H: Hasher>(
+    db: &dyn HashDBRef<H, DBValue>,
+    depth: usize,
+    null_hashes: &NullHashes<H>,
+    old_root: &H::Out,
+    new_root: &H::Out,
+    writer: &mut dyn Write,
+) -> Result<DiffStats, TreeError> {
+    write_record(writer, (BEGIN_RECORD, old_root.as_ref(), new_root.as_ref()))?;
+
+    let null_hashes = null_hashes.ladder(depth);
+    let present = |hash: H::Out, level: usize| Some(hash).filter(|h| h != &null_hashes[level]);
+    let read = |hash: &H::Out, level: usize| {
+        db.get(hash, EMPTY_PREFIX)
+            .ok_or_else(|| TreeError::missing_node::<H>(hash, level))
+    };
+    let children = |data: Option<&DBValue>, level: usize| -> Result<_, TreeError> {
+        match data {
+            Some(data) if level < depth => {
+                let node = Node::<H>::decode(data)?;
+                Ok([
+                    present(*node.get_left_child()?.get_hash(), level + 1),
+                    present(*node.get_right_child()?.get_hash(), level + 1),
+                ])
+            }
+            _ => Ok([None, None]),
+        }
+    };
+
+    let mut stats = DiffStats::default();
+    let mut pending = Vec::from([(present(*old_root, 0), present(*new_root, 0), 0)]);
+    while let Some((old, new, level)) = pending.pop() {
+        if old == new {
+            continue;
+        }
+
+        let new_data = new.map(|hash| read(&hash, level)).transpose()?;
+        if let (Some(hash), Some(data)) = (new, new_data.as_ref()) {
+            write_record(writer, (INSERT_RECORD, hash.as_ref(), data))?;
+            stats.inserted += 1;
+        }
+        let old_data = old.map(|hash| read(&hash, level)).transpose()?;
+        if let Some(hash) = old {
+            write_record(writer, (DELETE_RECORD, hash.as_ref(), &[]))?;
+            stats.deleted += 1;
+        }
+
+        let [old_left, old_right] = children(old_data.as_ref(), level)?;
+        let [new_left, new_right] = children(new_data.as_ref(), level)?;
+        pending.push((old_right, new_right, level + 1));
+        pending.push((old_left, new_left, level + 1));
+    }
+
+    write_record(writer, (END_RECORD, &[], &[]))?;
+    writer.flush().map_err(|_| TreeError::DiffFailed)?;
+    Ok(stats)
+}
+
+/// Apply a diff written by `stream_diff` to the replica `db` whose tree of the given depth and
+/// null hashes has the given root, and set the root to the new root of the diff.
+///
+/// Fails with `TreeError::DiffRootMismatch` if the diff starts from another root, and with
+/// `TreeError::DiffFailed` if the stream is malformed or truncated, an inserted node does not
+/// match its hash or the new root is neither inserted, stored nor the empty root, in which case
+/// nothing is applied.  Deletions drop one reference to a node, as in reference-counted
+/// databases such as `MemoryDB`, so nodes shared with other positions or trees are kept.
+///
+/// Compressed values are checked against their hash after decompressing them with
+/// `compression` and stored as shipped, so the replica must be opened with the same
+/// compression.  Without it, a diff holding compressed values fails.
+pub fn apply_diff<H: Hasher>(
+    reader: &mut dyn Read,
+    db: &mut dyn HashDB<H, DBValue>,
+    root: &mut H::Out,
+    depth: usize,
+    null_hashes: &NullHashes<H>,
+    compression: Option<&dyn ValueCompression>,
+) -> Result<DiffStats, TreeError> {
+    let mut read_record = || -> Result<(u8, Vec<u8>, Vec<u8>), TreeError> {
+        bincode::deserialize_from(&mut *reader).map_err(|_| TreeError::DiffFailed)
+    };
+
+    let new_root = match read_record()? {
+        (BEGIN_RECORD, old_root, new_root) => {
+            if diff_hash::<H>(&old_root)? != *root {
+                return Err(TreeError::DiffRootMismatch);
+            }
+            diff_hash::<H>(&new_root)?
+        }
+        _ => return Err(TreeError::DiffFailed),
+    };
+
+    let mut inserts = Vec::new();
+    let mut deletes = Vec::new();
+    loop {
+        match read_record()? {
+            (INSERT_RECORD, hash, data) => {
+                let hash = diff_hash::<H>(&hash)?;
+                let verified = compression::decode_stored(compression, data.clone())
+                    .and_then(|canonical| Node::<H>::decode(&canonical))
+                    .is_ok_and(|node| node.hash() == hash);
+                if !verified {
+                    return Err(TreeError::DiffFailed);
+                }
+                inserts.push((hash, data));
+            }
+            (DELETE_RECORD, hash, _) => deletes.push(diff_hash::<H>(&hash)?),
+            (END_RECORD, _, _) => break,
+            _ => return Err(TreeError::DiffFailed),
+        }
+    }
+
+    // the replica must be able to serve the new root once the diff is applied
+    let backed = new_root == null_hashes.at_height(depth)
+        || db.contains(&new_root, EMPTY_PREFIX)
+        || inserts.iter().any(|(hash, _)| hash == &new_root);
+    if !backed || deletes.contains(&new_root) {
+        return Err(TreeError::DiffFailed);
+    }
+
+    let stats = DiffStats {
+        inserted: inserts.len(),
+        deleted: deletes.len(),
+    };
+    for (hash, data) in inserts {
+        db.emplace(hash, EMPTY_PREFIX, data);
+    }
+    for hash in deletes {
+        db.remove(&hash, EMPTY_PREFIX);
+    }
+    *root = new_root;
+    Ok(stats)
+}
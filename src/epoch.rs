@@ -0,0 +1,180 @@
+use crate::{
+    rstd::{BTreeMap, HashMap, HashSet, Vec},
+    DBValue, Node,
+};
+use hash_db::{AsHashDB, HashDB, Hasher, Prefix, EMPTY_PREFIX};
+
+/// The nodes written during an epoch.
+struct Era<H: Hasher> {
+    /// Nodes first written during the epoch.
+    nodes: HashSet<H::Out>,
+    /// Nodes of earlier epochs referenced by nodes or roots of the epoch.
+    references: HashSet<H::Out>,
+}
+
+impl<H: Hasher> Era<H> {
+    fn new() -> Self {
+        Self {
+            nodes: HashSet::new(),
+            references: HashSet::new(),
+        }
+    }
+}
+
+/// A database wrapper that tags written nodes with the epoch they were written in.
+///
+/// Trees are committed through the wrapper and each epoch is closed with `end_epoch()`.  Whole
+/// epochs are dropped with `prune_before()`, which only walks the nodes of the dropped epochs
+/// that are still referenced by a retained epoch.
+pub struct EpochDB<'db, H: Hasher> {
+    db: &'db mut dyn HashDB<H, DBValue>,
+    epoch: u64,
+    eras: BTreeMap<u64, Era<H>>,
+    owners: HashMap<H::Out, u64>,
+}
+
+impl<'db, H: Hasher> EpochDB<'db, H> {
+    /// Wrap the given database starting at epoch 0.
+    pub fn new(db: &'db mut dyn HashDB<H, DBValue>) -> Self {
+        Self {
+            db,
+            epoch: 0,
+            eras: BTreeMap::from([(0, Era::new())]),
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Return the current epoch.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Return the epoch the given node is tagged with.
+    pub fn epoch_of(&self, hash: &H::Out) -> Option<u64> {
+        self.owners.get(hash).copied()
+    }
+
+    /// Close the current epoch with the root committed during it and start the next epoch.
+    /// Returns the new epoch.
+    pub fn end_epoch(&mut self, root: H::Out) -> u64 {
+        self.note_reference(root);
+        self.epoch += 1;
+        self.eras.insert(self.epoch, Era::new());
+        self.epoch
+    }
+
+    /// Drop all epochs before `epoch` and remove their nodes from the database, except for nodes
+    /// still referenced by the roots of retained epochs.  Surviving nodes are re-tagged with the
+    /// oldest retained epoch.  Returns the number of removed nodes.
+    pub fn prune_before(&mut self, epoch: u64) -> usize {
+        let epoch = epoch.min(self.epoch);
+        let retained = self.eras.split_off(&epoch);
+        let dropped = std::mem::replace(&mut self.eras, retained);
+
+        let mut live = HashSet::new();
+        let mut stack: Vec<H::Out> = self
+            .eras
+            .values()
+            .flat_map(|era| era.references.iter().copied())
+            .collect();
+        while let Some(hash) = stack.pop() {
+            if !matches!(self.owners.get(&hash), Some(owner) if *owner < epoch) {
+                continue;
+            }
+            if !live.insert(hash) {
+                continue;
+            }
+            if let Some(Node::Inner(left, right)) = self
+                .db
+                .get(&hash, EMPTY_PREFIX)
+                .and_then(|data| Node::<H>::decode(&data).ok())
+            {
+                stack.push(*left.get_hash());
+                stack.push(*right.get_hash());
+            }
+        }
+
+        let mut removed = 0;
+        for (_, era) in dropped {
+            for hash in era.nodes {
+                if live.contains(&hash) {
+                    self.owners.insert(hash, epoch);
+                    self.eras
+                        .get_mut(&epoch)
+                        .expect("the current epoch is always retained")
+                        .nodes
+                        .insert(hash);
+                } else {
+                    self.owners.remove(&hash);
+                    self.db.remove(&hash, EMPTY_PREFIX);
+                    removed += 1;
+                }
+            }
+        }
+
+        removed
+    }
+
+    fn current(&mut self) -> &mut Era<H> {
+        self.eras
+            .get_mut(&self.epoch)
+            .expect("the current epoch is always present")
+    }
+
+    /// Record a reference from the current epoch to the given node if it belongs to an earlier
+    /// epoch.
+    fn note_reference(&mut self, hash: H::Out) {
+        if matches!(self.owners.get(&hash), Some(owner) if *owner != self.epoch) {
+            self.current().references.insert(hash);
+        }
+    }
+
+    fn track(&mut self, hash: H::Out, value: &[u8]) {
+        if self.owners.contains_key(&hash) {
+            self.note_reference(hash);
+            return;
+        }
+
+        self.owners.insert(hash, self.epoch);
+        self.current().nodes.insert(hash);
+        if let Ok(Node::Inner(left, right)) = Node::<H>::decode(value) {
+            self.note_reference(*left.get_hash());
+            self.note_reference(*right.get_hash());
+        }
+    }
+}
+
+impl<'db, H: Hasher> HashDB<H, DBValue> for EpochDB<'db, H> {
+    fn get(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+        self.db.get(key, prefix)
+    }
+
+    fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+        self.db.contains(key, prefix)
+    }
+
+    fn insert(&mut self, prefix: Prefix, value: &[u8]) -> H::Out {
+        let key = self.db.insert(prefix, value);
+        self.track(key, value);
+        key
+    }
+
+    fn emplace(&mut self, key: H::Out, prefix: Prefix, value: DBValue) {
+        self.track(key, &value);
+        self.db.emplace(key, prefix, value);
+    }
+
+    fn remove(&mut self, key: &H::Out, prefix: Prefix) {
+        self.db.remove(key, prefix);
+    }
+}
+
+impl<'db, H: Hasher> AsHashDB<H, DBValue> for EpochDB<'db, H> {
+    fn as_hash_db(&self) -> &dyn HashDB<H, DBValue> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, DBValue> + 'a) {
+        self
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    rstd::{Vec, VecDeque},
+    Hasher, Tree, TreeDB, TreeError,
+};
+
+/// Hash a pair of nodes in sorted order, as OpenZeppelin's `MerkleProof` does.
+fn hash_pair<H: Hasher>(a: &H::Out, b: &H::Out) -> H::Out {
+    let (low, high) = if a.as_ref() <= b.as_ref() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    H::hash(&[low.as_ref(), high.as_ref()].concat())
+}
+
+/// Return the hashes of empty subtrees under commutative hashing, indexed by level.
+fn commutative_null_hashes<H: Hasher>(tree: &TreeDB<H>) -> Vec<H::Out> {
+    let mut null_hashes = Vec::from([tree.null_hash(tree.depth())]);
+    for height in 0..tree.depth() {
+        let child = null_hashes[height];
+        null_hashes.push(hash_pair::<H>(&child, &child));
+    }
+    null_hashes.reverse();
+    null_hashes
+}
+
+fn commutative_subtree<H: Hasher>(
+    tree: &TreeDB<H>,
+    hash: H::Out,
+    level: usize,
+    null_hashes: &[H::Out],
+) -> Result<H::Out, TreeError> {
+    if level == tree.depth() {
+        return Ok(hash);
+    }
+    if hash == tree.null_hash(level) {
+        return Ok(null_hashes[level]);
+    }
+
+    let node = tree.lookup(&hash, level)?;
+    let left = commutative_subtree(
+        tree,
+        *node.get_left_child()?.get_hash(),
+        level + 1,
+        null_hashes,
+    )?;
+    let right = commutative_subtree(
+        tree,
+        *node.get_right_child()?.get_hash(),
+        level + 1,
+        null_hashes,
+    )?;
+    Ok(hash_pair::<H>(&left, &right))
+}
+
+/// Return the hash of the subtree at the given key prefix.
+fn subtree_hash<H: Hasher>(tree: &TreeDB<H>, path: &[u8]) -> Result<H::Out, TreeError> {
+    let mut hash = *tree.root();
+    for (level, bit) in path.iter().enumerate() {
+        hash = *tree.lookup(&hash, level)?.get_child(*bit)?.get_hash();
+    }
+    Ok(hash)
+}
+
+/// Compute the root of the tree with every inner node hashed as `H(min(l, r) ++ max(l, r))`.
+///
+/// This is the root verified on-chain by OpenZeppelin's `MerkleProof.multiProofVerify`, which
+/// cannot check this crate's positional roots.  Leaves are hashed as in the tree.
+pub fn commutative_root<H: Hasher>(tree: &TreeDB<H>) -> Result<H::Out, TreeError> {
+    commutative_subtree(tree, *tree.root(), 0, &commutative_null_hashes(tree))
+}
+
+/// A multiproof in the flattened `(leaves, proof, proofFlags)` layout consumed by OpenZeppelin's
+/// `MerkleProof.multiProofVerify` against the `commutative_root` of a tree.
+#[derive(Debug)]
+pub struct EvmMultiProof<H: Hasher> {
+    pub leaves: Vec<H::Out>,
+    pub proof: Vec<H::Out>,
+    pub proof_flags: Vec<bool>,
+}
+
+impl<H: Hasher> Clone for EvmMultiProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            leaves: self.leaves.clone(),
+            proof: self.proof.clone(),
+            proof_flags: self.proof_flags.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for EvmMultiProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.leaves == other.leaves
+            && self.proof == other.proof
+            && self.proof_flags == other.proof_flags
+    }
+}
+
+impl<H: Hasher> Eq for EvmMultiProof<H> {}
+
+impl<H: Hasher> EvmMultiProof<H> {
+    /// Recompute the root the same way `MerkleProof.processMultiProof` does on-chain.  Returns
+    /// `None` if the proof is malformed.
+    pub fn process(&self) -> Option<H::Out> {
+        if self.leaves.len() + self.proof.len() != self.proof_flags.len() + 1 {
+            return None;
+        }
+        if self.proof_flags.is_empty() {
+            return self.leaves.first().or(self.proof.first()).copied();
+        }
+
+        let mut leaves = self.leaves.iter();
+        let mut proof = self.proof.iter();
+        let mut hashes: Vec<H::Out> = Vec::with_capacity(self.proof_flags.len());
+        let mut hash_position = 0;
+        for flag in self.proof_flags.iter() {
+            let mut next = || match leaves.next() {
+                Some(leaf) => Some(*leaf),
+                None => {
+                    hash_position += 1;
+                    hashes.get(hash_position - 1).copied()
+                }
+            };
+            let a = next()?;
+            let b = if *flag { next()? } else { *proof.next()? };
+            hashes.push(hash_pair::<H>(&a, &b));
+        }
+
+        proof
+            .next()
+            .is_none()
+            .then(|| *hashes.last().expect("flags are not empty"))
+    }
+
+    /// Verify the proof against a root computed by `commutative_root`.
+    pub fn verify(&self, root: &H::Out) -> bool {
+        self.process().as_ref() == Some(root)
+    }
+}
+
+/// Generate a multiproof of the leaves at the given keys for OpenZeppelin's
+/// `MerkleProof.multiProofVerify`.  Leaves are listed in key order and absent keys are proven
+/// by their null leaf.
+///
+/// Sibling hashes are recomputed under commutative hashing, which walks the sibling subtrees.
+pub fn evm_multiproof<H: Hasher>(
+    tree: &TreeDB<H>,
+    keys: &[&[u8]],
+) -> Result<EvmMultiProof<H>, TreeError> {
+    if keys.is_empty() || keys.iter().any(|key| key.len() != tree.depth()) {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+
+    let mut keys = keys.to_vec();
+    keys.sort();
+    keys.dedup();
+
+    let null_hashes = commutative_null_hashes(tree);
+    let mut queue: VecDeque<Vec<u8>> = keys.iter().map(|key| key.to_vec()).collect();
+    let mut multiproof = EvmMultiProof {
+        leaves: keys
+            .iter()
+            .map(|key| subtree_hash(tree, key))
+            .collect::<Result<_, _>>()?,
+        proof: Vec::new(),
+        proof_flags: Vec::new(),
+    };
+
+    while let Some(mut path) = queue.pop_front() {
+        let level = match path.len() {
+            0 => break,
+            level => level,
+        };
+
+        let mut sibling = path.clone();
+        sibling[level - 1] ^= 1;
+        if queue.front() == Some(&sibling) {
+            queue.pop_front();
+            multiproof.proof_flags.push(true);
+        } else {
+            let hash = subtree_hash(tree, &sibling)?;
+            multiproof
+                .proof
+                .push(commutative_subtree(tree, hash, level, &null_hashes)?);
+            multiproof.proof_flags.push(false);
+        }
+
+        path.pop();
+        queue.push_back(path);
+    }
+
+    Ok(multiproof)
+}
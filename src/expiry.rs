@@ -0,0 +1,27 @@
+use crate::{rstd::Vec, DBValue, TreeError};
+
+/// The length of the expiry annotation prefixed to values.
+const EXPIRY_LEN: usize = 8;
+
+/// Annotate `value` with the time it expires at, as stored by `TreeDBMut::insert_with_expiry`.
+///
+/// The annotation is the big-endian expiry followed by the value, so it is committed to by the
+/// leaf hash and proofs of the leaf prove its expiry.
+pub fn with_expiry(value: &[u8], expires_at: u64) -> DBValue {
+    let mut annotated = Vec::with_capacity(EXPIRY_LEN + value.len());
+    annotated.extend_from_slice(&expires_at.to_be_bytes());
+    annotated.extend_from_slice(value);
+    annotated
+}
+
+/// Split an annotated value into the time it expires at and the value.  Fails with
+/// `TreeError::MissingExpiry` if the value is too short to carry an annotation.
+pub fn split_expiry(annotated: &[u8]) -> Result<(u64, &[u8]), TreeError> {
+    if annotated.len() < EXPIRY_LEN {
+        return Err(TreeError::MissingExpiry);
+    }
+
+    let (expiry, value) = annotated.split_at(EXPIRY_LEN);
+    let expiry = u64::from_be_bytes(expiry.try_into().expect("split at the expiry length"));
+    Ok((expiry, value))
+}
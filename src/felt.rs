@@ -0,0 +1,130 @@
+use crate::{rstd::Vec, FoldHasher, TreeError};
+use core::marker::PhantomData;
+use hash_db::Hasher;
+
+/// A Stark field element as 32 big-endian bytes.
+pub type Felt = [u8; 32];
+
+/// The Stark field modulus `2^251 + 17 * 2^192 + 1`, big-endian.
+pub const STARK_PRIME: Felt = [
+    0x08, 0, 0, 0, 0, 0, 0, 0x11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0x01,
+];
+
+/// The number of bytes packed into each field element of a long input, as in Cairo's
+/// `bytes31`.
+const CHUNK_LEN: usize = 31;
+
+/// A hash over the Stark field, such as Pedersen or Poseidon.
+///
+/// `Pedersen` and `Poseidon` are provided with the `starknet` feature.  Use it through
+/// `FeltHash`.
+pub trait FeltHasher {
+    /// Hash two field elements into one.
+    fn hash_pair(a: &Felt, b: &Felt) -> Felt;
+}
+
+#[cfg(feature = "starknet")]
+fn field_element(felt: &Felt) -> starknet_crypto::FieldElement {
+    starknet_crypto::FieldElement::from_bytes_be(felt).expect("inputs are field elements")
+}
+
+/// The StarkNet Pedersen hash, as used by Cairo's `hash2`.
+#[cfg(feature = "starknet")]
+pub struct Pedersen;
+
+#[cfg(feature = "starknet")]
+impl FeltHasher for Pedersen {
+    fn hash_pair(a: &Felt, b: &Felt) -> Felt {
+        starknet_crypto::pedersen_hash(&field_element(a), &field_element(b)).to_bytes_be()
+    }
+}
+
+/// The StarkNet Poseidon hash of two elements, as used by Cairo's `poseidon_hash`.
+#[cfg(feature = "starknet")]
+pub struct Poseidon;
+
+#[cfg(feature = "starknet")]
+impl FeltHasher for Poseidon {
+    fn hash_pair(a: &Felt, b: &Felt) -> Felt {
+        starknet_crypto::poseidon_hash(field_element(a), field_element(b)).to_bytes_be()
+    }
+}
+
+/// Return true if the bytes encode an element of the Stark field.
+pub fn is_felt(bytes: &Felt) -> bool {
+    bytes < &STARK_PRIME
+}
+
+/// Encode up to 32 bytes as a field element by left-padding with zeros, failing if the value is
+/// not below the field modulus.
+pub fn felt_from_bytes(data: &[u8]) -> Result<Felt, TreeError> {
+    if data.len() > 32 {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+
+    let mut felt = [0; 32];
+    felt[32 - data.len()..].copy_from_slice(data);
+    if !is_felt(&felt) {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+    Ok(felt)
+}
+
+/// Adapt a `FeltHasher` into a `Hasher` for trees whose roots match Cairo binary merkle trees,
+/// e.g. `FeltHash<Pedersen>`.
+///
+/// Inputs are mapped onto field elements as follows:
+///
+/// - the empty input, the value of empty leaves, hashes to zero;
+/// - a single field element hashes to itself, so felt leaves are stored as is;
+/// - two field elements, the children of an inner node, hash to `hash_pair(left, right)`;
+/// - any other input is split into big-endian 31-byte chunks and hashed with Cairo's
+///   `compute_hash_on_elements`, `hash_pair(...hash_pair(hash_pair(0, e1), e2)..., n)`.
+///
+/// Like the Cairo trees it matches, leaves and inner nodes are not domain separated.
+pub struct FeltHash<F>(PhantomData<F>);
+
+impl<F: FeltHasher> FeltHash<F> {
+    fn hash_on_elements(elements: &[Felt]) -> Felt {
+        let hash = elements
+            .iter()
+            .fold([0; 32], |hash, element| F::hash_pair(&hash, element));
+        let mut len = [0; 32];
+        len[24..].copy_from_slice(&(elements.len() as u64).to_be_bytes());
+        F::hash_pair(&hash, &len)
+    }
+}
+
+impl<F: FeltHasher + Send + Sync> Hasher for FeltHash<F> {
+    type Out = Felt;
+
+    type StdHasher = FoldHasher;
+
+    const LENGTH: usize = 32;
+
+    fn hash(x: &[u8]) -> Felt {
+        if x.is_empty() {
+            return [0; 32];
+        }
+        if let Ok(felt) = Felt::try_from(x) {
+            if is_felt(&felt) {
+                return felt;
+            }
+        }
+        if x.len() == 64 {
+            let (left, right) = x.split_at(32);
+            let left: Felt = left.try_into().expect("split at 32");
+            let right: Felt = right.try_into().expect("split at 32");
+            if is_felt(&left) && is_felt(&right) {
+                return F::hash_pair(&left, &right);
+            }
+        }
+
+        let elements: Vec<Felt> = x
+            .chunks(CHUNK_LEN)
+            .map(|chunk| felt_from_bytes(chunk).expect("31 bytes are below the modulus"))
+            .collect();
+        Self::hash_on_elements(&elements)
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    indices,
+    node::{key_bit, NodeHash, Value},
+    rstd::{Arc, BTreeSet, Vec},
+    value_proof, DBValue, Hasher, Node, NodeView, NullHashes, Proof, Tree, TreeError, EMPTY_PREFIX,
+};
+use hash_db::HashDB;
+
+/// The depth of the deepest tree a `FlatTree` holds.
+pub const MAX_FLAT_DEPTH: usize = 20;
+
+/// A small complete tree held in memory as a flat array of node hashes.
+///
+/// Nodes are addressed by index like in the `Tree` docs, so reads and updates need no database
+/// lookups or hashing of keys.  Use it for small trees with mostly populated leaves, such as
+/// airdrops or epochs, and a `TreeDBMut` for deep sparse trees.  `persist` writes the tree to a
+/// database in the node encoding of `TreeDB`.
+///
+/// The choice is explicit: `TreeDBMut` always updates through the hash-addressed overlay, even
+/// for trees no deeper than `MAX_FLAT_DEPTH`, since it does not hold every leaf in memory.  Build
+/// and update small trees with a `FlatTree` and `persist` them to open them with `TreeDB` or
+/// `TreeDBMut`.
+#[derive(Debug)]
+pub struct FlatTree<H: Hasher> {
+    depth: usize,
+    /// Node hashes by index: the root is at 1 and the leaves start at `1 << depth`.
+    hashes: Vec<H::Out>,
+    values: Vec<DBValue>,
+    null_hashes: Arc<NullHashes<H>>,
+}
+
+impl<H: Hasher> FlatTree<H> {
+    /// Build a tree of the given depth holding `leaves` from the leftmost leaf, with the
+    /// remaining leaves empty.  Fails with `TreeError::IndexOutOfBounds` if the depth exceeds
+    /// `MAX_FLAT_DEPTH` or there are more leaves than the tree holds.
+    pub fn from_leaves(depth: usize, leaves: Vec<DBValue>) -> Result<Self, TreeError> {
+        Self::from_leaves_with_null_hashes(depth, leaves, Arc::default())
+    }
+
+    /// Build a tree like `from_leaves` whose empty leaves hold the default leaf of
+    /// `null_hashes`.
+    pub fn from_leaves_with_null_hashes(
+        depth: usize,
+        mut leaves: Vec<DBValue>,
+        null_hashes: Arc<NullHashes<H>>,
+    ) -> Result<Self, TreeError> {
+        let width = 1 << depth.min(MAX_FLAT_DEPTH);
+        if depth > MAX_FLAT_DEPTH || leaves.len() > width {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+        leaves.resize(width, null_hashes.default_leaf().to_vec());
+
+        Ok(Self {
+            depth,
+            hashes: hash_complete::<H>(&leaves),
+            values: leaves,
+            null_hashes,
+        })
+    }
+
+    /// Set the values of the leaves numbered by `updates`, counting from the leftmost leaf, and
+    /// return the new root.  Each changed node is hashed once however many of its leaves change.
+    pub fn update(&mut self, updates: &[(usize, DBValue)]) -> Result<&H::Out, TreeError> {
+        let width = self.values.len();
+        if updates.iter().any(|(leaf, _)| *leaf >= width) {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut dirty = BTreeSet::new();
+        for (leaf, value) in updates {
+            self.hashes[width + leaf] = H::hash(value);
+            self.values[*leaf] = value.clone();
+            dirty.insert((width + leaf) >> 1);
+        }
+        // children have greater indices than their parents, so they are hashed first
+        while let Some(index) = dirty.pop_last() {
+            if index == 0 {
+                break;
+            }
+            self.hashes[index] = hash_children::<H>(&self.hashes, index);
+            dirty.insert(index >> 1);
+        }
+
+        Ok(&self.hashes[1])
+    }
+
+    /// Return the value of the leaf numbered `leaf`, counting from the leftmost leaf.
+    pub fn value(&self, leaf: usize) -> Option<&DBValue> {
+        self.values.get(leaf)
+    }
+
+    /// Write the nodes of the tree to `db` and return the root.  Empty subtrees are not written,
+    /// as `TreeDB` synthesizes them on lookup.
+    pub fn persist(&self, db: &mut dyn HashDB<H, DBValue>) -> H::Out {
+        persist_complete(
+            self.depth,
+            &self.hashes,
+            &self.values,
+            &self.null_hashes,
+            db,
+        )
+    }
+
+    /// Return the index of the node at the end of the path `key_prefix`.
+    fn index(&self, key_prefix: &[u8]) -> Result<usize, TreeError> {
+        if key_prefix.len() > self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+        for bit in key_prefix {
+            key_bit(*bit)?;
+        }
+
+        Ok(indices::compute_index(key_prefix))
+    }
+
+    /// Return the index of the leaf at `key`.
+    fn leaf_index(&self, key: &[u8]) -> Result<usize, TreeError> {
+        if key.len() != self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        self.index(key)
+    }
+}
+
+/// Hash the complete tree over `leaves`, whose number is a power of two, into node hashes by
+/// index: the root at 1 and the leaves from `leaves.len()`.
+pub(crate) fn hash_complete<H: Hasher>(leaves: &[DBValue]) -> Vec<H::Out> {
+    let width = leaves.len();
+    let mut hashes = vec![H::Out::default(); 2 * width];
+    for (hash, value) in hashes[width..].iter_mut().zip(leaves.iter()) {
+        *hash = H::hash(value);
+    }
+    for index in (1..width).rev() {
+        hashes[index] = hash_children::<H>(&hashes, index);
+    }
+    hashes
+}
+
+/// Write the nodes of the complete tree of the given depth hashed by `hash_complete` to `db`
+/// and return its root.  Empty subtrees are not written.
+pub(crate) fn persist_complete<H: Hasher>(
+    depth: usize,
+    hashes: &[H::Out],
+    values: &[DBValue],
+    null_hashes: &NullHashes<H>,
+    db: &mut dyn HashDB<H, DBValue>,
+) -> H::Out {
+    let null_hashes = null_hashes.ladder(depth);
+    let mut stack = Vec::from([(1, 0)]);
+    while let Some((index, level)) = stack.pop() {
+        if hashes[index] == null_hashes[level] {
+            continue;
+        }
+        let node = if level == depth {
+            Node::<H>::Value(Value::Cached(values[index - values.len()].clone()))
+        } else {
+            stack.extend([(2 * index, level + 1), (2 * index + 1, level + 1)]);
+            Node::Inner(
+                NodeHash::Hash(hashes[2 * index]),
+                NodeHash::Hash(hashes[2 * index + 1]),
+            )
+        };
+        db.emplace(hashes[index], EMPTY_PREFIX, node.encode());
+    }
+
+    hashes[1]
+}
+
+fn hash_children<H: Hasher>(hashes: &[H::Out], index: usize) -> H::Out {
+    H::hash(&[hashes[2 * index].as_ref(), hashes[2 * index + 1].as_ref()].concat())
+}
+
+impl<H: Hasher> Tree<H> for FlatTree<H> {
+    fn root(&self) -> &H::Out {
+        &self.hashes[1]
+    }
+
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn get_value(&self, key: &[u8]) -> Result<DBValue, TreeError> {
+        let index = self.leaf_index(key)?;
+        Ok(self.values[index - self.values.len()].clone())
+    }
+
+    fn get_leaf(&self, key: &[u8]) -> Result<H::Out, TreeError> {
+        Ok(self.hashes[self.leaf_index(key)?])
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Result<Vec<(usize, DBValue)>, TreeError> {
+        let leaf = self.leaf_index(key)?;
+
+        let mut proof = Vec::with_capacity(2 * self.depth + 2);
+        proof.push((1, self.hashes[1].as_ref().to_vec()));
+        for level in (1..=self.depth).rev() {
+            let left = (leaf >> level) << 1;
+            proof.extend_from_slice(&[
+                (left, self.hashes[left].as_ref().to_vec()),
+                (left + 1, self.hashes[left + 1].as_ref().to_vec()),
+            ]);
+        }
+        proof.push((0, self.values[leaf - self.values.len()].clone()));
+
+        Ok(proof)
+    }
+
+    fn get_value_proof(&self, key: &[u8]) -> Result<(Option<DBValue>, Proof<H>), TreeError> {
+        value_proof(key, self.get_proof(key)?, self.null_hashes.default_leaf())
+    }
+
+    fn get_node(&self, key_prefix: &[u8]) -> Result<NodeView<H>, TreeError> {
+        let index = self.index(key_prefix)?;
+        if key_prefix.len() == self.depth {
+            return Ok(NodeView::Value(
+                self.values[index - self.values.len()].clone(),
+            ));
+        }
+
+        Ok(NodeView::Inner(
+            self.hashes[2 * index],
+            self.hashes[2 * index + 1],
+        ))
+    }
+}
+
+impl<H: Hasher> Clone for FlatTree<H> {
+    fn clone(&self) -> Self {
+        Self {
+            depth: self.depth,
+            hashes: self.hashes.clone(),
+            values: self.values.clone(),
+            null_hashes: self.null_hashes.clone(),
+        }
+    }
+}
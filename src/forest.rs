@@ -0,0 +1,256 @@
+use crate::{
+    compute_null_hashes, decode_hash,
+    rstd::{BTreeMap, HashMap, HashSet, Vec},
+    sharded::ShardDB,
+    DBValue, HashDBRef, Hasher, Node, TreeDB, TreeDBBuilder, TreeDBMut, TreeDBMutBuilder,
+    TreeError, EMPTY_PREFIX,
+};
+use hash_db::HashDB;
+
+/// A registry of named trees sharing one database.
+///
+/// Nodes are content addressed, so identical subtrees are shared between trees.  The registry
+/// itself is persisted in the database with `persist()` and loaded again with `open()`.
+pub struct Forest<H: Hasher> {
+    trees: BTreeMap<String, (H::Out, usize)>,
+}
+
+impl<H: Hasher> Forest<H> {
+    /// Create an empty forest.
+    pub fn new() -> Self {
+        Self {
+            trees: BTreeMap::new(),
+        }
+    }
+
+    /// Load the registry stored under the given hash.
+    pub fn open(db: &dyn HashDBRef<H, DBValue>, registry: &H::Out) -> Result<Self, TreeError> {
+        let data = db
+            .get(registry, EMPTY_PREFIX)
+            .ok_or_else(|| TreeError::missing_node::<H>(registry, 0))?;
+        let entries: Vec<(String, Vec<u8>, u64)> =
+            bincode::deserialize(&data).map_err(|_| TreeError::NodeDeserializationFailed)?;
+
+        let mut trees = BTreeMap::new();
+        for (name, root, depth) in entries {
+            trees.insert(name, (decode_hash::<H>(&root)?, depth as usize));
+        }
+
+        Ok(Self { trees })
+    }
+
+    /// Write the registry to the database and return the hash it is stored under.
+    pub fn persist(&self, db: &mut dyn HashDB<H, DBValue>) -> H::Out {
+        let entries: Vec<(&str, &[u8], u64)> = self
+            .trees
+            .iter()
+            .map(|(name, (root, depth))| (name.as_str(), root.as_ref(), *depth as u64))
+            .collect();
+        let data = bincode::serialize(&entries).expect("serializing to a vec cannot fail");
+        db.insert(EMPTY_PREFIX, &data)
+    }
+
+    /// Register a new empty tree of the given depth.
+    pub fn create(&mut self, name: &str, depth: usize) -> Result<&H::Out, TreeError> {
+        if self.trees.contains_key(name) {
+            return Err(TreeError::DuplicateTree);
+        }
+
+        let root = compute_null_hashes::<H>(depth)[0];
+        let (root, _) = self.trees.entry(name.into()).or_insert((root, depth));
+        Ok(root)
+    }
+
+    /// Return the names of all registered trees in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.trees.keys().map(|name| name.as_str())
+    }
+
+    /// Return the root of the named tree.
+    pub fn root(&self, name: &str) -> Option<&H::Out> {
+        self.trees.get(name).map(|(root, _)| root)
+    }
+
+    /// Return the depth of the named tree.
+    pub fn depth(&self, name: &str) -> Option<usize> {
+        self.trees.get(name).map(|(_, depth)| *depth)
+    }
+
+    /// Return a read-only handle to the named tree.
+    pub fn tree<'a>(
+        &'a self,
+        db: &'a dyn HashDBRef<H, DBValue>,
+        name: &str,
+    ) -> Result<TreeDB<'a, H>, TreeError> {
+        let (root, depth) = self.trees.get(name).ok_or(TreeError::UnknownTree)?;
+        Ok(TreeDBBuilder::new(db, root, *depth).build())
+    }
+
+    /// Return a mutable handle to the named tree.  Committing the handle updates the registry.
+    pub fn tree_mut<'a>(
+        &'a mut self,
+        db: &'a mut dyn HashDB<H, DBValue>,
+        name: &str,
+    ) -> Result<TreeDBMut<'a, H>, TreeError> {
+        let (root, depth) = self.trees.get_mut(name).ok_or(TreeError::UnknownTree)?;
+        Ok(TreeDBMutBuilder::new(db, root, *depth).build())
+    }
+
+    /// Start a transaction staging changes to several trees over `db`, which is only read until
+    /// the changes are passed to `commit_transaction`.
+    pub fn transaction<'db>(&self, db: &'db dyn HashDB<H, DBValue>) -> ForestTransaction<'db, H> {
+        ForestTransaction {
+            db: ShardDB::new(db),
+            base: self.trees.clone(),
+            trees: self.trees.clone(),
+        }
+    }
+
+    /// Write the nodes staged by a transaction and update the roots of its trees together.
+    ///
+    /// Fails with `TreeError::StaleTransaction`, writing nothing, if a tree changed by the
+    /// transaction was changed or unregistered since the transaction started.  Nodes are written
+    /// before any root is updated, so the registry never names a root whose nodes are missing.
+    pub fn commit_transaction(
+        &mut self,
+        db: &mut dyn HashDB<H, DBValue>,
+        changes: ForestChanges<H>,
+    ) -> Result<(), TreeError> {
+        if changes
+            .roots
+            .iter()
+            .any(|(name, (base, _))| self.root(name) != Some(base))
+        {
+            return Err(TreeError::StaleTransaction);
+        }
+
+        for (hash, value) in changes.nodes {
+            db.emplace(hash, EMPTY_PREFIX, value);
+        }
+        for (name, (_, root)) in changes.roots {
+            if let Some((current, _)) = self.trees.get_mut(&name) {
+                *current = root;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unregister the named tree and remove the nodes that no other registered tree references.
+    /// Returns the number of removed nodes.
+    pub fn remove(
+        &mut self,
+        db: &mut dyn HashDB<H, DBValue>,
+        name: &str,
+    ) -> Result<usize, TreeError> {
+        let (root, depth) = self.trees.remove(name).ok_or(TreeError::UnknownTree)?;
+
+        let mut retained = HashSet::new();
+        for (root, depth) in self.trees.values() {
+            retained.extend(reachable(&db, root, *depth)?);
+        }
+
+        let mut removed = 0;
+        for hash in reachable(&db, &root, depth)? {
+            if !retained.contains(&hash) {
+                db.remove(&hash, EMPTY_PREFIX);
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Changes to several trees of a `Forest` staged to be committed together.
+///
+/// Created by `Forest::transaction`.  Trees are committed into an overlay, so child trees can
+/// be updated and their new roots inserted into a parent tree before anything is written.  Pass
+/// the result of `into_changes()` to `Forest::commit_transaction`, or drop the transaction to
+/// discard it.
+pub struct ForestTransaction<'db, H: Hasher> {
+    db: ShardDB<'db, H>,
+    /// The registry when the transaction started.
+    base: BTreeMap<String, (H::Out, usize)>,
+    trees: BTreeMap<String, (H::Out, usize)>,
+}
+
+impl<'db, H: Hasher> ForestTransaction<'db, H> {
+    /// Return the staged root of the named tree.
+    pub fn root(&self, name: &str) -> Option<&H::Out> {
+        self.trees.get(name).map(|(root, _)| root)
+    }
+
+    /// Return a mutable handle to the named tree.  Committing the handle stages its nodes and
+    /// root in the transaction.
+    pub fn tree_mut(&mut self, name: &str) -> Result<TreeDBMut<'_, H>, TreeError> {
+        let (root, depth) = self.trees.get_mut(name).ok_or(TreeError::UnknownTree)?;
+        Ok(TreeDBMutBuilder::new(&mut self.db, root, *depth).build())
+    }
+
+    /// Consume the transaction and return the staged changes.
+    pub fn into_changes(self) -> ForestChanges<H> {
+        let roots = self
+            .trees
+            .into_iter()
+            .filter_map(|(name, (root, _))| {
+                let (base, _) = self.base.get(&name)?;
+                (base != &root).then_some((name, (*base, root)))
+            })
+            .collect();
+
+        ForestChanges {
+            nodes: self.db.changes,
+            roots,
+        }
+    }
+}
+
+/// The staged changes of a `ForestTransaction`.
+pub struct ForestChanges<H: Hasher> {
+    nodes: HashMap<H::Out, DBValue>,
+    /// The changed trees with their roots before and after the transaction.
+    roots: BTreeMap<String, (H::Out, H::Out)>,
+}
+
+impl<H: Hasher> ForestChanges<H> {
+    /// Return the names of the trees changed by the transaction.
+    pub fn trees(&self) -> impl Iterator<Item = &str> {
+        self.roots.keys().map(|name| name.as_str())
+    }
+}
+
+impl<H: Hasher> Default for Forest<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collect the hashes of all stored nodes reachable from `root`.
+fn reachable<H: Hasher>(
+    db: &dyn HashDBRef<H, DBValue>,
+    root: &H::Out,
+    depth: usize,
+) -> Result<HashSet<H::Out>, TreeError> {
+    let mut nodes = HashSet::new();
+    let mut stack = Vec::from([(*root, 0)]);
+
+    while let Some((hash, level)) = stack.pop() {
+        if nodes.contains(&hash) {
+            continue;
+        }
+        let data = match db.get(&hash, EMPTY_PREFIX) {
+            Some(data) => data,
+            None => continue,
+        };
+        nodes.insert(hash);
+
+        if level < depth {
+            let node: Node<H> = data.try_into()?;
+            stack.push((*node.get_left_child()?.get_hash(), level + 1));
+            stack.push((*node.get_right_child()?.get_hash(), level + 1));
+        }
+    }
+
+    Ok(nodes)
+}
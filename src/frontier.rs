@@ -0,0 +1,147 @@
+use crate::{
+    decode_hash,
+    rstd::{Arc, Vec},
+    Hasher, NullHashes, TreeError, MAX_KEY_DEPTH,
+};
+
+/// The state of an append-only tree whose first `next_index` leaves are populated.
+///
+/// The frontier is the left siblings along the path to leaf `next_index`, ordered from the leaf
+/// to the root, one per set bit of `next_index`.  It determines the root, so it can be
+/// checkpointed in place of the leaves and resumed with `TreeDBMut::from_frontier`.
+///
+/// The null hashes are not encoded, so a decoded frontier of a tree whose empty leaves hold
+/// another value than the empty value needs them set again with `with_null_hashes`.
+#[derive(Debug)]
+pub struct Frontier<H: Hasher> {
+    depth: usize,
+    next_index: u64,
+    siblings: Vec<H::Out>,
+    null_hashes: Arc<NullHashes<H>>,
+}
+
+impl<H: Hasher> Frontier<H> {
+    /// Create the frontier of a tree of the given depth.  Fails with
+    /// `TreeError::IndexOutOfBounds` if the depth exceeds `MAX_KEY_DEPTH`, the tree cannot hold
+    /// `next_index` leaves or there is not one sibling per set bit of `next_index`.  The
+    /// frontier of a full tree is its root.
+    pub fn new(depth: usize, next_index: u64, siblings: Vec<H::Out>) -> Result<Self, TreeError> {
+        if depth > MAX_KEY_DEPTH {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let expected = if next_index as u128 == capacity(depth) {
+            1
+        } else {
+            next_index.count_ones() as usize
+        };
+        if next_index as u128 > capacity(depth) || siblings.len() != expected {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        Ok(Self {
+            depth,
+            next_index,
+            siblings,
+            null_hashes: Arc::default(),
+        })
+    }
+
+    /// Compute the root with the given null hashes, for trees whose empty leaves hold another
+    /// value than the empty value.
+    pub fn with_null_hashes(mut self, null_hashes: Arc<NullHashes<H>>) -> Self {
+        self.null_hashes = null_hashes;
+        self
+    }
+
+    /// Return the depth of the tree.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Return the number of populated leaves.
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    /// Return the siblings ordered from the leaf to the root.
+    pub fn siblings(&self) -> &[H::Out] {
+        &self.siblings
+    }
+
+    /// Compute the root of the tree.
+    pub fn root(&self) -> H::Out {
+        if self.next_index as u128 == capacity(self.depth) {
+            return self.siblings[0];
+        }
+
+        let null_hashes = self.null_hashes.ladder(self.depth);
+        let mut siblings = self.siblings.iter();
+        (0..self.depth).fold(null_hashes[self.depth], |hash, height| {
+            let level = self.depth - 1 - height;
+            let bit = self.next_index.checked_shr(height as u32).unwrap_or(0) & 1;
+            let (left, right) = if bit == 1 {
+                (*siblings.next().expect("one sibling per set bit"), hash)
+            } else {
+                (hash, null_hashes[level + 1])
+            };
+            H::hash(&[left.as_ref(), right.as_ref()].concat())
+        })
+    }
+
+    /// Check that the frontier is the state of the tree with the given root and number of
+    /// populated leaves.  Fails with `TreeError::FrontierMismatch` otherwise.
+    pub fn check(&self, root: &H::Out, leaf_count: u64) -> Result<(), TreeError> {
+        if leaf_count != self.next_index || &self.root() != root {
+            return Err(TreeError::FrontierMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Encode the frontier for storage.
+    pub fn encode(&self) -> Vec<u8> {
+        let siblings: Vec<&[u8]> = self.siblings.iter().map(|hash| hash.as_ref()).collect();
+        let record = (self.depth as u64, self.next_index, siblings);
+        bincode::serialize(&record).expect("serializing to a vec cannot fail")
+    }
+
+    /// Decode a frontier encoded with `encode`.
+    pub fn decode(data: &[u8]) -> Result<Self, TreeError> {
+        let (depth, next_index, siblings): (u64, u64, Vec<Vec<u8>>) =
+            bincode::deserialize(data).map_err(|_| TreeError::NodeDeserializationFailed)?;
+        let siblings = siblings
+            .iter()
+            .map(|hash| decode_hash::<H>(hash))
+            .collect::<Result<_, _>>()?;
+
+        let depth = usize::try_from(depth).map_err(|_| TreeError::IndexOutOfBounds)?;
+        Self::new(depth, next_index, siblings)
+    }
+}
+
+/// Return the number of leaves of a tree of the given depth, saturating for deep trees.
+pub(crate) fn capacity(depth: usize) -> u128 {
+    1u128.checked_shl(depth as u32).unwrap_or(u128::MAX)
+}
+
+impl<H: Hasher> Clone for Frontier<H> {
+    fn clone(&self) -> Self {
+        Self {
+            depth: self.depth,
+            next_index: self.next_index,
+            siblings: self.siblings.clone(),
+            null_hashes: self.null_hashes.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for Frontier<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.depth == other.depth
+            && self.next_index == other.next_index
+            && self.siblings == other.siblings
+    }
+}
+
+impl<H: Hasher> Eq for Frontier<H> {}
@@ -0,0 +1,44 @@
+use crate::{
+    compute_null_hashes, conformance::Rng, frontier::capacity, rstd::HashSet, DBValue, Hasher,
+    TreeDBMutBuilder, TreeError, TreeMut,
+};
+use memory_db::{HashKey, MemoryDB};
+
+/// The database `generate_tree` stores a generated tree in.
+pub type GeneratedDb<H> = MemoryDB<H, HashKey<H>, DBValue>;
+
+/// Build a tree of the given depth holding `n_leaves` pseudo-random leaves derived from `seed`,
+/// and return the database holding its nodes and its root.
+///
+/// The same arguments always produce the same tree, so benchmarks and load tests can recreate
+/// large trees instead of storing them.  Keys are distinct and uniformly spread, and values are
+/// `value_size` random bytes, or one byte if `value_size` is zero as the empty value marks an
+/// empty leaf.  Returns `TreeError::IndexOutOfBounds` if the tree cannot hold `n_leaves`.
+pub fn generate_tree<H: Hasher>(
+    seed: u64,
+    n_leaves: usize,
+    value_size: usize,
+    depth: usize,
+) -> Result<(GeneratedDb<H>, H::Out), TreeError> {
+    if n_leaves as u128 > capacity(depth) {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+
+    let mut rng = Rng::new(seed);
+    let mut db = MemoryDB::default();
+    let mut root = compute_null_hashes::<H>(depth)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, depth).build();
+    let mut keys = HashSet::with_capacity(n_leaves);
+    while keys.len() < n_leaves {
+        let key = rng.key(depth);
+        if keys.contains(&key) {
+            continue;
+        }
+        let value = (0..value_size.max(1)).map(|_| rng.next() as u8).collect();
+        tree.insert(&key, value)?;
+        keys.insert(key);
+    }
+    tree.commit();
+    drop(tree);
+    Ok((db, root))
+}
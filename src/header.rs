@@ -0,0 +1,81 @@
+use crate::{Hasher, TreeError};
+
+/// The version of the proof serialization format written by this crate.
+pub const PROOF_FORMAT_VERSION: u8 = 1;
+
+/// The length of an encoded `ProofHeader`.
+pub const PROOF_HEADER_LEN: usize = 10;
+
+/// A self-describing header prefixed to serialized proofs.
+///
+/// A verifier decoding a proof built for another format, hasher, depth or arity fails with
+/// `TreeError::IncompatibleProof` instead of computing a wrong root.  The header is the format
+/// version, the hasher id, the depth as a little-endian `u32` and the arity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofHeader {
+    pub version: u8,
+    pub hasher_id: [u8; 4],
+    pub depth: u32,
+    pub arity: u8,
+}
+
+impl ProofHeader {
+    /// Describe a proof of a binary tree of the given depth hashed with `H`.
+    pub fn new<H: Hasher>(depth: usize) -> Self {
+        Self {
+            version: PROOF_FORMAT_VERSION,
+            hasher_id: Self::hasher_id::<H>(),
+            depth: depth as u32,
+            arity: 2,
+        }
+    }
+
+    /// Identify a hasher by the leading bytes of its hash of the empty value.  Hashers without
+    /// a registry of identifiers thus get a stable id that differs between hash functions.
+    pub fn hasher_id<H: Hasher>() -> [u8; 4] {
+        let mut id = [0; 4];
+        id.copy_from_slice(&H::hash(&[]).as_ref()[..4]);
+        id
+    }
+
+    /// Encode the header.
+    pub fn encode(&self) -> [u8; PROOF_HEADER_LEN] {
+        let mut data = [0; PROOF_HEADER_LEN];
+        data[0] = self.version;
+        data[1..5].copy_from_slice(&self.hasher_id);
+        data[5..9].copy_from_slice(&self.depth.to_le_bytes());
+        data[9] = self.arity;
+        data
+    }
+
+    /// Decode the header at the start of `data`, returning it and the data that follows.
+    pub fn decode(data: &[u8]) -> Result<(Self, &[u8]), TreeError> {
+        if data.len() < PROOF_HEADER_LEN {
+            return Err(TreeError::ProofDecodeFailed);
+        }
+
+        let (header, rest) = data.split_at(PROOF_HEADER_LEN);
+        let mut hasher_id = [0; 4];
+        hasher_id.copy_from_slice(&header[1..5]);
+        let mut depth = [0; 4];
+        depth.copy_from_slice(&header[5..9]);
+        let header = Self {
+            version: header[0],
+            hasher_id,
+            depth: u32::from_le_bytes(depth),
+            arity: header[9],
+        };
+
+        Ok((header, rest))
+    }
+
+    /// Check that the header describes a proof this crate can verify for a tree of the given
+    /// depth hashed with `H`.
+    pub fn validate<H: Hasher>(&self, depth: usize) -> Result<(), TreeError> {
+        if self != &Self::new::<H>(depth) {
+            return Err(TreeError::IncompatibleProof);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{
+    rstd::Vec, DBValue, Hasher, KeyDepth, Proof, TreeDBMut, TreeDBMutBuilder, TreeError, TreeMut,
+};
+use hash_db::HashDB;
+
+/// A tree of depth 64 addressed by `u64` indices instead of bit keys.
+///
+/// Leaf `index` is at the key holding the bits of `index` from the most significant, so
+/// indices are ordered like keys.  Use `key` to get the key an index maps to, for example to
+/// check a proof with `Proof::compute_root`.  Changes are committed like those of the wrapped
+/// `TreeDBMut`.
+pub struct IndexedTree<'db, H: Hasher> {
+    tree: TreeDBMut<'db, H>,
+}
+
+impl<'db, H: Hasher> IndexedTree<'db, H> {
+    /// Open the tree with the given root.
+    pub fn new(db: &'db mut dyn HashDB<H, DBValue>, root: &'db mut H::Out) -> Self {
+        Self {
+            tree: TreeDBMutBuilder::for_key::<u64>(db, root).build(),
+        }
+    }
+
+    /// Return the key of the leaf at `index`.
+    pub fn key(index: u64) -> Vec<u8> {
+        (0..u64::DEPTH)
+            .rev()
+            .map(|bit| (index >> bit) as u8 & 1)
+            .collect()
+    }
+
+    /// Get the value at `index`, empty for an unset leaf.
+    pub fn get(&self, index: u64) -> Result<DBValue, TreeError> {
+        self.tree.get_value(&Self::key(index))
+    }
+
+    /// Insert a value at `index` and return the old value.
+    pub fn insert(&mut self, index: u64, value: DBValue) -> Result<DBValue, TreeError> {
+        self.tree.insert(&Self::key(index), value)
+    }
+
+    /// Get the value at `index` and a proof for it, like `TreeMut::get_value_proof`.  The value
+    /// is `None` for an unset leaf.
+    pub fn prove(&self, index: u64) -> Result<(Option<DBValue>, Proof<H>), TreeError> {
+        self.tree.get_value_proof(&Self::key(index))
+    }
+
+    /// Commit the changes and return the root.
+    pub fn root(&mut self) -> &H::Out {
+        self.tree.root()
+    }
+
+    /// Return the wrapped tree.
+    pub fn into_inner(self) -> TreeDBMut<'db, H> {
+        self.tree
+    }
+}
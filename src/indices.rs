@@ -1,13 +1,12 @@
-use super::rstd::Vec;
-
+/// Return the index of the node at the given key prefix: the root is 1 and the children of `i`
+/// are `2i` and `2i + 1`.  Indices only identify nodes in the top 63 levels of a deeper tree, as
+/// higher bits are shifted out.
 pub(crate) fn compute_index(key: &[u8]) -> usize {
-    let len = key.len();
-    let base: usize = 1 << len;
-    let multiplier: Vec<usize> = (0..len).rev().map(|x| 1 << x).collect();
-    let sum: usize = key
-        .iter()
-        .zip(multiplier)
-        .map(|(x, y)| (*x as usize) * y)
-        .sum();
-    base + sum
+    key.iter()
+        .fold(1, |index, bit| child_index(index, *bit == 1))
+}
+
+/// Return the index of the child of the node at `index` in the direction of `bit`.
+pub(crate) fn child_index(index: usize, bit: bool) -> usize {
+    (index << 1) | usize::from(bit)
 }
@@ -0,0 +1,110 @@
+use crate::{
+    compression,
+    rstd::{HashSet, Vec},
+    DBValue, HashDBRef, Hasher, Node, ValueCompression, EMPTY_PREFIX,
+};
+
+/// A problem found while checking the integrity of a tree.
+///
+/// `path` is the key prefix of the offending node and `hash` the hash it was referenced by.
+#[derive(Debug)]
+pub enum IntegrityProblem<H: Hasher> {
+    /// The node is referenced but not present in the database.
+    MissingNode { path: Vec<u8>, hash: H::Out },
+    /// The node could not be decoded.
+    UndecodableNode { path: Vec<u8>, hash: H::Out },
+    /// The hash of the node does not match the hash it is referenced by.
+    HashMismatch { path: Vec<u8>, hash: H::Out },
+    /// A value node above the leaf layer or an inner node at the leaf layer.
+    UnexpectedNodeType { path: Vec<u8>, hash: H::Out },
+}
+
+/// The result of a full-tree integrity check.
+#[derive(Debug)]
+pub struct IntegrityReport<H: Hasher> {
+    /// The number of nodes fetched from the database.
+    pub nodes_checked: usize,
+    /// The problems found.
+    pub problems: Vec<IntegrityProblem<H>>,
+}
+
+impl<H: Hasher> IntegrityReport<H> {
+    /// Return true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Walk all nodes reachable from `root` and check their linkage.
+///
+/// Null subtrees that are not present in the database are valid and skipped.  Subtrees that are
+/// referenced multiple times at the same depth are only checked once.  Values stored compressed
+/// are decompressed with `compression` before they are checked.
+pub(crate) fn verify<H: Hasher>(
+    db: &dyn HashDBRef<H, DBValue>,
+    root: &H::Out,
+    depth: usize,
+    null_hashes: &[H::Out],
+    compression: Option<&dyn ValueCompression>,
+) -> IntegrityReport<H> {
+    let mut report = IntegrityReport {
+        nodes_checked: 0,
+        problems: Vec::new(),
+    };
+    let mut visited = HashSet::new();
+    let mut stack = Vec::from([(*root, Vec::new())]);
+
+    while let Some((hash, path)) = stack.pop() {
+        let level = path.len();
+        if !visited.insert((level, hash)) {
+            continue;
+        }
+
+        let data = match db.get(&hash, EMPTY_PREFIX) {
+            Some(data) => data,
+            None if hash == null_hashes[level] => continue,
+            None => {
+                report
+                    .problems
+                    .push(IntegrityProblem::MissingNode { path, hash });
+                continue;
+            }
+        };
+        report.nodes_checked += 1;
+
+        let node: Node<H> =
+            match compression::decode_stored(compression, data).and_then(|data| data.try_into()) {
+                Ok(node) => node,
+                Err(_) => {
+                    report
+                        .problems
+                        .push(IntegrityProblem::UndecodableNode { path, hash });
+                    continue;
+                }
+            };
+
+        if node.hash() != hash {
+            report
+                .problems
+                .push(IntegrityProblem::HashMismatch { path, hash });
+            continue;
+        }
+
+        match node {
+            Node::Inner(left, right) if level < depth => {
+                let mut right_path = path.clone();
+                right_path.push(1);
+                stack.push((*right.get_hash(), right_path));
+                let mut left_path = path;
+                left_path.push(0);
+                stack.push((*left.get_hash(), left_path));
+            }
+            Node::Value(_) if level == depth => (),
+            _ => report
+                .problems
+                .push(IntegrityProblem::UnexpectedNodeType { path, hash }),
+        }
+    }
+
+    report
+}
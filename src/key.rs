@@ -1,13 +1,67 @@
-#[derive(Eq, PartialEq)]
+use crate::{Hasher, TreeError};
+
+/// A key of `N` bytes addressing the leaves of a tree of depth `8 * N`, most significant bit
+/// first.  Keys order like the leaves they address.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Key<const N: usize>([u8; N]);
 
 const BYTE_SIZE: u8 = 8;
 
+/// The largest depth a `Key` can address, as bit positions are a `u8`.
+pub const MAX_KEY_DEPTH: usize = u8::MAX as usize + 1;
+
+/// Assert at compile time that `Key<N>` fits trees hashed with `H`: the key is no longer than
+/// the output of `H`, so keys derived by hashing fill it, and its depth is at most
+/// `MAX_KEY_DEPTH`.  A mismatched combination fails to build wherever the function is called,
+/// e.g. `assert_key_fits::<Sha3, 33>()`, instead of misbehaving at run time.
+pub fn assert_key_fits<H: Hasher, const N: usize>() {
+    const {
+        assert!(N <= H::LENGTH, "the key is longer than the hasher output");
+        assert!(
+            N * BYTE_SIZE as usize <= MAX_KEY_DEPTH,
+            "the key is deeper than MAX_KEY_DEPTH"
+        );
+    }
+}
+
 impl<const N: usize> Key<N> {
     pub fn new(key: [u8; N]) -> Key<N> {
         Key(key)
     }
 
+    /// Create the key of the leaf numbered `index`, counting from the leftmost leaf.  Fails with
+    /// `TreeError::IndexOutOfBounds` if the index does not fit in `8 * N` bits.
+    pub fn from_index(index: u128) -> Result<Self, TreeError> {
+        let bytes = index.to_be_bytes();
+        let mut key = [0u8; N];
+        if N >= bytes.len() {
+            key[N - bytes.len()..].copy_from_slice(&bytes);
+        } else {
+            let (high, low) = bytes.split_at(bytes.len() - N);
+            if high.iter().any(|byte| *byte != 0) {
+                return Err(TreeError::IndexOutOfBounds);
+            }
+            key.copy_from_slice(low);
+        }
+        Ok(Key(key))
+    }
+
+    /// Return the number of the leaf addressed by the key, the inverse of `from_index`.  Fails
+    /// with `TreeError::IndexOutOfBounds` if the index does not fit in a `u128`.
+    pub fn to_index(&self) -> Result<u128, TreeError> {
+        let mut bytes = [0u8; 16];
+        if N > bytes.len() {
+            let (high, low) = self.0.split_at(N - bytes.len());
+            if high.iter().any(|byte| *byte != 0) {
+                return Err(TreeError::IndexOutOfBounds);
+            }
+            bytes.copy_from_slice(low);
+        } else {
+            bytes[16 - N..].copy_from_slice(&self.0);
+        }
+        Ok(u128::from_be_bytes(bytes))
+    }
+
     pub const fn zero() -> Self {
         Self([0u8; N])
     }
@@ -33,22 +87,111 @@ impl<const N: usize> Key<N> {
             element: 0,
         }
     }
+
+    /// Return the key following this one, or `None` if this is the last key.
+    fn successor(&self) -> Option<Self> {
+        let mut next = self.0;
+        for byte in next.iter_mut().rev() {
+            let (incremented, overflow) = byte.overflowing_add(1);
+            *byte = incremented;
+            if !overflow {
+                return Some(Key(next));
+            }
+        }
+        None
+    }
+}
+
+/// The half-open range of keys from `start` up to but excluding `end`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct KeyRange<const N: usize> {
+    start: Key<N>,
+    end: Key<N>,
+}
+
+impl<const N: usize> KeyRange<N> {
+    pub fn new(start: Key<N>, end: Key<N>) -> Self {
+        Self { start, end }
+    }
+
+    pub fn start(&self) -> &Key<N> {
+        &self.start
+    }
+
+    pub fn end(&self) -> &Key<N> {
+        &self.end
+    }
+
+    pub fn contains(&self, key: &Key<N>) -> bool {
+        &self.start <= key && key < &self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Iterate over the keys of the range in order.
+    pub fn iter(&self) -> KeyRangeIter<N> {
+        KeyRangeIter {
+            next: Some(self.start).filter(|_| !self.is_empty()),
+            end: self.end,
+        }
+    }
+}
+
+impl<const N: usize> IntoIterator for KeyRange<N> {
+    type Item = Key<N>;
+    type IntoIter = KeyRangeIter<N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the keys of a `KeyRange`.
+pub struct KeyRangeIter<const N: usize> {
+    next: Option<Key<N>>,
+    end: Key<N>,
+}
+
+impl<const N: usize> Iterator for KeyRangeIter<N> {
+    type Item = Key<N>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.next?;
+        self.next = key.successor().filter(|next| next < &self.end);
+        Some(key)
+    }
+}
+
+/// A key representation with a fixed number of bits, used to derive the depth of a tree.
+pub trait KeyDepth {
+    /// The depth of a tree keyed by this type.
+    const DEPTH: usize;
+}
+
+impl<const N: usize> KeyDepth for Key<N> {
+    const DEPTH: usize = N * BYTE_SIZE as usize;
+}
+
+impl KeyDepth for u64 {
+    const DEPTH: usize = u64::BITS as usize;
 }
 
 pub struct KeyIter<'a, const N: usize> {
     key: &'a Key<N>,
-    element: u8,
+    element: usize,
 }
 
 impl<'a, const N: usize> Iterator for KeyIter<'a, N> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.element >= N as u8 * 8 {
+        if self.element >= N * BYTE_SIZE as usize {
             return None;
         }
 
-        let result = self.key.get_bit(&self.element);
+        let result = self.key.get_bit(&(self.element as u8));
         self.element += 1;
 
         Some(result)
@@ -3,8 +3,9 @@
 #[cfg(feature = "std")]
 mod rstd {
     pub use std::{
-        collections::{BTreeSet, HashMap},
-        convert, mem,
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+        convert,
+        sync::Arc,
         vec::Vec,
     };
 }
@@ -12,17 +13,57 @@ mod rstd {
 #[cfg(not(feature = "std"))]
 mod rstd {
     pub use alloc::collections::Vec;
-    pub use core::collections::{BTreeSet, HashMap};
+    pub use alloc::sync::Arc;
+    pub use core::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
     pub use core::mem;
 }
 
+mod attestation;
+mod audit;
+mod background;
+mod cache;
+mod codec;
+mod complete;
+mod compression;
+pub mod conformance;
+mod cursor;
+mod diff;
+mod epoch;
+mod evm;
+mod expiry;
+mod felt;
+mod flat;
+mod forest;
+mod frontier;
+mod generate;
+mod header;
+mod indexed;
 mod indices;
+mod integrity;
 mod key;
+mod light;
+mod metadata;
+mod meter;
+mod migrate;
+mod nmt;
 mod node;
+mod overlay;
 mod proof;
 mod recorder;
+mod redaction;
+mod repair;
+mod session;
+mod sharded;
+mod snapshot;
+mod ssz;
+mod stub;
+mod subtree;
+pub mod testvectors;
 mod treedb;
 mod treedbmut;
+mod verified;
+mod wal;
+mod witness;
 
 #[cfg(test)]
 mod test;
@@ -32,12 +73,65 @@ use hash_db::{HashDBRef, Hasher, EMPTY_PREFIX};
 use std::clone::Clone;
 
 // pub use proof::generate_proof;
-pub use key::{Key, KeyIter};
-pub use node::{compute_null_hashes, decode_hash, Node, NodeHash, Value};
-pub use proof::StorageProof;
-pub use recorder::Recorder;
-pub use treedb::{TreeDB, TreeDBBuilder};
-pub use treedbmut::{TreeDBMut, TreeDBMutBuilder};
+pub use attestation::{Attestation, RootSigner, RootStatement, RootVerifier};
+pub use audit::{Mutation, MutationLog};
+pub use background::{BackgroundDb, CommitTicket};
+pub use cache::{CacheStats, NodeCache, ProofCache};
+#[cfg(feature = "snappy")]
+pub use codec::Snappy;
+#[cfg(feature = "zstd")]
+pub use codec::Zstd;
+pub use codec::{ProofCodec, Uncompressed};
+pub use complete::{CompleteTreeBuilder, CompleteTreeDb};
+pub use compression::ValueCompression;
+pub use cursor::{Cursor, LeafIter};
+pub use diff::{apply_diff, stream_diff, DiffStats};
+pub use epoch::EpochDB;
+pub use evm::{commutative_root, evm_multiproof, EvmMultiProof};
+pub use expiry::{split_expiry, with_expiry};
+pub use felt::{felt_from_bytes, is_felt, Felt, FeltHash, FeltHasher, STARK_PRIME};
+#[cfg(feature = "starknet")]
+pub use felt::{Pedersen, Poseidon};
+pub use flat::{FlatTree, MAX_FLAT_DEPTH};
+pub use forest::{Forest, ForestChanges, ForestTransaction};
+pub use frontier::Frontier;
+pub use generate::{generate_tree, GeneratedDb};
+pub use header::{ProofHeader, PROOF_FORMAT_VERSION, PROOF_HEADER_LEN};
+pub use indexed::IndexedTree;
+pub use integrity::{IntegrityProblem, IntegrityReport};
+pub use key::{assert_key_fits, Key, KeyDepth, KeyIter, KeyRange, KeyRangeIter, MAX_KEY_DEPTH};
+pub use light::LightTree;
+pub use metadata::{TreeMetadata, NODE_CODEC_VERSION};
+pub use meter::AccessMeter;
+pub use migrate::migrate_indexed_tree;
+pub use nmt::{NamespaceNode, NamespaceProof, NamespacedTree};
+pub use node::{
+    compute_null_hashes, decode_hash, null_hash_at_depth, null_hashes_from_table, null_root,
+    shared_null_hashes, Node, NodeHash, NodeView, NullHashLadder, NullHashTable, NullHashes, Value,
+};
+pub use overlay::{BuildFoldHasher, FoldHasher};
+pub use proof::{verify_with_siblings, LeafProof, Proof, SparseProof, StorageProof};
+pub use recorder::{Recorder, TeeRecorder};
+pub use redaction::{blind_value, split_blinding, RedactedProof, BLINDING_SALT_LEN};
+pub use repair::{repair, RepairReport, RepairSource};
+pub use session::{Session, Sessions};
+pub use sharded::{Shard, ShardChanges, ShardedTreeDBMut};
+pub use snapshot::{Snapshot, VersionedRoots};
+pub use ssz::{
+    calculate_multi_merkle_root, generalized_index, helper_indices, ssz_multiproof, SszMultiProof,
+};
+pub use stub::{PartialTree, Stub, StubbedProof};
+pub use subtree::{copy_subtree, merge};
+pub use treedb::{LeafProofIter, LevelIter, TreeDB, TreeDBBuilder, MAX_HISTOGRAM_DEPTH};
+pub use treedbmut::{
+    CheckpointId, CommitStats, CommitSummary, TreeDBMut, TreeDBMutBuilder, WatchId,
+};
+pub use verified::{ProofTree, VerifiedProofDb};
+pub use wal::replay_wal;
+pub use witness::{
+    execute_against, execute_against_with_null_hashes, record_access_list, verify_batch_transition,
+    WitnessBundle,
+};
 
 /// Database value
 pub type DBValue = Vec<u8>;
@@ -45,13 +139,74 @@ pub type DBValue = Vec<u8>;
 /// Tree Errors
 #[derive(Clone, Debug)]
 pub enum TreeError {
-    DataNotFound,
+    /// A node is missing from the database, typically because a witness is incomplete.
+    ///
+    /// `hash` is the missing node, or empty if the missing data is not a stored node, `path`
+    /// the key prefix leading to it where known and `depth` its level below the root.
+    DataNotFound {
+        hash: Vec<u8>,
+        path: Vec<u8>,
+        depth: usize,
+    },
     IndexOutOfBounds,
     UnexpectedNodeType,
     NodeDeserializationFailed,
     NodeIndexOutOfBounds,
     DecodeHashFailed,
     UnexpectedError,
+    WalFailed,
+    HashMismatch,
+    UnknownTree,
+    DuplicateTree,
+    NamespaceOutOfOrder,
+    StaleCursor,
+    ProofDecodeFailed,
+    ProofTooLarge,
+    CommitCancelled,
+    PostStateRootMismatch,
+    UnlinkedProofNode,
+    StaleSession,
+    SessionConflict,
+    UnknownCheckpoint,
+    MetadataMismatch,
+    InvalidProof,
+    MissingExpiry,
+    IncompatibleProof,
+    UnknownVersion,
+    FrontierMismatch,
+    StaleTransaction,
+    MissingBlinding,
+    BackgroundCommitFailed,
+    DiffFailed,
+    DiffRootMismatch,
+    HooksAttached,
+    StaleRoot,
+    WalRootMismatch,
+}
+
+impl TreeError {
+    /// Return the error for a node missing at the given level.
+    pub(crate) fn missing_node<H: Hasher>(hash: &H::Out, depth: usize) -> Self {
+        TreeError::DataNotFound {
+            hash: hash.as_ref().to_vec(),
+            path: Vec::new(),
+            depth,
+        }
+    }
+
+    /// Fill in the path of a `DataNotFound` error raised while traversing towards `key`.
+    pub(crate) fn along(self, key: &[u8]) -> Self {
+        match self {
+            TreeError::DataNotFound { hash, path, depth } if path.is_empty() => {
+                TreeError::DataNotFound {
+                    hash,
+                    path: key[..depth.min(key.len())].to_vec(),
+                    depth,
+                }
+            }
+            error => error,
+        }
+    }
 }
 
 /// An index-value datastore implemented as a database-backed binary merkle tree
@@ -89,6 +244,15 @@ pub trait Tree<H: Hasher> {
 
     /// Get an inclusion proof for the leaf at the specified index.
     fn get_proof(&self, key: &[u8]) -> Result<Vec<(usize, DBValue)>, TreeError>;
+
+    /// Get the node at the end of the path `key_prefix`, which may be shorter than the depth of
+    /// the tree.  The empty prefix returns the root.
+    fn get_node(&self, key_prefix: &[u8]) -> Result<NodeView<H>, TreeError>;
+
+    /// Get the value and a proof for the leaf at the specified index.  The value is `None` for
+    /// an empty leaf, that is one holding the default leaf of the tree, in which case the proof
+    /// attests to its absence.
+    fn get_value_proof(&self, key: &[u8]) -> Result<(Option<DBValue>, Proof<H>), TreeError>;
 }
 
 /// An index-value datastore implemented as a database-backed binary merkle tree
@@ -108,15 +272,91 @@ pub trait TreeMut<H: Hasher> {
     /// Get an inclusion proof for the leaf at the specified index.
     fn get_proof(&self, key: &[u8]) -> Result<Vec<(usize, DBValue)>, TreeError>;
 
+    /// Get the node at the end of the path `key_prefix`, which may be shorter than the depth of
+    /// the tree.  The empty prefix returns the root.
+    fn get_node(&self, key_prefix: &[u8]) -> Result<NodeView<H>, TreeError>;
+
+    /// Get the value and a proof for the leaf at the specified index.  The value is `None` for
+    /// an empty leaf, that is one holding the default leaf of the tree, in which case the proof
+    /// attests to its absence.
+    fn get_value_proof(&self, key: &[u8]) -> Result<(Option<DBValue>, Proof<H>), TreeError>;
+
     /// Insert a value at the specified index.  Returns the old value at the specified index.
     fn insert(&mut self, key: &[u8], value: DBValue) -> Result<DBValue, TreeError>;
+
+    /// Insert a value at the specified index if its leaf is empty.  The leaf is checked and
+    /// written in a single traversal.
+    fn insert_if_absent(
+        &mut self,
+        key: &[u8],
+        value: DBValue,
+    ) -> Result<ConditionalWrite, TreeError>;
+
+    /// Replace the value at the specified index with `new` if it is `expected`, where the default
+    /// leaf of the tree, the empty value unless configured otherwise, stands for an empty leaf.
+    /// The leaf is checked and written in a single traversal.
+    fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: &[u8],
+        new: DBValue,
+    ) -> Result<ConditionalWrite, TreeError>;
+}
+
+/// The outcome of a conditional write such as `TreeMut::compare_and_swap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionalWrite {
+    /// The value was written, replacing the contained value.
+    Written(DBValue),
+    /// The condition did not hold and nothing was written.  Contains the current value.
+    Rejected(DBValue),
+}
+
+/// Split an indexed proof into the value, `None` if it is `default_leaf`, and the proof.
+pub(crate) fn value_proof<H: Hasher>(
+    key: &[u8],
+    mut proof: Vec<(usize, DBValue)>,
+    default_leaf: &[u8],
+) -> Result<(Option<DBValue>, Proof<H>), TreeError> {
+    let value = match proof.pop() {
+        Some((0, value)) => value,
+        _ => return Err(TreeError::UnexpectedError),
+    };
+    let value = (value != default_leaf).then_some(value);
+
+    Ok((value, Proof::from_indexed(key, &proof)?))
 }
 
 /// A tree recorder that can be used to record tree accesses.
 ///
 /// The `TreeRecorder is used to construct a proof that attests to the inclusion of accessed
 /// nodes in a tree.
+///
+/// Nodes are recorded as their hash and canonical encoding, so the trait is object safe and
+/// recorders of other systems can be adapted without depending on `Node`.
 pub trait TreeRecorder<H: Hasher> {
-    /// Record access of the the given node index.
-    fn record(&mut self, node: Node<H>);
+    /// Record access of the node with the given hash and encoding.
+    fn record(&mut self, hash: &H::Out, encoded: &[u8]);
+}
+
+/// A backend that can be told about upcoming node reads to hide storage latency.
+pub trait Prefetcher<H: Hasher> {
+    /// Start fetching the nodes with the given hashes.
+    fn prefetch(&self, hashes: &[H::Out]);
+}
+
+/// A source of nodes missing from the backend, such as peers serving state on demand.
+///
+/// Consulted by `TreeDB` and `TreeDBMut` when a node is neither stored nor an empty subtree.
+/// Fetched nodes are untrusted and always checked against the hash they were fetched for.
+pub trait NodeFetcher<H: Hasher> {
+    /// Fetch the node with the given hash as stored in the backend, or `None` if it cannot be
+    /// found, in which case the read fails with `TreeError::DataNotFound`.
+    fn fetch(&self, hash: &H::Out) -> Option<DBValue>;
+}
+
+/// A sink for structured mutation records emitted by a `TreeDBMut`.
+pub trait MutationSink<H: Hasher> {
+    /// Handle a mutation applied to the tree.
+    fn on_mutation(&mut self, mutation: Mutation<H>);
 }
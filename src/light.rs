@@ -0,0 +1,82 @@
+use crate::{DBValue, Hasher, NullHashes, Proof, TreeError};
+
+/// A verifying client holding only the root of a tree.
+///
+/// Reads and updates are checked against proofs served by a full node, so a light client needs
+/// no database.  Use `Tree::get_value_proof` on the full node to produce the proofs.
+#[derive(Debug)]
+pub struct LightTree<H: Hasher> {
+    root: H::Out,
+    depth: usize,
+    default_leaf: DBValue,
+}
+
+impl<H: Hasher> LightTree<H> {
+    /// Create a client trusting the given root of a tree of the given depth.
+    pub fn new(root: H::Out, depth: usize) -> Self {
+        Self {
+            root,
+            depth,
+            default_leaf: DBValue::new(),
+        }
+    }
+
+    /// Verify against a tree whose empty leaves hold the default leaf of `null_hashes`.
+    pub fn with_null_hashes(mut self, null_hashes: &NullHashes<H>) -> Self {
+        self.default_leaf = null_hashes.default_leaf().to_vec();
+        self
+    }
+
+    /// Return the trusted root.
+    pub fn root(&self) -> &H::Out {
+        &self.root
+    }
+
+    /// Return the depth of the tree.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Check that `value` is stored at `key`.
+    pub fn verify_inclusion(&self, key: &[u8], value: &[u8], proof: &Proof<H>) -> bool {
+        value != self.default_leaf.as_slice() && self.verify(key, value, proof)
+    }
+
+    /// Check that the leaf at `key` is empty.
+    pub fn verify_absence(&self, key: &[u8], proof: &Proof<H>) -> bool {
+        self.verify(key, &self.default_leaf, proof)
+    }
+
+    /// Replace `old_value` at `key` with `new_value` and return the new root.  Use the default
+    /// leaf, the empty value unless set with `with_null_hashes`, for an empty leaf.  Fails with
+    /// `TreeError::InvalidProof`, leaving the root unchanged, unless the proof shows `old_value`
+    /// at `key` under the current root.
+    pub fn apply_verified_update(
+        &mut self,
+        key: &[u8],
+        old_value: &[u8],
+        new_value: &[u8],
+        proof: &Proof<H>,
+    ) -> Result<&H::Out, TreeError> {
+        if !self.verify(key, old_value, proof) {
+            return Err(TreeError::InvalidProof);
+        }
+
+        self.root = proof.compute_root(key, new_value)?;
+        Ok(&self.root)
+    }
+
+    fn verify(&self, key: &[u8], value: &[u8], proof: &Proof<H>) -> bool {
+        key.len() == self.depth && proof.compute_root(key, value).ok().as_ref() == Some(&self.root)
+    }
+}
+
+impl<H: Hasher> Clone for LightTree<H> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root,
+            depth: self.depth,
+            default_leaf: self.default_leaf.clone(),
+        }
+    }
+}
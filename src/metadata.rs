@@ -0,0 +1,86 @@
+use crate::{rstd::Vec, DBValue, HashDBRef, Hasher, TreeError, EMPTY_PREFIX};
+use hash_db::HashDB;
+
+/// The version of the node encoding written by this crate.
+pub const NODE_CODEC_VERSION: u32 = 1;
+
+/// A record describing a tree, persisted in its database next to the nodes.
+///
+/// The record is stored under its hash, so the hash committed alongside a root pins the shape of
+/// the tree.  Pass it to `TreeDBBuilder::with_metadata` or `TreeDBMutBuilder::with_metadata` to
+/// fail with `TreeError::MetadataMismatch` when a tree is opened with the wrong depth or codec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeMetadata {
+    /// An application-defined identifier of the tree.
+    pub tree_id: Vec<u8>,
+    /// The depth of the tree.
+    pub depth: usize,
+    /// The number of children of an inner node.
+    pub arity: u8,
+    /// The version of the node encoding.
+    pub codec_version: u32,
+}
+
+impl TreeMetadata {
+    /// Describe a binary tree of the given depth using the current node encoding.
+    pub fn new(tree_id: &[u8], depth: usize) -> Self {
+        Self {
+            tree_id: tree_id.to_vec(),
+            depth,
+            arity: 2,
+            codec_version: NODE_CODEC_VERSION,
+        }
+    }
+
+    /// Load the record stored under the given hash.
+    pub fn open<H: Hasher>(
+        db: &dyn HashDBRef<H, DBValue>,
+        hash: &H::Out,
+    ) -> Result<Self, TreeError> {
+        let data = db
+            .get(hash, EMPTY_PREFIX)
+            .ok_or_else(|| TreeError::missing_node::<H>(hash, 0))?;
+        if &H::hash(&data) != hash {
+            return Err(TreeError::HashMismatch);
+        }
+        let (tree_id, depth, arity, codec_version): (Vec<u8>, u64, u8, u32) =
+            bincode::deserialize(&data).map_err(|_| TreeError::NodeDeserializationFailed)?;
+
+        Ok(Self {
+            tree_id,
+            depth: depth as usize,
+            arity,
+            codec_version,
+        })
+    }
+
+    /// Write the record to the database and return the hash it is stored under.
+    pub fn persist<H: Hasher>(&self, db: &mut dyn HashDB<H, DBValue>) -> H::Out {
+        let record = (
+            self.tree_id.as_slice(),
+            self.depth as u64,
+            self.arity,
+            self.codec_version,
+        );
+        let data = bincode::serialize(&record).expect("serializing to a vec cannot fail");
+        db.insert(EMPTY_PREFIX, &data)
+    }
+
+    /// Check that the record stored under `hash` describes a tree this crate can open at the
+    /// given depth.
+    pub(crate) fn validate<H: Hasher>(
+        db: &dyn HashDBRef<H, DBValue>,
+        hash: &H::Out,
+        depth: usize,
+    ) -> Result<(), TreeError> {
+        let metadata = Self::open(db, hash)?;
+        if metadata.depth != depth
+            || metadata.arity != 2
+            || metadata.codec_version != NODE_CODEC_VERSION
+        {
+            return Err(TreeError::MetadataMismatch);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use core::cell::{Cell, RefCell};
+
+/// A hook charging for the tree work done by each `get_value` and `insert`, such as gas or
+/// weight in a runtime.
+///
+/// An operation touches every stored node on its path, whether read from the database or from
+/// the uncommitted changes of a `TreeDBMut`.  Empty subtrees are not stored and are free.
+pub trait AccessMeter {
+    /// Charge for an operation that touched `nodes` stored nodes of `bytes` encoded bytes in
+    /// total.  Failed operations are charged for the work done before they failed.
+    fn charge(&mut self, nodes: usize, bytes: usize);
+}
+
+impl<T: AccessMeter + ?Sized> AccessMeter for &mut T {
+    fn charge(&mut self, nodes: usize, bytes: usize) {
+        (**self).charge(nodes, bytes)
+    }
+}
+
+/// The meter of a tree and the work touched by the operation in progress.
+pub(crate) struct Meter<'a> {
+    meter: RefCell<&'a mut dyn AccessMeter>,
+    nodes: Cell<usize>,
+    bytes: Cell<usize>,
+}
+
+impl<'a> Meter<'a> {
+    pub(crate) fn new(meter: &'a mut dyn AccessMeter) -> Self {
+        Self {
+            meter: RefCell::new(meter),
+            nodes: Cell::new(0),
+            bytes: Cell::new(0),
+        }
+    }
+
+    /// Start metering an operation.
+    pub(crate) fn start(&self) {
+        self.nodes.set(0);
+        self.bytes.set(0);
+    }
+
+    /// Account for a stored node of `bytes` encoded bytes.
+    pub(crate) fn touch(&self, bytes: usize) {
+        self.nodes.set(self.nodes.get() + 1);
+        self.bytes.set(self.bytes.get() + bytes);
+    }
+
+    /// Charge the meter for the operation in progress.
+    pub(crate) fn charge(&self) {
+        self.meter
+            .borrow_mut()
+            .charge(self.nodes.take(), self.bytes.take());
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{
+    compute_null_hashes, decode_hash, rstd::Vec, DBValue, HashDBRef, Hasher, Node, NodeHash,
+    TreeError, Value, EMPTY_PREFIX,
+};
+use hash_db::HashDB;
+
+/// Rewrite a tree stored in the index-addressed format under the current node encoding.
+///
+/// In the index-addressed format nodes are stored without a type tag: inner nodes are stored as
+/// the concatenation of their child hashes and leaves as the raw value bytes, the node type
+/// being implied by its layer.  Since the node hashes are unchanged the returned root is equal
+/// to `old_root`.  The migrated nodes are written to `new_db`; null subtrees are not written.
+pub fn migrate_indexed_tree<H: Hasher>(
+    old_db: &dyn HashDBRef<H, DBValue>,
+    old_root: &H::Out,
+    depth: usize,
+    new_db: &mut dyn HashDB<H, DBValue>,
+) -> Result<H::Out, TreeError> {
+    let null_hashes = compute_null_hashes::<H>(depth);
+    let mut stack = Vec::from([(*old_root, 0usize)]);
+
+    while let Some((hash, level)) = stack.pop() {
+        if new_db.contains(&hash, EMPTY_PREFIX) {
+            continue;
+        }
+
+        let data = match old_db.get(&hash, EMPTY_PREFIX) {
+            Some(data) => data,
+            None if hash == null_hashes[level] => continue,
+            None => return Err(TreeError::missing_node::<H>(&hash, level)),
+        };
+
+        let node: Node<H> = if level == depth {
+            Node::Value(Value::Cached(data))
+        } else {
+            if data.len() != 2 * H::LENGTH {
+                return Err(TreeError::NodeDeserializationFailed);
+            }
+            let left = decode_hash::<H>(&data[..H::LENGTH])?;
+            let right = decode_hash::<H>(&data[H::LENGTH..])?;
+            stack.push((left, level + 1));
+            stack.push((right, level + 1));
+            Node::Inner(NodeHash::Hash(left), NodeHash::Hash(right))
+        };
+
+        if node.hash() != hash {
+            return Err(TreeError::HashMismatch);
+        }
+        new_db.emplace(hash, EMPTY_PREFIX, node.into());
+    }
+
+    Ok(*old_root)
+}
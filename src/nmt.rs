@@ -0,0 +1,227 @@
+use crate::{rstd::Vec, DBValue, Hasher, TreeError};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// A node of a namespaced merkle tree, carrying the namespace range of the leaves below it.
+#[derive(Debug)]
+pub struct NamespaceNode<H: Hasher> {
+    pub min: Vec<u8>,
+    pub max: Vec<u8>,
+    pub digest: H::Out,
+}
+
+impl<H: Hasher> Clone for NamespaceNode<H> {
+    fn clone(&self) -> Self {
+        Self {
+            min: self.min.clone(),
+            max: self.max.clone(),
+            digest: self.digest,
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for NamespaceNode<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.min == other.min && self.max == other.max && self.digest == other.digest
+    }
+}
+
+impl<H: Hasher> Eq for NamespaceNode<H> {}
+
+impl<H: Hasher> NamespaceNode<H> {
+    fn leaf(namespace: &[u8], data: &[u8]) -> Self {
+        let digest = H::hash(&[&[LEAF_PREFIX][..], namespace, data].concat());
+        Self {
+            min: namespace.to_vec(),
+            max: namespace.to_vec(),
+            digest,
+        }
+    }
+
+    fn inner(left: &Self, right: &Self) -> Self {
+        let mut data = Vec::from([NODE_PREFIX]);
+        for node in [left, right] {
+            data.extend_from_slice(&node.min);
+            data.extend_from_slice(&node.max);
+            data.extend_from_slice(node.digest.as_ref());
+        }
+        Self {
+            min: left.min.clone(),
+            max: right.max.clone(),
+            digest: H::hash(&data),
+        }
+    }
+}
+
+/// Return the number of leaves in the left subtree of a tree with `n > 1` leaves, the largest
+/// power of two smaller than `n`.
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - (n - 1).leading_zeros() - 1)
+}
+
+/// A namespaced merkle tree over leaves ordered by namespace.
+///
+/// Every node commits to the minimum and maximum namespace below it, which allows proving that
+/// a proof contains all leaves of a namespace.
+pub struct NamespacedTree<H: Hasher> {
+    namespace_len: usize,
+    leaves: Vec<(Vec<u8>, DBValue)>,
+    nodes: Vec<NamespaceNode<H>>,
+}
+
+impl<H: Hasher> NamespacedTree<H> {
+    /// Create an empty tree with namespaces of `namespace_len` bytes.
+    pub fn new(namespace_len: usize) -> Self {
+        Self {
+            namespace_len,
+            leaves: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Append a leaf.  Leaves must be pushed in non-decreasing namespace order.
+    pub fn push(&mut self, namespace: &[u8], data: DBValue) -> Result<(), TreeError> {
+        if namespace.len() != self.namespace_len {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+        if matches!(self.leaves.last(), Some((last, _)) if last.as_slice() > namespace) {
+            return Err(TreeError::NamespaceOutOfOrder);
+        }
+
+        self.nodes.push(NamespaceNode::leaf(namespace, &data));
+        self.leaves.push((namespace.to_vec(), data));
+        Ok(())
+    }
+
+    /// Return the number of leaves.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Return true if the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Return the root of the tree.
+    pub fn root(&self) -> NamespaceNode<H> {
+        if self.nodes.is_empty() {
+            let zero = vec![0; self.namespace_len];
+            return NamespaceNode {
+                min: zero.clone(),
+                max: zero,
+                digest: H::hash(&[]),
+            };
+        }
+
+        self.subtree(0, self.nodes.len())
+    }
+
+    fn subtree(&self, start: usize, end: usize) -> NamespaceNode<H> {
+        if end - start == 1 {
+            return self.nodes[start].clone();
+        }
+
+        let mid = start + split_point(end - start);
+        NamespaceNode::inner(&self.subtree(start, mid), &self.subtree(mid, end))
+    }
+
+    /// Prove the leaves of the given namespace, or its absence if the tree holds none.
+    pub fn prove_namespace(&self, namespace: &[u8]) -> NamespaceProof<H> {
+        let start = self
+            .leaves
+            .partition_point(|(leaf, _)| leaf.as_slice() < namespace);
+        let end = self
+            .leaves
+            .partition_point(|(leaf, _)| leaf.as_slice() <= namespace);
+
+        let mut siblings = Vec::new();
+        if !self.nodes.is_empty() {
+            self.collect_siblings(0, self.nodes.len(), start, end, &mut siblings);
+        }
+
+        NamespaceProof {
+            start,
+            end,
+            leaf_count: self.nodes.len(),
+            siblings,
+        }
+    }
+
+    fn collect_siblings(
+        &self,
+        lo: usize,
+        hi: usize,
+        start: usize,
+        end: usize,
+        siblings: &mut Vec<NamespaceNode<H>>,
+    ) {
+        if hi <= start || lo >= end {
+            siblings.push(self.subtree(lo, hi));
+        } else if hi - lo > 1 {
+            let mid = lo + split_point(hi - lo);
+            self.collect_siblings(lo, mid, start, end, siblings);
+            self.collect_siblings(mid, hi, start, end, siblings);
+        }
+    }
+}
+
+/// A proof that a range of leaves holds all leaves of a namespace.
+#[derive(Debug)]
+pub struct NamespaceProof<H: Hasher> {
+    start: usize,
+    end: usize,
+    leaf_count: usize,
+    siblings: Vec<NamespaceNode<H>>,
+}
+
+impl<H: Hasher> NamespaceProof<H> {
+    /// Return the index range of the proven leaves.
+    pub fn range(&self) -> core::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    /// Verify that `leaves` are all leaves of `namespace` in the tree with the given root.  An
+    /// empty `leaves` slice proves the namespace is absent.
+    pub fn verify(&self, root: &NamespaceNode<H>, namespace: &[u8], leaves: &[DBValue]) -> bool {
+        if leaves.len() != self.end - self.start || self.end > self.leaf_count {
+            return false;
+        }
+        if self.leaf_count == 0 {
+            return self.siblings.is_empty() && root.digest == H::hash(&[]);
+        }
+
+        let mut siblings = self.siblings.iter();
+        let computed = self.verify_at(0, self.leaf_count, namespace, leaves, &mut siblings);
+        siblings.next().is_none() && computed.as_ref() == Some(root)
+    }
+
+    fn verify_at(
+        &self,
+        lo: usize,
+        hi: usize,
+        namespace: &[u8],
+        leaves: &[DBValue],
+        siblings: &mut core::slice::Iter<'_, NamespaceNode<H>>,
+    ) -> Option<NamespaceNode<H>> {
+        if hi <= self.start || lo >= self.end {
+            let sibling = siblings.next()?;
+            let complete = if hi <= self.start {
+                sibling.max.as_slice() < namespace
+            } else {
+                sibling.min.as_slice() > namespace
+            };
+            return complete.then(|| sibling.clone());
+        }
+
+        if hi - lo == 1 {
+            return Some(NamespaceNode::leaf(namespace, &leaves[lo - self.start]));
+        }
+
+        let mid = lo + split_point(hi - lo);
+        let left = self.verify_at(lo, mid, namespace, leaves, siblings)?;
+        let right = self.verify_at(mid, hi, namespace, leaves, siblings)?;
+        Some(NamespaceNode::inner(&left, &right))
+    }
+}
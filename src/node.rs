@@ -1,10 +1,14 @@
 use super::{
     rstd::{
         convert::{From, TryFrom},
-        Vec,
+        Arc, Vec,
     },
     DBValue, Hasher, TreeError,
 };
+use std::{
+    any::{Any, TypeId},
+    sync::{Mutex, OnceLock, RwLock},
+};
 
 #[derive(Debug)]
 pub enum NodeHash<H: Hasher> {
@@ -19,18 +23,58 @@ impl<H: Hasher> NodeHash<H> {
             NodeHash::InMemory(hash) => hash,
         }
     }
+
+    /// Return a copy of the hash, wherever the node is held.
+    pub fn hash(&self) -> H::Out {
+        *self.get_hash()
+    }
+
+    /// Order and compare handles like a derive would: by variant, then by the hash bytes, as
+    /// hashes need not be `Ord`.
+    fn sort_key(&self) -> (u8, &[u8]) {
+        match self {
+            NodeHash::InMemory(hash) => (0, hash.as_ref()),
+            NodeHash::Hash(hash) => (1, hash.as_ref()),
+        }
+    }
 }
 
 impl<H: Hasher> Clone for NodeHash<H> {
     fn clone(&self) -> Self {
         match self {
-            NodeHash::Hash(hash) => NodeHash::Hash(hash.clone()),
-            NodeHash::InMemory(hash) => NodeHash::InMemory(hash.clone()),
+            NodeHash::Hash(hash) => NodeHash::Hash(*hash),
+            NodeHash::InMemory(hash) => NodeHash::InMemory(*hash),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl<H: Hasher> PartialEq for NodeHash<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl<H: Hasher> Eq for NodeHash<H> {}
+
+impl<H: Hasher> PartialOrd for NodeHash<H> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<H: Hasher> Ord for NodeHash<H> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl<H: Hasher> core::hash::Hash for NodeHash<H> {
+    fn hash<S: core::hash::Hasher>(&self, state: &mut S) {
+        self.sort_key().hash(state)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Value {
     Cached(DBValue),
     New(DBValue),
@@ -43,6 +87,14 @@ impl Value {
             Value::New(value) => value,
         }
     }
+
+    /// Return the value, whether it is committed or new.
+    pub fn into_inner(self) -> DBValue {
+        match self {
+            Value::Cached(value) => value,
+            Value::New(value) => value,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -64,43 +116,66 @@ impl<H: Hasher> TryFrom<Vec<u8>> for Node<H> {
     type Error = TreeError;
 
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        match value.get(0) {
-            Some(0u8) => Ok(Node::Value(Value::Cached(value[1..].to_vec()))),
-            Some(1u8) => {
-                let left_hash = decode_hash::<H>(&value[1..(H::LENGTH + 1)])?;
-                let right_hash = decode_hash::<H>(&value[(H::LENGTH + 1)..])?;
+        Self::decode(&value)
+    }
+}
+
+impl<H: Hasher> From<Node<H>> for Vec<u8> {
+    fn from(node: Node<H>) -> Self {
+        node.encode()
+    }
+}
+
+impl<H: Hasher> Node<H> {
+    /// Decode a node from its canonical encoding without taking ownership of the buffer.
+    pub fn decode(data: &[u8]) -> Result<Self, TreeError> {
+        match data.first() {
+            Some(0u8) => Ok(Node::Value(Value::Cached(data[1..].to_vec()))),
+            Some(1u8) if data.len() > H::LENGTH => {
+                let left_hash = decode_hash::<H>(&data[1..(H::LENGTH + 1)])?;
+                let right_hash = decode_hash::<H>(&data[(H::LENGTH + 1)..])?;
                 Ok(Node::Inner(
                     NodeHash::Hash(left_hash),
                     NodeHash::Hash(right_hash),
                 ))
             }
+            Some(1u8) => Err(TreeError::DecodeHashFailed),
             _ => Err(TreeError::NodeDeserializationFailed),
         }
     }
-}
 
-impl<H: Hasher> From<Node<H>> for Vec<u8> {
-    fn from(node: Node<H>) -> Self {
-        match node {
+    /// Append the canonical encoding of the node to `out`, so callers can reuse one buffer
+    /// across nodes.
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.reserve(self.encoded_len());
+        match self {
             Node::Value(value) => {
-                let value = value.get();
-                let mut combined = Vec::with_capacity(value.len() + 1);
-                combined.push(0);
-                combined.extend_from_slice(value);
-                combined
+                out.push(0);
+                out.extend_from_slice(value.get());
             }
             Node::Inner(left, right) => {
-                let mut combined = Vec::with_capacity(1 + H::LENGTH * 2);
-                combined.push(1);
-                combined.extend_from_slice(left.get_hash().as_ref());
-                combined.extend_from_slice(right.get_hash().as_ref());
-                combined
+                out.push(1);
+                out.extend_from_slice(left.get_hash().as_ref());
+                out.extend_from_slice(right.get_hash().as_ref());
             }
         }
     }
-}
 
-impl<H: Hasher> Node<H> {
+    /// Return the canonical encoding of the node.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut encoded);
+        encoded
+    }
+
+    /// Return the length of the canonical encoding of the node.
+    pub(crate) fn encoded_len(&self) -> usize {
+        match self {
+            Node::Value(value) => 1 + value.get().len(),
+            Node::Inner(_, _) => 1 + H::LENGTH * 2,
+        }
+    }
+
     pub fn hash(&self) -> H::Out {
         match self {
             Node::Value(value) => H::hash(value.get()),
@@ -114,12 +189,15 @@ impl<H: Hasher> Node<H> {
     }
 
     pub fn get_child(&self, bit: u8) -> Result<&NodeHash<H>, TreeError> {
-        if bit == 0 {
-            self.get_left_child()
-        } else if bit == 1 {
+        self.get_child_bool(key_bit(bit)?)
+    }
+
+    /// Return the right child if `bit` is set and the left child otherwise.
+    pub fn get_child_bool(&self, bit: bool) -> Result<&NodeHash<H>, TreeError> {
+        if bit {
             self.get_right_child()
         } else {
-            Err(TreeError::NodeIndexOutOfBounds)
+            self.get_left_child()
         }
     }
 
@@ -145,12 +223,16 @@ impl<H: Hasher> Node<H> {
     }
 
     pub fn set_child_hash(&mut self, bit: u8, hash: NodeHash<H>) -> Result<H::Out, TreeError> {
-        if bit == 0 {
-            self.set_left_child_hash(hash)
-        } else if bit == 1 {
+        self.set_child_bool(key_bit(bit)?, hash)
+    }
+
+    /// Set the right child if `bit` is set and the left child otherwise.  Returns the hash of
+    /// the replaced child.
+    pub fn set_child_bool(&mut self, bit: bool, hash: NodeHash<H>) -> Result<H::Out, TreeError> {
+        if bit {
             self.set_rigth_child_hash(hash)
         } else {
-            Err(TreeError::NodeIndexOutOfBounds)
+            self.set_left_child_hash(hash)
         }
     }
 
@@ -158,7 +240,7 @@ impl<H: Hasher> Node<H> {
         match self {
             Node::Value(_) => Err(TreeError::UnexpectedNodeType),
             Node::Inner(left, _) => {
-                let old = left.get_hash().clone();
+                let old = *left.get_hash();
                 *left = hash;
                 Ok(old)
             }
@@ -169,7 +251,7 @@ impl<H: Hasher> Node<H> {
         match self {
             Node::Value(_) => Err(TreeError::UnexpectedNodeType),
             Node::Inner(_, right) => {
-                let old = right.get_hash().clone();
+                let old = *right.get_hash();
                 *right = hash;
                 Ok(old)
             }
@@ -188,6 +270,68 @@ impl<H: Hasher> Node<H> {
     }
 }
 
+/// A node of the tree as returned by `Tree::get_node` and `TreeMut::get_node`.
+///
+/// Unlike `Node` it carries no in-memory bookkeeping, so it reads the same whether the node is
+/// committed or only present in an uncommitted overlay.
+#[derive(Debug)]
+pub enum NodeView<H: Hasher> {
+    /// A leaf holding the value, empty for an unset leaf.
+    Value(DBValue),
+    /// An inner node holding the hashes of its left and right children.
+    Inner(H::Out, H::Out),
+}
+
+impl<H: Hasher> NodeView<H> {
+    /// Return the hash of the node.
+    pub fn hash(&self) -> H::Out {
+        match self {
+            NodeView::Value(value) => H::hash(value),
+            NodeView::Inner(left, right) => H::hash(&[left.as_ref(), right.as_ref()].concat()),
+        }
+    }
+}
+
+impl<H: Hasher> Clone for NodeView<H> {
+    fn clone(&self) -> Self {
+        match self {
+            NodeView::Value(value) => NodeView::Value(value.clone()),
+            NodeView::Inner(left, right) => NodeView::Inner(*left, *right),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for NodeView<H> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (NodeView::Value(a), NodeView::Value(b)) => a == b,
+            (NodeView::Inner(al, ar), NodeView::Inner(bl, br)) => al == bl && ar == br,
+            _ => false,
+        }
+    }
+}
+
+impl<H: Hasher> Eq for NodeView<H> {}
+
+impl<H: Hasher> From<Node<H>> for NodeView<H> {
+    fn from(node: Node<H>) -> Self {
+        match node {
+            Node::Value(value) => NodeView::Value(value.get().clone()),
+            Node::Inner(left, right) => NodeView::Inner(*left.get_hash(), *right.get_hash()),
+        }
+    }
+}
+
+/// Convert a bit of a byte-per-bit key, failing with `TreeError::NodeIndexOutOfBounds` for
+/// values other than 0 and 1.
+pub(crate) fn key_bit(bit: u8) -> Result<bool, TreeError> {
+    match bit {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(TreeError::NodeIndexOutOfBounds),
+    }
+}
+
 pub fn decode_hash<H: Hasher>(data: &[u8]) -> Result<H::Out, TreeError> {
     if data.len() != H::LENGTH {
         return Err(TreeError::DecodeHashFailed);
@@ -197,6 +341,8 @@ pub fn decode_hash<H: Hasher>(data: &[u8]) -> Result<H::Out, TreeError> {
     Ok(hash)
 }
 
+/// Return the hashes of the empty subtrees of a tree of the given depth, indexed by level from
+/// the root: the first entry is the empty root and the last the hash of the empty value.
 pub fn compute_null_hashes<H: Hasher>(depth: usize) -> Vec<H::Out> {
     (0..depth + 1)
         .scan(H::hash(&[]), |null_hash, _| {
@@ -209,3 +355,282 @@ pub fn compute_null_hashes<H: Hasher>(depth: usize) -> Vec<H::Out> {
         .rev()
         .collect()
 }
+
+/// A hasher with a precomputed table of null hashes.
+///
+/// Implement it with a `const` table, generated once for the hasher, to skip computing the null
+/// hash ladder whenever a tree is built.
+pub trait NullHashTable: Hasher
+where
+    Self::Out: 'static,
+{
+    /// The null hashes indexed by height above the leaves, starting with the hash of the empty
+    /// value.  Tables shipped for general use should hold 257 entries to support depth 256.
+    const NULL_HASHES: &'static [Self::Out];
+}
+
+/// Return the null hashes of a tree of the given depth from the precomputed table, indexed by
+/// level from the root like `compute_null_hashes`.  Returns `None` if the table is too short.
+pub fn null_hashes_from_table<H: NullHashTable>(depth: usize) -> Option<Vec<H::Out>>
+where
+    H::Out: 'static,
+{
+    let table = H::NULL_HASHES.get(..=depth)?;
+    Some(table.iter().rev().copied().collect())
+}
+
+/// A lazily grown ladder of null hashes indexed by height above the leaves.
+///
+/// Hashes are only computed once a height is requested, and the ladder can be shared between
+/// trees of any depth through an `Arc`.  Empty leaves hold the empty value unless the ladder is
+/// created with another default leaf: protocols defining empty leaves as some other value
+/// create one ladder with `with_default_leaf`, or a precomputed `NullHashLadder`, and pass it
+/// to every builder and verifier, e.g. `TreeDBBuilder::with_null_hashes` and
+/// `LightTree::with_null_hashes`, so all of them agree on the empty subtrees.
+pub struct NullHashes<H: Hasher> {
+    ladder: RwLock<Vec<H::Out>>,
+    default_leaf: DBValue,
+}
+
+impl<H: Hasher> NullHashes<H> {
+    /// Create an empty ladder.
+    pub fn new() -> Self {
+        Self::with_default_leaf(&[])
+    }
+
+    /// Create an empty ladder for trees whose empty leaves hold `default_leaf`.
+    pub fn with_default_leaf(default_leaf: &[u8]) -> Self {
+        Self {
+            ladder: RwLock::new(Vec::new()),
+            default_leaf: default_leaf.to_vec(),
+        }
+    }
+
+    /// Return the value held by empty leaves.
+    pub fn default_leaf(&self) -> &[u8] {
+        &self.default_leaf
+    }
+
+    /// Create a ladder seeded from the hasher's precomputed table.
+    pub fn from_table() -> Self
+    where
+        H: NullHashTable,
+        H::Out: 'static,
+    {
+        Self {
+            ladder: RwLock::new(H::NULL_HASHES.to_vec()),
+            default_leaf: DBValue::new(),
+        }
+    }
+
+    /// Return the number of heights computed so far.
+    pub fn len(&self) -> usize {
+        self.ladder.read().expect("lock poisoned").len()
+    }
+
+    /// Return true if no heights have been computed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the null hash of a subtree of the given height, growing the ladder if needed.
+    pub fn at_height(&self, height: usize) -> H::Out {
+        if let Some(hash) = self.ladder.read().expect("lock poisoned").get(height) {
+            return *hash;
+        }
+
+        let mut ladder = self.ladder.write().expect("lock poisoned");
+        if ladder.is_empty() {
+            ladder.push(H::hash(&self.default_leaf));
+        }
+        while ladder.len() <= height {
+            let top = ladder[ladder.len() - 1];
+            ladder.push(H::hash(&[top.as_ref(), top.as_ref()].concat()));
+        }
+        ladder[height]
+    }
+
+    /// Return the null hash at `level` of a tree of the given depth.
+    pub fn at_level(&self, level: usize, depth: usize) -> H::Out {
+        self.at_height(depth - level)
+    }
+
+    /// Return the null hashes of a tree of the given depth indexed by level from the root, like
+    /// `compute_null_hashes`.
+    pub fn ladder(&self, depth: usize) -> Vec<H::Out> {
+        self.at_height(depth);
+        let ladder = self.ladder.read().expect("lock poisoned");
+        ladder[..=depth].iter().rev().copied().collect()
+    }
+}
+
+impl<H: Hasher> Default for NullHashes<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: Hasher> core::fmt::Debug for NullHashes<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NullHashes")
+            .field("default_leaf", &self.default_leaf)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A null-hash ladder for a custom default leaf, precomputed up to a maximum depth.
+///
+/// Compute the ladder once and inject `null_hashes` into every builder and verifier of the
+/// protocol.  Trees deeper than `max_depth` still work, growing the shared ladder on demand.
+pub struct NullHashLadder<H: Hasher> {
+    null_hashes: Arc<NullHashes<H>>,
+    max_depth: usize,
+}
+
+impl<H: Hasher> NullHashLadder<H> {
+    /// Compute the null hashes of trees up to `max_depth` whose empty leaves hold `default_leaf`.
+    pub fn new(default_leaf: &[u8], max_depth: usize) -> Self {
+        let null_hashes = NullHashes::with_default_leaf(default_leaf);
+        null_hashes.at_height(max_depth);
+        Self {
+            null_hashes: Arc::new(null_hashes),
+            max_depth,
+        }
+    }
+
+    /// Return the depth the ladder was precomputed to.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Return the shared null hashes to pass to builders and verifiers.
+    pub fn null_hashes(&self) -> Arc<NullHashes<H>> {
+        self.null_hashes.clone()
+    }
+}
+
+impl<H: Hasher> core::ops::Deref for NullHashLadder<H> {
+    type Target = NullHashes<H>;
+
+    fn deref(&self) -> &NullHashes<H> {
+        &self.null_hashes
+    }
+}
+
+impl<H: Hasher> Clone for NullHashLadder<H> {
+    fn clone(&self) -> Self {
+        Self {
+            null_hashes: self.null_hashes.clone(),
+            max_depth: self.max_depth,
+        }
+    }
+}
+
+impl<H: Hasher> core::fmt::Debug for NullHashLadder<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NullHashLadder")
+            .field("null_hashes", &self.null_hashes)
+            .field("max_depth", &self.max_depth)
+            .finish()
+    }
+}
+
+/// Return the process-wide null-hash ladder of `H`, grown on demand and shared by all callers.
+pub fn shared_null_hashes<H: Hasher + 'static>() -> Arc<NullHashes<H>> {
+    type Ladders = Mutex<Vec<(TypeId, Box<dyn Any + Send + Sync>)>>;
+    static LADDERS: OnceLock<Ladders> = OnceLock::new();
+
+    let mut ladders = LADDERS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("lock poisoned");
+    let ladder = match ladders.iter().find(|(id, _)| *id == TypeId::of::<H>()) {
+        Some((_, ladder)) => ladder,
+        None => {
+            let ladder = Arc::new(NullHashes::<H>::new());
+            ladders.push((TypeId::of::<H>(), Box::new(ladder)));
+            &ladders.last().expect("just pushed").1
+        }
+    };
+    ladder
+        .downcast_ref::<Arc<NullHashes<H>>>()
+        .expect("ladders are keyed by hasher type")
+        .clone()
+}
+
+/// Return the hash of an empty subtree with `depth` levels below it.  The sibling `i` levels
+/// above the leaf in an absence proof is an empty subtree of depth `i` unless it is populated.
+///
+/// Hashes are cached per hasher for the lifetime of the process.
+pub fn null_hash_at_depth<H: Hasher + 'static>(depth: usize) -> H::Out {
+    shared_null_hashes::<H>().at_height(depth)
+}
+
+/// Return the root of an empty tree of the given depth, the root every new tree starts from.
+///
+/// Hashes are cached per hasher for the lifetime of the process.
+pub fn null_root<H: Hasher + 'static>(depth: usize) -> H::Out {
+    null_hash_at_depth::<H>(depth)
+}
+
+/// Synthesize the null node at `level` of a tree of the given depth if `key` is the null hash
+/// of that level.
+///
+/// Null nodes are not required to be present in the database, so every tree and every witness
+/// built from recorded nodes must synthesize them identically.
+pub(crate) fn null_node<H: Hasher>(
+    null_hashes: &NullHashes<H>,
+    key: &H::Out,
+    level: usize,
+    depth: usize,
+) -> Option<Node<H>> {
+    if key != &null_hashes.at_level(level, depth) {
+        return None;
+    }
+
+    if level == depth {
+        Some(Node::Value(Value::Cached(
+            null_hashes.default_leaf().to_vec(),
+        )))
+    } else {
+        let null_hash = null_hashes.at_level(level + 1, depth);
+        Some(Node::Inner(
+            NodeHash::Hash(null_hash),
+            NodeHash::Hash(null_hash),
+        ))
+    }
+}
+
+/// Return the key reached from `root` by descending along the non-null child on the `preferred`
+/// side where there is one, reading nodes with `lookup`.  Returns `None` for an empty tree.
+///
+/// Shared by the `first_key` and `last_key` of `TreeDB` and `TreeDBMut`.
+pub(crate) fn edge_key<H: Hasher>(
+    root: H::Out,
+    depth: usize,
+    null_hashes: &NullHashes<H>,
+    preferred: bool,
+    lookup: impl Fn(&H::Out, usize) -> Result<Node<H>, TreeError>,
+) -> Result<Option<Vec<u8>>, TreeError> {
+    let mut hash = root;
+    if hash == null_hashes.at_level(0, depth) {
+        return Ok(None);
+    }
+
+    let mut key = Vec::with_capacity(depth);
+    for level in 0..depth {
+        let node = lookup(&hash, level).map_err(|error| error.along(&key))?;
+        let child = *node.get_child_bool(preferred)?.get_hash();
+        let bit = if child != null_hashes.at_level(level + 1, depth) {
+            hash = child;
+            preferred
+        } else {
+            hash = *node.get_child_bool(!preferred)?.get_hash();
+            !preferred
+        };
+        key.push(bit as u8);
+    }
+
+    Ok(Some(key))
+}
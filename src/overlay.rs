@@ -0,0 +1,322 @@
+use crate::{
+    node::{decode_hash, NodeHash, Value},
+    rstd::{BTreeMap, HashMap, HashSet, Vec},
+    DBValue, Node,
+};
+use core::hash::{BuildHasher, BuildHasherDefault};
+use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+
+const VALUE_CACHED: u8 = 0;
+const VALUE_NEW: u8 = 1;
+const INNER: u8 = 2;
+
+/// Encode an uncommitted node for the spill backend, keeping which children are in memory and
+/// whether the value is new, which the canonical encoding drops.
+fn encode_spilled<H: Hasher>(node: Node<H>) -> DBValue {
+    match node {
+        Node::Value(value) => {
+            let (tag, mut value) = match value {
+                Value::Cached(value) => (VALUE_CACHED, value),
+                Value::New(value) => (VALUE_NEW, value),
+            };
+            value.insert(0, tag);
+            value
+        }
+        Node::Inner(left, right) => {
+            let in_memory = |child: &NodeHash<H>| matches!(child, NodeHash::InMemory(_)) as u8;
+            let flags = in_memory(&left) | (in_memory(&right) << 1);
+            let mut encoded = Vec::with_capacity(2 + H::LENGTH * 2);
+            encoded.push(INNER);
+            encoded.push(flags);
+            encoded.extend_from_slice(left.get_hash().as_ref());
+            encoded.extend_from_slice(right.get_hash().as_ref());
+            encoded
+        }
+    }
+}
+
+fn decode_spilled<H: Hasher>(data: &[u8]) -> Option<Node<H>> {
+    match *data.first()? {
+        VALUE_CACHED => Some(Node::Value(Value::Cached(data[1..].to_vec()))),
+        VALUE_NEW => Some(Node::Value(Value::New(data[1..].to_vec()))),
+        INNER if data.len() == 2 + H::LENGTH * 2 => {
+            let child = |bit: usize, hash: &[u8]| {
+                let hash = decode_hash::<H>(hash).ok()?;
+                Some(if data[1] & (1 << bit) != 0 {
+                    NodeHash::InMemory(hash)
+                } else {
+                    NodeHash::Hash(hash)
+                })
+            };
+            let (left, right) = data[2..].split_at(H::LENGTH);
+            Some(Node::Inner(child(0, left)?, child(1, right)?))
+        }
+        _ => None,
+    }
+}
+
+/// Spill configuration: the backend evicted nodes are moved to and the memory budget in bytes.
+struct Spill<'a, H: Hasher, S> {
+    backend: &'a mut dyn HashDB<H, DBValue>,
+    budget: usize,
+    spilled: HashSet<H::Out, S>,
+}
+
+/// A bump allocator for value payloads, released wholesale by `reset`.
+///
+/// Payloads are copied into large chunks that are never reallocated, so a slot stays valid
+/// until the arena is reset.
+struct Arena {
+    chunk_size: usize,
+    chunks: Vec<Vec<u8>>,
+}
+
+/// The location of a payload in an `Arena`.
+#[derive(Clone, Copy)]
+struct Slot {
+    chunk: usize,
+    start: usize,
+    len: usize,
+}
+
+impl Arena {
+    fn alloc(&mut self, data: &[u8]) -> Slot {
+        let fits = self
+            .chunks
+            .last()
+            .is_some_and(|chunk| chunk.capacity() - chunk.len() >= data.len());
+        if !fits {
+            self.chunks
+                .push(Vec::with_capacity(self.chunk_size.max(data.len())));
+        }
+
+        let chunk = self.chunks.len() - 1;
+        let start = self.chunks[chunk].len();
+        self.chunks[chunk].extend_from_slice(data);
+        Slot {
+            chunk,
+            start,
+            len: data.len(),
+        }
+    }
+
+    fn get(&self, slot: Slot) -> &[u8] {
+        &self.chunks[slot.chunk][slot.start..slot.start + slot.len]
+    }
+
+    /// Release all payloads, keeping the first chunk for reuse.
+    fn reset(&mut self) {
+        self.chunks.truncate(1);
+        if let Some(chunk) = self.chunks.first_mut() {
+            chunk.clear();
+        }
+    }
+}
+
+/// A node held by the overlay, with the value payload in the arena if there is one.
+enum Stored<H: Hasher> {
+    Node(Node<H>),
+    Value { new: bool, slot: Slot },
+}
+
+/// The in-memory overlay of uncommitted nodes of a `TreeDBMut`.
+///
+/// When a spill backend is configured, the least recently written nodes are moved to it once
+/// the encoded size of the nodes held in memory exceeds the budget.  When an arena is
+/// configured, value payloads are copied into it instead of being held one allocation each.
+pub(crate) struct Overlay<'a, H: Hasher, S> {
+    nodes: HashMap<H::Out, (Stored<H>, u64), S>,
+    /// Nodes held in memory by the tick they were last written at.
+    order: BTreeMap<u64, H::Out>,
+    tick: u64,
+    bytes: usize,
+    spill: Option<Spill<'a, H, S>>,
+    arena: Option<Arena>,
+}
+
+impl<'a, H: Hasher, S: BuildHasher + Default> Overlay<'a, H, S> {
+    pub(crate) fn new(
+        spill: Option<(&'a mut dyn HashDB<H, DBValue>, usize)>,
+        arena_chunk_size: Option<usize>,
+    ) -> Self {
+        Self {
+            nodes: HashMap::default(),
+            order: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            spill: spill.map(|(backend, budget)| Spill {
+                backend,
+                budget,
+                spilled: HashSet::default(),
+            }),
+            arena: arena_chunk_size.map(|chunk_size| Arena {
+                chunk_size,
+                chunks: Vec::new(),
+            }),
+        }
+    }
+
+    /// Return the bytes reserved by the arena, or zero without an arena.
+    pub(crate) fn arena_capacity(&self) -> usize {
+        self.arena.as_ref().map_or(0, |arena| {
+            arena.chunks.iter().map(|chunk| chunk.capacity()).sum()
+        })
+    }
+
+    fn store(&mut self, node: Node<H>) -> Stored<H> {
+        match (self.arena.as_mut(), node) {
+            (Some(arena), Node::Value(value)) => Stored::Value {
+                new: matches!(value, Value::New(_)),
+                slot: arena.alloc(value.get()),
+            },
+            (_, node) => Stored::Node(node),
+        }
+    }
+
+    fn load(&self, stored: &Stored<H>) -> Node<H> {
+        match stored {
+            Stored::Node(node) => node.clone(),
+            Stored::Value { new, slot } => {
+                let value = self
+                    .arena
+                    .as_ref()
+                    .expect("values are stored in the arena")
+                    .get(*slot)
+                    .to_vec();
+                Node::Value(if *new {
+                    Value::New(value)
+                } else {
+                    Value::Cached(value)
+                })
+            }
+        }
+    }
+
+    /// Take the node out of its stored form.  Arena space is only reclaimed by `clear`.
+    fn take(&self, stored: Stored<H>) -> Node<H> {
+        match stored {
+            Stored::Node(node) => node,
+            stored => self.load(&stored),
+        }
+    }
+
+    fn encoded_len(stored: &Stored<H>) -> usize {
+        match stored {
+            Stored::Node(node) => node.encoded_len(),
+            Stored::Value { slot, .. } => 1 + slot.len,
+        }
+    }
+
+    /// Return the number of nodes moved to the spill backend.
+    pub(crate) fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.spilled.len())
+    }
+
+    pub(crate) fn get(&self, hash: &H::Out) -> Option<Node<H>> {
+        if let Some((stored, _)) = self.nodes.get(hash) {
+            return Some(self.load(stored));
+        }
+
+        let spill = self.spill.as_ref()?;
+        if !spill.spilled.contains(hash) {
+            return None;
+        }
+        decode_spilled(&spill.backend.get(hash, EMPTY_PREFIX)?)
+    }
+
+    pub(crate) fn insert(&mut self, hash: H::Out, node: Node<H>) {
+        self.remove(&hash);
+
+        self.tick += 1;
+        self.bytes += node.encoded_len();
+        self.order.insert(self.tick, hash);
+        let stored = self.store(node);
+        self.nodes.insert(hash, (stored, self.tick));
+        self.evict();
+    }
+
+    pub(crate) fn remove(&mut self, hash: &H::Out) -> Option<Node<H>> {
+        if let Some((stored, tick)) = self.nodes.remove(hash) {
+            self.order.remove(&tick);
+            self.bytes -= Self::encoded_len(&stored);
+            return Some(self.take(stored));
+        }
+
+        let spill = self.spill.as_mut()?;
+        if !spill.spilled.remove(hash) {
+            return None;
+        }
+        let data = spill.backend.get(hash, EMPTY_PREFIX);
+        spill.backend.remove(hash, EMPTY_PREFIX);
+        decode_spilled(&data?)
+    }
+
+    pub(crate) fn extend(&mut self, nodes: impl IntoIterator<Item = (H::Out, Node<H>)>) {
+        for (hash, node) in nodes {
+            self.insert(hash, node);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.nodes.clear();
+        self.order.clear();
+        self.bytes = 0;
+        if let Some(arena) = self.arena.as_mut() {
+            arena.reset();
+        }
+        if let Some(spill) = self.spill.as_mut() {
+            for hash in spill.spilled.drain() {
+                spill.backend.remove(&hash, EMPTY_PREFIX);
+            }
+        }
+    }
+
+    /// Move the least recently written nodes to the spill backend until the nodes held in
+    /// memory fit the budget.
+    fn evict(&mut self) {
+        let budget = match self.spill.as_ref() {
+            Some(spill) => spill.budget,
+            None => return,
+        };
+
+        while self.bytes > budget {
+            let (_, hash) = match self.order.pop_first() {
+                Some(entry) => entry,
+                None => return,
+            };
+            let (stored, _) = self
+                .nodes
+                .remove(&hash)
+                .expect("ordered nodes are in memory");
+            self.bytes -= Self::encoded_len(&stored);
+            let node = self.take(stored);
+            let spill = self.spill.as_mut().expect("checked above");
+            spill
+                .backend
+                .emplace(hash, EMPTY_PREFIX, encode_spilled(node));
+            spill.spilled.insert(hash);
+        }
+    }
+}
+
+/// A hasher for keys that are already uniformly distributed hash outputs, folding the key bytes
+/// into a word by rotating and xoring eight bytes at a time instead of rehashing them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FoldHasher(u64);
+
+impl core::hash::Hasher for FoldHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.0 = self.0.rotate_left(5) ^ u64::from_le_bytes(word);
+        }
+    }
+}
+
+/// Builds `FoldHasher`s, see `TreeDBMutBuilder::with_hasher`.
+pub type BuildFoldHasher = BuildHasherDefault<FoldHasher>;
@@ -1,6 +1,8 @@
 use crate::{
+    compute_null_hashes, decode_hash, indices,
+    node::key_bit,
     rstd::{convert::From, BTreeSet, Vec},
-    Hasher,
+    DBValue, Hasher, NullHashes, ProofHeader, ProofTree, TreeError, VerifiedProofDb,
 };
 use hash_db::{AsHashDB, Prefix, EMPTY_PREFIX};
 use memory_db::{KeyFunction, MemoryDB};
@@ -28,6 +30,11 @@ impl StorageProof {
         }
     }
 
+    /// Iterate over the encoded nodes of the proof.
+    pub fn nodes(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.nodes.iter()
+    }
+
     pub fn into_nodes(self) -> BTreeSet<Vec<u8>> {
         self.nodes
     }
@@ -35,6 +42,24 @@ impl StorageProof {
     pub fn into_memory_db<H: Hasher>(self) -> MemoryDB<H, NoopKey<H>, Vec<u8>> {
         self.into()
     }
+
+    /// Verify the proof against `root` and return a database serving its nodes, see
+    /// `VerifiedProofDb::new`.
+    pub fn into_verified_db<H: Hasher>(
+        self,
+        root: H::Out,
+    ) -> Result<VerifiedProofDb<H>, TreeError> {
+        VerifiedProofDb::new(self, root)
+    }
+
+    /// Verify the proof against `root` and return a read-only tree of the given depth over it.
+    pub fn into_tree<H: Hasher>(
+        self,
+        root: H::Out,
+        depth: usize,
+    ) -> Result<ProofTree<H>, TreeError> {
+        ProofTree::new(self, root, depth)
+    }
 }
 
 impl<H: Hasher> From<StorageProof> for MemoryDB<H, NoopKey<H>, Vec<u8>> {
@@ -47,3 +72,226 @@ impl<H: Hasher> From<StorageProof> for MemoryDB<H, NoopKey<H>, Vec<u8>> {
         db
     }
 }
+
+/// The key, value and inclusion proof of a populated leaf.
+pub type LeafProof<H> = (Vec<u8>, DBValue, Proof<H>);
+
+/// An inclusion proof for a single leaf.
+///
+/// The proof holds the sibling hashes along the path from the leaf to the root, ordered from
+/// the leaf to the root.
+#[derive(Debug)]
+pub struct Proof<H: Hasher> {
+    siblings: Vec<H::Out>,
+}
+
+impl<H: Hasher> Proof<H> {
+    /// Build a `Proof` from sibling hashes ordered from the leaf to the root.
+    pub fn from_siblings(siblings: Vec<H::Out>) -> Self {
+        Self { siblings }
+    }
+
+    /// Return the sibling hashes ordered from the leaf to the root.
+    pub fn siblings(&self) -> Vec<H::Out> {
+        self.siblings.clone()
+    }
+
+    /// Build a `Proof` for `key` from a proof returned by `get_proof`.
+    ///
+    /// The child pairs of `get_proof` are listed from the root down, so siblings are taken by
+    /// their position in the proof.  Node indices overflow below level 63 and cannot tell deep
+    /// siblings apart, so they are only checked where they fit in a `usize`.
+    pub fn from_indexed(key: &[u8], proof: &[(usize, DBValue)]) -> Result<Self, TreeError> {
+        let siblings = (1..=key.len())
+            .rev()
+            .map(|len| {
+                let bit = key_bit(key[len - 1])?;
+                let missing = || TreeError::DataNotFound {
+                    hash: Vec::new(),
+                    path: key[..len].to_vec(),
+                    depth: len,
+                };
+                let (index, hash) = proof.get(2 * len - usize::from(bit)).ok_or_else(missing)?;
+                if len < usize::BITS as usize && *index != indices::compute_index(&key[..len]) ^ 1 {
+                    return Err(missing());
+                }
+                decode_hash::<H>(hash)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { siblings })
+    }
+
+    /// Compute the root implied by the proof for the given key and value.  Use an empty value
+    /// for the proof of an empty leaf.
+    pub fn compute_root(&self, key: &[u8], value: &[u8]) -> Result<H::Out, TreeError> {
+        if key.len() != self.siblings.len() {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        Ok(root_from_leaf::<H>(key, H::hash(value), &self.siblings))
+    }
+}
+
+/// Check a proof given as hashes only: the leaf hash at `key_bits` and its sibling hashes
+/// ordered from the leaf to the root.  The depth is the length of the key, one byte per bit.
+///
+/// Proofs of other sparse merkle tree implementations that hash an inner node as the hash of
+/// the concatenated child hashes can be checked without converting them to a `Proof`.
+pub fn verify_with_siblings<H: Hasher>(
+    root: &H::Out,
+    key_bits: &[u8],
+    leaf_hash: &H::Out,
+    siblings: &[H::Out],
+) -> bool {
+    key_bits.len() == siblings.len()
+        && key_bits.iter().all(|bit| key_bit(*bit).is_ok())
+        && &root_from_leaf::<H>(key_bits, *leaf_hash, siblings) == root
+}
+
+/// Hash up from the leaf at `key` along the siblings ordered from the leaf to the root.
+fn root_from_leaf<H: Hasher>(key: &[u8], leaf_hash: H::Out, siblings: &[H::Out]) -> H::Out {
+    key.iter()
+        .rev()
+        .zip(siblings.iter())
+        .fold(leaf_hash, |hash, (bit, sibling)| {
+            let (left, right) = if *bit == 0 {
+                (&hash, sibling)
+            } else {
+                (sibling, &hash)
+            };
+            H::hash(&[left.as_ref(), right.as_ref()].concat())
+        })
+}
+
+/// A `Proof` with null-hash siblings omitted.
+///
+/// Bit `i` of the presence bitmap is set if sibling `i`, counted from the leaf, is present.
+/// Absent siblings are null hashes, which the verifier recomputes from the proof length.
+#[derive(Debug)]
+pub struct SparseProof<H: Hasher> {
+    depth: usize,
+    bitmap: Vec<u8>,
+    siblings: Vec<H::Out>,
+}
+
+impl<H: Hasher> Proof<H> {
+    /// Omit the null-hash siblings of the proof.
+    pub fn to_sparse(&self) -> SparseProof<H> {
+        self.to_sparse_with(&compute_null_hashes::<H>(self.siblings.len()))
+    }
+
+    /// Omit the null-hash siblings of a proof of a tree with the given null hashes.
+    pub fn to_sparse_with_null_hashes(&self, null_hashes: &NullHashes<H>) -> SparseProof<H> {
+        self.to_sparse_with(&null_hashes.ladder(self.siblings.len()))
+    }
+
+    /// Omit the siblings equal to `null_hashes`, indexed by level from the root.
+    fn to_sparse_with(&self, null_hashes: &[H::Out]) -> SparseProof<H> {
+        let depth = self.siblings.len();
+
+        let mut bitmap = vec![0; depth.div_ceil(8)];
+        let mut siblings = Vec::new();
+        for (i, sibling) in self.siblings.iter().enumerate() {
+            if sibling != &null_hashes[depth - i] {
+                bitmap[i / 8] |= 1 << (i % 8);
+                siblings.push(*sibling);
+            }
+        }
+
+        SparseProof {
+            depth,
+            bitmap,
+            siblings,
+        }
+    }
+}
+
+impl<H: Hasher> SparseProof<H> {
+    /// Restore the full proof for a tree of the given depth.
+    pub fn to_proof(&self, depth: usize) -> Result<Proof<H>, TreeError> {
+        self.to_proof_with(depth, &compute_null_hashes::<H>(depth))
+    }
+
+    /// Restore the full proof for a tree of the given depth with the given null hashes.
+    pub fn to_proof_with_null_hashes(
+        &self,
+        depth: usize,
+        null_hashes: &NullHashes<H>,
+    ) -> Result<Proof<H>, TreeError> {
+        self.to_proof_with(depth, &null_hashes.ladder(depth))
+    }
+
+    /// Restore the absent siblings from `null_hashes`, indexed by level from the root.
+    fn to_proof_with(&self, depth: usize, null_hashes: &[H::Out]) -> Result<Proof<H>, TreeError> {
+        if self.depth != depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut present = self.siblings.iter();
+        let siblings = (0..depth)
+            .map(|i| {
+                if self.bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                    present.next().copied().ok_or(TreeError::ProofDecodeFailed)
+                } else {
+                    Ok(null_hashes[depth - i])
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        if present.next().is_some() {
+            return Err(TreeError::ProofDecodeFailed);
+        }
+
+        Ok(Proof::from_siblings(siblings))
+    }
+
+    /// Encode the proof as a `ProofHeader`, the presence bitmap and the present siblings.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = ProofHeader::new::<H>(self.depth).encode().to_vec();
+        data.extend_from_slice(&self.bitmap);
+        for sibling in self.siblings.iter() {
+            data.extend_from_slice(sibling.as_ref());
+        }
+        data
+    }
+
+    /// Decode a proof for a tree of the given depth produced by `encode()`.  Fails with
+    /// `TreeError::IncompatibleProof` if the proof was encoded for another hasher or depth.
+    pub fn decode(data: &[u8], depth: usize) -> Result<Self, TreeError> {
+        let (header, data) = ProofHeader::decode(data)?;
+        header.validate::<H>(depth)?;
+
+        let bitmap_len = depth.div_ceil(8);
+        if data.len() < bitmap_len || !(data.len() - bitmap_len).is_multiple_of(H::LENGTH) {
+            return Err(TreeError::ProofDecodeFailed);
+        }
+
+        let (bitmap, siblings) = data.split_at(bitmap_len);
+        let siblings = siblings
+            .chunks(H::LENGTH)
+            .map(decode_hash::<H>)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            depth,
+            bitmap: bitmap.to_vec(),
+            siblings,
+        })
+    }
+}
+
+impl<H: Hasher> Clone for Proof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            siblings: self.siblings.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for Proof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.siblings == other.siblings
+    }
+}
+
+impl<H: Hasher> Eq for Proof<H> {}
@@ -1,31 +1,144 @@
-use crate::{Hasher, Node, StorageProof, TreeRecorder};
+use crate::{
+    rstd::{HashMap, Vec},
+    DBValue, Hasher, Node, StorageProof, TreeRecorder,
+};
 
 /// Record node accesses.
 pub struct Recorder<H: Hasher> {
     // TODO recorder should use a set
-    nodes: Vec<Node<H>>,
+    nodes: Vec<(H::Out, DBValue)>,
+    filter: Option<Vec<u8>>,
+    /// The paths of the children of the inner nodes seen so far, used to place nodes under the
+    /// filter.
+    paths: HashMap<H::Out, Vec<Vec<u8>>>,
 }
 
 impl<H: Hasher> Recorder<H> {
     /// Create a new `Recorder`.
     pub fn new() -> Self {
-        Self { nodes: Vec::new() }
+        Self {
+            nodes: Vec::new(),
+            filter: None,
+            paths: HashMap::new(),
+        }
     }
 
-    /// Drain all visited nodes.
-    pub fn drain(&mut self) -> Vec<Node<H>> {
-        let nodes = std::mem::take(&mut self.nodes);
-        nodes.into_iter().collect()
+    /// Only record the nodes on the path to `prefix` and in the subtree below it, so a witness
+    /// can be scoped to one namespace of the tree.  The recorder places each node by the inner
+    /// node it was reached from, and takes a node reached from no node it has seen to be the
+    /// root, so traversals must start at the root as they do in `TreeDB`.
+    pub fn with_filter(mut self, prefix: &[u8]) -> Self {
+        self.filter = Some(prefix.to_vec());
+        self
+    }
+
+    /// Drain the hash and encoding of all visited nodes.
+    pub fn drain(&mut self) -> Vec<(H::Out, DBValue)> {
+        std::mem::take(&mut self.nodes)
     }
 
     pub fn drain_storage_proof(self) -> StorageProof {
-        let encoded_nodes: Vec<Vec<u8>> = self.nodes.into_iter().map(|node| node.into()).collect();
-        StorageProof::new(encoded_nodes)
+        StorageProof::new(self.nodes.into_iter().map(|(_, encoded)| encoded))
+    }
+}
+
+impl<H: Hasher> Default for Recorder<H> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<H: Hasher> TreeRecorder<H> for Recorder<H> {
-    fn record(&mut self, node: Node<H>) {
-        self.nodes.push(node);
+    fn record(&mut self, hash: &H::Out, encoded: &[u8]) {
+        let filter = match self.filter.as_ref() {
+            Some(filter) => filter,
+            None => return self.nodes.push((*hash, encoded.to_vec())),
+        };
+
+        let paths = self
+            .paths
+            .get(hash)
+            .cloned()
+            .unwrap_or_else(|| Vec::from([Vec::new()]));
+        // place the children of every inner node, so nodes reached through skipped nodes are
+        // not mistaken for roots
+        if let Ok(Node::Inner(left, right)) = Node::<H>::decode(encoded) {
+            for path in paths.iter() {
+                for (bit, child) in [(0, left.get_hash()), (1, right.get_hash())] {
+                    let mut child_path = path.clone();
+                    child_path.push(bit);
+                    let child_paths = self.paths.entry(*child).or_default();
+                    if !child_paths.contains(&child_path) {
+                        child_paths.push(child_path);
+                    }
+                }
+            }
+        }
+
+        // a node is in scope if it is an ancestor of the prefix or lies below it
+        if paths
+            .iter()
+            .any(|path| filter.starts_with(path) || path.starts_with(filter))
+        {
+            self.nodes.push((*hash, encoded.to_vec()));
+        }
+    }
+}
+
+/// Forward node accesses to multiple recorders.
+///
+/// Each recorded node is passed to every inner recorder in the order they were added.
+pub struct TeeRecorder<'a, H: Hasher> {
+    recorders: Vec<&'a mut dyn TreeRecorder<H>>,
+}
+
+impl<'a, H: Hasher> TeeRecorder<'a, H> {
+    /// Create a new `TeeRecorder` without any inner recorders.
+    pub fn new() -> Self {
+        Self {
+            recorders: Vec::new(),
+        }
+    }
+
+    /// Add a recorder that should receive all recorded nodes.
+    pub fn with_recorder(mut self, recorder: &'a mut dyn TreeRecorder<H>) -> Self {
+        self.recorders.push(recorder);
+        self
+    }
+
+    /// Return the number of inner recorders.
+    pub fn len(&self) -> usize {
+        self.recorders.len()
+    }
+
+    /// Return true if there are no inner recorders.
+    pub fn is_empty(&self) -> bool {
+        self.recorders.is_empty()
+    }
+}
+
+impl<'a, H: Hasher> Default for TeeRecorder<'a, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, H: Hasher> TreeRecorder<H> for TeeRecorder<'a, H> {
+    fn record(&mut self, hash: &H::Out, encoded: &[u8]) {
+        for recorder in self.recorders.iter_mut() {
+            recorder.record(hash, encoded);
+        }
+    }
+}
+
+impl<H: Hasher, T: TreeRecorder<H> + ?Sized> TreeRecorder<H> for &mut T {
+    fn record(&mut self, hash: &H::Out, encoded: &[u8]) {
+        (**self).record(hash, encoded)
+    }
+}
+
+impl<H: Hasher, T: TreeRecorder<H> + ?Sized> TreeRecorder<H> for Box<T> {
+    fn record(&mut self, hash: &H::Out, encoded: &[u8]) {
+        (**self).record(hash, encoded)
     }
 }
@@ -0,0 +1,85 @@
+use crate::{rstd::Vec, verify_with_siblings, DBValue, Hasher, Proof, TreeError};
+
+/// The length of the salt blinding a value.
+pub const BLINDING_SALT_LEN: usize = 32;
+
+/// Blind `value` with `salt` for storage in a tree whose leaves are disclosed by
+/// `RedactedProof`.
+///
+/// The blinded value is the salt followed by the value, so the leaf hash commits to both and
+/// cannot be matched against guessed values without the salt.  The salt must be secret and
+/// drawn at random per leaf, and is handed to whoever should see the value along with it.
+pub fn blind_value(value: &[u8], salt: &[u8; BLINDING_SALT_LEN]) -> DBValue {
+    let mut blinded = Vec::with_capacity(BLINDING_SALT_LEN + value.len());
+    blinded.extend_from_slice(salt);
+    blinded.extend_from_slice(value);
+    blinded
+}
+
+/// Split a blinded value into its salt and the value.  Fails with `TreeError::MissingBlinding`
+/// if the value is too short to carry a salt.
+pub fn split_blinding(blinded: &[u8]) -> Result<(&[u8; BLINDING_SALT_LEN], &[u8]), TreeError> {
+    if blinded.len() < BLINDING_SALT_LEN {
+        return Err(TreeError::MissingBlinding);
+    }
+
+    let (salt, value) = blinded.split_at(BLINDING_SALT_LEN);
+    let salt = salt.try_into().expect("split at the salt length");
+    Ok((salt, value))
+}
+
+/// A proof of a leaf that reveals its hash, the leaf commitment, instead of its value.
+///
+/// Created by `TreeDB::get_redacted_proof`.  Anyone can check the commitment is in the tree
+/// with `verify`, and a party handed the opening, the stored value, can check it with
+/// `verify_opening`.  Store values with `blind_value` so the commitment does not reveal
+/// guessable values.
+#[derive(Debug)]
+pub struct RedactedProof<H: Hasher> {
+    commitment: H::Out,
+    proof: Proof<H>,
+}
+
+impl<H: Hasher> RedactedProof<H> {
+    pub(crate) fn new(commitment: H::Out, proof: Proof<H>) -> Self {
+        Self { commitment, proof }
+    }
+
+    /// Return the leaf commitment, the hash of the stored value.
+    pub fn commitment(&self) -> &H::Out {
+        &self.commitment
+    }
+
+    /// Return the proof of the commitment.
+    pub fn proof(&self) -> &Proof<H> {
+        &self.proof
+    }
+
+    /// Check that the commitment is the leaf at `key` in the tree with the given root.
+    pub fn verify(&self, root: &H::Out, key: &[u8]) -> bool {
+        verify_with_siblings::<H>(root, key, &self.commitment, &self.proof.siblings())
+    }
+
+    /// Check that `opening` is the value stored at `key` in the tree with the given root.  For
+    /// a blinded leaf the opening is `blind_value(value, salt)`.
+    pub fn verify_opening(&self, root: &H::Out, key: &[u8], opening: &[u8]) -> bool {
+        H::hash(opening) == self.commitment && self.verify(root, key)
+    }
+}
+
+impl<H: Hasher> Clone for RedactedProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            commitment: self.commitment,
+            proof: self.proof.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for RedactedProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.commitment == other.commitment && self.proof == other.proof
+    }
+}
+
+impl<H: Hasher> Eq for RedactedProof<H> {}
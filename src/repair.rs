@@ -0,0 +1,208 @@
+use crate::{
+    compression, integrity,
+    rstd::{HashMap, HashSet, Vec},
+    DBValue, Hasher, IntegrityProblem, Node, NodeHash, NullHashes, StorageProof, TreeError, Value,
+    ValueCompression, EMPTY_PREFIX,
+};
+use hash_db::{HashDB, HashDBRef};
+
+/// The data used to rebuild damaged subtrees.
+pub enum RepairSource<'a> {
+    /// A dump of `(key, value)` pairs covering every populated leaf under the damaged subtrees.
+    Leaves(&'a [(Vec<u8>, DBValue)]),
+    /// Encoded nodes, e.g. subtree proofs provided by a peer.
+    Nodes(&'a StorageProof),
+}
+
+/// The result of a `repair` run.
+#[derive(Debug)]
+pub struct RepairReport<H: Hasher> {
+    /// The paths of the subtrees that were rebuilt.
+    pub repaired: Vec<Vec<u8>>,
+    /// The problems that could not be repaired from the supplied source.
+    pub unrepaired: Vec<IntegrityProblem<H>>,
+}
+
+/// Build the subtree rooted at `level` from the given leaves, appending all created nodes to
+/// `nodes`.  Leaf keys must share the prefix of the subtree.
+pub(crate) fn build_subtree<H: Hasher>(
+    leaves: &[&(Vec<u8>, DBValue)],
+    level: usize,
+    depth: usize,
+    null_hashes: &[H::Out],
+    nodes: &mut Vec<(H::Out, Node<H>)>,
+) -> H::Out {
+    if leaves.is_empty() {
+        return null_hashes[level];
+    }
+
+    let node = if level == depth {
+        Node::Value(Value::New(leaves[leaves.len() - 1].1.clone()))
+    } else {
+        let (left, right): (Vec<_>, Vec<_>) = leaves.iter().partition(|(key, _)| key[level] == 0);
+        let left = build_subtree(&left, level + 1, depth, null_hashes, nodes);
+        let right = build_subtree(&right, level + 1, depth, null_hashes, nodes);
+        Node::Inner(NodeHash::Hash(left), NodeHash::Hash(right))
+    };
+
+    let hash = node.hash();
+    nodes.push((hash, node));
+    hash
+}
+
+fn collect_nodes<H: Hasher>(
+    hash: H::Out,
+    level: usize,
+    source: &HashMap<H::Out, DBValue>,
+    nodes: &mut Vec<(H::Out, Node<H>)>,
+) -> Result<(), TreeError> {
+    let node: Node<H> = source
+        .get(&hash)
+        .cloned()
+        .ok_or_else(|| TreeError::missing_node::<H>(&hash, level))?
+        .try_into()?;
+
+    if let Node::Inner(left, right) = &node {
+        for child in [left, right] {
+            if source.contains_key(child.get_hash()) {
+                collect_nodes(*child.get_hash(), level + 1, source, nodes)?;
+            }
+        }
+    }
+
+    nodes.push((hash, node));
+    Ok(())
+}
+
+fn rebuild<H: Hasher>(
+    path: &[u8],
+    hash: &H::Out,
+    depth: usize,
+    source: &RepairSource,
+    proof_nodes: &HashMap<H::Out, DBValue>,
+    null_hashes: &[H::Out],
+) -> Option<Vec<(H::Out, Node<H>)>> {
+    let mut nodes = Vec::new();
+    match source {
+        RepairSource::Leaves(leaves) => {
+            let leaves: Vec<_> = leaves
+                .iter()
+                .filter(|(key, _)| key.len() == depth && key.starts_with(path))
+                .collect();
+            let root = build_subtree(&leaves, path.len(), depth, null_hashes, &mut nodes);
+            (&root == hash).then_some(nodes)
+        }
+        RepairSource::Nodes(_) => collect_nodes(*hash, path.len(), proof_nodes, &mut nodes)
+            .ok()
+            .map(|_| nodes),
+    }
+}
+
+/// Store `node` under `hash` unless a valid encoding is already stored.  A stored encoding that
+/// does not hash to `hash` is replaced, keeping the reference count of the entry.
+fn store<H: Hasher>(
+    db: &mut dyn HashDB<H, DBValue>,
+    hash: &H::Out,
+    node: &Node<H>,
+    compression: Option<&dyn ValueCompression>,
+) {
+    let valid = db.get(hash, EMPTY_PREFIX).is_some_and(|data| {
+        compression::decode_stored(compression, data)
+            .and_then(|data| Node::<H>::decode(&data))
+            .is_ok_and(|stored| &stored.hash() == hash)
+    });
+    if valid {
+        return;
+    }
+
+    // a reference counted database keeps the bytes until every reference is removed
+    let mut references = 0;
+    while db.contains(hash, EMPTY_PREFIX) {
+        db.remove(hash, EMPTY_PREFIX);
+        references += 1;
+    }
+    let data = compression::encode_stored(compression, node);
+    for _ in 0..references.max(1) {
+        db.emplace(*hash, EMPTY_PREFIX, data.clone());
+    }
+}
+
+/// Rebuild missing or corrupt subtrees of the tree of the given depth and null hashes at `root`
+/// from the given source.
+///
+/// Damaged subtrees are located with an integrity check, rebuilt from `source` and written back
+/// to the database if their rebuilt hash matches the hash referenced by their parent, replacing
+/// corrupt entries.  The check is repeated while it reports damage not yet attempted, since
+/// missing subtrees may hide further damage below them.  Each damaged subtree is attempted once
+/// and only subtrees that pass the final check are reported as repaired.
+///
+/// For a database holding compressed values, pass the tree's `compression`: stored values are
+/// decompressed before they are checked and rebuilt values are compressed like on commit.
+pub fn repair<H: Hasher>(
+    db: &mut dyn HashDB<H, DBValue>,
+    root: &H::Out,
+    depth: usize,
+    null_hashes: &NullHashes<H>,
+    source: RepairSource,
+    compression: Option<&dyn ValueCompression>,
+) -> RepairReport<H> {
+    let null_hashes = null_hashes.ladder(depth);
+    let proof_nodes: HashMap<H::Out, DBValue> = match &source {
+        RepairSource::Nodes(proof) => (*proof)
+            .clone()
+            .into_nodes()
+            .into_iter()
+            .map(|node| (H::hash(&node[1..]), node))
+            .collect(),
+        RepairSource::Leaves(_) => HashMap::new(),
+    };
+
+    let mut attempted = HashSet::new();
+    let mut rebuilt = Vec::new();
+    loop {
+        let report = {
+            let db_ref: &dyn HashDBRef<H, DBValue> = &db;
+            integrity::verify(db_ref, root, depth, &null_hashes, compression)
+        };
+
+        let mut progress = false;
+        for problem in report.problems.iter() {
+            let (path, hash) = problem_location(problem);
+            if !attempted.insert((path.clone(), *hash)) {
+                continue;
+            }
+            progress = true;
+
+            if let Some(nodes) =
+                rebuild::<H>(path, hash, depth, &source, &proof_nodes, &null_hashes)
+            {
+                for (hash, node) in nodes.iter() {
+                    store(db, hash, node, compression);
+                }
+                rebuilt.push(path.clone());
+            }
+        }
+
+        if !progress {
+            let damaged: HashSet<_> = report
+                .problems
+                .iter()
+                .map(|problem| problem_location(problem).0.clone())
+                .collect();
+            rebuilt.retain(|path| !damaged.contains(path));
+            return RepairReport {
+                repaired: rebuilt,
+                unrepaired: report.problems,
+            };
+        }
+    }
+}
+
+fn problem_location<H: Hasher>(problem: &IntegrityProblem<H>) -> (&Vec<u8>, &H::Out) {
+    match problem {
+        IntegrityProblem::MissingNode { path, hash }
+        | IntegrityProblem::UndecodableNode { path, hash }
+        | IntegrityProblem::HashMismatch { path, hash }
+        | IntegrityProblem::UnexpectedNodeType { path, hash } => (path, hash),
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    rstd::{BTreeMap, BTreeSet, Vec},
+    DBValue, Hasher, Tree, TreeDB, TreeDBMut, TreeError, TreeMut,
+};
+
+/// A writer branched from a base root by `Sessions::branch`.
+///
+/// Reads are served from the session's own writes or from the base tree and are recorded, and
+/// writes are buffered until the session is committed with `Sessions::commit`.  Sessions are
+/// independent of each other, so they can execute in parallel against the same base.
+#[derive(Debug)]
+pub struct Session<H: Hasher> {
+    base: H::Out,
+    reads: BTreeSet<Vec<u8>>,
+    writes: BTreeMap<Vec<u8>, DBValue>,
+}
+
+impl<H: Hasher> Session<H> {
+    /// Return the root the session branched from.
+    pub fn base(&self) -> &H::Out {
+        &self.base
+    }
+
+    /// Return the keys read from the base tree.
+    pub fn reads(&self) -> &BTreeSet<Vec<u8>> {
+        &self.reads
+    }
+
+    /// Return the buffered writes.
+    pub fn writes(&self) -> &BTreeMap<Vec<u8>, DBValue> {
+        &self.writes
+    }
+
+    /// Get the value at `key` as seen by the session.  `base` must be a tree at the base root.
+    pub fn get_value(&mut self, base: &TreeDB<H>, key: &[u8]) -> Result<DBValue, TreeError> {
+        if base.root() != &self.base {
+            return Err(TreeError::StaleSession);
+        }
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+
+        let value = base.get_value(key)?;
+        self.reads.insert(key.to_vec());
+        Ok(value)
+    }
+
+    /// Buffer a write of `value` at `key`.
+    pub fn insert(&mut self, key: &[u8], value: DBValue) {
+        self.writes.insert(key.to_vec(), value);
+    }
+}
+
+/// Optimistic concurrency over a `TreeDBMut`: sessions branch from a common base root and are
+/// committed one after the other.
+///
+/// A session conflicts if it read a key written by a session committed after the base, as its
+/// reads are then stale, and is rejected with `TreeError::SessionConflict`.  Sessions that
+/// write the same keys without reading them are merged, the last committed write winning.
+#[derive(Debug)]
+pub struct Sessions<H: Hasher> {
+    base: H::Out,
+    head: H::Out,
+    written: BTreeSet<Vec<u8>>,
+}
+
+impl<H: Hasher> Sessions<H> {
+    /// Start branching sessions from `base`.  Sessions are committed to a tree at that root.
+    pub fn new(base: H::Out) -> Self {
+        Self {
+            base,
+            head: base,
+            written: BTreeSet::new(),
+        }
+    }
+
+    /// Return the root sessions branch from.
+    pub fn base(&self) -> &H::Out {
+        &self.base
+    }
+
+    /// Branch a new session from the base root.
+    pub fn branch(&self) -> Session<H> {
+        Session {
+            base: self.base,
+            reads: BTreeSet::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Validate the session and apply its writes to `tree`, returning the new uncommitted root.
+    ///
+    /// Fails with `TreeError::StaleSession` if the session was branched from another base or
+    /// `tree` is not at the root left by the sessions committed so far, and with
+    /// `TreeError::SessionConflict` if a read of the session is stale.  A session rejected by
+    /// validation leaves the tree unchanged and can be re-executed from a new base.  Writes are
+    /// applied all or nothing: if one fails, those already applied are reverted and the error
+    /// returned.
+    pub fn commit<S: core::hash::BuildHasher + Default>(
+        &mut self,
+        tree: &mut TreeDBMut<H, S>,
+        session: Session<H>,
+    ) -> Result<H::Out, TreeError> {
+        if session.base != self.base || tree.peek_root() != self.head {
+            return Err(TreeError::StaleSession);
+        }
+        if session.reads.iter().any(|key| self.written.contains(key)) {
+            return Err(TreeError::SessionConflict);
+        }
+        if session.writes.keys().any(|key| key.len() != tree.depth()) {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let checkpoint = tree.checkpoint();
+        for (key, value) in &session.writes {
+            if let Err(err) = tree.insert(key, value.clone()) {
+                tree.revert_to(checkpoint)?;
+                return Err(err);
+            }
+        }
+        tree.release_checkpoint(checkpoint)?;
+        self.written.extend(session.writes.into_keys());
+        self.head = tree.peek_root();
+        Ok(self.head)
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{
+    rstd::{HashMap, Vec},
+    DBValue, Node, NodeHash, NullHashes, TreeDBBuilder, TreeDBMut, TreeDBMutBuilder, TreeError,
+};
+use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher, Prefix, EMPTY_PREFIX};
+
+/// A writable overlay over a shared read-only backing database.
+pub(crate) struct ShardDB<'db, H: Hasher> {
+    base: &'db dyn HashDB<H, DBValue>,
+    pub(crate) changes: HashMap<H::Out, DBValue>,
+}
+
+impl<'db, H: Hasher> ShardDB<'db, H> {
+    pub(crate) fn new(base: &'db dyn HashDB<H, DBValue>) -> Self {
+        Self {
+            base,
+            changes: HashMap::new(),
+        }
+    }
+}
+
+impl<'db, H: Hasher> HashDB<H, DBValue> for ShardDB<'db, H> {
+    fn get(&self, key: &H::Out, prefix: Prefix) -> Option<DBValue> {
+        match self.changes.get(key) {
+            Some(value) => Some(value.clone()),
+            None => self.base.get(key, prefix),
+        }
+    }
+
+    fn contains(&self, key: &H::Out, prefix: Prefix) -> bool {
+        self.changes.contains_key(key) || self.base.contains(key, prefix)
+    }
+
+    fn insert(&mut self, _prefix: Prefix, value: &[u8]) -> H::Out {
+        let key = H::hash(value);
+        self.changes.insert(key, value.to_vec());
+        key
+    }
+
+    fn emplace(&mut self, key: H::Out, _prefix: Prefix, value: DBValue) {
+        self.changes.insert(key, value);
+    }
+
+    fn remove(&mut self, key: &H::Out, _prefix: Prefix) {
+        self.changes.remove(key);
+    }
+}
+
+impl<'db, H: Hasher> AsHashDB<H, DBValue> for ShardDB<'db, H> {
+    fn as_hash_db(&self) -> &dyn HashDB<H, DBValue> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, DBValue> + 'a) {
+        self
+    }
+}
+
+/// A subtree of a `ShardedTreeDBMut` that can be mutated independently of the other shards.
+///
+/// Shards are `Send` and can be moved to worker threads.  Changes must be committed through the
+/// `TreeDBMut` returned by `tree_mut()` before calling `into_changes()`.
+pub struct Shard<'db, H: Hasher> {
+    index: usize,
+    prefix: Vec<u8>,
+    root: H::Out,
+    depth: usize,
+    db: ShardDB<'db, H>,
+}
+
+impl<'db, H: Hasher> Shard<'db, H> {
+    /// Return the key prefix of the subtree.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Return the current root of the subtree.
+    pub fn root(&self) -> &H::Out {
+        &self.root
+    }
+
+    /// Return the depth of the subtree.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Return a mutable tree over the subtree.  Keys are relative to `prefix()`.
+    pub fn tree_mut(&mut self) -> TreeDBMut<'_, H> {
+        TreeDBMutBuilder::new(&mut self.db, &mut self.root, self.depth).build()
+    }
+
+    /// Consume the shard and return the committed changes.
+    pub fn into_changes(self) -> ShardChanges<H> {
+        ShardChanges {
+            index: self.index,
+            root: self.root,
+            changes: self.db.changes,
+        }
+    }
+}
+
+/// The committed changes of a `Shard`.
+pub struct ShardChanges<H: Hasher> {
+    index: usize,
+    root: H::Out,
+    changes: HashMap<H::Out, DBValue>,
+}
+
+/// A mutable tree partitioned by the top `shard_bits` key bits into independently writable
+/// subtrees.
+///
+/// Use `shards()` to obtain the subtrees, mutate them (possibly concurrently) and pass the
+/// resulting changes to `commit()` to write them and recompute the root.
+pub struct ShardedTreeDBMut<'db, H: Hasher> {
+    db: &'db mut dyn HashDB<H, DBValue>,
+    root: &'db mut H::Out,
+    depth: usize,
+    shard_bits: usize,
+}
+
+impl<'db, H: Hasher> ShardedTreeDBMut<'db, H> {
+    pub fn new(
+        db: &'db mut dyn HashDB<H, DBValue>,
+        root: &'db mut H::Out,
+        depth: usize,
+        shard_bits: usize,
+    ) -> Self {
+        Self {
+            db,
+            root,
+            depth,
+            shard_bits,
+        }
+    }
+
+    /// Return the root of the tree.
+    pub fn root(&self) -> &H::Out {
+        self.root
+    }
+
+    fn subtree_roots(&self) -> Result<Vec<H::Out>, TreeError> {
+        if self.shard_bits > self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let db: &dyn HashDBRef<H, DBValue> = &self.db;
+        let tree = TreeDBBuilder::new(db, self.root, self.depth).build();
+
+        let mut level = Vec::from([*self.root]);
+        for depth in 0..self.shard_bits {
+            let mut next_level = Vec::with_capacity(level.len() * 2);
+            for hash in level {
+                let node = tree.lookup(&hash, depth)?;
+                next_level.push(*node.get_left_child()?.get_hash());
+                next_level.push(*node.get_right_child()?.get_hash());
+            }
+            level = next_level;
+        }
+
+        Ok(level)
+    }
+
+    /// Return one shard per top-level prefix, ordered by prefix.
+    pub fn shards(&self) -> Result<Vec<Shard<'_, H>>, TreeError> {
+        let base: &dyn HashDB<H, DBValue> = &*self.db;
+        let shards = self
+            .subtree_roots()?
+            .into_iter()
+            .enumerate()
+            .map(|(index, root)| Shard {
+                index,
+                prefix: (0..self.shard_bits)
+                    .rev()
+                    .map(|bit| ((index >> bit) & 1) as u8)
+                    .collect(),
+                root,
+                depth: self.depth - self.shard_bits,
+                db: ShardDB::new(base),
+            })
+            .collect();
+
+        Ok(shards)
+    }
+
+    /// Write the changes of the given shards to the backing database and recompute the root.
+    ///
+    /// Fails with `TreeError::IndexOutOfBounds`, before writing anything, if a shard does not
+    /// belong to a tree with this many shards.
+    pub fn commit(
+        &mut self,
+        changes: impl IntoIterator<Item = ShardChanges<H>>,
+    ) -> Result<&H::Out, TreeError> {
+        let mut level = self.subtree_roots()?;
+        let changes: Vec<_> = changes.into_iter().collect();
+        if changes.iter().any(|shard| shard.index >= level.len()) {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        for shard in changes {
+            for (hash, value) in shard.changes {
+                self.db.emplace(hash, EMPTY_PREFIX, value);
+            }
+            level[shard.index] = shard.root;
+        }
+
+        let null_hashes = NullHashes::<H>::new();
+        let mut parent_level = self.shard_bits;
+        while level.len() > 1 {
+            parent_level -= 1;
+            let null_hash = null_hashes.at_level(parent_level, self.depth);
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let node = Node::<H>::Inner(NodeHash::Hash(pair[0]), NodeHash::Hash(pair[1]));
+                    let hash = node.hash();
+                    if hash != null_hash {
+                        self.db.emplace(hash, EMPTY_PREFIX, node.into());
+                    }
+                    hash
+                })
+                .collect();
+        }
+
+        *self.root = level[0];
+        Ok(self.root)
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{DBValue, HashDBRef, Hasher, TreeDB, TreeDBBuilder, TreeMut};
+use std::{collections::BTreeMap, sync::RwLock};
+
+struct Versions<H: Hasher> {
+    roots: BTreeMap<u64, H::Out>,
+    pins: BTreeMap<u64, usize>,
+    latest: u64,
+}
+
+/// A registry of named roots: committed roots by version.
+///
+/// Writers publish a root only once all of its nodes have been committed, and readers open a
+/// `Snapshot` of a published version, so a reader is never handed the root of a half-written
+/// tree.  Snapshots pin their version until dropped so that callers pruning old state can
+/// avoid pinned roots.
+///
+/// The registry does not isolate reads from writes to the database: it only names roots.
+/// Reading a version while a writer commits newer ones requires a backend that serves
+/// concurrent reads and writes, such as one behind a lock, which the registry does not
+/// provide.
+pub struct VersionedRoots<H: Hasher> {
+    versions: RwLock<Versions<H>>,
+}
+
+impl<H: Hasher> VersionedRoots<H> {
+    /// Create a new registry with `root` published as version 0.
+    pub fn new(root: H::Out) -> Self {
+        Self {
+            versions: RwLock::new(Versions {
+                roots: BTreeMap::from([(0, root)]),
+                pins: BTreeMap::new(),
+                latest: 0,
+            }),
+        }
+    }
+
+    /// Publish a committed root as the next version.  Returns the new version.
+    pub fn publish(&self, root: H::Out) -> u64 {
+        let mut versions = self.versions.write().expect("lock poisoned");
+        versions.latest += 1;
+        let latest = versions.latest;
+        versions.roots.insert(latest, root);
+        latest
+    }
+
+    /// Commit the given tree and publish its root as the next version.
+    pub fn commit<T: TreeMut<H> + ?Sized>(&self, tree: &mut T) -> u64 {
+        let root = *tree.root();
+        self.publish(root)
+    }
+
+    /// Return the latest published version and its root.
+    pub fn latest(&self) -> (u64, H::Out) {
+        let versions = self.versions.read().expect("lock poisoned");
+        (versions.latest, versions.roots[&versions.latest])
+    }
+
+    /// Return the root published at the given version.
+    pub fn root_at(&self, version: u64) -> Option<H::Out> {
+        let versions = self.versions.read().expect("lock poisoned");
+        versions.roots.get(&version).copied()
+    }
+
+    /// Open a snapshot of the latest version.
+    pub fn snapshot(&self) -> Snapshot<'_, H> {
+        let mut versions = self.versions.write().expect("lock poisoned");
+        let version = versions.latest;
+        let root = versions.roots[&version];
+        *versions.pins.entry(version).or_insert(0) += 1;
+        Snapshot {
+            registry: self,
+            version,
+            root,
+        }
+    }
+
+    /// Open a snapshot of the given version.
+    pub fn snapshot_at(&self, version: u64) -> Option<Snapshot<'_, H>> {
+        let mut versions = self.versions.write().expect("lock poisoned");
+        let root = *versions.roots.get(&version)?;
+        *versions.pins.entry(version).or_insert(0) += 1;
+        Some(Snapshot {
+            registry: self,
+            version,
+            root,
+        })
+    }
+
+    /// Return the oldest version pinned by an open snapshot.
+    pub fn oldest_pinned(&self) -> Option<u64> {
+        let versions = self.versions.read().expect("lock poisoned");
+        versions.pins.keys().next().copied()
+    }
+
+    /// Forget all unpinned versions older than `version`.  The latest version is always kept.
+    pub fn prune_before(&self, version: u64) {
+        let mut versions = self.versions.write().expect("lock poisoned");
+        let Versions {
+            roots,
+            pins,
+            latest,
+        } = &mut *versions;
+        roots.retain(|v, _| *v >= version || *v == *latest || pins.contains_key(v));
+    }
+
+    fn unpin(&self, version: u64) {
+        let mut versions = self.versions.write().expect("lock poisoned");
+        if let Some(count) = versions.pins.get_mut(&version) {
+            *count -= 1;
+            if *count == 0 {
+                versions.pins.remove(&version);
+            }
+        }
+    }
+}
+
+/// A pinned published version and its root.
+pub struct Snapshot<'r, H: Hasher> {
+    registry: &'r VersionedRoots<H>,
+    version: u64,
+    root: H::Out,
+}
+
+impl<'r, H: Hasher> Snapshot<'r, H> {
+    /// Return the version of the snapshot.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Return the root of the snapshot.
+    pub fn root(&self) -> &H::Out {
+        &self.root
+    }
+
+    /// Open a read-only tree at the snapshot root.
+    pub fn tree<'a>(&'a self, db: &'a dyn HashDBRef<H, DBValue>, depth: usize) -> TreeDB<'a, H> {
+        TreeDBBuilder::new(db, &self.root, depth).build()
+    }
+}
+
+impl<'r, H: Hasher> Drop for Snapshot<'r, H> {
+    fn drop(&mut self) {
+        self.registry.unpin(self.version);
+    }
+}
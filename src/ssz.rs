@@ -0,0 +1,160 @@
+use crate::{
+    rstd::{BTreeMap, BTreeSet, Vec},
+    Hasher, Tree, TreeDB, TreeError,
+};
+
+/// Return the generalized index of the node at the given key prefix: the root is 1 and the
+/// children of `g` are `2g` and `2g + 1`.  Returns `None` if the prefix is longer than 63 bits.
+pub fn generalized_index(path: &[u8]) -> Option<u64> {
+    if path.len() >= u64::BITS as usize {
+        return None;
+    }
+    Some(
+        path.iter()
+            .fold(1, |index, bit| (index << 1) | u64::from(*bit)),
+    )
+}
+
+/// Return the key prefix of the node at the given generalized index.
+fn index_path(index: u64) -> Vec<u8> {
+    let len = u64::BITS - index.leading_zeros() - 1;
+    (0..len)
+        .rev()
+        .map(|bit| ((index >> bit) & 1) as u8)
+        .collect()
+}
+
+fn branch_indices(index: u64) -> impl Iterator<Item = u64> {
+    (1..u64::BITS - index.leading_zeros()).map(move |shift| (index >> (shift - 1)) ^ 1)
+}
+
+fn path_indices(index: u64) -> impl Iterator<Item = u64> {
+    (0..u64::BITS - index.leading_zeros() - 1).map(move |shift| index >> shift)
+}
+
+/// Return the generalized indices of the nodes needed to prove the given indices, in
+/// descending order, as `get_helper_indices` of the SSZ specification.  The invalid index 0 is
+/// ignored.
+pub fn helper_indices(indices: &[u64]) -> Vec<u64> {
+    let indices = indices.iter().copied().filter(|index| *index > 0);
+    let branches: BTreeSet<u64> = indices.clone().flat_map(branch_indices).collect();
+    let paths: BTreeSet<u64> = indices.flat_map(path_indices).collect();
+    let mut helpers: Vec<u64> = branches.difference(&paths).copied().collect();
+    helpers.reverse();
+    helpers
+}
+
+/// Compute the root from the nodes at `indices` and the helper nodes of `proof`, as
+/// `calculate_multi_merkle_root` of the SSZ specification.  Returns `None` if the proof is
+/// malformed.
+pub fn calculate_multi_merkle_root<H: Hasher>(
+    leaves: &[H::Out],
+    proof: &[H::Out],
+    indices: &[u64],
+) -> Option<H::Out> {
+    let helpers = helper_indices(indices);
+    if indices.contains(&0) || leaves.len() != indices.len() || proof.len() != helpers.len() {
+        return None;
+    }
+
+    let mut objects: BTreeMap<u64, H::Out> = indices
+        .iter()
+        .copied()
+        .zip(leaves.iter().copied())
+        .collect();
+    objects.extend(helpers.iter().copied().zip(proof.iter().copied()));
+
+    let mut keys: Vec<u64> = objects.keys().rev().copied().collect();
+    let mut position = 0;
+    while position < keys.len() {
+        let index = keys[position];
+        if index > 1 && objects.contains_key(&(index ^ 1)) && !objects.contains_key(&(index / 2)) {
+            let left = objects[&(index & !1)];
+            let right = objects[&(index | 1)];
+            objects.insert(
+                index / 2,
+                H::hash(&[left.as_ref(), right.as_ref()].concat()),
+            );
+            keys.push(index / 2);
+        }
+        position += 1;
+    }
+
+    objects.get(&1).copied()
+}
+
+/// A multiproof of the nodes at a set of SSZ generalized indices.
+#[derive(Debug)]
+pub struct SszMultiProof<H: Hasher> {
+    pub indices: Vec<u64>,
+    pub leaves: Vec<H::Out>,
+    /// The helper nodes in the order of `helper_indices`.
+    pub proof: Vec<H::Out>,
+}
+
+impl<H: Hasher> Clone for SszMultiProof<H> {
+    fn clone(&self) -> Self {
+        Self {
+            indices: self.indices.clone(),
+            leaves: self.leaves.clone(),
+            proof: self.proof.clone(),
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for SszMultiProof<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.indices == other.indices && self.leaves == other.leaves && self.proof == other.proof
+    }
+}
+
+impl<H: Hasher> Eq for SszMultiProof<H> {}
+
+impl<H: Hasher> SszMultiProof<H> {
+    /// Recompute the root of the proof.
+    pub fn calculate_root(&self) -> Option<H::Out> {
+        calculate_multi_merkle_root::<H>(&self.leaves, &self.proof, &self.indices)
+    }
+
+    /// Verify the proof against the given root.
+    pub fn verify(&self, root: &H::Out) -> bool {
+        self.calculate_root().as_ref() == Some(root)
+    }
+}
+
+/// Return the hash of the node at the given generalized index.
+fn node_at<H: Hasher>(tree: &TreeDB<H>, index: u64) -> Result<H::Out, TreeError> {
+    let mut hash = *tree.root();
+    for (level, bit) in index_path(index).into_iter().enumerate() {
+        hash = *tree.lookup(&hash, level)?.get_child(bit)?.get_hash();
+    }
+    Ok(hash)
+}
+
+/// Generate a multiproof of the nodes at the given generalized indices.
+///
+/// Leaves of a tree of depth `d` are at indices `2^d + key`, and inner nodes may be proven as
+/// well.  Only the top 63 levels of deeper trees can be addressed with `u64` indices.
+pub fn ssz_multiproof<H: Hasher>(
+    tree: &TreeDB<H>,
+    indices: &[u64],
+) -> Result<SszMultiProof<H>, TreeError> {
+    if indices
+        .iter()
+        .any(|index| *index == 0 || index.ilog2() as usize > tree.depth())
+    {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+
+    Ok(SszMultiProof {
+        indices: indices.to_vec(),
+        leaves: indices
+            .iter()
+            .map(|index| node_at(tree, *index))
+            .collect::<Result<_, _>>()?,
+        proof: helper_indices(indices)
+            .into_iter()
+            .map(|index| node_at(tree, index))
+            .collect::<Result<_, _>>()?,
+    })
+}
@@ -0,0 +1,151 @@
+use crate::{
+    decode_hash,
+    rstd::{Arc, BTreeMap, Vec},
+    HashDBRef, Hasher, Node, NullHashes, StorageProof, TreeDB, TreeDBBuilder, TreeError,
+    VerifiedProofDb, EMPTY_PREFIX,
+};
+
+/// The key prefix and root hash of an omitted subtree.
+pub type Stub<H> = (Vec<u8>, <H as Hasher>::Out);
+
+/// The bincode layout of a `StubbedProof`: its nodes and its stubs with raw hashes.
+type EncodedStubbedProof = (Vec<Vec<u8>>, Vec<(Vec<u8>, Vec<u8>)>);
+
+/// A subtree export with deliberately omitted subtrees replaced by stubs.
+///
+/// Created by `TreeDB::prove_subtree_with_stubs`.  A stub is the key prefix and root hash of an
+/// omitted subtree.  The hash is committed to by the parent node in the proof, so the importer
+/// can record the subtree as unexpanded and expand it later from a proof of it, see
+/// `PartialTree`.
+#[derive(Debug)]
+pub struct StubbedProof<H: Hasher> {
+    proof: StorageProof,
+    stubs: Vec<Stub<H>>,
+}
+
+impl<H: Hasher> StubbedProof<H> {
+    pub(crate) fn new(proof: StorageProof, stubs: Vec<Stub<H>>) -> Self {
+        Self { proof, stubs }
+    }
+
+    /// Return the exported nodes.
+    pub fn proof(&self) -> &StorageProof {
+        &self.proof
+    }
+
+    /// Return the key prefix and root hash of every omitted subtree, in key order.
+    pub fn stubs(&self) -> &[Stub<H>] {
+        &self.stubs
+    }
+
+    pub fn into_parts(self) -> (StorageProof, Vec<Stub<H>>) {
+        (self.proof, self.stubs)
+    }
+
+    /// Encode the nodes and stubs with bincode.
+    pub fn encode(&self) -> Vec<u8> {
+        let nodes: Vec<&Vec<u8>> = self.proof.nodes().collect();
+        let stubs: Vec<(&[u8], &[u8])> = self
+            .stubs
+            .iter()
+            .map(|(prefix, hash)| (prefix.as_slice(), hash.as_ref()))
+            .collect();
+        bincode::serialize(&(nodes, stubs)).expect("serializing to a vec cannot fail")
+    }
+
+    /// Decode an export produced by `encode()`.
+    pub fn decode(data: &[u8]) -> Result<Self, TreeError> {
+        let (nodes, stubs): EncodedStubbedProof =
+            bincode::deserialize(data).map_err(|_| TreeError::ProofDecodeFailed)?;
+        let stubs = stubs
+            .into_iter()
+            .map(|(prefix, hash)| Ok((prefix, decode_hash::<H>(&hash)?)))
+            .collect::<Result<_, TreeError>>()?;
+        Ok(Self::new(StorageProof::new(nodes), stubs))
+    }
+
+    /// Verify the export against `root` and import it as a tree of the given depth.  Fails like
+    /// `VerifiedProofDb::new`, and with `TreeError::UnlinkedProofNode` if a stub is not the node
+    /// at its prefix.
+    pub fn import(self, root: H::Out, depth: usize) -> Result<PartialTree<H>, TreeError> {
+        let db = VerifiedProofDb::new(self.proof, root)?;
+        let mut stubs = BTreeMap::new();
+        for (prefix, hash) in self.stubs {
+            if prefix.len() > depth || node_at(&db, &prefix)? != hash {
+                return Err(TreeError::UnlinkedProofNode);
+            }
+            stubs.insert(prefix, hash);
+        }
+
+        Ok(PartialTree {
+            db,
+            depth,
+            stubs,
+            null_hashes: Arc::default(),
+        })
+    }
+}
+
+/// Return the hash of the node at `prefix`, reading the path from `db`.
+fn node_at<H: Hasher>(db: &VerifiedProofDb<H>, prefix: &[u8]) -> Result<H::Out, TreeError> {
+    let mut hash = *db.root();
+    for (level, &bit) in prefix.iter().enumerate() {
+        let data = db
+            .get(&hash, EMPTY_PREFIX)
+            .ok_or_else(|| TreeError::missing_node::<H>(&hash, level))?;
+        hash = *Node::<H>::decode(&data)?.get_child(bit)?.get_hash();
+    }
+    Ok(hash)
+}
+
+/// A tree imported from a `StubbedProof`, whose stubbed subtrees are unexpanded.
+///
+/// Reads under an unexpanded subtree fail with `TreeError::DataNotFound`; `unexpanded_at`
+/// tells such reads apart from reads outside the export, and `expand` adds the subtree once it
+/// has been fetched.
+pub struct PartialTree<H: Hasher> {
+    db: VerifiedProofDb<H>,
+    depth: usize,
+    stubs: BTreeMap<Vec<u8>, H::Out>,
+    null_hashes: Arc<NullHashes<H>>,
+}
+
+impl<H: Hasher> PartialTree<H> {
+    /// Return the key prefix and root hash of every unexpanded subtree, in key order.
+    pub fn unexpanded(&self) -> impl Iterator<Item = (&Vec<u8>, &H::Out)> {
+        self.stubs.iter()
+    }
+
+    /// Return the prefix of the unexpanded subtree holding `key`, if any.
+    pub fn unexpanded_at(&self, key: &[u8]) -> Option<&[u8]> {
+        (0..=key.len().min(self.depth))
+            .find_map(|len| self.stubs.get_key_value(&key[..len]))
+            .map(|(prefix, _)| prefix.as_slice())
+    }
+
+    /// Add the nodes of `proof`, e.g. from `TreeDB::prove_subtree` at a stub prefix, and mark
+    /// the subtrees it completes as expanded.  Fails like `VerifiedProofDb::new` if the proof
+    /// does not verify against the root of the tree.  Returns the number of subtrees expanded.
+    pub fn expand(&mut self, proof: StorageProof) -> Result<usize, TreeError> {
+        let proof = VerifiedProofDb::new(proof, *self.db.root())?;
+        self.db.extend(proof);
+
+        let stubs = self.stubs.len();
+        let db = &self.db;
+        self.stubs
+            .retain(|_, hash| !db.contains(hash, EMPTY_PREFIX));
+        Ok(stubs - self.stubs.len())
+    }
+
+    /// Return a `TreeDB` over the imported nodes.
+    pub fn tree(&self) -> TreeDB<'_, H> {
+        TreeDBBuilder::new(&self.db, self.db.root(), self.depth)
+            .with_null_hashes(self.null_hashes.clone())
+            .build()
+    }
+
+    /// Return the verified database backing the tree.
+    pub fn db(&self) -> &VerifiedProofDb<H> {
+        &self.db
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    compression, rstd::Vec, DBValue, Hasher, Node, NodeHash, Tree, TreeDB, TreeDBMut, TreeError,
+    TreeMut, Value, EMPTY_PREFIX,
+};
+
+/// Graft the subtree of `src_tree` at `src_prefix` into `dst_tree` at `dst_prefix`.
+///
+/// Nodes of the source subtree missing from the destination database are copied, so the trees
+/// may use different databases.  Copied values are decompressed with the value compression of
+/// `src_tree` and stored with that of `dst_tree`.  Both subtrees must have the same height.
+/// Returns the root hash of the grafted subtree.
+///
+/// The graft replaces whole subtrees rather than writing keys, so it fails with
+/// `TreeError::HooksAttached` if a write-ahead log, an audit log or a watcher is attached to
+/// `dst_tree`.
+pub fn copy_subtree<H: Hasher>(
+    src_tree: &TreeDB<H>,
+    src_prefix: &[u8],
+    dst_tree: &mut TreeDBMut<H>,
+    dst_prefix: &[u8],
+) -> Result<H::Out, TreeError> {
+    let depth = src_tree.depth();
+    if src_prefix.len() > depth
+        || dst_prefix.len() > dst_tree.depth()
+        || depth - src_prefix.len() != dst_tree.depth() - dst_prefix.len()
+    {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+    if dst_tree.has_hooks() {
+        return Err(TreeError::HooksAttached);
+    }
+
+    let subtree_root = src_tree.get(src_prefix)?.hash();
+    copy_nodes(src_tree, subtree_root, src_prefix.len(), dst_tree)?;
+
+    dst_tree.replace_subtree(dst_prefix, NodeHash::Hash(subtree_root))?;
+    Ok(subtree_root)
+}
+
+/// Copy the nodes of the subtree at `root` missing from the database of `dst_tree`.
+fn copy_nodes<H: Hasher>(
+    src_tree: &TreeDB<H>,
+    root: H::Out,
+    level: usize,
+    dst_tree: &mut TreeDBMut<H>,
+) -> Result<(), TreeError> {
+    let depth = src_tree.depth();
+    let mut stack = Vec::from([(root, level)]);
+
+    while let Some((hash, level)) = stack.pop() {
+        if dst_tree.db().contains(&hash, EMPTY_PREFIX) {
+            continue;
+        }
+
+        let data = match src_tree.db().get(&hash, EMPTY_PREFIX) {
+            Some(data) => data,
+            None if hash == src_tree.null_hash(level) => continue,
+            None => return Err(TreeError::missing_node::<H>(&hash, level)),
+        };
+
+        let node = Node::<H>::decode(&compression::decode_stored(src_tree.compression(), data)?)?;
+        if level < depth {
+            if let Node::Inner(left, right) = &node {
+                stack.push((*left.get_hash(), level + 1));
+                stack.push((*right.get_hash(), level + 1));
+            }
+        }
+
+        let data = compression::encode_stored(dst_tree.compression(), &node);
+        dst_tree.db_mut().emplace(hash, EMPTY_PREFIX, data);
+    }
+
+    Ok(())
+}
+
+/// Merge the key set of `tree_b` into `tree_a`.
+///
+/// Only subtrees whose hashes differ are traversed.  Keys populated in only one of the trees
+/// keep their value, subtrees only populated in `tree_b` are copied into the database of
+/// `tree_a`, and for keys populated in both trees with different values `resolve` is called
+/// with the key, the value in `tree_a` and the value in `tree_b`.  Returns the uncommitted root
+/// of `tree_a`.
+///
+/// Like `copy_subtree`, the merge relinks whole subtrees and fails with
+/// `TreeError::HooksAttached` if a write-ahead log, an audit log or a watcher is attached to
+/// `tree_a`.
+pub fn merge<H: Hasher, F: Fn(&[u8], DBValue, DBValue) -> DBValue>(
+    tree_a: &mut TreeDBMut<H>,
+    tree_b: &TreeDB<H>,
+    resolve: F,
+) -> Result<H::Out, TreeError> {
+    if tree_a.depth() != tree_b.depth() {
+        return Err(TreeError::IndexOutOfBounds);
+    }
+    if tree_a.has_hooks() {
+        return Err(TreeError::HooksAttached);
+    }
+
+    let root_a = tree_a.root_handle().clone();
+    let root = merge_at(
+        tree_a,
+        tree_b,
+        root_a,
+        *tree_b.root(),
+        &mut Vec::new(),
+        &resolve,
+    )?;
+    let root_hash = *root.get_hash();
+    tree_a.replace_subtree(&[], root)?;
+    Ok(root_hash)
+}
+
+fn merge_at<H: Hasher, F: Fn(&[u8], DBValue, DBValue) -> DBValue>(
+    tree_a: &mut TreeDBMut<H>,
+    tree_b: &TreeDB<H>,
+    handle_a: NodeHash<H>,
+    hash_b: H::Out,
+    path: &mut Vec<u8>,
+    resolve: &F,
+) -> Result<NodeHash<H>, TreeError> {
+    let level = path.len();
+    let hash_a = *handle_a.get_hash();
+    let null_hash = tree_b.null_hash(level);
+
+    if hash_a == hash_b || hash_b == null_hash {
+        return Ok(handle_a);
+    }
+
+    if hash_a == null_hash {
+        copy_nodes(tree_b, hash_b, level, tree_a)?;
+        return Ok(NodeHash::Hash(hash_b));
+    }
+
+    let node_a = tree_a.lookup(&hash_a, level)?;
+    let node_b = tree_b.lookup(&hash_b, level)?;
+
+    if level == tree_b.depth() {
+        let value_a = node_a.get_value()?.get().clone();
+        let value_b = node_b.get_value()?.get().clone();
+        let value = resolve(path, value_a, value_b);
+        return Ok(tree_a.store(Node::Value(Value::New(value))));
+    }
+
+    let mut children = Vec::with_capacity(2);
+    for bit in [false, true] {
+        path.push(bit as u8);
+        children.push(merge_at(
+            tree_a,
+            tree_b,
+            node_a.get_child_bool(bit)?.clone(),
+            *node_b.get_child_bool(bit)?.get_hash(),
+            path,
+            resolve,
+        )?);
+        path.pop();
+    }
+
+    let right = children.pop().expect("two children");
+    let left = children.pop().expect("two children");
+    Ok(tree_a.store(Node::Inner(left, right)))
+}
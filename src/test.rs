@@ -1,13 +1,35 @@
+#[cfg(feature = "snappy")]
+use crate::Snappy;
+#[cfg(feature = "zstd")]
+use crate::Zstd;
 use crate::{
-    compute_null_hashes, DBValue, Hasher, Key, Node, NodeHash, Recorder, Tree, TreeDBBuilder,
-    TreeDBMutBuilder, TreeMut, Value, EMPTY_PREFIX,
+    apply_diff, assert_key_fits, blind_value, commutative_root, compute_null_hashes, conformance,
+    copy_subtree, evm_multiproof, execute_against, execute_against_with_null_hashes,
+    felt_from_bytes, generalized_index, generate_tree, helper_indices, merge, migrate_indexed_tree,
+    null_hash_at_depth, null_hashes_from_table, null_root, record_access_list, repair, replay_wal,
+    shared_null_hashes, split_blinding, split_expiry, ssz_multiproof, stream_diff, testvectors,
+    verify_batch_transition, verify_with_siblings, AccessMeter, Attestation, BackgroundDb,
+    BuildFoldHasher, CommitStats, CommitSummary, CompleteTreeBuilder, ConditionalWrite, Cursor,
+    DBValue, EpochDB, Felt, FeltHash, FeltHasher, FlatTree, Forest, Frontier, Hasher, IndexedTree,
+    IntegrityProblem, Key, KeyRange, LightTree, Mutation, MutationLog, NamespacedTree, Node,
+    NodeCache, NodeFetcher, NodeHash, NodeView, NullHashLadder, NullHashTable, NullHashes,
+    Prefetcher, Proof, ProofCache, ProofCodec, ProofHeader, Recorder, RepairSource, RootSigner,
+    RootVerifier, Sessions, ShardedTreeDBMut, SparseProof, StorageProof, StubbedProof, TeeRecorder,
+    Tree, TreeDB, TreeDBBuilder, TreeDBMut, TreeDBMutBuilder, TreeError, TreeMetadata, TreeMut,
+    TreeRecorder, Uncompressed, Value, ValueCompression, VerifiedProofDb, VersionedRoots,
+    WitnessBundle, BLINDING_SALT_LEN, EMPTY_PREFIX, MAX_FLAT_DEPTH, MAX_HISTOGRAM_DEPTH,
+    MAX_KEY_DEPTH, NODE_CODEC_VERSION, PROOF_FORMAT_VERSION, PROOF_HEADER_LEN, STARK_PRIME,
 };
 
-use std::marker::PhantomData;
-use std::slice;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashSet},
+    marker::PhantomData,
+    sync::Arc,
+};
 
 use hash256_std_hasher::Hash256StdHasher;
-use hash_db::{AsHashDB, Prefix};
+use hash_db::{AsHashDB, HashDB, Prefix};
 use memory_db::{KeyFunction, MemoryDB};
 
 #[derive(Default, Debug, Clone, PartialEq)]
@@ -26,6 +48,36 @@ impl Hasher for Sha3 {
     }
 }
 
+impl NullHashTable for Sha3 {
+    const NULL_HASHES: &'static [[u8; 32]] = &[
+        [
+            0xa7, 0xff, 0xc6, 0xf8, 0xbf, 0x1e, 0xd7, 0x66, 0x51, 0xc1, 0x47, 0x56, 0xa0, 0x61,
+            0xd6, 0x62, 0xf5, 0x80, 0xff, 0x4d, 0xe4, 0x3b, 0x49, 0xfa, 0x82, 0xd8, 0x0a, 0x4b,
+            0x80, 0xf8, 0x43, 0x4a,
+        ],
+        [
+            0x63, 0x43, 0x20, 0xe1, 0x82, 0x8f, 0xfb, 0x11, 0xda, 0xc5, 0x1a, 0x7a, 0xde, 0xe6,
+            0xa7, 0x39, 0x27, 0x8f, 0xbe, 0x7f, 0x82, 0x87, 0x9d, 0x76, 0x44, 0x33, 0xfb, 0xa0,
+            0xa5, 0xf9, 0xb2, 0x5e,
+        ],
+        [
+            0xa2, 0x05, 0x70, 0x3b, 0xee, 0x0d, 0x7a, 0x9a, 0xc7, 0x78, 0x51, 0x82, 0x90, 0x5c,
+            0x75, 0xf0, 0xa2, 0x0e, 0xe1, 0x67, 0x71, 0x29, 0x88, 0xa8, 0x7d, 0xb5, 0x0f, 0x4a,
+            0xbe, 0xb8, 0xa3, 0x5b,
+        ],
+        [
+            0xb0, 0xa0, 0x1d, 0x23, 0x34, 0x2d, 0x09, 0xd8, 0xb1, 0x34, 0xe0, 0x8a, 0xad, 0xcb,
+            0xe1, 0x3d, 0x26, 0xfd, 0xc2, 0x69, 0xaf, 0xd0, 0x50, 0xa5, 0x4d, 0xef, 0x32, 0x2d,
+            0x70, 0xec, 0xe3, 0x17,
+        ],
+        [
+            0x33, 0xea, 0x94, 0xfe, 0x72, 0x18, 0x44, 0x64, 0xc0, 0xc6, 0x8e, 0x5b, 0xdb, 0xd6,
+            0x51, 0xf3, 0xcf, 0xd4, 0x12, 0x27, 0x5e, 0xde, 0x48, 0x0c, 0x18, 0x70, 0xa5, 0x7d,
+            0x38, 0x1b, 0x51, 0xbe,
+        ],
+    ];
+}
+
 pub struct NoopKey<H: Hasher>(PhantomData<H>);
 
 impl<H: Hasher> KeyFunction<H> for NoopKey<H> {
@@ -54,12 +106,16 @@ fn test_values() -> Vec<u32> {
     values
 }
 
-fn build_data() -> (
+type TestData = (
     Vec<Node<Sha3>>,
     Vec<Node<Sha3>>,
     usize,
     <Sha3 as Hasher>::Out,
-) {
+);
+
+type TestDb = MemoryDB<Sha3, NoopKey<Sha3>, Vec<u8>>;
+
+fn build_data() -> TestData {
     let depth = 3usize;
     let values: Vec<u32> = test_values();
     let values: Vec<Node<Sha3>> = values
@@ -68,18 +124,13 @@ fn build_data() -> (
         .collect();
 
     let n = values.len();
-    let mut nodes: Vec<Node<Sha3>> = Vec::with_capacity(2 * n);
-    unsafe { nodes.set_len(2 * n) }
-
-    nodes[0] = Node::Value(Value::Cached(Vec::new()));
+    let mut nodes: Vec<Node<Sha3>> = vec![Node::Value(Value::Cached(Vec::new())); 2 * n];
     nodes[n..].clone_from_slice(&values);
 
-    let leaf_pairs = unsafe { slice::from_raw_parts(nodes.as_ptr() as *const [Node<Sha3>; 2], n) };
-
     for i in (1..n).rev() {
-        let left = &leaf_pairs[i][0];
-        let right = &leaf_pairs[i][1];
-        nodes[i] = Node::Inner(NodeHash::Hash(left.hash()), NodeHash::Hash(right.hash()));
+        let left = nodes[2 * i].hash();
+        let right = nodes[2 * i + 1].hash();
+        nodes[i] = Node::Inner(NodeHash::Hash(left), NodeHash::Hash(right));
     }
 
     let root = nodes[1].hash();
@@ -87,29 +138,16 @@ fn build_data() -> (
     (values, nodes, depth, root)
 }
 
-fn build_db_mock() -> (
-    MemoryDB<Sha3, NoopKey<Sha3>, Vec<u8>>,
-    <Sha3 as Hasher>::Out,
-    usize,
-) {
-    let (values, nodes, depth, root) = build_data();
-    let mut memory_db = MemoryDB::<Sha3, NoopKey<Sha3>, Vec<u8>>::default();
-
-    for node in values.into_iter() {
-        let hash = node.hash();
-        let encoded_node: Vec<u8> = node.into();
-        memory_db
-            .as_hash_db_mut()
-            .emplace(hash, EMPTY_PREFIX, encoded_node);
-    }
-
-    for index in 1..nodes.len() {
-        let hash = nodes[index].hash();
-        let encoded_node: Vec<u8> = nodes[index].clone().into();
-        memory_db
-            .as_hash_db_mut()
-            .emplace(hash, EMPTY_PREFIX, encoded_node);
-    }
+fn build_db_mock() -> (TestDb, <Sha3 as Hasher>::Out, usize) {
+    let depth = 3;
+    let leaves = test_values()
+        .into_iter()
+        .map(|value| value.to_le_bytes().to_vec())
+        .collect();
+    let mut memory_db = TestDb::default();
+    let root = CompleteTreeBuilder::<Sha3>::new(depth, leaves)
+        .build_into(&mut memory_db)
+        .unwrap();
 
     (memory_db, root, depth)
 }
@@ -119,7 +157,7 @@ fn test_get_value() {
     let (mut memory_db, mut root, depth) = build_db_mock();
     let test_values = test_values();
 
-    let tree_db_builder = TreeDBBuilder::<Sha3>::new(&mut memory_db, &root, depth);
+    let tree_db_builder = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth);
     let tree_db = tree_db_builder.build();
     let keys: Vec<Vec<u8>> = test_keys();
     for (value, key) in test_values.iter().zip(&keys) {
@@ -143,7 +181,7 @@ fn test_get_leaf() {
     let (mut memory_db, mut root, depth) = build_db_mock();
     let test_values = test_values();
 
-    let tree_db_builder = TreeDBBuilder::<Sha3>::new(&mut memory_db, &root, depth);
+    let tree_db_builder = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth);
     let tree_db = tree_db_builder.build();
 
     let keys: Vec<Vec<u8>> = Vec::from([
@@ -174,87 +212,88 @@ fn test_get_proof() {
     let (mut memory_db, mut root, depth) = build_db_mock();
     let test_values = test_values();
 
-    let tree_db_builder = TreeDBBuilder::<Sha3>::new(&mut memory_db, &root, depth);
+    let tree_db_builder = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth);
     let tree_db = tree_db_builder.build();
     let key = [0, 1, 1];
 
-    let mut expected: Vec<(usize, DBValue)> = Vec::new();
-    expected.push((0, test_values[3].to_le_bytes().to_vec()));
-    expected.push((1, tree_db.root().as_ref().to_vec()));
-    expected.push((
-        2,
-        tree_db
-            .get(&[])
-            .unwrap()
-            .get_child(0)
-            .unwrap()
-            .get_hash()
-            .as_ref()
-            .to_vec(),
-    ));
-    expected.push((
-        3,
-        tree_db
-            .get(&[])
-            .unwrap()
-            .get_child(1)
-            .unwrap()
-            .get_hash()
-            .as_ref()
-            .to_vec(),
-    ));
-    expected.push((
-        4,
-        tree_db
-            .get(&[0])
-            .unwrap()
-            .get_child(0)
-            .unwrap()
-            .get_hash()
-            .as_ref()
-            .to_vec(),
-    ));
-    expected.push((
-        5,
-        tree_db
-            .get(&[0])
-            .unwrap()
-            .get_child(1)
-            .unwrap()
-            .get_hash()
-            .as_ref()
-            .to_vec(),
-    ));
-    expected.push((
-        10,
-        tree_db
-            .get(&[0, 1])
-            .unwrap()
-            .get_child(0)
-            .unwrap()
-            .get_hash()
-            .as_ref()
-            .to_vec(),
-    ));
-    expected.push((
-        11,
-        tree_db
-            .get(&[0, 1])
-            .unwrap()
-            .get_child(1)
-            .unwrap()
-            .get_hash()
-            .as_ref()
-            .to_vec(),
-    ));
+    let expected: Vec<(usize, DBValue)> = vec![
+        (0, test_values[3].to_le_bytes().to_vec()),
+        (1, tree_db.root().as_ref().to_vec()),
+        (
+            2,
+            tree_db
+                .get(&[])
+                .unwrap()
+                .get_child(0)
+                .unwrap()
+                .get_hash()
+                .as_ref()
+                .to_vec(),
+        ),
+        (
+            3,
+            tree_db
+                .get(&[])
+                .unwrap()
+                .get_child(1)
+                .unwrap()
+                .get_hash()
+                .as_ref()
+                .to_vec(),
+        ),
+        (
+            4,
+            tree_db
+                .get(&[0])
+                .unwrap()
+                .get_child(0)
+                .unwrap()
+                .get_hash()
+                .as_ref()
+                .to_vec(),
+        ),
+        (
+            5,
+            tree_db
+                .get(&[0])
+                .unwrap()
+                .get_child(1)
+                .unwrap()
+                .get_hash()
+                .as_ref()
+                .to_vec(),
+        ),
+        (
+            10,
+            tree_db
+                .get(&[0, 1])
+                .unwrap()
+                .get_child(0)
+                .unwrap()
+                .get_hash()
+                .as_ref()
+                .to_vec(),
+        ),
+        (
+            11,
+            tree_db
+                .get(&[0, 1])
+                .unwrap()
+                .get_child(1)
+                .unwrap()
+                .get_hash()
+                .as_ref()
+                .to_vec(),
+        ),
+    ];
 
     // let mut proof = tree_db.get_proof(&key).unwrap();
-    // proof.sort_by(|a, b| a.0.cmp(&b.0));
+    // proof.sort_by_key(|a| a.0);
     // assert_eq!(proof, expected);
 
     let tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
     let mut proof = tree_db_mut.get_proof(&key).unwrap();
-    proof.sort_by(|a, b| a.0.cmp(&b.0));
+    proof.sort_by_key(|a| a.0);
     assert_eq!(proof, expected);
 }
 
@@ -262,8 +301,7 @@ fn test_get_proof() {
 fn test_insert_tree_db_mut() {
     let (mut memory_db, mut root, depth) = build_db_mock();
     let test_values = test_values();
-    let mut tree_db_mut =
-        TreeDBMutBuilder::new(&mut memory_db, &mut root, depth.try_into().unwrap()).build();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
 
     let key = Vec::from([0, 0, 0]);
     let new_value = 67u32;
@@ -273,7 +311,7 @@ fn test_insert_tree_db_mut() {
         .unwrap();
     assert_eq!(old_value, test_values[0].to_le_bytes().to_vec());
 
-    let expected_leaf = Sha3::hash(&new_value.to_le_bytes().to_vec());
+    let expected_leaf = Sha3::hash(&new_value.to_le_bytes());
     assert_eq!(tree_db_mut.get_leaf(&key).unwrap(), expected_leaf);
 
     let expected_value = new_value.to_le_bytes();
@@ -341,18 +379,19 @@ fn test_commit_tree_db_mut() {
 #[test]
 fn test_recorder() {
     let mut recorder = Recorder::new();
-    let (mut memory_db, root, depth) = build_db_mock();
+    let (memory_db, root, depth) = build_db_mock();
     let tree_db_builder =
-        TreeDBBuilder::<Sha3>::new(&mut memory_db, &root, depth).with_recorder(&mut recorder);
+        TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).with_recorder(&mut recorder);
     let tree_db = tree_db_builder.build();
 
     let expected_value = tree_db.get_value(&[0, 0, 0]).unwrap();
     let expected_leaf = tree_db.get_leaf(&[0, 1, 0]).unwrap();
     let expected_proof = tree_db.get_proof(&[0, 1, 1]).unwrap();
 
-    let storage_proof = recorder.drain_storage_proof();
-    let proof_db: MemoryDB<Sha3, _, Vec<u8>> = storage_proof.into_memory_db();
-    let proof_tree = TreeDBBuilder::<Sha3>::new(&proof_db, &root, depth).build();
+    let proof_tree = recorder
+        .drain_storage_proof()
+        .into_tree::<Sha3>(root, depth)
+        .unwrap();
 
     let value = proof_tree.get_value(&[0, 0, 0]).unwrap();
     let leaf = proof_tree.get_leaf(&[0, 1, 0]).unwrap();
@@ -361,63 +400,4446 @@ fn test_recorder() {
     assert_eq!(value, expected_value);
     assert_eq!(leaf, expected_leaf);
     assert_eq!(proof, expected_proof);
+    assert_eq!(proof_tree.root(), &root);
+    assert!(matches!(
+        proof_tree.get_value(&[1, 1, 1]),
+        Err(TreeError::DataNotFound { .. })
+    ));
 }
 
 #[test]
-fn test_null_hash() {
-    let null_hashes = compute_null_hashes::<Sha3>(64);
-    let leaf = Sha3::hash(&[]);
-    let concatenated = [leaf.as_ref(), leaf.as_ref()].concat();
-    let layer_2 = Sha3::hash(&concatenated);
-    let layer_3 = Sha3::hash(&[layer_2.as_ref(), layer_2.as_ref()].concat());
-    assert_eq!(null_hashes[64], leaf);
-    assert_eq!(null_hashes[63], layer_2);
-    assert_eq!(null_hashes[62], layer_3);
+fn test_tee_recorder() {
+    let mut recorder = Recorder::new();
+    let mut boxed_recorder: Box<Recorder<Sha3>> = Box::default();
+    let (memory_db, root, depth) = build_db_mock();
+    {
+        let mut tee_recorder = TeeRecorder::new()
+            .with_recorder(&mut recorder)
+            .with_recorder(&mut boxed_recorder);
+        let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+            .with_recorder(&mut tee_recorder)
+            .build();
+        tree_db.get_value(&[0, 1, 1]).unwrap();
+    }
+
+    let storage_proof = recorder.drain_storage_proof();
+    assert_eq!(storage_proof, boxed_recorder.drain_storage_proof());
+
+    let proof_db: MemoryDB<Sha3, _, Vec<u8>> = storage_proof.into_memory_db();
+    let proof_tree = TreeDBBuilder::<Sha3>::new(&proof_db, &root, depth).build();
+    assert_eq!(
+        proof_tree.get_value(&[0, 1, 1]).unwrap(),
+        test_values()[3].to_le_bytes().to_vec()
+    );
 }
 
 #[test]
-fn test_key_iter() {
-    let hash: [u8; 2] = [17, 24];
-    // 00010001 00011000
-    let expected = [
-        false, false, false, true, false, false, false, true, false, false, false, true, true,
-        false, false, false,
-    ];
-    let key = Key::new(hash);
-    for (bit, expected) in key.iter().zip(expected.iter()) {
-        assert_eq!(&bit, expected);
+fn test_proof_cache() {
+    let (mut memory_db, root, depth) = build_db_mock();
+    let mut cache = ProofCache::<Sha3>::new();
+    let key = [0, 1, 1];
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let proof = cache.get_proof(&tree_db, &key).unwrap();
+    assert_eq!(proof, tree_db.get_proof(&key).unwrap());
+    assert_eq!(cache.get_proof(&tree_db, &key).unwrap(), proof);
+    assert_eq!(cache.len(), 1);
+
+    let mut new_root = root;
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut new_root, depth).build();
+    tree_db_mut
+        .insert(&key, 67u32.to_le_bytes().to_vec())
+        .unwrap();
+    tree_db_mut.commit();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &new_root, depth).build();
+    let new_proof = cache.get_proof(&tree_db, &key).unwrap();
+    assert_ne!(new_proof, proof);
+    assert_eq!(cache.root(), Some(&new_root));
+    assert_eq!(cache.len(), 1);
+}
+
+struct MacSigner([u8; 32]);
+
+impl RootSigner for MacSigner {
+    type Signature = [u8; 32];
+
+    fn sign(&self, message: &[u8]) -> Self::Signature {
+        Sha3::hash(&[&self.0[..], message].concat())
+    }
+}
+
+impl RootVerifier for MacSigner {
+    type Signature = [u8; 32];
+
+    fn verify(&self, message: &[u8], signature: &Self::Signature) -> bool {
+        &self.sign(message) == signature
     }
 }
 
 #[test]
-fn test_empty_sparse_merkle() {
-    let depth = 4;
-    let null_hashes = compute_null_hashes::<Sha3>(depth);
-    let root = null_hashes[0];
-    let memory_db = MemoryDB::<Sha3, NoopKey<Sha3>, Vec<u8>>::default();
+fn test_attestation() {
+    let (_, root, _) = build_db_mock();
+    let signer = MacSigner([7u8; 32]);
+    let mut attestation = Attestation::<Sha3, _>::sign(&signer, root, 1, b"block".to_vec());
+    assert!(attestation.verify(&signer));
+    assert!(!attestation.verify(&MacSigner([8u8; 32])));
+
+    attestation.statement.version = 2;
+    assert!(!attestation.verify(&signer));
+}
+
+#[test]
+fn test_audit_log() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut audit_log = MutationLog::new();
+    let old_root = root;
+    let new_value = 67u32.to_le_bytes().to_vec();
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+            .with_audit_log(&mut audit_log)
+            .build();
+        tree_db_mut.insert(&[0, 1, 1], new_value.clone()).unwrap();
+        tree_db_mut.commit();
+    }
+    assert_ne!(root, old_root);
+
+    let mutations = audit_log.drain();
+    assert_eq!(mutations.len(), 2);
+    match &mutations[0] {
+        Mutation::Insert {
+            key,
+            old_value_hash,
+            new_value_hash,
+            root: insert_root,
+        } => {
+            assert_eq!(key, &vec![0, 1, 1]);
+            assert_eq!(old_value_hash, &Sha3::hash(&test_values()[3].to_le_bytes()));
+            assert_eq!(new_value_hash, &Sha3::hash(&new_value));
+            assert_eq!(insert_root, &root);
+        }
+        _ => panic!("expected insert mutation"),
+    }
+    assert!(matches!(mutations[1], Mutation::Commit { root: commit_root } if commit_root == root));
+}
+
+#[test]
+fn test_watch() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut watched = Vec::new();
+    let mut callback = |key: &[u8]| watched.push(key.to_vec());
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+        tree_db_mut.watch(&[0, 1], &mut callback);
+        tree_db_mut.insert(&[0, 1, 1], vec![1]).unwrap();
+        tree_db_mut.insert(&[1, 1, 1], vec![2]).unwrap();
+        tree_db_mut.insert(&[0, 1, 0], vec![3]).unwrap();
+        tree_db_mut.commit();
+    }
+    assert_eq!(watched, vec![vec![0, 1, 0], vec![0, 1, 1]]);
+}
+
+#[test]
+fn test_sharded_tree_db_mut() {
+    let updates: Vec<(Vec<u8>, DBValue)> = vec![
+        (vec![0, 0, 1], vec![1]),
+        (vec![0, 1, 0], vec![2]),
+        (vec![1, 1, 1], vec![3]),
+    ];
+
+    let (mut expected_db, mut expected_root, depth) = build_db_mock();
+    let mut tree_db_mut =
+        TreeDBMutBuilder::new(&mut expected_db, &mut expected_root, depth).build();
+    for (key, value) in updates.iter() {
+        tree_db_mut.insert(key, value.clone()).unwrap();
+    }
+    tree_db_mut.commit();
+
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut sharded = ShardedTreeDBMut::new(&mut memory_db, &mut root, depth, 1);
+    let changes: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = sharded
+            .shards()
+            .unwrap()
+            .into_iter()
+            .map(|mut shard| {
+                let updates = &updates;
+                scope.spawn(move || {
+                    let prefix = shard.prefix().to_vec();
+                    let mut tree = shard.tree_mut();
+                    for (key, value) in updates.iter().filter(|(key, _)| key.starts_with(&prefix)) {
+                        tree.insert(&key[prefix.len()..], value.clone()).unwrap();
+                    }
+                    tree.commit();
+                    drop(tree);
+                    shard.into_changes()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    sharded.commit(changes).unwrap();
+    assert_eq!(root, expected_root);
+
     let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
-    let test_value = tree_db.get_value(&[0, 0, 0, 0]).unwrap();
-    let test_leaf = tree_db.get_leaf(&[0, 0, 0, 0]).unwrap();
-    let _test_proof = tree_db.get_proof(&[0, 0, 0, 0]).unwrap();
-    assert_eq!(test_value, []);
-    assert_eq!(test_leaf, Sha3::hash(&[]));
+    for (key, value) in updates.iter() {
+        assert_eq!(&tree_db.get_value(key).unwrap(), value);
+    }
 }
 
 #[test]
-fn test_sparse_merkle_tree() {
+fn test_sharded_commit_sparse() {
     let depth = 3;
-    let null_hashes = compute_null_hashes::<Sha3>(depth);
-    let mut root = null_hashes[0];
-    let mut memory_db = MemoryDB::<Sha3, NoopKey<Sha3>, Vec<u8>>::default();
-    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
-    let values = test_values();
-    let keys = test_keys();
-    for (key, value) in keys.iter().zip(values.iter()) {
-        tree_db_mut.insert(key, value.to_le_bytes().to_vec()).unwrap();
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut expected_db = TestDb::default();
+    let mut expected_root = root;
+    let mut tree_db_mut =
+        TreeDBMutBuilder::new(&mut expected_db, &mut expected_root, depth).build();
+    tree_db_mut.insert(&[0, 0, 1], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let sharded = ShardedTreeDBMut::new(&mut memory_db, &mut root, depth, 2);
+    let mut shard = sharded.shards().unwrap().into_iter().next().unwrap();
+    let mut tree = shard.tree_mut();
+    tree.insert(&[1], vec![1]).unwrap();
+    tree.commit();
+    drop(tree);
+    let changes = shard.into_changes();
+
+    // shards of a tree split into more shards are rejected before anything is written
+    let (mut other_db, mut other_root, _) = build_db_mock();
+    let other = ShardedTreeDBMut::new(&mut other_db, &mut other_root, depth, 2);
+    let foreign = other.shards().unwrap().pop().unwrap().into_changes();
+    let mut narrow = ShardedTreeDBMut::new(&mut memory_db, &mut root, depth, 1);
+    assert!(matches!(
+        narrow.commit([foreign]),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+    assert!(memory_db.keys().is_empty());
+
+    // empty subtrees above the shards are not stored
+    let mut sharded = ShardedTreeDBMut::new(&mut memory_db, &mut root, depth, 2);
+    sharded.commit([changes]).unwrap();
+    assert_eq!(root, expected_root);
+    assert_eq!(memory_db.keys().len(), expected_db.keys().len());
+}
+
+#[test]
+fn test_versioned_roots_snapshot() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let versions = VersionedRoots::<Sha3>::new(root);
+    let key = [0, 1, 1];
+    let old_value = test_values()[3].to_le_bytes().to_vec();
+
+    let snapshot = versions.snapshot();
+    assert_eq!(snapshot.version(), 0);
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+        tree_db_mut.insert(&key, vec![1]).unwrap();
+        assert_eq!(versions.commit(&mut tree_db_mut), 1);
     }
-    let root = tree_db_mut.root();
-    let (_, _, _, expected_root) = build_data();
-    assert_eq!(root, &expected_root); 
+    assert_eq!(versions.oldest_pinned(), Some(0));
+
+    let tree_db = snapshot.tree(&memory_db, depth);
+    assert_eq!(tree_db.get_value(&key).unwrap(), old_value);
+
+    let latest = versions.snapshot();
+    assert_eq!(latest.root(), &root);
+    assert_eq!(
+        latest.tree(&memory_db, depth).get_value(&key).unwrap(),
+        vec![1]
+    );
+
+    drop(tree_db);
+    drop(snapshot);
+    versions.prune_before(1);
+    assert_eq!(versions.oldest_pinned(), Some(1));
+    assert_eq!(versions.root_at(0), None);
+}
+
+#[test]
+fn test_wal_replay() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut wal: Vec<u8> = Vec::new();
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+            .with_wal(&mut wal)
+            .build();
+        tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+        tree_db_mut.commit();
+        tree_db_mut.insert(&[0, 1, 1], vec![2]).unwrap();
+        tree_db_mut.insert(&[1, 0, 1], vec![3]).unwrap();
+    }
+
+    // simulate a crash during the last append
+    wal.truncate(wal.len() - 1);
+
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    assert_eq!(
+        replay_wal(&mut wal.as_slice(), &mut tree_db_mut).unwrap(),
+        1
+    );
+    assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![1]);
+    assert_eq!(tree_db_mut.get_value(&[0, 1, 1]).unwrap(), vec![2]);
+    assert_eq!(
+        tree_db_mut.get_value(&[1, 0, 1]).unwrap(),
+        test_values()[5].to_le_bytes().to_vec()
+    );
+
+    // the inserts do not follow a tree at another root
+    let (mut other_db, mut other_root, _) = build_db_mock();
+    let mut other_tree = TreeDBMutBuilder::new(&mut other_db, &mut other_root, depth).build();
+    assert!(matches!(
+        replay_wal(&mut wal.as_slice(), &mut other_tree),
+        Err(TreeError::WalRootMismatch)
+    ));
+}
+
+#[test]
+fn test_wal_replay_default_leaf() {
+    let depth = 3;
+    let null_hashes = Arc::new(NullHashes::<Sha3>::with_default_leaf(&[9]));
+    let mut memory_db = TestDb::default();
+    let mut root = null_hashes.at_height(depth);
+    let mut wal: Vec<u8> = Vec::new();
+    let expected_root;
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+            .with_null_hashes(null_hashes.clone())
+            .with_wal(&mut wal)
+            .build();
+        for key in [[0, 0, 0], [0, 1, 1], [1, 0, 1]] {
+            tree_db_mut.insert(&key, vec![1]).unwrap();
+        }
+        tree_db_mut.commit();
+        tree_db_mut.remove_batch(&[&[0, 0, 0]]).unwrap();
+        tree_db_mut.move_value(&[0, 1, 1], &[1, 1, 1]).unwrap();
+        expected_root = tree_db_mut.peek_root();
+    }
+
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_null_hashes(null_hashes)
+        .build();
+    assert_eq!(
+        replay_wal(&mut wal.as_slice(), &mut tree_db_mut).unwrap(),
+        3
+    );
+    assert_eq!(tree_db_mut.peek_root(), expected_root);
+}
+
+/// A write-ahead log whose writes fail while `fail` is set.
+struct FlakyWal<'a> {
+    data: Vec<u8>,
+    fail: &'a Cell<bool>,
+}
+
+impl std::io::Write for FlakyWal<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.fail.get() {
+            return Err(std::io::ErrorKind::Other.into());
+        }
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_wal_commit_record_failure() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let fail = Cell::new(false);
+    let mut wal = FlakyWal {
+        data: Vec::new(),
+        fail: &fail,
+    };
+    let committed_root;
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+            .with_wal(&mut wal)
+            .build();
+        tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+        fail.set(true);
+        assert!(matches!(
+            tree_db_mut.try_commit(),
+            Err(TreeError::WalFailed)
+        ));
+        committed_root = tree_db_mut.peek_root();
+
+        // no insert is logged before the missing commit record
+        assert!(matches!(
+            tree_db_mut.insert(&[0, 1, 1], vec![2]),
+            Err(TreeError::WalFailed)
+        ));
+        fail.set(false);
+        tree_db_mut.insert(&[0, 1, 1], vec![2]).unwrap();
+    }
+    assert_eq!(root, committed_root);
+
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    assert_eq!(
+        replay_wal(&mut wal.data.as_slice(), &mut tree_db_mut).unwrap(),
+        1
+    );
+    assert_eq!(tree_db_mut.get_value(&[0, 1, 1]).unwrap(), vec![2]);
+}
+
+#[test]
+fn test_hash_verification() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let leaf = Sha3::hash(&test_values()[0].to_le_bytes());
+    memory_db.as_hash_db_mut().remove(&leaf, EMPTY_PREFIX);
+    memory_db
+        .as_hash_db_mut()
+        .emplace(leaf, EMPTY_PREFIX, vec![0, 1, 2, 3]);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&[0, 0, 0]).unwrap(), vec![1, 2, 3]);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_hash_verification()
+        .build();
+    assert!(matches!(
+        tree_db.get_value(&[0, 0, 0]),
+        Err(TreeError::HashMismatch)
+    ));
+    assert!(tree_db.get_value(&[0, 0, 1]).is_ok());
+
+    let tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_hash_verification()
+        .build();
+    assert!(matches!(
+        tree_db_mut.get_value(&[0, 0, 0]),
+        Err(TreeError::HashMismatch)
+    ));
+}
+
+#[test]
+fn test_verify_integrity() {
+    let (mut memory_db, root, depth) = build_db_mock();
+    let report = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .build()
+        .verify_integrity();
+    assert!(report.is_ok());
+    // two leaves share the same value and are only checked once
+    assert_eq!(report.nodes_checked, 14);
+
+    let missing = Sha3::hash(&test_values()[1].to_le_bytes());
+    memory_db.as_hash_db_mut().remove(&missing, EMPTY_PREFIX);
+    let corrupt = Sha3::hash(&test_values()[6].to_le_bytes());
+    memory_db.as_hash_db_mut().remove(&corrupt, EMPTY_PREFIX);
+    memory_db
+        .as_hash_db_mut()
+        .emplace(corrupt, EMPTY_PREFIX, vec![0, 1]);
+
+    let report = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .build()
+        .verify_integrity();
+    assert_eq!(report.problems.len(), 2);
+    assert!(matches!(
+        &report.problems[0],
+        IntegrityProblem::MissingNode { path, hash } if path == &vec![0, 0, 1] && hash == &missing
+    ));
+    assert!(matches!(
+        &report.problems[1],
+        IntegrityProblem::HashMismatch { path, hash } if path == &vec![1, 1, 0] && hash == &corrupt
+    ));
 }
 
+#[test]
+fn test_repair() {
+    let (mut memory_db, root, depth) = build_db_mock();
+    let leaves: Vec<(Vec<u8>, DBValue)> = test_keys()
+        .into_iter()
+        .zip(test_values())
+        .map(|(key, value)| (key, value.to_le_bytes().to_vec()))
+        .collect();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let inner = tree_db.get(&[0, 0]).unwrap().hash();
+    let corrupt = tree_db.get(&[1, 1]).unwrap().hash();
+
+    let mut recorder = Recorder::new();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_recorder(&mut recorder)
+        .build();
+    tree_db.get_value(&[0, 0, 0]).unwrap();
+    tree_db.get_value(&[0, 0, 1]).unwrap();
+    let peer_proof = recorder.drain_storage_proof();
+
+    memory_db.as_hash_db_mut().remove(&inner, EMPTY_PREFIX);
+    memory_db.as_hash_db_mut().remove(&corrupt, EMPTY_PREFIX);
+    memory_db
+        .as_hash_db_mut()
+        .emplace(corrupt, EMPTY_PREFIX, vec![2]);
+
+    let report = repair(
+        &mut memory_db,
+        &root,
+        depth,
+        &NullHashes::new(),
+        RepairSource::Nodes(&peer_proof),
+        None,
+    );
+    assert_eq!(report.repaired, vec![vec![0, 0]]);
+    assert_eq!(report.unrepaired.len(), 1);
+
+    let report = repair(
+        &mut memory_db,
+        &root,
+        depth,
+        &NullHashes::new(),
+        RepairSource::Leaves(&leaves),
+        None,
+    );
+    assert_eq!(report.repaired, vec![vec![1, 1]]);
+    assert!(report.unrepaired.is_empty());
 
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert!(tree_db.verify_integrity().is_ok());
+
+    // a corrupt entry referenced twice is overwritten rather than kept by removing it once
+    memory_db.as_hash_db_mut().remove(&corrupt, EMPTY_PREFIX);
+    for _ in 0..2 {
+        memory_db
+            .as_hash_db_mut()
+            .emplace(corrupt, EMPTY_PREFIX, vec![2]);
+    }
+    let report = repair(
+        &mut memory_db,
+        &root,
+        depth,
+        &NullHashes::new(),
+        RepairSource::Leaves(&leaves),
+        None,
+    );
+    assert_eq!(report.repaired, vec![vec![1, 1]]);
+    assert!(report.unrepaired.is_empty());
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert!(tree_db.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_migrate_indexed_tree() {
+    let (values, nodes, depth, root) = build_data();
+    let mut old_db = TestDb::default();
+    for node in values.iter().chain(nodes.iter().skip(1)) {
+        let encoded_node: Vec<u8> = node.clone().into();
+        old_db
+            .as_hash_db_mut()
+            .emplace(node.hash(), EMPTY_PREFIX, encoded_node[1..].to_vec());
+    }
+
+    let mut new_db = TestDb::default();
+    let new_root = migrate_indexed_tree::<Sha3>(&old_db, &root, depth, &mut new_db).unwrap();
+    assert_eq!(new_root, root);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&new_db, &new_root, depth).build();
+    assert!(tree_db.verify_integrity().is_ok());
+    for (key, value) in test_keys().iter().zip(test_values()) {
+        assert_eq!(
+            tree_db.get_value(key).unwrap(),
+            value.to_le_bytes().to_vec()
+        );
+    }
+}
+
+#[test]
+fn test_remove_batch() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+
+    let removed = tree_db_mut
+        .remove_batch(&[&[0, 0, 1], &[0, 0, 0], &[1, 1, 1], &[0, 0, 1]])
+        .unwrap();
+    assert_eq!(removed, vec![vec![0, 0, 0], vec![0, 0, 1], vec![1, 1, 1]]);
+    assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![]);
+    assert_eq!(
+        tree_db_mut.get(&[0, 0]).unwrap().hash(),
+        compute_null_hashes::<Sha3>(depth)[2]
+    );
+
+    let removed = tree_db_mut.remove_batch(&[&[0, 0, 0], &[0, 1, 0]]).unwrap();
+    assert_eq!(removed, vec![vec![0, 1, 0]]);
+
+    let keys = test_keys();
+    let removed = tree_db_mut
+        .remove_batch(&keys.iter().map(|key| key.as_slice()).collect::<Vec<_>>())
+        .unwrap();
+    assert_eq!(
+        removed,
+        vec![vec![0, 1, 1], vec![1, 0, 0], vec![1, 0, 1], vec![1, 1, 0]]
+    );
+    tree_db_mut.commit();
+    assert_eq!(root, compute_null_hashes::<Sha3>(depth)[0]);
+}
+
+#[test]
+fn test_move_and_swap() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let values = test_values();
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+        tree_db_mut.insert(&[1, 1, 0], vec![7]).unwrap();
+
+        let old_leaf = tree_db_mut.move_value(&[1, 1, 0], &[0, 0, 0]).unwrap();
+        assert_eq!(old_leaf, Sha3::hash(&values[0].to_le_bytes()));
+        assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![7]);
+        assert_eq!(tree_db_mut.get_value(&[1, 1, 0]).unwrap(), vec![]);
+
+        tree_db_mut.swap(&[0, 0, 0], &[0, 1, 1]).unwrap();
+        assert_eq!(tree_db_mut.get_value(&[0, 1, 1]).unwrap(), vec![7]);
+        assert_eq!(
+            tree_db_mut.get_value(&[0, 0, 0]).unwrap(),
+            values[3].to_le_bytes().to_vec()
+        );
+        tree_db_mut.commit();
+    }
+
+    let (mut expected_db, mut expected_root, depth) = build_db_mock();
+    let mut tree_db_mut =
+        TreeDBMutBuilder::new(&mut expected_db, &mut expected_root, depth).build();
+    tree_db_mut
+        .insert(&[0, 0, 0], values[3].to_le_bytes().to_vec())
+        .unwrap();
+    tree_db_mut.insert(&[0, 1, 1], vec![7]).unwrap();
+    tree_db_mut.insert(&[1, 1, 0], vec![]).unwrap();
+    assert_eq!(tree_db_mut.root(), &root);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&[0, 1, 1]).unwrap(), vec![7]);
+}
+
+#[test]
+fn test_copy_subtree() {
+    let (src_db, src_root, depth) = build_db_mock();
+    let src_tree = TreeDBBuilder::<Sha3>::new(&src_db, &src_root, depth).build();
+
+    let mut dst_db = TestDb::default();
+    let mut dst_root = compute_null_hashes::<Sha3>(depth)[0];
+    {
+        let mut dst_tree = TreeDBMutBuilder::new(&mut dst_db, &mut dst_root, depth).build();
+        let subtree_root = copy_subtree(&src_tree, &[0], &mut dst_tree, &[1]).unwrap();
+        assert_eq!(subtree_root, src_tree.get(&[0]).unwrap().hash());
+        dst_tree.commit();
+    }
+
+    let dst_tree = TreeDBBuilder::<Sha3>::new(&dst_db, &dst_root, depth).build();
+    assert!(dst_tree.verify_integrity().is_ok());
+    for key in test_keys().into_iter().filter(|key| key[0] == 0) {
+        let mut dst_key = key.clone();
+        dst_key[0] = 1;
+        assert_eq!(
+            dst_tree.get_value(&dst_key).unwrap(),
+            src_tree.get_value(&key).unwrap()
+        );
+        assert_eq!(dst_tree.get_value(&key).unwrap(), vec![]);
+    }
+    drop(dst_tree);
+
+    // the graft cannot be recorded in the write-ahead log
+    let mut wal: Vec<u8> = Vec::new();
+    let mut dst_tree = TreeDBMutBuilder::new(&mut dst_db, &mut dst_root, depth)
+        .with_wal(&mut wal)
+        .build();
+    assert!(matches!(
+        copy_subtree(&src_tree, &[0], &mut dst_tree, &[0]),
+        Err(TreeError::HooksAttached)
+    ));
+}
+
+#[test]
+fn test_merge() {
+    let (mut db_a, mut root_a, depth) = build_db_mock();
+    let (mut db_b, mut root_b, _) = build_db_mock();
+    {
+        let mut tree_b = TreeDBMutBuilder::new(&mut db_b, &mut root_b, depth).build();
+        tree_b.insert(&[0, 1, 1], vec![1]).unwrap();
+        tree_b.insert(&[1, 0, 0], vec![2]).unwrap();
+        tree_b.commit();
+    }
+    let tree_b = TreeDBBuilder::<Sha3>::new(&db_b, &root_b, depth).build();
+
+    let mut tree_a = TreeDBMutBuilder::new(&mut db_a, &mut root_a, depth).build();
+    tree_a.insert(&[1, 0, 0], vec![3]).unwrap();
+    tree_a.remove_batch(&[&[1, 1, 1]]).unwrap();
+    let merged_root = merge(&mut tree_a, &tree_b, |key, a, b| {
+        assert!(key == [0, 1, 1] || key == [1, 0, 0]);
+        [a, b].concat()
+    })
+    .unwrap();
+    assert_eq!(tree_a.root(), &merged_root);
+
+    let (mut expected_db, mut expected_root, _) = build_db_mock();
+    let mut expected = TreeDBMutBuilder::new(&mut expected_db, &mut expected_root, depth).build();
+    let conflict = [test_values()[3].to_le_bytes().to_vec(), vec![1]].concat();
+    expected.insert(&[0, 1, 1], conflict).unwrap();
+    expected.insert(&[1, 0, 0], vec![3, 2]).unwrap();
+    assert_eq!(expected.root(), &merged_root);
+    drop(tree_a);
+
+    let mut audit_log = MutationLog::<Sha3>::new();
+    let mut tree_a = TreeDBMutBuilder::new(&mut db_a, &mut root_a, depth)
+        .with_audit_log(&mut audit_log)
+        .build();
+    assert!(matches!(
+        merge(&mut tree_a, &tree_b, |_, a, _| a),
+        Err(TreeError::HooksAttached)
+    ));
+}
+
+#[test]
+fn test_proof_compute_root() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let key = [0, 1, 1];
+    let proof = Proof::<Sha3>::from_indexed(&key, &tree_db.get_proof(&key).unwrap()).unwrap();
+
+    let value = test_values()[3].to_le_bytes();
+    assert_eq!(proof.compute_root(&key, &value).unwrap(), root);
+
+    let (mut memory_db, mut new_root, depth) = build_db_mock();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut new_root, depth).build();
+    tree_db_mut.insert(&key, vec![1, 2]).unwrap();
+    assert_eq!(
+        &proof.compute_root(&key, &[1, 2]).unwrap(),
+        tree_db_mut.root()
+    );
+    assert!(proof.compute_root(&[0, 1], &[1, 2]).is_err());
+}
+
+#[test]
+fn test_proof_siblings() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let key = [1, 0, 1];
+    let proof = Proof::<Sha3>::from_indexed(&key, &tree_db.get_proof(&key).unwrap()).unwrap();
+
+    let siblings = proof.siblings();
+    assert_eq!(
+        siblings,
+        vec![
+            tree_db.get_leaf(&[1, 0, 0]).unwrap(),
+            tree_db.get(&[1, 1]).unwrap().hash(),
+            tree_db.get(&[0]).unwrap().hash(),
+        ]
+    );
+    assert_eq!(Proof::<Sha3>::from_siblings(siblings), proof);
+}
+
+#[test]
+fn test_prove_all() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.remove_batch(&[&[0, 1, 0]]).unwrap();
+    tree_db_mut.commit();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let proofs = tree_db.prove_all().unwrap();
+    let expected_keys: Vec<Vec<u8>> = test_keys()
+        .into_iter()
+        .filter(|key| key != &[0, 1, 0])
+        .collect();
+    assert_eq!(
+        proofs
+            .iter()
+            .map(|(key, _, _)| key.clone())
+            .collect::<Vec<_>>(),
+        expected_keys
+    );
+    for (key, value, proof) in proofs {
+        assert_eq!(value, tree_db.get_value(&key).unwrap());
+        assert_eq!(proof.compute_root(&key, &value).unwrap(), root);
+    }
+}
+
+#[test]
+fn test_forest() {
+    let mut memory_db = TestDb::default();
+    let mut forest = Forest::<Sha3>::new();
+    forest.create("a", 3).unwrap();
+    forest.create("b", 3).unwrap();
+    assert!(matches!(
+        forest.create("a", 3),
+        Err(TreeError::DuplicateTree)
+    ));
+
+    for (name, value) in [("a", vec![1]), ("b", vec![2])] {
+        let mut tree = forest.tree_mut(&mut memory_db, name).unwrap();
+        tree.insert(&[0, 0, 0], vec![7]).unwrap();
+        tree.insert(&[1, 1, 1], value).unwrap();
+        tree.commit();
+    }
+    assert_ne!(forest.root("a"), forest.root("b"));
+
+    let registry = forest.persist(&mut memory_db);
+    let mut forest = Forest::<Sha3>::open(&memory_db, &registry).unwrap();
+    assert_eq!(forest.names().collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(forest.depth("b"), Some(3));
+    assert_eq!(
+        forest
+            .tree(&memory_db, "a")
+            .unwrap()
+            .get_value(&[1, 1, 1])
+            .unwrap(),
+        vec![1]
+    );
+
+    assert!(forest.remove(&mut memory_db, "a").unwrap() > 0);
+    assert!(matches!(
+        forest.tree(&memory_db, "a"),
+        Err(TreeError::UnknownTree)
+    ));
+    assert!(!memory_db
+        .as_hash_db()
+        .contains(&Sha3::hash(&[1]), EMPTY_PREFIX));
+
+    let tree = forest.tree(&memory_db, "b").unwrap();
+    assert_eq!(tree.get_value(&[0, 0, 0]).unwrap(), vec![7]);
+    assert_eq!(tree.get_value(&[1, 1, 1]).unwrap(), vec![2]);
+}
+
+#[test]
+fn test_epoch_db() {
+    let mut memory_db = TestDb::default();
+    let mut epoch_db = EpochDB::<Sha3>::new(&mut memory_db);
+    let depth = 3;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+
+    let mut tree = TreeDBMutBuilder::new(&mut epoch_db, &mut root, depth).build();
+    tree.insert(&[0, 0, 0], vec![1]).unwrap();
+    tree.insert(&[1, 1, 1], vec![2]).unwrap();
+    tree.commit();
+    let old_root = root;
+    assert_eq!(epoch_db.end_epoch(root), 1);
+
+    let mut tree = TreeDBMutBuilder::new(&mut epoch_db, &mut root, depth).build();
+    tree.insert(&[1, 1, 1], vec![3]).unwrap();
+    tree.commit();
+    epoch_db.end_epoch(root);
+    assert_eq!(epoch_db.epoch_of(&Sha3::hash(&[1])), Some(0));
+
+    assert!(epoch_db.prune_before(1) > 0);
+    assert_eq!(epoch_db.epoch_of(&Sha3::hash(&[1])), Some(1));
+    assert_eq!(epoch_db.epoch_of(&Sha3::hash(&[2])), None);
+    let db = epoch_db.as_hash_db();
+    assert!(!db.contains(&old_root, EMPTY_PREFIX));
+    assert!(!db.contains(&Sha3::hash(&[2]), EMPTY_PREFIX));
+
+    let tree = TreeDBBuilder::<Sha3>::new(&db, &root, depth).build();
+    assert_eq!(tree.get_value(&[0, 0, 0]).unwrap(), vec![1]);
+    assert_eq!(tree.get_value(&[1, 1, 1]).unwrap(), vec![3]);
+}
+
+#[test]
+fn test_depth_from_key_type() {
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(64)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::for_key::<u64>(&mut memory_db, &mut root).build();
+    assert_eq!(tree_db_mut.depth(), 64);
+    tree_db_mut.insert(&[1; 64], vec![1]).unwrap();
+    tree_db_mut.commit();
+
+    let tree_db = TreeDBBuilder::<Sha3>::for_key::<u64>(&memory_db, &root).build();
+    assert_eq!(tree_db.get_value(&[1; 64]).unwrap(), vec![1]);
+
+    let null_root = compute_null_hashes::<Sha3>(16)[0];
+    let tree_db = TreeDBBuilder::<Sha3>::for_key::<Key<2>>(&memory_db, &null_root).build();
+    assert_eq!(tree_db.depth(), 16);
+}
+
+#[test]
+fn test_extend_depth() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    assert!(tree_db_mut.extend_depth(depth - 1).is_err());
+    tree_db_mut.extend_depth(depth + 2).unwrap();
+    assert_eq!(tree_db_mut.depth(), depth + 2);
+    tree_db_mut.insert(&[1, 0, 0, 0, 0], vec![42]).unwrap();
+    tree_db_mut.commit();
+
+    let mut expected_db = TestDb::default();
+    let mut expected_root = compute_null_hashes::<Sha3>(depth + 2)[0];
+    let mut expected =
+        TreeDBMutBuilder::new(&mut expected_db, &mut expected_root, depth + 2).build();
+    for (key, value) in test_keys().into_iter().zip(test_values()) {
+        let key = [vec![0, 0], key].concat();
+        expected.insert(&key, value.to_le_bytes().to_vec()).unwrap();
+    }
+    expected.insert(&[1, 0, 0, 0, 0], vec![42]).unwrap();
+    assert_eq!(*expected.root(), root);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth + 2).build();
+    assert_eq!(
+        tree_db.get_value(&[0, 0, 1, 1, 1]).unwrap(),
+        100u32.to_le_bytes().to_vec()
+    );
+    drop(tree_db);
+
+    // the write-ahead log cannot record the change
+    let mut wal: Vec<u8> = Vec::new();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth + 2)
+        .with_wal(&mut wal)
+        .build();
+    assert!(matches!(
+        tree_db_mut.extend_depth(depth + 3),
+        Err(TreeError::HooksAttached)
+    ));
+    assert_eq!(tree_db_mut.depth(), depth + 2);
+}
+
+#[test]
+fn test_namespaced_tree() {
+    let empty = NamespacedTree::<Sha3>::new(1);
+    assert!(empty.prove_namespace(&[1]).verify(&empty.root(), &[1], &[]));
+
+    let mut tree = NamespacedTree::<Sha3>::new(1);
+    for (namespace, data) in [(1, b"a"), (2, b"b"), (2, b"c"), (4, b"d"), (5, b"e")] {
+        tree.push(&[namespace], data.to_vec()).unwrap();
+    }
+    assert!(tree.push(&[3], vec![]).is_err());
+    assert!(tree.push(&[5, 0], vec![]).is_err());
+    let root = tree.root();
+    assert_eq!((root.min.clone(), root.max.clone()), (vec![1], vec![5]));
+
+    let proof = tree.prove_namespace(&[2]);
+    assert_eq!(proof.range(), 1..3);
+    assert!(proof.verify(&root, &[2], &[b"b".to_vec(), b"c".to_vec()]));
+    assert!(!proof.verify(&root, &[2], &[b"b".to_vec(), b"x".to_vec()]));
+    assert!(!proof.verify(&root, &[2], &[b"b".to_vec()]));
+
+    let proof = tree.prove_namespace(&[3]);
+    assert_eq!(proof.range(), 3..3);
+    assert!(proof.verify(&root, &[3], &[]));
+    assert!(!proof.verify(&root, &[4], &[]));
+
+    for namespace in [0, 1, 4, 5, 6] {
+        let proof = tree.prove_namespace(&[namespace]);
+        let leaves: Vec<DBValue> = proof.range().map(|i| [b"abcde"[i]].to_vec()).collect();
+        assert!(proof.verify(&root, &[namespace], &leaves));
+    }
+}
+
+#[test]
+fn test_get_value_proof() {
+    let depth = 4;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 0, 0, 1], vec![1]).unwrap();
+
+    let (value, proof) = tree_db_mut.get_value_proof(&[1, 0, 1, 0]).unwrap();
+    assert_eq!(value, None);
+    assert_eq!(
+        proof.compute_root(&[1, 0, 1, 0], &[]).unwrap(),
+        *tree_db_mut.root()
+    );
+    tree_db_mut.commit();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let (value, proof) = tree_db.get_value_proof(&[0, 0, 0, 1]).unwrap();
+    assert_eq!(value, Some(vec![1]));
+    assert_eq!(proof.compute_root(&[0, 0, 0, 1], &[1]).unwrap(), root);
+
+    let (value, proof) = tree_db.get_value_proof(&[0, 0, 0, 0]).unwrap();
+    assert_eq!(value, None);
+    assert_eq!(proof.compute_root(&[0, 0, 0, 0], &[]).unwrap(), root);
+    assert_ne!(proof.compute_root(&[0, 0, 0, 0], &[1]).unwrap(), root);
+}
+
+#[test]
+fn test_get_value_proof_deep_key() {
+    let depth = 70;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let keys: Vec<Vec<u8>> = [0x5a5a_5a5a_5a5a_5a5a_5a5au128, 0x3c3c_3c3c_3c3c_3c3c_3c3d]
+        .iter()
+        .map(|word| {
+            (0..depth)
+                .rev()
+                .map(|bit| (word >> bit) as u8 & 1)
+                .collect()
+        })
+        .collect();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    for (value, key) in keys.iter().enumerate() {
+        tree_db_mut.insert(key, vec![value as u8 + 1]).unwrap();
+    }
+    let (value, proof) = tree_db_mut.get_value_proof(&keys[0]).unwrap();
+    assert_eq!(value, Some(vec![1]));
+    assert_eq!(
+        proof.compute_root(&keys[0], &[1]).unwrap(),
+        tree_db_mut.peek_root()
+    );
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let (value, proof) = tree_db.get_value_proof(&keys[1]).unwrap();
+    assert_eq!(value, Some(vec![2]));
+    assert_eq!(proof.compute_root(&keys[1], &[2]).unwrap(), root);
+    assert_eq!(
+        proof.siblings(),
+        tree_db.get_leaf_proof(&keys[1]).unwrap().1.siblings()
+    );
+}
+
+#[test]
+fn test_empty_slot_witness() {
+    let depth = 4;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 0, 0, 1], vec![1]).unwrap();
+    tree_db_mut.commit();
+
+    let empty_key = [0, 1, 1, 0];
+    let mut recorder = Recorder::new();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_recorder(&mut recorder)
+        .build();
+    assert_eq!(tree_db.get_value(&empty_key).unwrap(), Vec::<u8>::new());
+    let expected_proof = tree_db.get_value_proof(&empty_key).unwrap();
+
+    let mut proof_db = recorder.drain_storage_proof().into_memory_db::<Sha3>();
+    let proof_tree = TreeDBBuilder::<Sha3>::new(&proof_db, &root, depth).build();
+    assert_eq!(proof_tree.get_value(&empty_key).unwrap(), Vec::<u8>::new());
+    assert_eq!(
+        proof_tree.get_value_proof(&empty_key).unwrap(),
+        expected_proof
+    );
+
+    let mut expected_root = root;
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut expected_root, depth).build();
+    tree_db_mut.insert(&empty_key, vec![2]).unwrap();
+    tree_db_mut.commit();
+
+    let mut witness_root = root;
+    let mut witness_tree = TreeDBMutBuilder::new(&mut proof_db, &mut witness_root, depth).build();
+    witness_tree.insert(&empty_key, vec![2]).unwrap();
+    witness_tree.commit();
+    assert_eq!(witness_root, expected_root);
+}
+
+struct BatchLog(RefCell<Vec<Vec<<Sha3 as Hasher>::Out>>>);
+
+impl Prefetcher<Sha3> for BatchLog {
+    fn prefetch(&self, hashes: &[<Sha3 as Hasher>::Out]) {
+        self.0.borrow_mut().push(hashes.to_vec());
+    }
+}
+
+#[test]
+fn test_prefetch() {
+    let (memory_db, root, depth) = build_db_mock();
+    let batches = BatchLog(RefCell::new(Vec::new()));
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_prefetcher(&batches)
+        .build();
+
+    assert_eq!(tree_db.prefetch(&[&[0, 0, 0], &[0, 0, 1]]).unwrap(), 5);
+    let batches = batches.0.borrow();
+    assert_eq!(
+        batches.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![1, 1, 1, 2]
+    );
+    assert_eq!(batches[0], vec![root]);
+    assert_eq!(
+        batches[3],
+        vec![
+            tree_db.get_leaf(&[0, 0, 0]).unwrap(),
+            tree_db.get_leaf(&[0, 0, 1]).unwrap()
+        ]
+    );
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.prefetch(&[&[0, 0, 0]]).unwrap(), 0);
+
+    // nodes missing locally are read through the fetcher
+    struct Remote<'a>(&'a TestDb);
+
+    impl NodeFetcher<Sha3> for Remote<'_> {
+        fn fetch(&self, hash: &<Sha3 as Hasher>::Out) -> Option<DBValue> {
+            HashDB::get(self.0, hash, EMPTY_PREFIX)
+        }
+    }
+
+    let local_db = TestDb::default();
+    let remote = Remote(&memory_db);
+    let batches = BatchLog(RefCell::new(Vec::new()));
+    let tree_db = TreeDBBuilder::<Sha3>::new(&local_db, &root, depth)
+        .with_node_fetcher(&remote)
+        .with_prefetcher(&batches)
+        .build();
+    assert_eq!(tree_db.prefetch(&[&[0, 0, 0], &[0, 0, 1]]).unwrap(), 5);
+}
+
+#[test]
+fn test_iter_with_proofs() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+
+    let mut iter = tree_db.iter_with_proofs();
+    let (key, value, proof) = iter.next().unwrap().unwrap();
+    assert_eq!(key, vec![0, 0, 0]);
+    assert_eq!(proof.compute_root(&key, &value).unwrap(), root);
+    assert_eq!(iter.count(), 7);
+
+    for (key, value, proof) in tree_db.iter_with_proofs().map(Result::unwrap) {
+        let expected = Proof::from_indexed(&key, &tree_db.get_proof(&key).unwrap()).unwrap();
+        assert_eq!(proof, expected);
+        assert_eq!(value, tree_db.get_value(&key).unwrap());
+    }
+}
+
+#[test]
+fn test_paginated_iteration() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+
+    let mut pages = Vec::new();
+    let mut cursor =
+        Some(Cursor::<Sha3>::decode(&[root.as_ref(), &[0, 0, 0][..]].concat()).unwrap());
+    while let Some(current) = cursor {
+        let encoded = current.encode();
+        let current = Cursor::<Sha3>::decode(&encoded).unwrap();
+        let mut iter = tree_db.resume(&current).unwrap();
+        let page: Vec<_> = iter.by_ref().take(3).map(Result::unwrap).collect();
+        cursor = iter.cursor();
+        pages.push(page);
+    }
+    assert_eq!(
+        pages.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![3, 3, 2]
+    );
+    let keys: Vec<Vec<u8>> = pages.concat().into_iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, test_keys());
+
+    let leaves: Vec<_> = tree_db
+        .iter_from(&[1, 0, 1])
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(leaves.len(), 3);
+    assert_eq!(leaves[0].0, vec![1, 0, 1]);
+
+    let stale = Cursor::<Sha3>::decode(&[[0u8; 32].as_ref(), &[0, 0, 0][..]].concat()).unwrap();
+    assert!(matches!(
+        tree_db.resume(&stale),
+        Err(TreeError::StaleCursor)
+    ));
+}
+
+/// A run-length codec standing in for a real compression library.
+struct RunLength;
+
+impl ProofCodec for RunLength {
+    fn id(&self) -> u8 {
+        0xff
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for byte in data {
+            match out.len() {
+                len if len >= 2 && out[len - 1] == *byte && out[len - 2] < u8::MAX => {
+                    out[len - 2] += 1
+                }
+                _ => out.extend_from_slice(&[1, *byte]),
+            }
+        }
+        out
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, TreeError> {
+        let mut out = Vec::new();
+        for run in data.chunks(2) {
+            if run.len() != 2 || out.len() + run[0] as usize > max_len {
+                return Err(TreeError::ProofTooLarge);
+            }
+            out.extend(std::iter::repeat_n(run[1], run[0] as usize));
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn test_proof_codec() {
+    let mut recorder = Recorder::new();
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_recorder(&mut recorder)
+        .build();
+    tree_db.get_value(&[0, 1, 1]).unwrap();
+    let proof = recorder.drain_storage_proof();
+
+    let encoded = proof.encode_with(&Uncompressed).unwrap();
+    assert_eq!(
+        StorageProof::decode_with(&encoded, &Uncompressed, 1 << 20).unwrap(),
+        proof
+    );
+
+    let compressed = proof.encode_with(&RunLength).unwrap();
+    assert_eq!(
+        StorageProof::decode_with(&compressed, &RunLength, 1 << 20).unwrap(),
+        proof
+    );
+    assert!(matches!(
+        StorageProof::decode_with(&compressed, &Uncompressed, 1 << 20),
+        Err(TreeError::ProofDecodeFailed)
+    ));
+    assert!(matches!(
+        StorageProof::decode_with(&compressed, &RunLength, 16),
+        Err(TreeError::ProofTooLarge)
+    ));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_proof_codec() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let proof = tree_db.prove_subtree(&[]).unwrap();
+
+    let compressed = proof.encode_with(&Zstd::default()).unwrap();
+    assert_eq!(
+        StorageProof::decode_with(&compressed, &Zstd::default(), 1 << 20).unwrap(),
+        proof
+    );
+    assert!(matches!(
+        StorageProof::decode_with(&compressed, &Zstd::default(), 16),
+        Err(TreeError::ProofTooLarge)
+    ));
+
+    // a header understating the length does not let the payload grow past the limit
+    let mut forged = compressed.clone();
+    forged[2..6].copy_from_slice(&16u32.to_le_bytes());
+    assert!(StorageProof::decode_with(&forged, &Zstd::default(), 1 << 20).is_err());
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy_proof_codec() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let proof = tree_db.prove_subtree(&[]).unwrap();
+
+    let compressed = proof.encode_with(&Snappy).unwrap();
+    assert_eq!(
+        StorageProof::decode_with(&compressed, &Snappy, 1 << 20).unwrap(),
+        proof
+    );
+    assert!(matches!(
+        StorageProof::decode_with(&compressed, &Snappy, 16),
+        Err(TreeError::ProofTooLarge)
+    ));
+    assert!(StorageProof::decode_with(&compressed, &Uncompressed, 1 << 20).is_err());
+
+    // a header understating the length does not let the payload grow past the limit
+    let mut forged = compressed.clone();
+    forged[2..6].copy_from_slice(&16u32.to_le_bytes());
+    assert!(matches!(
+        StorageProof::decode_with(&forged, &Snappy, 1 << 20),
+        Err(TreeError::ProofTooLarge)
+    ));
+}
+
+#[test]
+fn test_sparse_proof() {
+    let depth = 16;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    let key_a = [0u8; 16];
+    let mut key_b = [0u8; 16];
+    key_b[0] = 1;
+    tree_db_mut.insert(&key_a, vec![1]).unwrap();
+    tree_db_mut.insert(&key_b, vec![2]).unwrap();
+    tree_db_mut.commit();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let (_, proof) = tree_db.get_value_proof(&key_a).unwrap();
+    let sparse = proof.to_sparse();
+    let encoded = sparse.encode();
+    assert_eq!(encoded.len(), PROOF_HEADER_LEN + 2 + 32);
+
+    let decoded = SparseProof::<Sha3>::decode(&encoded, depth).unwrap();
+    assert_eq!(decoded.to_proof(depth).unwrap(), proof);
+    assert_eq!(
+        decoded
+            .to_proof(depth)
+            .unwrap()
+            .compute_root(&key_a, &[1])
+            .unwrap(),
+        root
+    );
+    assert!(SparseProof::<Sha3>::decode(&encoded[..33], depth).is_err());
+    assert!(decoded.to_proof(8).is_err());
+}
+
+#[test]
+fn test_null_hash_table() {
+    assert_eq!(
+        null_hashes_from_table::<Sha3>(4).unwrap(),
+        compute_null_hashes::<Sha3>(4)
+    );
+    assert!(null_hashes_from_table::<Sha3>(5).is_none());
+
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_null_hash_table()
+        .build();
+    tree_db_mut.insert(&[0, 1, 0], vec![]).unwrap();
+    tree_db_mut.commit();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_null_hash_table()
+        .build();
+    assert_eq!(tree_db.get_value(&[0, 1, 0]).unwrap(), Vec::<u8>::new());
+    assert!(tree_db.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_commit_skips_null_subtrees() {
+    let depth = 4;
+    let null_hashes = compute_null_hashes::<Sha3>(depth);
+    let mut memory_db = TestDb::default();
+    let mut root = null_hashes[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 1, 0, 1], vec![1]).unwrap();
+    tree_db_mut.insert(&[0, 1, 0, 1], vec![]).unwrap();
+    tree_db_mut.commit();
+    assert_eq!(root, null_hashes[0]);
+    assert!(memory_db.keys().is_empty());
+
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[1, 1, 1, 1], vec![1]).unwrap();
+    tree_db_mut.insert(&[0, 0, 0, 0], vec![]).unwrap();
+    tree_db_mut.commit();
+    assert_eq!(memory_db.keys().len(), depth + 1);
+    for null_hash in null_hashes.iter() {
+        assert!(!memory_db.as_hash_db().contains(null_hash, EMPTY_PREFIX));
+    }
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&[1, 1, 1, 1]).unwrap(), vec![1]);
+    assert_eq!(tree_db.get_value(&[0, 0, 0, 0]).unwrap(), Vec::<u8>::new());
+    assert!(tree_db.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_shared_null_hashes() {
+    let null_hashes = std::sync::Arc::new(NullHashes::<Sha3>::new());
+    let (memory_db, root, depth) = build_db_mock();
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_null_hashes(null_hashes.clone())
+        .build();
+    tree_db.get_value(&[0, 1, 1]).unwrap();
+    assert!(null_hashes.is_empty());
+
+    let mut empty_db = TestDb::default();
+    let mut empty_root = compute_null_hashes::<Sha3>(8)[0];
+    let tree_db_mut = TreeDBMutBuilder::new(&mut empty_db, &mut empty_root, 8)
+        .with_null_hashes(null_hashes.clone())
+        .build();
+    assert_eq!(tree_db_mut.get_value(&[0; 8]).unwrap(), Vec::<u8>::new());
+    assert_eq!(null_hashes.len(), 9);
+    assert_eq!(
+        null_hashes.ladder(depth),
+        compute_null_hashes::<Sha3>(depth)
+    );
+}
+
+#[test]
+fn test_commit_progress() {
+    let depth = 4;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut reports = Vec::new();
+    let cancel_after = RefCell::new(Some(4));
+    let mut progress = |nodes: usize, bytes: usize| {
+        reports.push((nodes, bytes));
+        cancel_after.borrow().is_none_or(|limit| nodes < limit)
+    };
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_commit_progress(2, &mut progress)
+        .build();
+    for key in [[0, 0, 0, 0], [0, 0, 0, 1], [1, 1, 1, 1]] {
+        tree_db_mut.insert(&key, key.to_vec()).unwrap();
+    }
+    assert!(matches!(
+        tree_db_mut.try_commit(),
+        Err(TreeError::CommitCancelled)
+    ));
+    assert_eq!(
+        tree_db_mut.get_value(&[0, 0, 0, 1]).unwrap(),
+        vec![0, 0, 0, 1]
+    );
+    *cancel_after.borrow_mut() = None;
+    tree_db_mut.try_commit().unwrap();
+    drop(tree_db_mut);
+
+    // 10 nodes: 3 leaves, 6 inner nodes below the root and the root
+    let nodes: Vec<usize> = reports.iter().map(|(nodes, _)| *nodes).collect();
+    assert_eq!(nodes, vec![2, 4, 2, 4, 6]);
+    assert!(reports
+        .windows(2)
+        .all(|w| w[0].0 > w[1].0 || w[0].1 < w[1].1));
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&[1, 1, 1, 1]).unwrap(), vec![1, 1, 1, 1]);
+    assert!(tree_db.verify_integrity().is_ok());
+    drop(tree_db);
+
+    // commit() cannot be cancelled
+    let mut cancel = |_: usize, _: usize| false;
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_commit_progress(1, &mut cancel)
+        .build();
+    tree_db_mut.insert(&[1, 0, 1, 0], vec![5]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&[1, 0, 1, 0]).unwrap(), vec![5]);
+    assert!(tree_db.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_spill_overlay() {
+    let depth = 4;
+    let keys: Vec<Vec<u8>> = (0..16u8)
+        .map(|i| (0..depth).rev().map(|bit| (i >> bit) & 1).collect())
+        .collect();
+
+    let mut expected_db = TestDb::default();
+    let mut expected_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut =
+        TreeDBMutBuilder::new(&mut expected_db, &mut expected_root, depth).build();
+    for key in keys.iter() {
+        tree_db_mut.insert(key, key.clone()).unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let mut memory_db = TestDb::default();
+    let mut spill_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_spill(&mut spill_db, 200)
+        .build();
+    for key in keys.iter() {
+        tree_db_mut.insert(key, key.clone()).unwrap();
+    }
+    assert!(tree_db_mut.spilled_nodes() > 0);
+    assert!(!tree_db_mut
+        .db()
+        .contains(&Sha3::hash(&keys[0]), EMPTY_PREFIX));
+    assert_eq!(tree_db_mut.get_value(&keys[3]).unwrap(), keys[3]);
+
+    tree_db_mut.commit();
+    assert_eq!(tree_db_mut.spilled_nodes(), 0);
+    drop(tree_db_mut);
+
+    assert_eq!(root, expected_root);
+    assert!(spill_db.keys().is_empty());
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert!(tree_db.verify_integrity().is_ok());
+}
+
+#[test]
+fn test_overlay_hasher() {
+    let depth = 4;
+    let keys: Vec<Vec<u8>> = (0..16u8)
+        .map(|i| (0..depth).rev().map(|bit| (i >> bit) & 1).collect())
+        .collect();
+
+    let mut default_db = TestDb::default();
+    let mut default_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut default_db, &mut default_root, depth).build();
+    for key in keys.iter() {
+        tree_db_mut.insert(key, key.clone()).unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_hasher::<BuildFoldHasher>()
+        .build();
+    for key in keys.iter() {
+        tree_db_mut.insert(key, key.clone()).unwrap();
+    }
+    assert_eq!(tree_db_mut.get_value(&keys[5]).unwrap(), keys[5]);
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    assert_eq!(root, default_root);
+}
+
+#[test]
+fn test_commit_deduplicates_nodes() {
+    let depth = 4;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    // identical left and right subtrees below the root
+    tree_db_mut.insert(&[0, 0, 0, 0], vec![7]).unwrap();
+    tree_db_mut.insert(&[1, 0, 0, 0], vec![7]).unwrap();
+
+    let stats = tree_db_mut.try_commit().unwrap();
+    assert_eq!(
+        stats,
+        CommitStats {
+            written: 5,
+            skipped: 1
+        }
+    );
+    assert_eq!(tree_db_mut.try_commit().unwrap(), CommitStats::default());
+    drop(tree_db_mut);
+
+    assert_eq!(memory_db.keys().len(), 5);
+    assert!(memory_db.keys().values().all(|rc| *rc == 1));
+}
+
+#[test]
+fn test_evm_multiproof() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+
+    let pair = |a: [u8; 32], b: [u8; 32]| Sha3::hash(&[a.min(b), a.max(b)].concat());
+    let mut level: Vec<[u8; 32]> = test_values()
+        .iter()
+        .map(|value| Sha3::hash(&value.to_le_bytes()))
+        .collect();
+    while level.len() > 1 {
+        level = level.chunks(2).map(|c| pair(c[0], c[1])).collect();
+    }
+    let evm_root = commutative_root(&tree_db).unwrap();
+    assert_eq!(evm_root, level[0]);
+
+    let keys = test_keys();
+    let multiproof = evm_multiproof(&tree_db, &[&keys[7], &keys[1], &keys[6]]).unwrap();
+    assert_eq!(multiproof.leaves.len(), 3);
+    assert_eq!(
+        multiproof.leaves.len() + multiproof.proof.len(),
+        multiproof.proof_flags.len() + 1
+    );
+    assert!(multiproof.verify(&evm_root));
+
+    let mut tampered = multiproof.clone();
+    tampered.leaves[0] = Sha3::hash(b"tampered");
+    assert!(!tampered.verify(&evm_root));
+
+    // sparse trees prove absent keys by their null leaf
+    let depth = 4;
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 1, 1, 0], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let multiproof = evm_multiproof(&tree_db, &[&[0, 1, 1, 0], &[1, 0, 0, 1]]).unwrap();
+    assert!(multiproof.verify(&commutative_root(&tree_db).unwrap()));
+}
+
+/// A stand-in for Pedersen, reducing Sha3 below the Stark field modulus.
+struct MockPedersen;
+
+impl FeltHasher for MockPedersen {
+    fn hash_pair(a: &Felt, b: &Felt) -> Felt {
+        let mut hash = Sha3::hash(&[a.as_ref(), b.as_ref()].concat());
+        hash[0] &= 0x07;
+        hash
+    }
+}
+
+#[test]
+fn test_felt_hash_profile() {
+    type Pedersen = FeltHash<MockPedersen>;
+    let felt = |n: u8| felt_from_bytes(&[n]).unwrap();
+    assert!(felt_from_bytes(&STARK_PRIME).is_err());
+
+    let depth = 2;
+    let mut memory_db = MemoryDB::<Pedersen, NoopKey<Pedersen>, Vec<u8>>::default();
+    let mut root = compute_null_hashes::<Pedersen>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 0], felt(1).to_vec()).unwrap();
+    tree_db_mut.insert(&[1, 1], felt(2).to_vec()).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    // empty leaves are zero and felt leaves are their own hash, as in Cairo
+    let zero = [0; 32];
+    let left = MockPedersen::hash_pair(&felt(1), &zero);
+    let right = MockPedersen::hash_pair(&zero, &felt(2));
+    assert_eq!(root, MockPedersen::hash_pair(&left, &right));
+
+    // longer values are hashed on 31-byte elements followed by their count
+    let value = [0xff; 40];
+    let first = felt_from_bytes(&value[..31]).unwrap();
+    let second = felt_from_bytes(&value[31..]).unwrap();
+    let expected = [first, second].iter().fold(zero, |hash, element| {
+        MockPedersen::hash_pair(&hash, element)
+    });
+    assert_eq!(
+        Pedersen::hash(&value),
+        MockPedersen::hash_pair(&expected, &felt(2))
+    );
+}
+
+#[cfg(feature = "starknet")]
+#[test]
+fn test_starknet_felt_hashes() {
+    use crate::{Pedersen, Poseidon};
+
+    let felt = |hex: &str| -> Felt {
+        let mut felt = [0; 32];
+        let digits = format!("{:0>64}", hex);
+        for (byte, pair) in felt.iter_mut().zip(digits.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap();
+        }
+        felt
+    };
+
+    // vectors from StarkWare's signature test data and cairo-lang
+    let pedersen = [
+        "03d937c035c878245caf64531a5756109c53068da139362728feb561405371cb",
+        "0208a0a10250e382e1e4bbe2880906c2791bf6275695e02fbbc6aeff9cd8b31a",
+        "030e480bed5fe53fa909cc0f8c4d99b8f9f2c016be4c41e13a4848797979c662",
+    ]
+    .map(felt);
+    assert_eq!(Pedersen::hash_pair(&pedersen[0], &pedersen[1]), pedersen[2]);
+    let poseidon = [
+        "b662f9017fa7956fd70e26129b1833e10ad000fd37b4d9f4e0ce6884b7bbe",
+        "1fe356bf76102cdae1bfbdc173602ead228b12904c00dad9cf16e035468bea",
+        "75540825a6ecc5dc7d7c2f5f868164182742227f1367d66c43ee51ec7937a81",
+    ]
+    .map(felt);
+    assert_eq!(Poseidon::hash_pair(&poseidon[0], &poseidon[1]), poseidon[2]);
+
+    // inner nodes hash their children with Pedersen, as in Cairo
+    let depth = 1;
+    let mut memory_db = MemoryDB::<FeltHash<Pedersen>, NoopKey<_>, Vec<u8>>::default();
+    let mut root = compute_null_hashes::<FeltHash<Pedersen>>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0], pedersen[0].to_vec()).unwrap();
+    tree_db_mut.insert(&[1], pedersen[1].to_vec()).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    assert_eq!(root, pedersen[2]);
+}
+
+#[test]
+fn test_ssz_multiproof() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let keys = test_keys();
+
+    let indices = [
+        generalized_index(&keys[1]).unwrap(),
+        generalized_index(&keys[6]).unwrap(),
+    ];
+    assert_eq!(indices, [9, 14]);
+    assert_eq!(helper_indices(&indices), vec![15, 8, 6, 5]);
+
+    let multiproof = ssz_multiproof(&tree_db, &indices).unwrap();
+    assert_eq!(
+        multiproof.leaves[0],
+        Sha3::hash(&test_values()[1].to_le_bytes())
+    );
+    assert!(multiproof.verify(&root));
+
+    // inner nodes are addressable too
+    let multiproof = ssz_multiproof(&tree_db, &[5, 12]).unwrap();
+    assert!(multiproof.verify(&root));
+
+    let mut tampered = multiproof.clone();
+    tampered.proof[0] = Sha3::hash(b"tampered");
+    assert!(!tampered.verify(&root));
+    assert!(ssz_multiproof(&tree_db, &[16]).is_err());
+}
+
+#[test]
+fn test_witness_bundle() {
+    let (mut memory_db, pre_state_root, depth) = build_db_mock();
+    let keys = test_keys();
+    let block = |tree: &mut TreeDBMut<Sha3>| {
+        let balance = tree.get_value(&keys[2])?;
+        tree.insert(&keys[5], balance)?;
+        tree.get_value(&keys[5])
+    };
+
+    let mut recorder = Recorder::new();
+    let mut post_state_root = pre_state_root;
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut post_state_root, depth)
+        .with_recorder(&mut recorder)
+        .build();
+    let expected = block(&mut tree_db_mut).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let bundle = WitnessBundle::<Sha3> {
+        pre_state_root,
+        proof: recorder.drain_storage_proof(),
+        access_list: vec![keys[2].clone(), keys[5].clone()],
+        post_state_root,
+        depth,
+    };
+    assert_eq!(execute_against(&bundle, block).unwrap(), expected);
+
+    let mut wrong_post_state = bundle.clone();
+    wrong_post_state.post_state_root = pre_state_root;
+    assert!(matches!(
+        execute_against(&wrong_post_state, block),
+        Err(TreeError::PostStateRootMismatch)
+    ));
+
+    // nodes outside the witness cannot be read
+    assert!(execute_against(&bundle, |tree| tree.get_value(&keys[0])).is_err());
+
+    // nor can witness nodes off the paths of the access list
+    let full_witness = WitnessBundle::<Sha3> {
+        proof: TreeDBBuilder::<Sha3>::new(&memory_db, &pre_state_root, depth)
+            .build()
+            .prove_subtree(&[])
+            .unwrap(),
+        ..bundle.clone()
+    };
+    assert!(matches!(
+        execute_against(&full_witness, |tree| tree.get_value(&keys[0])),
+        Err(TreeError::DataNotFound { .. })
+    ));
+    assert_eq!(execute_against(&full_witness, block).unwrap(), expected);
+}
+
+#[test]
+fn test_null_hash_ladder() {
+    let ladder = NullHashLadder::<Sha3>::new(&[9], 4);
+    assert_eq!(ladder.max_depth(), 4);
+    assert_eq!(ladder.len(), 5);
+    assert_eq!(ladder.default_leaf(), &[9]);
+
+    let depth = 3;
+    let mut memory_db = TestDb::default();
+    let mut root = ladder.at_height(depth);
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+        .with_null_hashes(ladder.null_hashes())
+        .build();
+    tree_db_mut.insert(&[0, 1, 0], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let expected = FlatTree::<Sha3>::from_leaves_with_null_hashes(
+        depth,
+        vec![vec![9], vec![9], vec![1]],
+        Arc::new(NullHashes::with_default_leaf(&[9])),
+    )
+    .unwrap();
+    assert_eq!(&root, expected.root());
+}
+
+#[test]
+fn test_default_leaf_proofs_and_witness() {
+    let depth = 3;
+    let null_hashes = Arc::new(NullHashes::<Sha3>::with_default_leaf(&[9]));
+    let mut memory_db = TestDb::default();
+    let mut root = null_hashes.at_height(depth);
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+            .with_null_hashes(null_hashes.clone())
+            .build();
+        tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+        tree_db_mut.commit();
+    }
+    let proof = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_null_hashes(null_hashes.clone())
+        .build()
+        .prove_subtree(&[])
+        .unwrap();
+
+    // empty leaves are proven absent by every tree
+    let flat =
+        FlatTree::<Sha3>::from_leaves_with_null_hashes(depth, vec![vec![1]], null_hashes.clone())
+            .unwrap();
+    assert_eq!(flat.root(), &root);
+    let proof_tree = proof
+        .clone()
+        .into_tree::<Sha3>(root, depth)
+        .unwrap()
+        .with_null_hashes(null_hashes.clone());
+    for tree in [&flat as &dyn Tree<Sha3>, &proof_tree] {
+        assert_eq!(tree.get_value_proof(&[1, 1, 1]).unwrap().0, None);
+        assert_eq!(tree.get_value_proof(&[0, 0, 0]).unwrap().0, Some(vec![1]));
+    }
+
+    let block = |tree: &mut TreeDBMut<Sha3>| tree.insert(&[1, 1, 1], vec![2]);
+    let mut post_state_root = root;
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut post_state_root, depth)
+        .with_null_hashes(null_hashes.clone())
+        .build();
+    block(&mut tree_db_mut).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let bundle = WitnessBundle::<Sha3> {
+        pre_state_root: root,
+        proof,
+        access_list: vec![vec![1, 1, 1]],
+        post_state_root,
+        depth,
+    };
+    assert_eq!(
+        execute_against_with_null_hashes(&bundle, null_hashes, block).unwrap(),
+        vec![9]
+    );
+}
+
+#[test]
+fn test_conformance() {
+    for seed in [1, 42, 0xdead_beef] {
+        conformance::check_all::<Sha3, _>(TestDb::default, 6, seed);
+    }
+    conformance::check_all::<Sha3, _>(TestDb::default, 16, 7);
+}
+
+#[test]
+fn test_key_range() {
+    let key = |a, b| Key::new([a, b]);
+    assert!(key(0, 0xff) < key(1, 0));
+    assert_eq!(
+        [key(1, 0), key(0, 2), key(0, 1)]
+            .into_iter()
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![key(0, 1), key(0, 2), key(1, 0)]
+    );
+
+    let range = KeyRange::new(key(0, 0xfe), key(1, 1));
+    assert!(range.contains(&key(0, 0xff)));
+    assert!(!range.contains(&key(1, 1)));
+    assert_eq!(
+        range.iter().collect::<Vec<_>>(),
+        vec![key(0, 0xfe), key(0, 0xff), key(1, 0)]
+    );
+
+    assert!(KeyRange::new(key(1, 0), key(1, 0)).is_empty());
+    assert_eq!(KeyRange::new(key(1, 0), key(0, 0)).iter().count(), 0);
+    // the last key has no successor
+    let last = KeyRange::new(key(0xff, 0xfe), key(0xff, 0xff));
+    assert_eq!(last.into_iter().collect::<Vec<_>>(), vec![key(0xff, 0xfe)]);
+}
+
+#[test]
+fn test_key_index() {
+    assert_eq!(Key::<2>::from_index(0x0102).unwrap(), Key::new([1, 2]));
+    assert_eq!(
+        Key::<2>::from_index(0x0102).unwrap().to_index().unwrap(),
+        0x0102
+    );
+    assert!(Key::<2>::from_index(0x1_0000).is_err());
+
+    let key = Key::<20>::from_index(u128::MAX).unwrap();
+    assert_eq!(&key.as_slice()[..4], &[0; 4]);
+    assert_eq!(key.to_index().unwrap(), u128::MAX);
+    assert!(Key::new([1; 20]).to_index().is_err());
+
+    // leaf 5 of a depth 16 tree is the sixth leaf from the left
+    let bits: Vec<bool> = Key::<2>::from_index(5).unwrap().iter().collect();
+    assert_eq!(&bits[13..], &[true, false, true]);
+    assert!(bits[..13].iter().all(|bit| !bit));
+}
+
+#[test]
+fn test_node_bool_children() {
+    let left = Sha3::hash(b"left");
+    let right = Sha3::hash(b"right");
+    let mut node = Node::<Sha3>::Inner(NodeHash::Hash(left), NodeHash::Hash(right));
+
+    assert_eq!(node.get_child_bool(false).unwrap().get_hash(), &left);
+    assert_eq!(node.get_child_bool(true).unwrap().get_hash(), &right);
+    assert!(matches!(
+        node.get_child(2),
+        Err(TreeError::NodeIndexOutOfBounds)
+    ));
+
+    let key = Key::new([0b1000_0000]);
+    let first_bit = key.iter().next().unwrap();
+    let new = Sha3::hash(b"new");
+    assert_eq!(
+        node.set_child_bool(first_bit, NodeHash::Hash(new)).unwrap(),
+        right
+    );
+    assert_eq!(node.get_child(1).unwrap().get_hash(), &new);
+
+    let value = Node::<Sha3>::Value(Value::Cached(vec![1]));
+    assert!(matches!(
+        value.get_child_bool(true),
+        Err(TreeError::UnexpectedNodeType)
+    ));
+}
+
+#[test]
+fn test_null_root_helpers() {
+    let depth = 5;
+    let null_hashes = compute_null_hashes::<Sha3>(depth);
+    assert_eq!(null_root::<Sha3>(depth), null_hashes[0]);
+    for (level, hash) in null_hashes.iter().enumerate() {
+        assert_eq!(null_hash_at_depth::<Sha3>(depth - level), *hash);
+    }
+
+    // the ladder is shared between callers
+    assert!(shared_null_hashes::<Sha3>().len() > depth);
+
+    // the sibling at height i of an absence proof in an empty tree is an empty subtree of
+    // depth i
+    let memory_db = TestDb::default();
+    let root = null_root::<Sha3>(depth);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let (value, proof) = tree_db.get_value_proof(&[0, 1, 1, 0, 1]).unwrap();
+    assert_eq!(value, None);
+    let expected: Vec<_> = (0..depth).map(null_hash_at_depth::<Sha3>).collect();
+    assert_eq!(proof.siblings(), expected);
+}
+
+#[test]
+fn test_data_not_found_details() {
+    let (mut memory_db, root, depth) = build_db_mock();
+    let (_, nodes, _, _) = build_data();
+    let missing = nodes[2].hash();
+    memory_db.as_hash_db_mut().remove(&missing, EMPTY_PREFIX);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let expected = TreeError::DataNotFound {
+        hash: missing.to_vec(),
+        path: vec![0],
+        depth: 1,
+    };
+    for result in [
+        tree_db.get_value(&[0, 1, 0]).map(|_| ()),
+        tree_db.get_proof(&[0, 1, 0]).map(|_| ()),
+    ] {
+        assert_eq!(
+            format!("{:?}", result.unwrap_err()),
+            format!("{expected:?}")
+        );
+    }
+    assert!(tree_db.get_proof(&[1, 1, 0]).is_ok());
+}
+
+#[test]
+fn test_get_proof_deep_tree() {
+    let depth = 256;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let keys: Vec<Vec<u8>> = (0..4)
+        .map(|i| {
+            (0..depth)
+                .map(|bit| match bit {
+                    0 | 1 => (i >> bit) as u8 & 1,
+                    _ => ((i + bit) % 3 == 0) as u8,
+                })
+                .collect()
+        })
+        .collect();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    for (i, key) in keys.iter().enumerate() {
+        tree_db_mut.insert(key, vec![i as u8 + 1]).unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    for (i, key) in keys.iter().enumerate() {
+        let proof = tree_db.get_proof(key).unwrap();
+        assert_eq!(proof.len(), 2 * depth + 2);
+        assert_eq!(proof.last().unwrap(), &(0, vec![i as u8 + 1]));
+
+        // hash back up the pairs of children listed from the root down
+        let mut hash = Sha3::hash(&[i as u8 + 1]);
+        for (level, bit) in key.iter().enumerate().rev() {
+            let (left, right) = (&proof[1 + 2 * level].1, &proof[2 + 2 * level].1);
+            assert_eq!([left, right][*bit as usize], &hash.to_vec());
+            hash = Sha3::hash(&[left.as_slice(), right.as_slice()].concat());
+        }
+        assert_eq!(hash, root);
+    }
+}
+
+#[test]
+fn test_get_proof_before_commit() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let committed = root;
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    assert_eq!(tree_db_mut.peek_root(), committed);
+
+    let updates: [(&[u8], u8); 3] = [(&[0, 0, 1], 10), (&[1, 1, 0], 11), (&[0, 0, 1], 12)];
+    for (key, value) in updates {
+        tree_db_mut.insert(key, vec![value]).unwrap();
+        let peek_root = tree_db_mut.peek_root();
+        assert_ne!(peek_root, committed);
+
+        // both the updated key and an untouched key read from the uncommitted root
+        for key in [key, &[1, 0, 1]] {
+            let proof = tree_db_mut.get_proof(key).unwrap();
+            assert_eq!(proof[0], (1, peek_root.to_vec()));
+
+            let (value, proof) = tree_db_mut.get_value_proof(key).unwrap();
+            let value = value.unwrap_or_default();
+            assert_eq!(proof.compute_root(key, &value).unwrap(), peek_root);
+        }
+    }
+
+    let peek_root = tree_db_mut.peek_root();
+    tree_db_mut.commit();
+    assert_eq!(tree_db_mut.root(), &peek_root);
+}
+
+#[test]
+fn test_record_access_list() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let keys: [&[u8]; 4] = [&[0, 1, 0], &[0, 0, 1], &[1, 1, 1], &[0, 0, 1]];
+    let proof = record_access_list(&tree_db, &keys).unwrap();
+    // the root, both nodes of the first level, three of the second and three leaves
+    assert_eq!(proof.nodes().count(), 9);
+
+    let witness = proof.clone().into_memory_db::<Sha3>();
+    let witness_db = TreeDBBuilder::<Sha3>::new(&witness, &root, depth).build();
+    for key in keys {
+        assert_eq!(
+            witness_db.get_value(key).unwrap(),
+            tree_db.get_value(key).unwrap()
+        );
+    }
+    assert!(matches!(
+        witness_db.get_value(&[1, 0, 0]),
+        Err(TreeError::DataNotFound { .. })
+    ));
+
+    // absent keys are proven by the empty subtree above them
+    let mut sparse_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut sparse_db = TestDb::default();
+    let mut tree_db_mut =
+        TreeDBMutBuilder::<Sha3>::new(&mut sparse_db, &mut sparse_root, depth).build();
+    tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let sparse_tree = TreeDBBuilder::<Sha3>::new(&sparse_db, &sparse_root, depth).build();
+    let keys: [&[u8]; 2] = [&[0, 0, 0], &[1, 1, 0]];
+    let proof = record_access_list(&sparse_tree, &keys).unwrap();
+    assert_eq!(proof.nodes().count(), 4);
+
+    let mut expected_root = sparse_root;
+    let mut expected_db = sparse_db.clone();
+    let mut tree_db_mut =
+        TreeDBMutBuilder::<Sha3>::new(&mut expected_db, &mut expected_root, depth).build();
+    tree_db_mut.insert(&[1, 1, 0], vec![2]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let bundle = WitnessBundle::<Sha3> {
+        pre_state_root: sparse_root,
+        proof,
+        access_list: keys.iter().map(|key| key.to_vec()).collect(),
+        post_state_root: expected_root,
+        depth,
+    };
+    let old = execute_against(&bundle, |tree| tree.insert(&[1, 1, 0], vec![2])).unwrap();
+    assert_eq!(old, Vec::<u8>::new());
+}
+
+#[test]
+fn test_verified_proof_db() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let keys: [&[u8]; 2] = [&[0, 1, 1], &[1, 0, 0]];
+    let proof = record_access_list(&tree_db, &keys).unwrap();
+
+    let verified_db = proof.clone().into_verified_db::<Sha3>(root).unwrap();
+    assert_eq!(verified_db.len(), proof.nodes().count());
+    let verified_tree = TreeDBBuilder::<Sha3>::new(&verified_db, &root, depth).build();
+    for key in keys {
+        assert_eq!(
+            verified_tree.get_value(key).unwrap(),
+            tree_db.get_value(key).unwrap()
+        );
+    }
+
+    // a node that does not hang off the root is rejected
+    let mut nodes: Vec<Vec<u8>> = proof.nodes().cloned().collect();
+    nodes.push(vec![0, 42]);
+    assert!(matches!(
+        VerifiedProofDb::<Sha3>::new(StorageProof::new(nodes), root),
+        Err(TreeError::UnlinkedProofNode)
+    ));
+
+    // so is every node against another root
+    let other_root = compute_null_hashes::<Sha3>(depth)[0];
+    assert!(matches!(
+        proof.clone().into_verified_db::<Sha3>(other_root),
+        Err(TreeError::UnlinkedProofNode)
+    ));
+    assert!(StorageProof::new([])
+        .into_verified_db::<Sha3>(other_root)
+        .unwrap()
+        .is_empty());
+
+    let truncated = StorageProof::new([vec![1, 0, 0]]);
+    assert!(matches!(
+        truncated.into_verified_db::<Sha3>(root),
+        Err(TreeError::NodeDeserializationFailed)
+    ));
+}
+/// A recorder in the shape of other systems' proof recorders, keyed by raw bytes.
+#[derive(Default)]
+struct BytesRecorder {
+    nodes: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl TreeRecorder<Sha3> for BytesRecorder {
+    fn record(&mut self, hash: &<Sha3 as Hasher>::Out, encoded: &[u8]) {
+        self.nodes.insert(hash.to_vec(), encoded.to_vec());
+    }
+}
+
+#[test]
+fn test_bytes_recorder() {
+    let (memory_db, root, depth) = build_db_mock();
+    let mut bytes_recorder = BytesRecorder::default();
+    let mut recorder = Recorder::new();
+    {
+        let mut tee_recorder = TeeRecorder::new()
+            .with_recorder(&mut bytes_recorder)
+            .with_recorder(&mut recorder);
+        let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+            .with_recorder(&mut tee_recorder)
+            .build();
+        tree_db.get_value(&[1, 0, 1]).unwrap();
+    }
+
+    let recorded = recorder.drain();
+    assert_eq!(recorded.len(), depth + 1);
+    for (hash, encoded) in recorded {
+        assert_eq!(bytes_recorder.nodes[hash.as_slice()], encoded);
+        assert_eq!(Sha3::hash(&encoded[1..]), hash);
+    }
+    let proof = StorageProof::new(bytes_recorder.nodes.into_values());
+    let proof_tree = proof.into_tree::<Sha3>(root, depth).unwrap();
+    assert_eq!(
+        proof_tree.get_value(&[1, 0, 1]).unwrap(),
+        test_values()[5].to_le_bytes().to_vec()
+    );
+}
+#[derive(Default)]
+struct TestMeter {
+    charges: Vec<(usize, usize)>,
+}
+
+impl AccessMeter for TestMeter {
+    fn charge(&mut self, nodes: usize, bytes: usize) {
+        self.charges.push((nodes, bytes));
+    }
+}
+
+#[test]
+fn test_access_meter() {
+    // three inner nodes and a four byte value on every path of the mock tree
+    let path_cost = (4, 3 * (1 + 2 * Sha3::LENGTH) + 5);
+
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut meter = TestMeter::default();
+    {
+        let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+            .with_access_meter(&mut meter)
+            .build();
+        tree_db.get_value(&[0, 1, 1]).unwrap();
+        tree_db.get_proof(&[0, 1, 1]).unwrap();
+        tree_db.get_value(&[1, 0, 0]).unwrap();
+    }
+    assert_eq!(meter.charges, vec![path_cost, path_cost]);
+
+    let mut meter = TestMeter::default();
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth)
+            .with_access_meter(&mut meter)
+            .build();
+        tree_db_mut.insert(&[0, 1, 1], vec![1, 2, 3, 4]).unwrap();
+        // uncommitted nodes cost the same as stored ones
+        tree_db_mut.get_value(&[0, 1, 1]).unwrap();
+        tree_db_mut.commit();
+    }
+    assert_eq!(meter.charges, vec![path_cost, path_cost]);
+
+    // empty subtrees are free
+    let mut meter = TestMeter::default();
+    let mut empty_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut empty_db = TestDb::default();
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut empty_db, &mut empty_root, depth)
+            .with_access_meter(&mut meter)
+            .build();
+        tree_db_mut.insert(&[1, 1, 1], vec![1]).unwrap();
+        tree_db_mut.get_value(&[1, 1, 1]).unwrap();
+    }
+    assert_eq!(
+        meter.charges,
+        vec![(0, 0), (4, 3 * (1 + 2 * Sha3::LENGTH) + 2)]
+    );
+}
+/// A database logging every write, standing in for a replication journal.
+#[derive(Default)]
+struct JournalDb {
+    db: TestDb,
+    journal: Vec<(<Sha3 as Hasher>::Out, DBValue)>,
+}
+
+impl HashDB<Sha3, DBValue> for JournalDb {
+    fn get(&self, key: &<Sha3 as Hasher>::Out, prefix: Prefix) -> Option<DBValue> {
+        HashDB::get(&self.db, key, prefix)
+    }
+
+    fn contains(&self, key: &<Sha3 as Hasher>::Out, prefix: Prefix) -> bool {
+        HashDB::contains(&self.db, key, prefix)
+    }
+
+    fn insert(&mut self, prefix: Prefix, value: &[u8]) -> <Sha3 as Hasher>::Out {
+        let hash = Sha3::hash(value);
+        self.emplace(hash, prefix, value.to_vec());
+        hash
+    }
+
+    fn emplace(&mut self, key: <Sha3 as Hasher>::Out, prefix: Prefix, value: DBValue) {
+        self.journal.push((key, value.clone()));
+        self.db.emplace(key, prefix, value);
+    }
+
+    fn remove(&mut self, key: &<Sha3 as Hasher>::Out, prefix: Prefix) {
+        self.db.remove(key, prefix);
+    }
+}
+
+impl AsHashDB<Sha3, DBValue> for JournalDb {
+    fn as_hash_db(&self) -> &dyn HashDB<Sha3, DBValue> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<Sha3, DBValue> + 'a) {
+        self
+    }
+}
+
+#[test]
+fn test_commit_order() {
+    let depth = 4;
+    let keys: Vec<Vec<u8>> = (0..16u8)
+        .step_by(3)
+        .map(|i| (0..depth).rev().map(|bit| (i >> bit) & 1).collect())
+        .collect();
+
+    let mut journals = Vec::new();
+    for reverse in [false, true] {
+        let mut journal_db = JournalDb::default();
+        let mut root = compute_null_hashes::<Sha3>(depth)[0];
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut journal_db, &mut root, depth).build();
+        let mut ordered = keys.clone();
+        if reverse {
+            ordered.reverse();
+        }
+        for key in ordered.iter() {
+            tree_db_mut.insert(key, key.clone()).unwrap();
+        }
+        tree_db_mut.commit();
+        drop(tree_db_mut);
+
+        // the root comes first and every other node follows its parent
+        assert_eq!(journal_db.journal[0].0, root);
+        for (position, (hash, _)) in journal_db.journal.iter().enumerate().skip(1) {
+            assert!(journal_db.journal[..position].iter().any(|(_, parent)| {
+                parent[0] == 1 && parent[1..].chunks(Sha3::LENGTH).any(|child| child == hash)
+            }));
+        }
+        journals.push(journal_db.journal);
+    }
+    assert_eq!(journals[0], journals[1]);
+
+    // the left subtree is written before the right one
+    let leaves: Vec<DBValue> = journals[0]
+        .iter()
+        .filter(|(_, encoded)| encoded[0] == 0)
+        .map(|(_, encoded)| encoded[1..].to_vec())
+        .collect();
+    assert_eq!(leaves, keys);
+}
+
+#[test]
+fn test_prove_subtree() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+
+    let proof = tree_db.prove_subtree(&[1]).unwrap();
+    // the root, the subtree root, its two children and four leaves, two of which are equal
+    assert_eq!(proof.nodes().count(), 7);
+    let proof_tree = proof.into_tree::<Sha3>(root, depth).unwrap();
+    for (key, value) in test_keys().iter().zip(test_values()) {
+        let result = proof_tree.get_value(key);
+        if key[0] == 1 {
+            assert_eq!(result.unwrap(), value.to_le_bytes().to_vec());
+        } else {
+            assert!(matches!(result, Err(TreeError::DataNotFound { .. })));
+        }
+    }
+
+    // the whole tree and a single leaf
+    let proof = tree_db.prove_subtree(&[]).unwrap();
+    assert_eq!(proof.nodes().count(), 14);
+    let proof = tree_db.prove_subtree(&[0, 1, 1]).unwrap();
+    assert_eq!(proof.nodes().count(), 4);
+    assert!(tree_db.prove_subtree(&[0, 1, 1, 0]).is_err());
+
+    // the path to an empty subtree proves its absence
+    let mut sparse_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut sparse_db = TestDb::default();
+    let mut tree_db_mut =
+        TreeDBMutBuilder::<Sha3>::new(&mut sparse_db, &mut sparse_root, depth).build();
+    tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let sparse_tree = TreeDBBuilder::<Sha3>::new(&sparse_db, &sparse_root, depth).build();
+    let proof_tree = sparse_tree
+        .prove_subtree(&[1, 1])
+        .unwrap()
+        .into_tree::<Sha3>(sparse_root, depth)
+        .unwrap();
+    assert_eq!(proof_tree.db().len(), 1);
+    assert_eq!(proof_tree.get_value(&[1, 1, 0]).unwrap(), Vec::<u8>::new());
+}
+
+#[test]
+fn test_get_proof_reads() {
+    let (memory_db, root, depth) = build_db_mock();
+    let mut recorder = Recorder::new();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_recorder(&mut recorder)
+        .build();
+    let proof = tree_db.get_proof(&[1, 0, 1]).unwrap();
+    drop(tree_db);
+
+    // only the nodes on the path are read, siblings come from their parents
+    let read: Vec<DBValue> = recorder
+        .drain()
+        .into_iter()
+        .map(|(_, encoded)| encoded)
+        .collect();
+    assert_eq!(read.len(), depth + 1);
+    for level in 0..depth {
+        let (left, right) = (&proof[1 + 2 * level].1, &proof[2 + 2 * level].1);
+        assert_eq!(read[level], [vec![1], left.clone(), right.clone()].concat());
+    }
+}
+impl ValueCompression for RunLength {
+    fn compress(&self, value: &[u8]) -> Vec<u8> {
+        ProofCodec::compress(self, value)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, TreeError> {
+        ProofCodec::decompress(self, data, usize::MAX)
+    }
+}
+
+#[test]
+fn test_value_compression() {
+    let depth = 3;
+    let values: Vec<(Vec<u8>, DBValue)> =
+        vec![(vec![0, 0, 1], vec![7; 64]), (vec![1, 0, 1], vec![1, 2, 3])];
+
+    let mut roots = Vec::new();
+    let mut dbs = Vec::new();
+    for compress in [false, true] {
+        let mut memory_db = TestDb::default();
+        let mut root = compute_null_hashes::<Sha3>(depth)[0];
+        let mut builder = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth);
+        if compress {
+            builder = builder.with_value_compression(&RunLength);
+        }
+        let mut tree_db_mut = builder.build();
+        for (key, value) in values.iter() {
+            tree_db_mut.insert(key, value.clone()).unwrap();
+        }
+        tree_db_mut.commit();
+        drop(tree_db_mut);
+        roots.push(root);
+        dbs.push(memory_db);
+    }
+    assert_eq!(roots[0], roots[1]);
+    let root = roots[0];
+
+    // the repetitive value is stored compressed under its usual hash, the other one as is
+    let stored = HashDB::get(&dbs[1], &Sha3::hash(&values[0].1), EMPTY_PREFIX).unwrap();
+    assert_eq!(stored, vec![2, 64, 7]);
+    let stored = HashDB::get(&dbs[1], &Sha3::hash(&values[1].1), EMPTY_PREFIX).unwrap();
+    assert_eq!(stored, vec![0, 1, 2, 3]);
+
+    let mut recorder = Recorder::new();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&dbs[1], &root, depth)
+        .with_value_compression(&RunLength)
+        .with_recorder(&mut recorder)
+        .build();
+    for (key, value) in values.iter() {
+        assert_eq!(&tree_db.get_value(key).unwrap(), value);
+    }
+    drop(tree_db);
+
+    // recorded witnesses hold canonical nodes
+    let proof_tree = recorder
+        .drain_storage_proof()
+        .into_tree::<Sha3>(root, depth)
+        .unwrap();
+    assert_eq!(proof_tree.get_value(&values[0].0).unwrap(), values[0].1);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&dbs[1], &root, depth).build();
+    assert!(matches!(
+        tree_db.get_value(&values[0].0),
+        Err(TreeError::NodeDeserializationFailed)
+    ));
+}
+
+#[test]
+fn test_value_compression_readers() {
+    let depth = 3;
+    let key = vec![0, 0, 1];
+    let value = vec![7; 64];
+    let mut memory_db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth)
+        .with_value_compression(&RunLength)
+        .build();
+    tree_db_mut.insert(&key, value.clone()).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_value_compression(&RunLength)
+        .build();
+    assert!(tree_db.verify_integrity().is_ok());
+
+    // subtrees copied to an uncompressed tree are stored uncompressed
+    let mut dst_db = TestDb::default();
+    let mut dst_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut dst_tree = TreeDBMutBuilder::new(&mut dst_db, &mut dst_root, depth).build();
+    copy_subtree(&tree_db, &[0], &mut dst_tree, &[1]).unwrap();
+    dst_tree.commit();
+    drop(dst_tree);
+    let dst_tree = TreeDBBuilder::<Sha3>::new(&dst_db, &dst_root, depth).build();
+    assert!(dst_tree.verify_integrity().is_ok());
+    assert_eq!(dst_tree.get_value(&[1, 0, 1]).unwrap(), value);
+    drop(tree_db);
+
+    // rebuilt values are stored compressed
+    let leaf = Sha3::hash(&value);
+    memory_db.remove(&leaf, EMPTY_PREFIX);
+    let leaves = [(key, value)];
+    let report = repair(
+        &mut memory_db,
+        &root,
+        depth,
+        &NullHashes::new(),
+        RepairSource::Leaves(&leaves),
+        Some(&RunLength),
+    );
+    assert_eq!(report.repaired, vec![vec![0, 0, 1]]);
+    assert_eq!(
+        HashDB::get(&memory_db, &leaf, EMPTY_PREFIX).unwrap(),
+        vec![2, 64, 7]
+    );
+}
+
+#[test]
+fn test_sessions() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let sessions = Sessions::<Sha3>::new(root);
+    let mut executed = Vec::new();
+    {
+        let base = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+
+        let mut session = sessions.branch();
+        let value = session.get_value(&base, &[0, 0, 0]).unwrap();
+        session.insert(&[0, 0, 0], [value, vec![1]].concat());
+        executed.push(session);
+
+        let mut session = sessions.branch();
+        session.get_value(&base, &[0, 1, 0]).unwrap();
+        session.insert(&[1, 1, 1], vec![2]);
+        // reads see the session's own writes
+        assert_eq!(session.get_value(&base, &[1, 1, 1]).unwrap(), vec![2]);
+        executed.push(session);
+
+        // reads a key written by the first session
+        let mut session = sessions.branch();
+        session.get_value(&base, &[0, 0, 0]).unwrap();
+        session.insert(&[0, 0, 1], vec![3]);
+        executed.push(session);
+
+        // a blind write to the same key merges
+        let mut session = sessions.branch();
+        session.insert(&[0, 0, 0], vec![4]);
+        executed.push(session);
+    }
+    assert_eq!(executed[1].reads().len(), 1);
+
+    let mut sessions = sessions;
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let results: Vec<_> = executed
+        .into_iter()
+        .map(|session| sessions.commit(&mut tree_db_mut, session))
+        .collect();
+    assert!(results[0].is_ok() && results[1].is_ok() && results[3].is_ok());
+    assert!(matches!(results[2], Err(TreeError::SessionConflict)));
+    assert_eq!(results[3].as_ref().unwrap(), &tree_db_mut.peek_root());
+
+    assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![4]);
+    assert_eq!(tree_db_mut.get_value(&[1, 1, 1]).unwrap(), vec![2]);
+    assert_eq!(
+        tree_db_mut.get_value(&[0, 0, 1]).unwrap(),
+        test_values()[1].to_le_bytes().to_vec()
+    );
+
+    // sessions of another base, or a tree changed behind the sessions' back, are stale
+    let other = Sessions::<Sha3>::new(compute_null_hashes::<Sha3>(depth)[0]);
+    assert!(matches!(
+        sessions.commit(&mut tree_db_mut, other.branch()),
+        Err(TreeError::StaleSession)
+    ));
+    tree_db_mut.insert(&[1, 0, 0], vec![5]).unwrap();
+    assert!(matches!(
+        sessions.commit(&mut tree_db_mut, sessions.branch()),
+        Err(TreeError::StaleSession)
+    ));
+}
+
+#[test]
+fn test_sessions_commit_atomic() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let NodeView::Inner(_, right) = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .build()
+        .get_node(&[])
+        .unwrap()
+    else {
+        panic!("the root is an inner node");
+    };
+    memory_db.remove(&right, EMPTY_PREFIX);
+
+    let mut sessions = Sessions::<Sha3>::new(root);
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let mut session = sessions.branch();
+    session.insert(&[0, 0, 0], vec![1]);
+    session.insert(&[1, 1, 1], vec![2]);
+    assert!(matches!(
+        sessions.commit(&mut tree_db_mut, session),
+        Err(TreeError::DataNotFound { .. })
+    ));
+    assert_eq!(tree_db_mut.peek_root(), *sessions.base());
+
+    // the failed session did not move the head
+    let mut session = sessions.branch();
+    session.insert(&[0, 0, 0], vec![1]);
+    sessions.commit(&mut tree_db_mut, session).unwrap();
+    assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![1]);
+}
+
+#[test]
+fn test_checkpoint_revert_logs() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut wal: Vec<u8> = Vec::new();
+    let mut audit_log = MutationLog::<Sha3>::new();
+    let reverted_root;
+    {
+        let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth)
+            .with_wal(&mut wal)
+            .with_audit_log(&mut audit_log)
+            .build();
+        tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+        reverted_root = tree_db_mut.peek_root();
+        let checkpoint = tree_db_mut.checkpoint();
+        tree_db_mut.insert(&[0, 1, 1], vec![2]).unwrap();
+        tree_db_mut.revert_to(checkpoint).unwrap();
+        tree_db_mut.insert(&[1, 0, 1], vec![3]).unwrap();
+    }
+
+    let mutations = audit_log.drain();
+    assert_eq!(mutations.len(), 4);
+    assert!(matches!(
+        mutations[2],
+        Mutation::Revert { retained: 1, root } if root == reverted_root
+    ));
+
+    // the reverted insert is not replayed
+    let mut tree_db_mut = TreeDBMutBuilder::new(&mut memory_db, &mut root, depth).build();
+    assert_eq!(
+        replay_wal(&mut wal.as_slice(), &mut tree_db_mut).unwrap(),
+        2
+    );
+    assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![1]);
+    assert_eq!(tree_db_mut.get_value(&[1, 0, 1]).unwrap(), vec![3]);
+    assert_eq!(
+        tree_db_mut.get_value(&[0, 1, 1]).unwrap(),
+        test_values()[3].to_le_bytes().to_vec()
+    );
+}
+
+#[test]
+fn test_checkpoint_revert() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let committed = root;
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+
+    // an outer frame that succeeds around an inner frame that is unwound
+    let outer = tree_db_mut.checkpoint();
+    tree_db_mut.insert(&[0, 0, 0], vec![1]).unwrap();
+    let after_outer = tree_db_mut.peek_root();
+    let inner = tree_db_mut.checkpoint();
+    tree_db_mut.insert(&[0, 0, 0], vec![2]).unwrap();
+    tree_db_mut.insert(&[1, 1, 1], vec![3]).unwrap();
+    let nested = tree_db_mut.checkpoint();
+
+    tree_db_mut.revert_to(inner).unwrap();
+    assert_eq!(tree_db_mut.peek_root(), after_outer);
+    assert_eq!(tree_db_mut.get_value(&[0, 0, 0]).unwrap(), vec![1]);
+    assert!(matches!(
+        tree_db_mut.revert_to(nested),
+        Err(TreeError::UnknownCheckpoint)
+    ));
+
+    // changes after a revert can be unwound again
+    tree_db_mut.insert(&[1, 0, 0], vec![4]).unwrap();
+    tree_db_mut.revert_to(outer).unwrap();
+    assert_eq!(tree_db_mut.peek_root(), committed);
+    assert_eq!(
+        tree_db_mut.get_value(&[0, 0, 0]).unwrap(),
+        test_values()[0].to_le_bytes().to_vec()
+    );
+
+    let checkpoint = tree_db_mut.checkpoint();
+    tree_db_mut.insert(&[0, 1, 0], vec![5]).unwrap();
+    tree_db_mut.commit();
+    assert!(matches!(
+        tree_db_mut.revert_to(checkpoint),
+        Err(TreeError::UnknownCheckpoint)
+    ));
+    drop(tree_db_mut);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&[0, 1, 0]).unwrap(), vec![5]);
+    assert_eq!(
+        tree_db.get_value(&[1, 1, 1]).unwrap(),
+        test_values()[7].to_le_bytes().to_vec()
+    );
+}
+
+#[test]
+fn test_get_node_through_traits() {
+    // recompute the hash of the node at `prefix` from its children, using only the trait
+    fn rehash<T: Tree<Sha3>>(tree: &T, prefix: &mut Vec<u8>) -> <Sha3 as Hasher>::Out {
+        match tree.get_node(prefix).unwrap() {
+            NodeView::Value(value) => Sha3::hash(&value),
+            NodeView::Inner(left, right) => {
+                for (bit, expected) in [(0, left), (1, right)] {
+                    prefix.push(bit);
+                    assert_eq!(rehash(tree, prefix), expected);
+                    prefix.pop();
+                }
+                Sha3::hash(&[left.as_ref(), right.as_ref()].concat())
+            }
+        }
+    }
+
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(rehash(&tree_db, &mut Vec::new()), root);
+    assert_eq!(
+        tree_db.get_node(&[0, 1, 0]).unwrap(),
+        NodeView::Value(test_values()[2].to_le_bytes().to_vec())
+    );
+    let NodeView::Inner(_, right) = tree_db.get_node(&[]).unwrap() else {
+        panic!("the root is an inner node");
+    };
+    assert_eq!(tree_db.get_node(&[1]).unwrap().hash(), right);
+    assert!(matches!(
+        tree_db.get_node(&[0, 1, 0, 1]),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+
+    // uncommitted inserts are visible through `TreeMut::get_node`
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 1, 0], vec![42]).unwrap();
+    assert_eq!(
+        tree_db_mut.get_node(&[0, 1, 0]).unwrap(),
+        NodeView::Value(vec![42])
+    );
+    let NodeView::Inner(left, _) = TreeMut::get_node(&tree_db_mut, &[]).unwrap() else {
+        panic!("the root is an inner node");
+    };
+    assert_eq!(tree_db_mut.get_node(&[0]).unwrap().hash(), left);
+    tree_db_mut.commit();
+}
+
+#[test]
+fn test_subtree_view() {
+    // written for any tree: the value and root recomputed from its proof
+    fn read<T: Tree<Sha3>>(tree: &T, key: &[u8]) -> DBValue {
+        let (value, proof) = tree.get_value_proof(key).unwrap();
+        let value = value.unwrap_or_default();
+        assert_eq!(&proof.compute_root(key, &value).unwrap(), tree.root());
+        value
+    }
+
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let view = tree_db.subtree_view(&[0, 1]).unwrap();
+    assert_eq!(view.depth(), 1);
+    assert_eq!(view.root(), &tree_db.get_node(&[0, 1]).unwrap().hash());
+    assert_eq!(read(&view, &[0]), read(&tree_db, &[0, 1, 0]));
+    assert_eq!(read(&view, &[1]), read(&tree_db, &[0, 1, 1]));
+    assert_eq!(tree_db.subtree_view(&[]).unwrap().root(), &root);
+    assert_eq!(
+        tree_db
+            .subtree_view(&[1, 0, 1])
+            .unwrap()
+            .get_value(&[])
+            .unwrap(),
+        tree_db.get_value(&[1, 0, 1]).unwrap()
+    );
+    assert!(matches!(
+        tree_db.subtree_view(&[0, 0, 0, 0]),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+
+    // a view of an empty shard of a sparse tree
+    let depth = 8;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0; 8], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let view = tree_db.subtree_view(&[1, 1, 0]).unwrap();
+    assert_eq!(view.root(), &compute_null_hashes::<Sha3>(5)[0]);
+    assert_eq!(read(&view, &[0, 1, 0, 1, 1]), Vec::<u8>::new());
+    let view = tree_db.subtree_view(&[0, 0, 0, 0]).unwrap();
+    assert_eq!(read(&view, &[0; 4]), vec![1]);
+}
+
+#[test]
+fn test_tree_metadata() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let metadata = TreeMetadata::new(b"accounts", depth);
+    let hash = metadata.persist::<Sha3>(&mut memory_db);
+    assert_eq!(
+        TreeMetadata::open::<Sha3>(&memory_db, &hash).unwrap(),
+        metadata
+    );
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_metadata(&hash)
+        .unwrap()
+        .build();
+    assert_eq!(
+        tree_db.get_value(&[0, 1, 0]).unwrap(),
+        test_values()[2].to_le_bytes().to_vec()
+    );
+    assert!(matches!(
+        TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth + 1).with_metadata(&hash),
+        Err(TreeError::MetadataMismatch)
+    ));
+
+    let newer = TreeMetadata {
+        codec_version: NODE_CODEC_VERSION + 1,
+        ..metadata
+    };
+    let newer = newer.persist::<Sha3>(&mut memory_db);
+    assert!(matches!(
+        TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).with_metadata(&newer),
+        Err(TreeError::MetadataMismatch)
+    ));
+    assert!(matches!(
+        TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).with_metadata(&Sha3::hash(b"missing")),
+        Err(TreeError::DataNotFound { .. })
+    ));
+
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth)
+        .with_metadata(&hash)
+        .unwrap()
+        .build();
+    tree_db_mut.insert(&[0, 1, 0], vec![1]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    assert!(TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, 2)
+        .with_metadata(&hash)
+        .is_err());
+}
+
+#[test]
+fn test_assert_root_consistency() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let stale_root = root;
+    let versions = VersionedRoots::<Sha3>::new(root);
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 1, 0], vec![1]).unwrap();
+    versions.commit(&mut tree_db_mut);
+    drop(tree_db_mut);
+    TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .build()
+        .assert_root_consistency(&versions)
+        .unwrap();
+
+    // a corrupt node below the root is caught by recomputing the root
+    let mut corrupt_db = memory_db.clone();
+    let leaf = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .build()
+        .get_leaf(&[0, 1, 0])
+        .unwrap();
+    corrupt_db.remove(&leaf, EMPTY_PREFIX);
+    corrupt_db.emplace(
+        leaf,
+        EMPTY_PREFIX,
+        Node::<Sha3>::Value(Value::Cached(vec![2])).into(),
+    );
+    assert!(matches!(
+        TreeDBBuilder::<Sha3>::new(&corrupt_db, &root, depth)
+            .build()
+            .assert_root_consistency(&versions),
+        Err(TreeError::HashMismatch)
+    ));
+
+    // the stale root is caught while its nodes are still stored
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &stale_root, depth).build();
+    assert!(matches!(
+        tree_db.assert_root_consistency(&versions),
+        Err(TreeError::StaleRoot)
+    ));
+
+    // a pruned root, a node stored under another hash and a leaf passed as the root of a
+    // deeper tree
+    memory_db.remove(&stale_root, EMPTY_PREFIX);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &stale_root, depth).build();
+    assert!(matches!(
+        tree_db.assert_root_consistency(&VersionedRoots::new(stale_root)),
+        Err(TreeError::DataNotFound { depth: 0, .. })
+    ));
+    let bogus = Sha3::hash(b"bogus");
+    let node: Vec<u8> = Node::<Sha3>::Value(Value::Cached(vec![7])).into();
+    memory_db.emplace(bogus, EMPTY_PREFIX, node);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &bogus, depth).build();
+    assert!(matches!(
+        tree_db.assert_root_consistency(&VersionedRoots::new(bogus)),
+        Err(TreeError::HashMismatch)
+    ));
+    let leaf = Sha3::hash(&[7]);
+    memory_db.emplace(leaf, EMPTY_PREFIX, vec![0, 7]);
+    let versions = VersionedRoots::new(leaf);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &leaf, depth).build();
+    assert!(matches!(
+        tree_db.assert_root_consistency(&versions),
+        Err(TreeError::UnexpectedNodeType)
+    ));
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &leaf, 0).build();
+    tree_db.assert_root_consistency(&versions).unwrap();
+}
+
+#[test]
+fn test_first_and_last_key() {
+    let depth = 8;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    assert_eq!(tree_db_mut.first_key().unwrap(), None);
+    assert_eq!(tree_db_mut.last_key().unwrap(), None);
+
+    let keys = [
+        [0, 0, 1, 1, 0, 1, 0, 0],
+        [0, 1, 0, 0, 0, 0, 0, 1],
+        [1, 0, 1, 1, 1, 1, 1, 0],
+    ];
+    for key in keys.iter() {
+        tree_db_mut.insert(key, vec![1]).unwrap();
+    }
+    // uncommitted inserts are seen
+    assert_eq!(tree_db_mut.first_key().unwrap(), Some(keys[0].to_vec()));
+    assert_eq!(tree_db_mut.last_key().unwrap(), Some(keys[2].to_vec()));
+
+    // clearing the last value moves the frontier back
+    tree_db_mut.insert(&keys[2], Vec::new()).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.first_key().unwrap(), Some(keys[0].to_vec()));
+    assert_eq!(tree_db.last_key().unwrap(), Some(keys[1].to_vec()));
+
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.first_key().unwrap(), Some(vec![0, 0, 0]));
+    assert_eq!(tree_db.last_key().unwrap(), Some(vec![1, 1, 1]));
+}
+
+#[test]
+fn test_count_prefix() {
+    let depth = 8;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let keys: Vec<Vec<u8>> = (0..40u8)
+        .map(|i| (0..8).map(|bit| (i.wrapping_mul(37) >> bit) & 1).collect())
+        .collect();
+    for key in keys.iter() {
+        tree_db_mut.insert(key, vec![1]).unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    for prefix in [&[][..], &[0], &[1, 0], &[1, 1, 0, 1], &keys[3]] {
+        let expected = keys.iter().filter(|key| key.starts_with(prefix)).count();
+        assert_eq!(tree_db.count_prefix(prefix).unwrap(), expected);
+    }
+    assert!(matches!(
+        tree_db.count_prefix(&[0; 9]),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+
+    // only the path to the prefix and the subtree under it are read
+    let right = *tree_db
+        .get(&[])
+        .unwrap()
+        .get_right_child()
+        .unwrap()
+        .get_hash();
+    drop(tree_db);
+    memory_db.remove(&right, EMPTY_PREFIX);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let expected = keys.iter().filter(|key| key[0] == 0).count();
+    assert_eq!(tree_db.count_prefix(&[0]).unwrap(), expected);
+    assert!(tree_db.count_prefix(&[1]).is_err());
+}
+
+#[test]
+fn test_light_tree() {
+    let depth = 8;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let key = [0, 1, 1, 0, 1, 0, 0, 1];
+    let empty_key = [1; 8];
+    tree_db_mut.insert(&key, vec![3]).unwrap();
+    tree_db_mut.commit();
+
+    let mut light = LightTree::<Sha3>::new(tree_db_mut.peek_root(), depth);
+    let (value, proof) = tree_db_mut.get_value_proof(&key).unwrap();
+    assert_eq!(value, Some(vec![3]));
+    assert!(light.verify_inclusion(&key, &[3], &proof));
+    assert!(!light.verify_inclusion(&key, &[4], &proof));
+    assert!(!light.verify_absence(&key, &proof));
+
+    let (value, proof) = tree_db_mut.get_value_proof(&empty_key).unwrap();
+    assert_eq!(value, None);
+    assert!(light.verify_absence(&empty_key, &proof));
+    assert!(!light.verify_inclusion(&empty_key, &[], &proof));
+
+    // the client follows the full node through a verified update
+    assert!(matches!(
+        light.apply_verified_update(&empty_key, &[1], &[9], &proof),
+        Err(TreeError::InvalidProof)
+    ));
+    light
+        .apply_verified_update(&empty_key, &[], &[9], &proof)
+        .unwrap();
+    tree_db_mut.insert(&empty_key, vec![9]).unwrap();
+    assert_eq!(light.root(), tree_db_mut.root());
+    let (_, proof) = tree_db_mut.get_value_proof(&empty_key).unwrap();
+    assert!(light.verify_inclusion(&empty_key, &[9], &proof));
+}
+
+#[test]
+fn test_verify_batch_transition() {
+    let depth = 8;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    for i in 0..16u8 {
+        let key: Vec<u8> = (0..8).map(|bit| (i.wrapping_mul(53) >> bit) & 1).collect();
+        tree_db_mut.insert(&key, vec![i + 1]).unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    let old_root = root;
+
+    // the sequencer records the witness, applies the batch and publishes the new root
+    let updated = vec![1, 0, 1, 0, 1, 1, 0, 0];
+    let created = vec![1, 1, 1, 1, 1, 1, 1, 1];
+    let updates = vec![
+        (updated.clone(), vec![7]),
+        (created.clone(), vec![8]),
+        (updated.clone(), vec![9]),
+    ];
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let multiproof = record_access_list(&tree_db, &[&updated, &created]).unwrap();
+    drop(tree_db);
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    for (key, value) in updates.iter() {
+        tree_db_mut.insert(key, value.clone()).unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    verify_batch_transition::<Sha3>(&old_root, depth, &multiproof, &updates, &root).unwrap();
+    assert!(matches!(
+        verify_batch_transition::<Sha3>(&old_root, depth, &multiproof, &updates[..2], &root),
+        Err(TreeError::PostStateRootMismatch)
+    ));
+    let uncovered = vec![(vec![0; 8], vec![1])];
+    assert!(matches!(
+        verify_batch_transition::<Sha3>(&old_root, depth, &multiproof, &uncovered, &root),
+        Err(TreeError::DataNotFound { .. })
+    ));
+}
+
+#[test]
+fn test_node_fetcher() {
+    // a peer serving nodes from its copy of the state, optionally corrupting them
+    struct Peer<'a> {
+        db: &'a TestDb,
+        fetched: RefCell<usize>,
+        corrupt: bool,
+    }
+
+    impl NodeFetcher<Sha3> for Peer<'_> {
+        fn fetch(&self, hash: &<Sha3 as Hasher>::Out) -> Option<DBValue> {
+            *self.fetched.borrow_mut() += 1;
+            let mut node = HashDB::get(self.db, hash, EMPTY_PREFIX)?;
+            if self.corrupt {
+                *node.last_mut()? ^= 1;
+            }
+            Some(node)
+        }
+    }
+
+    let (remote_db, root, depth) = build_db_mock();
+    let peer = Peer {
+        db: &remote_db,
+        fetched: RefCell::new(0),
+        corrupt: false,
+    };
+    let local_db = TestDb::default();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&local_db, &root, depth)
+        .with_node_fetcher(&peer)
+        .build();
+    assert_eq!(
+        tree_db.get_value(&[1, 0, 1]).unwrap(),
+        test_values()[5].to_le_bytes().to_vec()
+    );
+    assert_eq!(*peer.fetched.borrow(), depth + 1);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&local_db, &root, depth).build();
+    assert!(matches!(
+        tree_db.get_value(&[1, 0, 1]),
+        Err(TreeError::DataNotFound { .. })
+    ));
+
+    let liar = Peer {
+        corrupt: true,
+        ..peer
+    };
+    let tree_db = TreeDBBuilder::<Sha3>::new(&local_db, &root, depth)
+        .with_node_fetcher(&liar)
+        .build();
+    assert!(matches!(
+        tree_db.get_value(&[1, 0, 1]),
+        Err(TreeError::HashMismatch)
+    ));
+
+    // writes pull in the path they touch and commit the changed nodes locally
+    let peer = Peer {
+        corrupt: false,
+        ..liar
+    };
+    let (mut expected_db, mut expected_root, _) = build_db_mock();
+    let mut expected =
+        TreeDBMutBuilder::<Sha3>::new(&mut expected_db, &mut expected_root, depth).build();
+    expected.insert(&[0, 0, 1], vec![42]).unwrap();
+    expected.commit();
+    drop(expected);
+
+    let mut local_db = TestDb::default();
+    let mut local_root = root;
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut local_db, &mut local_root, depth)
+        .with_node_fetcher(&peer)
+        .build();
+    tree_db_mut.insert(&[0, 0, 1], vec![42]).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+    assert_eq!(local_root, expected_root);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&local_db, &local_root, depth).build();
+    assert_eq!(tree_db.get_value(&[0, 0, 1]).unwrap(), vec![42]);
+}
+
+#[test]
+fn test_sweep_expired() {
+    let depth = 8;
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let keys: Vec<Vec<u8>> = (0..20u8)
+        .map(|i| (0..8).map(|bit| (i.wrapping_mul(29) >> bit) & 1).collect())
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        tree_db_mut
+            .insert_with_expiry(key, b"session key", 100 + i as u64 % 5)
+            .unwrap();
+    }
+    tree_db_mut.commit();
+
+    assert!(tree_db_mut.sweep_expired(99).unwrap().is_empty());
+    let mut expected: Vec<Vec<u8>> = keys
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i % 5 <= 1)
+        .map(|(_, key)| key.clone())
+        .collect();
+    expected.sort();
+    assert_eq!(tree_db_mut.sweep_expired(101).unwrap(), expected);
+    let value = tree_db_mut.get_value(&keys[2]).unwrap();
+    assert_eq!(split_expiry(&value).unwrap(), (102, &b"session key"[..]));
+    assert_eq!(tree_db_mut.get_value(&keys[1]).unwrap(), Vec::<u8>::new());
+
+    // sweeping everything leaves the empty tree
+    assert_eq!(tree_db_mut.sweep_expired(u64::MAX).unwrap().len(), 12);
+    assert_eq!(tree_db_mut.root(), &compute_null_hashes::<Sha3>(depth)[0]);
+
+    tree_db_mut.insert(&keys[0], vec![1]).unwrap();
+    assert!(matches!(
+        tree_db_mut.sweep_expired(0),
+        Err(TreeError::MissingExpiry)
+    ));
+    tree_db_mut.commit();
+}
+
+#[test]
+fn test_conditional_writes() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut meter = TestMeter {
+        charges: Vec::new(),
+    };
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth)
+        .with_access_meter(&mut meter)
+        .build();
+    let tree: &mut dyn TreeMut<Sha3> = &mut tree_db_mut;
+    let stored = test_values()[2].to_le_bytes().to_vec();
+
+    // a populated leaf is not overwritten and the tree is unchanged
+    let before = *tree.root();
+    assert_eq!(
+        tree.insert_if_absent(&[0, 1, 0], vec![1]).unwrap(),
+        ConditionalWrite::Rejected(stored.clone())
+    );
+    assert_eq!(
+        tree.compare_and_swap(&[0, 1, 0], &[1], vec![2]).unwrap(),
+        ConditionalWrite::Rejected(stored.clone())
+    );
+    assert_eq!(tree.root(), &before);
+
+    assert_eq!(
+        tree.compare_and_swap(&[0, 1, 0], &stored, vec![2]).unwrap(),
+        ConditionalWrite::Written(stored)
+    );
+    assert_eq!(tree.get_value(&[0, 1, 0]).unwrap(), vec![2]);
+
+    // an emptied leaf is absent again
+    tree.insert(&[1, 1, 0], Vec::new()).unwrap();
+    assert_eq!(
+        tree.insert_if_absent(&[1, 1, 0], vec![3]).unwrap(),
+        ConditionalWrite::Written(Vec::new())
+    );
+    assert_eq!(
+        tree.compare_and_swap(&[1, 1, 0], &[3], Vec::new()).unwrap(),
+        ConditionalWrite::Written(vec![3])
+    );
+    assert!(matches!(
+        tree.insert_if_absent(&[1, 1], vec![3]),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    // each check and write reads the path to the leaf once
+    assert_eq!(meter.charges[0].0, depth + 1);
+    assert_eq!(meter.charges[1].0, depth + 1);
+}
+
+#[test]
+fn test_verify_with_siblings() {
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let key = [1, 0, 1];
+    let (value, proof) = tree_db.get_value_proof(&key).unwrap();
+    let leaf = Sha3::hash(&value.unwrap());
+    let siblings = proof.siblings();
+    assert!(verify_with_siblings::<Sha3>(&root, &key, &leaf, &siblings));
+    assert!(!verify_with_siblings::<Sha3>(
+        &root,
+        &[1, 0, 0],
+        &leaf,
+        &siblings
+    ));
+    assert!(!verify_with_siblings::<Sha3>(
+        &root,
+        &[1, 0, 2],
+        &leaf,
+        &siblings
+    ));
+    assert!(!verify_with_siblings::<Sha3>(
+        &root,
+        &key,
+        &leaf,
+        &siblings[1..]
+    ));
+
+    // the lower siblings alone prove the leaf under the subtree at the key's prefix
+    let subtree_root = tree_db.get_node(&key[..1]).unwrap().hash();
+    assert!(verify_with_siblings::<Sha3>(
+        &subtree_root,
+        &key[1..],
+        &leaf,
+        &siblings[..2]
+    ));
+}
+
+#[test]
+fn test_proof_header() {
+    let depth = 16;
+    let proof = Proof::<Sha3>::from_siblings(compute_null_hashes::<Sha3>(depth)[1..].to_vec());
+    let encoded = proof.to_sparse().encode();
+    let (header, _) = ProofHeader::decode(&encoded).unwrap();
+    assert_eq!(header, ProofHeader::new::<Sha3>(depth));
+    assert_eq!(header.version, PROOF_FORMAT_VERSION);
+    SparseProof::<Sha3>::decode(&encoded, depth).unwrap();
+
+    // proofs for another depth, hasher or format version are rejected
+    assert!(matches!(
+        SparseProof::<Sha3>::decode(&encoded, 24),
+        Err(TreeError::IncompatibleProof)
+    ));
+    assert!(matches!(
+        SparseProof::<FeltHash<MockPedersen>>::decode(&encoded, depth),
+        Err(TreeError::IncompatibleProof)
+    ));
+    let mut newer = encoded.clone();
+    newer[0] += 1;
+    assert!(matches!(
+        SparseProof::<Sha3>::decode(&newer, depth),
+        Err(TreeError::IncompatibleProof)
+    ));
+    assert!(matches!(
+        ProofHeader::decode(&encoded[..PROOF_HEADER_LEN - 1]),
+        Err(TreeError::ProofDecodeFailed)
+    ));
+}
+
+#[test]
+fn test_commit_summary() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let mut summaries = Vec::new();
+    let mut on_commit = |summary: &CommitSummary<Sha3>| summaries.push(summary.clone());
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth)
+        .with_commit_summary(&mut on_commit)
+        .build();
+    tree_db_mut.insert(&[0, 1, 0], vec![1]).unwrap();
+    tree_db_mut.insert(&[0, 1, 1], vec![2]).unwrap();
+    let stats = tree_db_mut.try_commit().unwrap();
+    tree_db_mut.commit();
+    let new_root = *tree_db_mut.root();
+    drop(tree_db_mut);
+
+    // the root, two inner nodes and two leaves of the changed path
+    assert_eq!(stats.written, 5);
+    assert_eq!(summaries.len(), 3);
+    assert_eq!(summaries[0].written, stats.written);
+    assert_eq!(summaries[0].skipped, stats.skipped);
+    assert_eq!(summaries[0].bytes, 3 * (1 + 2 * Sha3::LENGTH) + 2 * 2);
+    assert_eq!(summaries[0].root, new_root);
+
+    // commits with nothing to write are summarized too
+    assert_eq!(summaries[1].written, 0);
+    assert_eq!(summaries[1].bytes, 0);
+    assert_eq!(summaries[2].root, new_root);
+}
+
+#[test]
+fn test_tree_at_version() {
+    let (mut memory_db, mut root, depth) = build_db_mock();
+    let versions = VersionedRoots::<Sha3>::new(root);
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    tree_db_mut.insert(&[0, 1, 0], vec![1]).unwrap();
+    assert_eq!(versions.commit(&mut tree_db_mut), 1);
+    drop(tree_db_mut);
+
+    let state_at =
+        |version| TreeDB::at_version(&memory_db, &versions, version, depth)?.get_value(&[0, 1, 0]);
+    assert_eq!(
+        state_at(0).unwrap(),
+        test_values()[2].to_le_bytes().to_vec()
+    );
+    assert_eq!(state_at(1).unwrap(), vec![1]);
+    assert!(matches!(state_at(2), Err(TreeError::UnknownVersion)));
+}
+
+#[test]
+fn test_flat_tree() {
+    let depth = 6;
+    let key = |leaf: usize| -> Vec<u8> {
+        (0..depth)
+            .rev()
+            .map(|bit| ((leaf >> bit) & 1) as u8)
+            .collect()
+    };
+    let leaves: Vec<DBValue> = (1..51u8).map(|i| vec![i; 1 + i as usize % 3]).collect();
+    let mut flat = FlatTree::<Sha3>::from_leaves(depth, leaves.clone()).unwrap();
+
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    for (leaf, value) in leaves.iter().enumerate() {
+        tree_db_mut.insert(&key(leaf), value.clone()).unwrap();
+    }
+    assert_eq!(flat.root(), tree_db_mut.root());
+
+    // a batch update matches the same inserts on the hash-addressed tree
+    let updates = vec![(3, vec![9]), (4, Vec::new()), (63, vec![1]), (3, vec![7])];
+    flat.update(&updates).unwrap();
+    for (leaf, value) in updates.iter() {
+        tree_db_mut.insert(&key(*leaf), value.clone()).unwrap();
+    }
+    assert_eq!(flat.root(), tree_db_mut.root());
+    assert_eq!(flat.value(3), Some(&vec![7]));
+    drop(tree_db_mut);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    for leaf in [0, 3, 4, 49, 50, 63] {
+        assert_eq!(
+            flat.get_proof(&key(leaf)).unwrap(),
+            tree_db.get_proof(&key(leaf)).unwrap()
+        );
+        assert_eq!(
+            flat.get_leaf(&key(leaf)).unwrap(),
+            tree_db.get_leaf(&key(leaf)).unwrap()
+        );
+    }
+    assert_eq!(
+        flat.get_node(&[1, 1]).unwrap(),
+        tree_db.get_node(&[1, 1]).unwrap()
+    );
+
+    // persisted nodes are read through the hash-addressed path
+    let mut persisted = TestDb::default();
+    let persisted_root = flat.persist(&mut persisted);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&persisted, &persisted_root, depth).build();
+    assert_eq!(tree_db.get_value(&key(10)).unwrap(), leaves[10]);
+    assert_eq!(tree_db.get_value(&key(63)).unwrap(), vec![1]);
+    assert_eq!(tree_db.get_value(&key(60)).unwrap(), Vec::<u8>::new());
+
+    assert!(FlatTree::<Sha3>::from_leaves(MAX_FLAT_DEPTH + 1, Vec::new()).is_err());
+    assert!(FlatTree::<Sha3>::from_leaves(2, vec![vec![1]; 5]).is_err());
+    assert!(flat.update(&[(64, vec![1])]).is_err());
+}
+
+#[test]
+fn test_get_leaf_proof_skips_values() {
+    let (mut memory_db, root, depth) = build_db_mock();
+    let key = [0, 1, 0];
+    let value = 13u32.to_le_bytes().to_vec();
+    let leaf = Sha3::hash(&value);
+    while memory_db.contains(&leaf, EMPTY_PREFIX) {
+        memory_db.remove(&leaf, EMPTY_PREFIX);
+    }
+
+    // the value node is gone, but hashes and proofs only read the inner nodes
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let (leaf_hash, proof) = tree_db.get_leaf_proof(&key).unwrap();
+    assert_eq!(leaf_hash, leaf);
+    assert_eq!(tree_db.get_leaf(&key).unwrap(), leaf);
+    assert!(verify_with_siblings::<Sha3>(
+        &root,
+        &key,
+        &leaf_hash,
+        &proof.siblings()
+    ));
+    assert_eq!(proof.compute_root(&key, &value).unwrap(), root);
+
+    // reading the value or a proof carrying it still fails
+    assert!(matches!(
+        tree_db.get_value(&key),
+        Err(TreeError::DataNotFound { .. })
+    ));
+    assert!(matches!(
+        tree_db.get_proof(&key),
+        Err(TreeError::DataNotFound { .. })
+    ));
+}
+
+#[test]
+fn test_recorder_filter() {
+    let mut recorder = Recorder::new().with_filter(&[0]);
+    let (memory_db, root, depth) = build_db_mock();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_recorder(&mut recorder)
+        .build();
+    let expected_value = tree_db.get_value(&[0, 1, 0]).unwrap();
+    tree_db.get_value(&[1, 0, 0]).unwrap();
+    tree_db.get_proof(&[1, 1, 1]).unwrap();
+
+    // only the root and the nodes along [0, 1, 0] are recorded
+    let proof = recorder.drain_storage_proof();
+    assert_eq!(proof.nodes().count(), depth + 1);
+    let proof_tree = proof.into_tree::<Sha3>(root, depth).unwrap();
+    assert_eq!(proof_tree.get_value(&[0, 1, 0]).unwrap(), expected_value);
+    assert!(matches!(
+        proof_tree.get_value(&[1, 0, 0]),
+        Err(TreeError::DataNotFound { .. })
+    ));
+}
+
+#[test]
+fn test_from_frontier() {
+    let depth = 4;
+    let key = |index: u64| -> Vec<u8> {
+        (0..depth)
+            .rev()
+            .map(|height| (index >> height) as u8 & 1)
+            .collect()
+    };
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    for index in 0..11 {
+        tree_db_mut
+            .insert(&key(index), vec![index as u8 + 1])
+            .unwrap();
+    }
+    let frontier = tree_db_mut.frontier(11).unwrap();
+    assert_eq!(frontier.siblings().len(), 3);
+    for index in 11..13 {
+        tree_db_mut
+            .insert(&key(index), vec![index as u8 + 1])
+            .unwrap();
+    }
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    // appending to the resumed tree reaches the same root without the earlier leaves
+    let mut resumed_root = Default::default();
+    let mut resumed_db = TestDb::default();
+    let mut resumed = TreeDBMut::from_frontier(
+        &mut resumed_db,
+        &mut resumed_root,
+        frontier.siblings(),
+        frontier.next_index(),
+        depth,
+    )
+    .unwrap();
+    for index in 11..13 {
+        resumed.insert(&key(index), vec![index as u8 + 1]).unwrap();
+    }
+    assert!(matches!(
+        resumed.get_value(&key(3)),
+        Err(TreeError::DataNotFound { .. })
+    ));
+    resumed.commit();
+    drop(resumed);
+    assert_eq!(resumed_root, root);
+
+    let mut empty_root = Default::default();
+    let mut empty =
+        TreeDBMut::from_frontier(&mut resumed_db, &mut empty_root, &[], 0, depth).unwrap();
+    assert_eq!(empty.root(), &compute_null_hashes::<Sha3>(depth)[0]);
+    assert!(matches!(
+        TreeDBMut::from_frontier(
+            &mut resumed_db,
+            &mut empty_root,
+            frontier.siblings(),
+            12,
+            depth
+        ),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+}
+
+#[test]
+fn test_frontier_encoding() {
+    let depth = 5;
+    let key = |index: u64| -> Vec<u8> {
+        (0..depth)
+            .rev()
+            .map(|height| (index >> height) as u8 & 1)
+            .collect()
+    };
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let empty = tree_db_mut.frontier(0).unwrap();
+    for index in 0..21 {
+        tree_db_mut
+            .insert(&key(index), vec![index as u8 + 1])
+            .unwrap();
+    }
+    let frontier = tree_db_mut.frontier(21).unwrap();
+    tree_db_mut.commit();
+    drop(tree_db_mut);
+
+    let decoded = Frontier::<Sha3>::decode(&frontier.encode()).unwrap();
+    assert_eq!(decoded, frontier);
+    assert_eq!(decoded.root(), root);
+    decoded.check(&root, 21).unwrap();
+    assert!(matches!(
+        decoded.check(&root, 20),
+        Err(TreeError::FrontierMismatch)
+    ));
+    assert!(matches!(
+        empty.check(&root, 21),
+        Err(TreeError::FrontierMismatch)
+    ));
+    empty
+        .check(&compute_null_hashes::<Sha3>(depth)[0], 0)
+        .unwrap();
+
+    // truncated data and sibling counts not matching the leaf count are rejected
+    let encoded = frontier.encode();
+    assert!(Frontier::<Sha3>::decode(&encoded[..encoded.len() - 1]).is_err());
+    assert!(matches!(
+        Frontier::<Sha3>::new(depth, 24, frontier.siblings().to_vec()),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+
+    // trees deeper than 64 levels have no set bits above the index, deeper than keys allow none
+    let deep = Frontier::<Sha3>::new(70, 1, vec![Sha3::hash(&[1])]).unwrap();
+    let mut deep_db = TestDb::default();
+    let mut deep_root = compute_null_hashes::<Sha3>(70)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut deep_db, &mut deep_root, 70).build();
+    tree.insert(&[0; 70], vec![1]).unwrap();
+    assert_eq!(&deep.root(), tree.root());
+    assert!(matches!(
+        Frontier::<Sha3>::new(MAX_KEY_DEPTH + 1, 0, Vec::new()),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+    let siblings: Vec<Vec<u8>> = Vec::new();
+    let huge = bincode::serialize(&(u64::MAX, 0u64, siblings)).unwrap();
+    assert!(matches!(
+        Frontier::<Sha3>::decode(&huge),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+}
+
+#[test]
+fn test_forest_transaction() {
+    let mut memory_db = TestDb::default();
+    let mut forest = Forest::<Sha3>::new();
+    forest.create("parent", 3).unwrap();
+    forest.create("child", 3).unwrap();
+    let parent_root = *forest.root("parent").unwrap();
+
+    let mut transaction = forest.transaction(&memory_db);
+    {
+        let mut child = transaction.tree_mut("child").unwrap();
+        child.insert(&[0, 1, 0], vec![9]).unwrap();
+        child.commit();
+    }
+    let child_root = *transaction.root("child").unwrap();
+    {
+        let mut parent = transaction.tree_mut("parent").unwrap();
+        parent
+            .insert(&[1, 0, 0], child_root.as_ref().to_vec())
+            .unwrap();
+        parent.commit();
+    }
+    let staged_parent = *transaction.root("parent").unwrap();
+    let changes = transaction.into_changes();
+    assert_eq!(changes.trees().collect::<Vec<_>>(), vec!["child", "parent"]);
+
+    // nothing is written or published before the commit
+    assert_eq!(forest.root("parent"), Some(&parent_root));
+    assert!(!memory_db.contains(&staged_parent, EMPTY_PREFIX));
+
+    forest.commit_transaction(&mut memory_db, changes).unwrap();
+    assert_eq!(forest.root("parent"), Some(&staged_parent));
+    let parent = forest.tree(&memory_db, "parent").unwrap();
+    assert_eq!(
+        parent.get_value(&[1, 0, 0]).unwrap(),
+        child_root.as_ref().to_vec()
+    );
+    let child = forest.tree(&memory_db, "child").unwrap();
+    assert_eq!(child.get_value(&[0, 1, 0]).unwrap(), vec![9]);
+
+    // a transaction started before a tree changed is rejected as a whole
+    let mut stale = forest.transaction(&memory_db);
+    stale
+        .tree_mut("child")
+        .unwrap()
+        .insert(&[0, 0, 0], vec![1])
+        .unwrap();
+    {
+        let mut tree = stale.tree_mut("parent").unwrap();
+        tree.insert(&[0, 0, 0], vec![2]).unwrap();
+        tree.commit();
+    }
+    let stale = stale.into_changes();
+    {
+        let mut tree = forest.tree_mut(&mut memory_db, "parent").unwrap();
+        tree.insert(&[1, 1, 1], vec![3]).unwrap();
+        tree.commit();
+    }
+    let child_root = *forest.root("child").unwrap();
+    assert!(matches!(
+        forest.commit_transaction(&mut memory_db, stale),
+        Err(TreeError::StaleTransaction)
+    ));
+    assert_eq!(forest.root("child"), Some(&child_root));
+}
+
+#[test]
+fn test_node_cache() {
+    let (memory_db, root, depth) = build_db_mock();
+    let inner_size = 32 + 1 + 2 * 32;
+    let leaf_size = 32 + 1 + 4;
+    let cache = NodeCache::<Sha3>::new(3 * inner_size + leaf_size);
+
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_node_cache(&cache)
+        .build();
+    let value = tree_db.get_value(&[0, 1, 0]).unwrap();
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 4, 0));
+    assert_eq!(stats.bytes, 3 * inner_size + leaf_size);
+
+    // a second tree sharing the cache is served the cached nodes
+    let empty_db = TestDb::default();
+    let cached_tree = TreeDBBuilder::<Sha3>::new(&empty_db, &root, depth)
+        .with_node_cache(&cache)
+        .build();
+    assert_eq!(
+        cached_tree.get_leaf(&[0, 1, 0]).unwrap(),
+        Sha3::hash(&value)
+    );
+    assert_eq!(cache.stats().hits, 3);
+
+    // shrinking the limit evicts the least recently used nodes, starting with the leaf and root
+    cache.set_byte_limit(inner_size);
+    let stats = cache.stats();
+    assert_eq!((stats.entries, stats.evictions), (1, 3));
+    assert!(matches!(
+        cached_tree.get_leaf(&[0, 1, 0]),
+        Err(TreeError::DataNotFound { .. })
+    ));
+
+    cache.clear();
+    assert_eq!(cache.stats().bytes, 0);
+    assert_eq!(tree_db.get_value(&[0, 1, 0]).unwrap(), value);
+}
+
+#[test]
+fn test_node_cache_rejects_corrupt_nodes() {
+    let (mut memory_db, root, depth) = build_db_mock();
+    let (clean_db, _, _) = build_db_mock();
+    let leaf = Sha3::hash(&test_values()[0].to_le_bytes());
+    memory_db.as_hash_db_mut().remove(&leaf, EMPTY_PREFIX);
+    memory_db
+        .as_hash_db_mut()
+        .emplace(leaf, EMPTY_PREFIX, vec![0, 1, 2, 3]);
+    let cache = NodeCache::<Sha3>::new(1 << 16);
+
+    // a tree that does not verify hashes reads the corrupt leaf but does not cache it
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth)
+        .with_node_cache(&cache)
+        .build();
+    assert_eq!(tree_db.get_value(&[0, 0, 0]).unwrap(), vec![1, 2, 3]);
+    assert!(cache.get(&leaf).is_none());
+    cache.insert(leaf, vec![0, 1, 2, 3]);
+    assert!(cache.get(&leaf).is_none());
+
+    let verified = TreeDBBuilder::<Sha3>::new(&clean_db, &root, depth)
+        .with_node_cache(&cache)
+        .with_hash_verification()
+        .build();
+    assert_eq!(
+        verified.get_value(&[0, 0, 0]).unwrap(),
+        test_values()[0].to_le_bytes().to_vec()
+    );
+}
+
+#[test]
+fn test_arena_overlay() {
+    let depth = 8;
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..200u32)
+        .map(|index| {
+            let key = (0..depth).map(|bit| (index >> bit) as u8 & 1).collect();
+            (key, vec![index as u8 + 1; 40])
+        })
+        .collect();
+
+    let mut expected_root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut expected_db = TestDb::default();
+    let mut expected =
+        TreeDBMutBuilder::<Sha3>::new(&mut expected_db, &mut expected_root, depth).build();
+    for (key, value) in entries.iter() {
+        expected.insert(key, value.clone()).unwrap();
+    }
+    expected.commit();
+    drop(expected);
+
+    let mut root = compute_null_hashes::<Sha3>(depth)[0];
+    let mut memory_db = TestDb::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth)
+        .with_arena(1024)
+        .build();
+    for (key, value) in entries.iter() {
+        tree_db_mut.insert(key, value.clone()).unwrap();
+    }
+    // uncommitted values are read back from the arena
+    assert_eq!(tree_db_mut.get_value(&entries[7].0).unwrap(), entries[7].1);
+    assert!(tree_db_mut.arena_capacity() >= 200 * 40);
+
+    // the commit writes the same nodes and releases all but the first chunk
+    tree_db_mut.commit();
+    assert_eq!(tree_db_mut.arena_capacity(), 1024);
+    drop(tree_db_mut);
+    assert_eq!(root, expected_root);
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    assert_eq!(tree_db.get_value(&entries[199].0).unwrap(), entries[199].1);
+}
+
+#[test]
+fn test_node_hash_and_value_traits() {
+    let low = Sha3::hash(&[1]);
+    let high = Sha3::hash(&[2]);
+    let (low, high) = if low.as_ref() < high.as_ref() {
+        (low, high)
+    } else {
+        (high, low)
+    };
+
+    let handles: BTreeSet<NodeHash<Sha3>> = [
+        NodeHash::Hash(high),
+        NodeHash::InMemory(high),
+        NodeHash::Hash(low),
+        NodeHash::Hash(low),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(
+        handles.into_iter().collect::<Vec<_>>(),
+        vec![
+            NodeHash::InMemory(high),
+            NodeHash::Hash(low),
+            NodeHash::Hash(high)
+        ]
+    );
+    assert_ne!(NodeHash::<Sha3>::Hash(low), NodeHash::InMemory(low));
+    assert_eq!(NodeHash::<Sha3>::InMemory(low).hash(), low);
+
+    let values: HashSet<Value> = [Value::New(vec![1]), Value::Cached(vec![1])]
+        .into_iter()
+        .collect();
+    assert_eq!(values.len(), 2);
+    assert!(Value::Cached(vec![2]) < Value::New(vec![1]));
+    assert_eq!(Value::New(vec![3]).into_inner(), vec![3]);
+}
+
+#[test]
+fn test_node_encode_into_and_decode() {
+    let value = Node::<Sha3>::Value(Value::New(vec![4, 5]));
+    let inner = Node::<Sha3>::Inner(
+        NodeHash::Hash(Sha3::hash(&[1])),
+        NodeHash::InMemory(Sha3::hash(&[2])),
+    );
+
+    // encodings are appended to a reused buffer and match the owned encodings
+    let mut buffer = Vec::new();
+    value.encode_into(&mut buffer);
+    let split = buffer.len();
+    inner.encode_into(&mut buffer);
+    assert_eq!(buffer[..split], Vec::<u8>::from(value.clone()));
+    assert_eq!(buffer[split..], Vec::<u8>::from(inner.clone()));
+
+    let decoded = Node::<Sha3>::decode(&buffer[split..]).unwrap();
+    assert_eq!(decoded.hash(), inner.hash());
+    assert_eq!(
+        Node::<Sha3>::decode(&buffer[..split])
+            .unwrap()
+            .get_value()
+            .unwrap()
+            .get(),
+        &vec![4, 5]
+    );
+    assert!(Node::<Sha3>::decode(&buffer[split..split + 10]).is_err());
+    assert!(Node::<Sha3>::decode(&[]).is_err());
+}
+
+#[test]
+fn test_indexed_tree() {
+    let mut root = null_root::<Sha3>(64);
+    let mut memory_db = TestDb::default();
+    let mut tree = IndexedTree::<Sha3>::new(&mut memory_db, &mut root);
+    tree.insert(0, vec![1]).unwrap();
+    tree.insert(u64::MAX, vec![2]).unwrap();
+    assert_eq!(tree.insert(1 << 40, vec![3]).unwrap(), Vec::<u8>::new());
+    assert_eq!(tree.get(u64::MAX).unwrap(), vec![2]);
+    assert_eq!(tree.get(7).unwrap(), Vec::<u8>::new());
+    let committed = *tree.root();
+
+    // proofs check against the key the index maps to
+    let (value, proof) = tree.prove(1 << 40).unwrap();
+    assert_eq!(value, Some(vec![3]));
+    let key = IndexedTree::<Sha3>::key(1 << 40);
+    assert_eq!(key.iter().position(|bit| *bit == 1), Some(23));
+    assert_eq!(proof.compute_root(&key, &[3]).unwrap(), committed);
+    let (value, proof) = tree.prove(5).unwrap();
+    assert_eq!(value, None);
+    assert_eq!(
+        proof
+            .compute_root(&IndexedTree::<Sha3>::key(5), &[])
+            .unwrap(),
+        committed
+    );
+
+    drop(tree);
+    assert_eq!(root, committed);
+    let tree_db = TreeDBBuilder::<Sha3>::for_key::<u64>(&memory_db, &root).build();
+    assert_eq!(
+        tree_db.get_value(&IndexedTree::<Sha3>::key(0)).unwrap(),
+        vec![1]
+    );
+}
+
+#[test]
+fn test_vectors_fixture() {
+    let expected = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testvectors/sha3-256.json"
+    ))
+    .unwrap();
+    assert_eq!(
+        testvectors::render_json::<Sha3>("sha3-256").unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn test_generate_tree() {
+    let (db, root) = generate_tree::<Sha3>(7, 50, 12, 16).unwrap();
+    let (_, again) = generate_tree::<Sha3>(7, 50, 12, 16).unwrap();
+    let (_, other) = generate_tree::<Sha3>(8, 50, 12, 16).unwrap();
+    assert_eq!(root, again);
+    assert_ne!(root, other);
+
+    let db: &dyn HashDB<Sha3, DBValue> = &db;
+    let tree = TreeDBBuilder::new(&db, &root, 16).build();
+    let leaves: Vec<_> = tree
+        .iter_from(&[0; 16])
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(leaves.len(), 50);
+    assert!(leaves.iter().all(|(_, value)| value.len() == 12));
+
+    let (_, full) = generate_tree::<Sha3>(1, 8, 0, 3).unwrap();
+    assert_ne!(full, compute_null_hashes::<Sha3>(3)[0]);
+    assert!(matches!(
+        generate_tree::<Sha3>(1, 9, 4, 3),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+}
+
+#[test]
+fn test_iter_level() {
+    let (db, root, depth) = build_db_mock();
+    let tree = TreeDBBuilder::new(&db, &root, depth).build();
+    let levels: Vec<Vec<(Vec<u8>, _)>> = (0..=depth)
+        .map(|level| {
+            tree.iter_level(level)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(levels[0], vec![(vec![], root)]);
+    assert_eq!(levels[3].len(), 8);
+    for level in levels.iter().skip(1) {
+        for (prefix, hash) in level.iter() {
+            assert_eq!(tree.get_node(prefix).unwrap().hash(), *hash);
+        }
+    }
+
+    let mut db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(4)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, 4).build();
+    tree.insert(&[0, 1, 1, 0], vec![7]).unwrap();
+    tree.insert(&[1, 1, 0, 1], vec![9]).unwrap();
+    tree.commit();
+    drop(tree);
+    let tree = TreeDBBuilder::new(&db, &root, 4).build();
+    let prefixes: Vec<Vec<u8>> = tree
+        .iter_level(2)
+        .unwrap()
+        .map(|node| node.unwrap().0)
+        .collect();
+    assert_eq!(prefixes, vec![vec![0, 1], vec![1, 1]]);
+    assert!(matches!(
+        tree.iter_level(5),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+}
+
+#[test]
+fn test_assert_key_fits() {
+    assert_key_fits::<Sha3, 2>();
+    assert_key_fits::<Sha3, 32>();
+
+    let key = Key::new([0xff; 32]);
+    assert_eq!(key.iter().count(), MAX_KEY_DEPTH);
+    assert!(key.iter().all(|bit| bit));
+}
+
+#[test]
+fn test_redacted_proof() {
+    let salt = [42; BLINDING_SALT_LEN];
+    let blinded = blind_value(b"secret", &salt);
+    assert_eq!(split_blinding(&blinded).unwrap(), (&salt, &b"secret"[..]));
+    assert!(matches!(
+        split_blinding(&[1, 2]),
+        Err(TreeError::MissingBlinding)
+    ));
+
+    let key = [1, 0, 1];
+    let mut db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(3)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, 3).build();
+    tree.insert(&key, blinded.clone()).unwrap();
+    tree.insert(&[0, 0, 1], vec![5]).unwrap();
+    tree.commit();
+    drop(tree);
+
+    let tree = TreeDBBuilder::new(&db, &root, 3).build();
+    let proof = tree.get_redacted_proof(&key).unwrap();
+    assert_eq!(*proof.commitment(), Sha3::hash(&blinded));
+    assert!(proof.verify(&root, &key));
+    assert!(!proof.verify(&root, &[1, 0, 0]));
+    assert!(proof.verify_opening(&root, &key, &blind_value(b"secret", &salt)));
+    assert!(!proof.verify_opening(&root, &key, &blind_value(b"guess", &salt)));
+    assert!(!proof.verify_opening(&root, &key, b"secret"));
+}
+
+#[test]
+fn test_subtree_stubs() {
+    let (db, root, depth) = build_db_mock();
+    let tree = TreeDBBuilder::new(&db, &root, depth).build();
+    let export = tree
+        .prove_subtree_with_stubs(&[], &[&[0, 1], &[1], &[0, 0, 0, 0]])
+        .unwrap();
+    let stubbed: Vec<Vec<u8>> = export.stubs().iter().map(|(p, _)| p.clone()).collect();
+    assert_eq!(stubbed, vec![vec![0, 1], vec![1]]);
+    assert_eq!(export.stubs()[1].1, tree.get(&[1]).unwrap().hash());
+
+    let decoded = StubbedProof::<Sha3>::decode(&export.encode()).unwrap();
+    assert_eq!(decoded.stubs(), export.stubs());
+    assert_eq!(decoded.proof(), export.proof());
+
+    let mut partial = decoded.import(root, depth).unwrap();
+    assert_eq!(partial.unexpanded().count(), 2);
+    assert_eq!(
+        partial.tree().get_value(&[0, 0, 1]).unwrap(),
+        10u32.to_le_bytes().to_vec()
+    );
+    assert!(matches!(
+        partial.tree().get_value(&[1, 1, 0]),
+        Err(TreeError::DataNotFound { .. })
+    ));
+    assert_eq!(partial.unexpanded_at(&[1, 1, 0]), Some(&[1][..]));
+    assert_eq!(partial.unexpanded_at(&[0, 0, 1]), None);
+
+    assert_eq!(
+        partial.expand(tree.prove_subtree(&[1]).unwrap()).unwrap(),
+        1
+    );
+    assert_eq!(
+        partial.tree().get_value(&[1, 1, 0]).unwrap(),
+        23u32.to_le_bytes().to_vec()
+    );
+    assert_eq!(partial.unexpanded_at(&[1, 1, 0]), None);
+
+    let (proof, mut stubs) = tree
+        .prove_subtree_with_stubs(&[], &[&[1]])
+        .unwrap()
+        .into_parts();
+    stubs[0].0 = vec![0];
+    assert!(matches!(
+        StubbedProof::<Sha3>::new(proof, stubs).import(root, depth),
+        Err(TreeError::UnlinkedProofNode)
+    ));
+}
+
+#[test]
+fn test_background_commit() {
+    let key_of = |n: u8| (0..8).rev().map(|bit| (n >> bit) & 1).collect::<Vec<u8>>();
+    let mut db = BackgroundDb::new(TestDb::default());
+    let mut root = compute_null_hashes::<Sha3>(8)[0];
+    let mut tickets = Vec::new();
+    for block in 0..4u8 {
+        let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, 8).build();
+        // reads nodes of previous blocks, written or not
+        if block > 0 {
+            assert_eq!(tree.get_value(&key_of(block - 1)).unwrap(), vec![block]);
+        }
+        tree.insert(&key_of(block), vec![block + 1]).unwrap();
+        tree.commit();
+        drop(tree);
+        tickets.push(db.submit());
+    }
+
+    tickets[3].wait().unwrap();
+    assert!(tickets.iter().all(|ticket| ticket.is_done()));
+    let backend = db.backend();
+    let tree = TreeDBBuilder::new(&*backend, &root, 8).build();
+    for block in 0..4u8 {
+        assert_eq!(tree.get_value(&key_of(block)).unwrap(), vec![block + 1]);
+    }
+}
+
+#[test]
+fn test_background_backpressure() {
+    let mut db = BackgroundDb::new(TestDb::default()).with_max_queue_depth(1);
+    let mut root = compute_null_hashes::<Sha3>(4)[0];
+    for block in 0..6u8 {
+        let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, 4).build();
+        tree.insert(&[block & 1, 1, 0, 1], vec![block + 1]).unwrap();
+        tree.commit();
+        drop(tree);
+        db.submit();
+        assert!(db.queue_depth() <= 1);
+    }
+
+    db.flush().unwrap();
+    assert_eq!(db.queue_depth(), 0);
+    assert_eq!(db.pending_nodes(), 0);
+    let backend = db.backend();
+    let tree = TreeDBBuilder::new(&*backend, &root, 4).build();
+    assert_eq!(tree.get_value(&[1, 1, 0, 1]).unwrap(), vec![6]);
+
+    let mut db = BackgroundDb::new(TestDb::default()).with_max_queue_depth(0);
+    db.insert(EMPTY_PREFIX, b"node");
+    assert_eq!(db.pending_nodes(), 1);
+    assert!(db.submit().is_done());
+    assert_eq!(db.queue_depth(), 0);
+}
+
+#[test]
+fn test_stream_diff() {
+    let (mut db, old_root, depth) = build_db_mock();
+    let mut replica = db.clone();
+    let mut new_root = old_root;
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut new_root, depth).build();
+    tree.insert(&[0, 1, 0], vec![7, 7]).unwrap();
+    tree.remove_batch(&[&[1, 1, 1]]).unwrap();
+    tree.commit();
+    drop(tree);
+
+    let mut stream = Vec::new();
+    let stats = stream_diff::<Sha3>(
+        &db,
+        depth,
+        &NullHashes::new(),
+        &old_root,
+        &new_root,
+        &mut stream,
+    )
+    .unwrap();
+    // the paths to both leaves and the new leaf are inserted, the old paths and leaves deleted
+    assert_eq!(stats.inserted, 6);
+    assert_eq!(stats.deleted, 7);
+
+    let mut root = new_root;
+    assert!(matches!(
+        apply_diff::<Sha3>(
+            &mut stream.as_slice(),
+            &mut replica,
+            &mut root,
+            depth,
+            &NullHashes::new(),
+            None
+        ),
+        Err(TreeError::DiffRootMismatch)
+    ));
+    root = old_root;
+    let truncated = &stream[..stream.len() - 1];
+    assert!(matches!(
+        apply_diff::<Sha3>(
+            &mut &truncated[..],
+            &mut replica,
+            &mut root,
+            depth,
+            &NullHashes::new(),
+            None
+        ),
+        Err(TreeError::DiffFailed)
+    ));
+    assert_eq!(root, old_root);
+
+    let applied = apply_diff::<Sha3>(
+        &mut stream.as_slice(),
+        &mut replica,
+        &mut root,
+        depth,
+        &NullHashes::new(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(applied, stats);
+    assert_eq!(root, new_root);
+    let tree = TreeDBBuilder::new(&replica, &root, depth).build();
+    assert_eq!(tree.get_value(&[0, 1, 0]).unwrap(), vec![7, 7]);
+    assert_eq!(tree.get_value(&[1, 1, 1]).unwrap(), Vec::<u8>::new());
+    assert_eq!(
+        tree.get_value(&[1, 1, 0]).unwrap(),
+        23u32.to_le_bytes().to_vec()
+    );
+    assert!(!replica.contains(&old_root, EMPTY_PREFIX));
+}
+
+#[test]
+fn test_apply_diff_checks_nodes() {
+    let depth = 3;
+    let null_root = compute_null_hashes::<Sha3>(depth)[0];
+    let diff = |new_root: &[u8], records: &[(u8, Vec<u8>, Vec<u8>)]| {
+        let mut stream = Vec::new();
+        bincode::serialize_into(&mut stream, &(0u8, null_root.to_vec(), new_root.to_vec()))
+            .unwrap();
+        for record in records {
+            bincode::serialize_into(&mut stream, record).unwrap();
+        }
+        bincode::serialize_into(&mut stream, &(3u8, Vec::<u8>::new(), Vec::<u8>::new())).unwrap();
+        stream
+    };
+
+    // a compressed value is not stored under a hash it does not decompress to
+    let victim = Sha3::hash(b"victim");
+    let forged = diff(&null_root, &[(1, victim.to_vec(), vec![2, 9, 9])]);
+    for compression in [None, Some(&RunLength as &dyn ValueCompression)] {
+        let mut replica = TestDb::default();
+        let mut root = null_root;
+        assert!(matches!(
+            apply_diff::<Sha3>(
+                &mut forged.as_slice(),
+                &mut replica,
+                &mut root,
+                depth,
+                &NullHashes::new(),
+                compression
+            ),
+            Err(TreeError::DiffFailed)
+        ));
+        assert!(!replica.contains(&victim, EMPTY_PREFIX));
+    }
+
+    // a compressed value matching its hash is stored as shipped
+    let value = vec![7; 64];
+    let compressed = diff(
+        &null_root,
+        &[(1, Sha3::hash(&value).to_vec(), vec![2, 64, 7])],
+    );
+    let mut replica = TestDb::default();
+    let mut root = null_root;
+    assert!(matches!(
+        apply_diff::<Sha3>(
+            &mut compressed.as_slice(),
+            &mut replica,
+            &mut root,
+            depth,
+            &NullHashes::new(),
+            None
+        ),
+        Err(TreeError::DiffFailed)
+    ));
+    apply_diff::<Sha3>(
+        &mut compressed.as_slice(),
+        &mut replica,
+        &mut root,
+        depth,
+        &NullHashes::new(),
+        Some(&RunLength),
+    )
+    .unwrap();
+    assert_eq!(
+        HashDB::get(&replica, &Sha3::hash(&value), EMPTY_PREFIX).unwrap(),
+        vec![2, 64, 7]
+    );
+
+    // the new root must be served by the replica
+    let unbacked = diff(&victim, &[]);
+    assert!(matches!(
+        apply_diff::<Sha3>(
+            &mut unbacked.as_slice(),
+            &mut replica,
+            &mut root,
+            depth,
+            &NullHashes::new(),
+            None
+        ),
+        Err(TreeError::DiffFailed)
+    ));
+    assert_eq!(root, null_root);
+}
+
+#[test]
+fn test_null_hashes_with_default_leaf() {
+    let default_leaf = vec![0xde, 0xad];
+    let null_hashes = Arc::new(NullHashes::<Sha3>::with_default_leaf(&default_leaf));
+    assert_eq!(null_hashes.at_height(0), Sha3::hash(&default_leaf));
+    assert_ne!(null_hashes.at_height(4), compute_null_hashes::<Sha3>(4)[0]);
+    assert_eq!(null_hashes.ladder(4)[0], null_hashes.at_height(4));
+    let null_root = null_hashes.at_height(4);
+
+    let mut db = TestDb::default();
+    let mut root = null_root;
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, 4)
+        .with_null_hashes(null_hashes.clone())
+        .build();
+    assert_eq!(
+        tree.insert_if_absent(&[0, 1, 1, 0], vec![1, 2, 3]).unwrap(),
+        ConditionalWrite::Written(default_leaf.clone())
+    );
+    assert_eq!(
+        tree.compare_and_swap(&[1, 0, 0, 0], &default_leaf, vec![4])
+            .unwrap(),
+        ConditionalWrite::Written(default_leaf.clone())
+    );
+    assert_eq!(
+        tree.insert_if_absent(&[1, 0, 0, 0], vec![5]).unwrap(),
+        ConditionalWrite::Rejected(vec![4])
+    );
+    tree.commit();
+    assert_eq!(tree.get_value_proof(&[1, 1, 1, 1]).unwrap().0, None);
+    tree.insert(&[1, 0, 0, 0], default_leaf.clone()).unwrap();
+    tree.commit();
+    drop(tree);
+
+    let tree = TreeDBBuilder::new(&db, &root, 4)
+        .with_null_hashes(null_hashes.clone())
+        .build();
+    assert_eq!(tree.get_value(&[1, 1, 1, 1]).unwrap(), default_leaf);
+    let (value, proof) = tree.get_value_proof(&[1, 0, 0, 0]).unwrap();
+    assert_eq!(value, None);
+    let sparse = proof.to_sparse_with_null_hashes(&null_hashes);
+    assert_eq!(
+        sparse.to_proof_with_null_hashes(4, &null_hashes).unwrap(),
+        proof
+    );
+
+    let light = LightTree::<Sha3>::new(root, 4).with_null_hashes(&null_hashes);
+    assert!(light.verify_absence(&[1, 0, 0, 0], &proof));
+    assert!(!light.verify_inclusion(&[1, 0, 0, 0], &default_leaf, &proof));
+    let (value, proof) = tree.get_value_proof(&[0, 1, 1, 0]).unwrap();
+    assert_eq!(value, Some(vec![1, 2, 3]));
+    assert!(light.verify_inclusion(&[0, 1, 1, 0], &[1, 2, 3], &proof));
+
+    // builders and diffs given the null hashes agree with the tree
+    let mut leaves = vec![default_leaf.clone(); 16];
+    leaves[6] = vec![1, 2, 3];
+    let mut complete_db = TestDb::default();
+    let complete_root = CompleteTreeBuilder::<Sha3>::new(4, leaves.clone())
+        .with_null_hashes(null_hashes.clone())
+        .build_into(&mut complete_db)
+        .unwrap();
+    assert_eq!(complete_root, root);
+    let flat = FlatTree::<Sha3>::from_leaves_with_null_hashes(
+        4,
+        leaves[..7].to_vec(),
+        null_hashes.clone(),
+    )
+    .unwrap();
+    let mut flat_db = TestDb::default();
+    assert_eq!(flat.persist(&mut flat_db), root);
+    assert!(!flat_db.contains(&null_hashes.at_height(0), EMPTY_PREFIX));
+
+    let mut stream = Vec::new();
+    stream_diff::<Sha3>(&db, 4, &null_hashes, &null_root, &root, &mut stream).unwrap();
+    let mut replica = TestDb::default();
+    let mut replica_root = null_root;
+    let stats = apply_diff::<Sha3>(
+        &mut stream.as_slice(),
+        &mut replica,
+        &mut replica_root,
+        4,
+        &null_hashes,
+        None,
+    )
+    .unwrap();
+    assert_eq!(replica_root, root);
+    assert_eq!(stats.inserted, 5);
+
+    // removing the last populated leaf returns to the empty root of the ladder
+    let mut root_mut = root;
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root_mut, 4)
+        .with_null_hashes(null_hashes.clone())
+        .build();
+    let frontier = tree.frontier(7).unwrap();
+    assert_eq!(frontier.root(), root);
+    tree.remove_batch(&[&[0, 1, 1, 0]]).unwrap();
+    tree.commit();
+    drop(tree);
+    assert_eq!(root_mut, null_root);
+}
+
+#[test]
+fn test_complete_tree_builder() {
+    let (_, _, depth, expected_root) = build_data();
+    let leaves: Vec<DBValue> = test_values()
+        .into_iter()
+        .map(|value| value.to_le_bytes().to_vec())
+        .collect();
+    let (db, root) = CompleteTreeBuilder::<Sha3>::new(depth, leaves.clone())
+        .build()
+        .unwrap();
+    assert_eq!(root, expected_root);
+    let tree = TreeDBBuilder::new(&db, &root, depth).build();
+    for (key, value) in test_keys().iter().zip(leaves.iter()) {
+        assert_eq!(&tree.get_value(key).unwrap(), value);
+    }
+
+    // empty subtrees are not stored and match the root of inserting the populated leaves
+    let mut sparse = vec![Vec::new(); 16];
+    sparse[3] = vec![1];
+    sparse[12] = vec![2];
+    let (db, root) = CompleteTreeBuilder::<Sha3>::new(4, sparse).build().unwrap();
+    assert_eq!(db.keys().len(), 2 * 5 - 1);
+    let mut expected_db = TestDb::default();
+    let mut expected = compute_null_hashes::<Sha3>(4)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut expected_db, &mut expected, 4).build();
+    tree.insert(&[0, 0, 1, 1], vec![1]).unwrap();
+    tree.insert(&[1, 1, 0, 0], vec![2]).unwrap();
+    tree.commit();
+    drop(tree);
+    assert_eq!(root, expected);
+
+    assert!(matches!(
+        CompleteTreeBuilder::<Sha3>::new(3, vec![vec![1]; 7]).build(),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+
+    // builders are cloneable and printable for hashers that are neither
+    let builder = CompleteTreeBuilder::<FeltHash<MockPedersen>>::new(1, vec![vec![1]; 2]);
+    assert!(format!("{:?}", builder.clone()).starts_with("CompleteTreeBuilder"));
+}
+
+#[test]
+fn test_prefix_histogram() {
+    let mut db = TestDb::default();
+    let mut root = compute_null_hashes::<Sha3>(6)[0];
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, 6).build();
+    for key in [
+        [0, 0, 1, 0, 1, 1],
+        [0, 0, 0, 0, 0, 1],
+        [1, 1, 0, 1, 0, 0],
+        [1, 1, 1, 1, 1, 1],
+        [1, 1, 1, 0, 0, 0],
+    ] {
+        tree.insert(&key, vec![9]).unwrap();
+    }
+    tree.commit();
+    drop(tree);
+
+    let tree = TreeDBBuilder::new(&db, &root, 6).build();
+    assert_eq!(tree.prefix_histogram(0).unwrap(), vec![5]);
+    assert_eq!(tree.prefix_histogram(2).unwrap(), vec![2, 0, 0, 3]);
+    let histogram = tree.prefix_histogram(3).unwrap();
+    assert_eq!(histogram, vec![1, 1, 0, 0, 0, 0, 1, 2]);
+    for (index, count) in histogram.iter().enumerate() {
+        let prefix: Vec<u8> = (0..3).rev().map(|bit| (index >> bit) as u8 & 1).collect();
+        assert_eq!(tree.count_prefix(&prefix).unwrap(), *count);
+    }
+    assert_eq!(tree.prefix_histogram(6).unwrap().iter().sum::<usize>(), 5);
+    for k in [7, 63, usize::MAX] {
+        assert!(matches!(
+            tree.prefix_histogram(k),
+            Err(TreeError::IndexOutOfBounds)
+        ));
+    }
+
+    // deep trees are only counted under prefixes short enough for a dense histogram
+    let deep_root = compute_null_hashes::<Sha3>(64)[0];
+    let tree = TreeDBBuilder::new(&db, &deep_root, 64).build();
+    assert!(matches!(
+        tree.prefix_histogram(MAX_HISTOGRAM_DEPTH + 1),
+        Err(TreeError::IndexOutOfBounds)
+    ));
+    assert_eq!(tree.prefix_histogram(4).unwrap(), vec![0; 16]);
+}
+
+#[test]
+fn test_null_hash() {
+    let null_hashes = compute_null_hashes::<Sha3>(64);
+    let leaf = Sha3::hash(&[]);
+    let concatenated = [leaf.as_ref(), leaf.as_ref()].concat();
+    let layer_2 = Sha3::hash(&concatenated);
+    let layer_3 = Sha3::hash(&[layer_2.as_ref(), layer_2.as_ref()].concat());
+    assert_eq!(null_hashes[64], leaf);
+    assert_eq!(null_hashes[63], layer_2);
+    assert_eq!(null_hashes[62], layer_3);
+}
+
+#[test]
+fn test_key_iter() {
+    let hash: [u8; 2] = [17, 24];
+    // 00010001 00011000
+    let expected = [
+        false, false, false, true, false, false, false, true, false, false, false, true, true,
+        false, false, false,
+    ];
+    let key = Key::new(hash);
+    for (bit, expected) in key.iter().zip(expected.iter()) {
+        assert_eq!(&bit, expected);
+    }
+}
+
+#[test]
+fn test_empty_sparse_merkle() {
+    let depth = 4;
+    let null_hashes = compute_null_hashes::<Sha3>(depth);
+    let root = null_hashes[0];
+    let memory_db = MemoryDB::<Sha3, NoopKey<Sha3>, Vec<u8>>::default();
+    let tree_db = TreeDBBuilder::<Sha3>::new(&memory_db, &root, depth).build();
+    let test_value = tree_db.get_value(&[0, 0, 0, 0]).unwrap();
+    let test_leaf = tree_db.get_leaf(&[0, 0, 0, 0]).unwrap();
+    let _test_proof = tree_db.get_proof(&[0, 0, 0, 0]).unwrap();
+    assert_eq!(test_value, []);
+    assert_eq!(test_leaf, Sha3::hash(&[]));
+}
+
+#[test]
+fn test_sparse_merkle_tree() {
+    let depth = 3;
+    let null_hashes = compute_null_hashes::<Sha3>(depth);
+    let mut root = null_hashes[0];
+    let mut memory_db = MemoryDB::<Sha3, NoopKey<Sha3>, Vec<u8>>::default();
+    let mut tree_db_mut = TreeDBMutBuilder::<Sha3>::new(&mut memory_db, &mut root, depth).build();
+    let values = test_values();
+    let keys = test_keys();
+    for (key, value) in keys.iter().zip(values.iter()) {
+        tree_db_mut
+            .insert(key, value.to_le_bytes().to_vec())
+            .unwrap();
+    }
+    let root = tree_db_mut.root();
+    let (_, _, _, expected_root) = build_data();
+    assert_eq!(root, &expected_root);
+}
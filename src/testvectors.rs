@@ -0,0 +1,140 @@
+//! Canonical roots and proofs for fixed key/value sets.
+//!
+//! `render_json` builds every set in `vector_sets` with a hasher and renders the roots, the
+//! sibling hashes of every populated leaf and of one empty leaf, and their sparse encodings as
+//! JSON.  The rendered vectors for the hashers the crate is tested with are checked in under
+//! `testvectors/`, so other implementations can validate compatibility against them and any
+//! change to the roots or the proof encoding fails the tests.
+
+use crate::{
+    compute_null_hashes, proof::NoopKey, rstd::Vec, DBValue, Hasher, ProofHeader, Tree,
+    TreeDBBuilder, TreeDBMutBuilder, TreeError, TreeMut, PROOF_FORMAT_VERSION,
+};
+use core::fmt::Write;
+use memory_db::MemoryDB;
+use std::string::String;
+
+/// A named set of entries inserted into an empty tree of the given depth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorSet {
+    pub name: &'static str,
+    pub depth: usize,
+    pub entries: Vec<(Vec<u8>, DBValue)>,
+    /// A key left empty by the entries, proved absent.
+    pub absent: Vec<u8>,
+}
+
+/// Return the key of the given depth holding the bits of `index` from the most significant.
+fn key(index: u64, depth: usize) -> Vec<u8> {
+    (0..depth)
+        .rev()
+        .map(|bit| index.checked_shr(bit as u32).unwrap_or(0) as u8 & 1)
+        .collect()
+}
+
+/// Return the defined vector sets.
+pub fn vector_sets() -> Vec<VectorSet> {
+    Vec::from([
+        VectorSet {
+            name: "empty",
+            depth: 4,
+            entries: Vec::new(),
+            absent: key(9, 4),
+        },
+        VectorSet {
+            name: "single",
+            depth: 4,
+            entries: Vec::from([(key(5, 4), b"leaf".to_vec())]),
+            absent: key(4, 4),
+        },
+        VectorSet {
+            name: "dense",
+            depth: 3,
+            entries: (0..7)
+                .map(|index| (key(index, 3), Vec::from([index as u8 + 1; 3])))
+                .collect(),
+            absent: key(7, 3),
+        },
+        VectorSet {
+            name: "sparse-deep",
+            depth: 32,
+            entries: [0, 1, 0x8000_0000, 0xffff_ffff]
+                .iter()
+                .map(|index| (key(*index, 32), index.to_be_bytes().to_vec()))
+                .collect(),
+            absent: key(0x1234_5678, 32),
+        },
+    ])
+}
+
+/// Render the vectors of every set in `vector_sets` under `H` as JSON, naming the hasher
+/// `hasher`.  Byte strings are lowercase hex and keys strings of bits.
+pub fn render_json<H: Hasher>(hasher: &str) -> Result<String, TreeError> {
+    let mut json = String::new();
+    let out = &mut json;
+    let _ = writeln!(out, "{{");
+    let _ = writeln!(out, "  \"hasher\": \"{}\",", hasher);
+    let hasher_id = ProofHeader::hasher_id::<H>();
+    let _ = writeln!(out, "  \"hasher_id\": \"{}\",", hex(&hasher_id));
+    let _ = writeln!(out, "  \"proof_format_version\": {},", PROOF_FORMAT_VERSION);
+    let _ = writeln!(out, "  \"sets\": [");
+
+    let sets = vector_sets();
+    for (i, set) in sets.iter().enumerate() {
+        let mut db = MemoryDB::<H, NoopKey<H>, DBValue>::default();
+        let mut root = compute_null_hashes::<H>(set.depth)[0];
+        let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, set.depth).build();
+        for (key, value) in set.entries.iter() {
+            tree.insert(key, value.clone())?;
+        }
+        tree.commit();
+        drop(tree);
+        let tree = TreeDBBuilder::new(&db, &root, set.depth).build();
+
+        let _ = writeln!(out, "    {{");
+        let _ = writeln!(out, "      \"name\": \"{}\",", set.name);
+        let _ = writeln!(out, "      \"depth\": {},", set.depth);
+        let _ = writeln!(out, "      \"root\": \"{}\",", hex(root.as_ref()));
+        let _ = writeln!(out, "      \"leaves\": [");
+        let leaves = set
+            .entries
+            .iter()
+            .map(|(key, _)| key)
+            .chain(core::iter::once(&set.absent));
+        for (j, key) in leaves.enumerate() {
+            let (value, proof) = tree.get_value_proof(key)?;
+            let siblings: Vec<String> = proof
+                .siblings()
+                .iter()
+                .map(|sibling| format!("\"{}\"", hex(sibling.as_ref())))
+                .collect();
+            let bits: String = key.iter().map(|bit| char::from(b'0' + bit)).collect();
+            let separator = if j == set.entries.len() { "" } else { "," };
+            let _ = writeln!(out, "        {{");
+            let _ = writeln!(out, "          \"key\": \"{}\",", bits);
+            let _ = writeln!(
+                out,
+                "          \"value\": \"{}\",",
+                hex(&value.unwrap_or_default())
+            );
+            let _ = writeln!(out, "          \"siblings\": [{}],", siblings.join(", "));
+            let _ = writeln!(
+                out,
+                "          \"sparse_proof\": \"{}\"",
+                hex(&proof.to_sparse().encode())
+            );
+            let _ = writeln!(out, "        }}{}", separator);
+        }
+        let _ = writeln!(out, "      ]");
+        let separator = if i + 1 == sets.len() { "" } else { "," };
+        let _ = writeln!(out, "    }}{}", separator);
+    }
+
+    let _ = writeln!(out, "  ]");
+    let _ = writeln!(out, "}}");
+    Ok(json)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
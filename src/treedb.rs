@@ -1,25 +1,56 @@
 use crate::{
-    compute_null_hashes, indices, DBValue, HashDBRef, Hasher, Node, NodeHash, Tree, TreeError,
-    TreeRecorder, Value, EMPTY_PREFIX,
+    compression, indices, integrity,
+    meter::Meter,
+    node::edge_key,
+    node::key_bit,
+    node::null_node,
+    rstd::{Arc, HashMap},
+    value_proof, AccessMeter, Cursor, DBValue, HashDBRef, Hasher, IntegrityReport, KeyDepth,
+    LeafIter, LeafProof, Node, NodeCache, NodeFetcher, NodeHash, NodeView, NullHashTable,
+    NullHashes, Prefetcher, Proof, RedactedProof, StorageProof, StubbedProof, Tree, TreeError,
+    TreeMetadata, TreeRecorder, ValueCompression, VersionedRoots, EMPTY_PREFIX,
 };
 
+/// The longest prefix `TreeDB::prefix_histogram` counts leaves under, bounding the histogram to
+/// about a million entries.
+pub const MAX_HISTOGRAM_DEPTH: usize = 20;
+
 pub struct TreeDBBuilder<'db, H: Hasher> {
     db: &'db dyn HashDBRef<H, DBValue>,
-    root: &'db H::Out,
+    root: H::Out,
     depth: usize,
     recorder: Option<&'db mut dyn TreeRecorder<H>>,
+    prefetcher: Option<&'db dyn Prefetcher<H>>,
+    fetcher: Option<&'db dyn NodeFetcher<H>>,
+    cache: Option<&'db NodeCache<H>>,
+    verify_hashes: bool,
+    compression: Option<&'db dyn ValueCompression>,
+    null_hashes: Option<Arc<NullHashes<H>>>,
+    meter: Option<&'db mut dyn AccessMeter>,
 }
 
 impl<'db, H: Hasher> TreeDBBuilder<'db, H> {
-    pub fn new(db: &'db dyn HashDBRef<H, DBValue>, root: &'db H::Out, depth: usize) -> Self {
+    pub fn new(db: &'db dyn HashDBRef<H, DBValue>, root: &H::Out, depth: usize) -> Self {
         Self {
             db,
-            root,
+            root: *root,
             depth,
             recorder: None,
+            prefetcher: None,
+            fetcher: None,
+            cache: None,
+            verify_hashes: false,
+            compression: None,
+            null_hashes: None,
+            meter: None,
         }
     }
 
+    /// Create a builder for a tree keyed by `K`, deriving the depth from the key type.
+    pub fn for_key<K: KeyDepth>(db: &'db dyn HashDBRef<H, DBValue>, root: &H::Out) -> Self {
+        Self::new(db, root, K::DEPTH)
+    }
+
     pub fn with_recorder<'recorder: 'db>(
         mut self,
         recorder: &'recorder mut dyn TreeRecorder<H>,
@@ -36,13 +67,85 @@ impl<'db, H: Hasher> TreeDBBuilder<'db, H> {
         self
     }
 
+    /// Issue prefetch hints to the given backend on `TreeDB::prefetch`.
+    pub fn with_prefetcher(mut self, prefetcher: &'db dyn Prefetcher<H>) -> Self {
+        self.prefetcher = Some(prefetcher);
+        self
+    }
+
+    /// Fetch nodes missing from the database from `fetcher` instead of failing, see
+    /// `NodeFetcher`.
+    pub fn with_node_fetcher(mut self, fetcher: &'db dyn NodeFetcher<H>) -> Self {
+        self.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Serve nodes from `cache` before reading the database and cache the nodes read.  Share one
+    /// cache between the trees of many roots to bound the memory spent on hot nodes.
+    pub fn with_node_cache(mut self, cache: &'db NodeCache<H>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Seed the null hashes from the hasher's precomputed table.  Heights beyond the table are
+    /// computed on demand.
+    pub fn with_null_hash_table(mut self) -> Self
+    where
+        H: NullHashTable,
+        H::Out: 'static,
+    {
+        self.null_hashes = Some(Arc::new(NullHashes::from_table()));
+        self
+    }
+
+    /// Share the given null-hash ladder instead of growing a new one for this tree.  Use a
+    /// ladder created with `NullHashes::with_default_leaf` for trees whose empty leaves hold
+    /// another value than the empty value.
+    pub fn with_null_hashes(mut self, null_hashes: Arc<NullHashes<H>>) -> Self {
+        self.null_hashes = Some(null_hashes);
+        self
+    }
+
+    /// Recompute the hash of every node fetched from the database and fail with
+    /// `TreeError::HashMismatch` if it does not match the key it was fetched with.
+    pub fn with_hash_verification(mut self) -> Self {
+        self.verify_hashes = true;
+        self
+    }
+
+    /// Charge `meter` for the nodes touched by every `get_value`.
+    pub fn with_access_meter(mut self, meter: &'db mut dyn AccessMeter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Compress values at rest with `compression`, see `ValueCompression`.
+    pub fn with_value_compression(mut self, compression: &'db dyn ValueCompression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Check the tree against the `TreeMetadata` record stored under `metadata`.  Fails with
+    /// `TreeError::MetadataMismatch` if the record describes a different depth, arity or node
+    /// encoding, and with `TreeError::DataNotFound` if there is no such record.
+    pub fn with_metadata(self, metadata: &H::Out) -> Result<Self, TreeError> {
+        TreeMetadata::validate(self.db, metadata, self.depth)?;
+        Ok(self)
+    }
+
     pub fn build(self) -> TreeDB<'db, H> {
         TreeDB {
             db: self.db,
             root: self.root,
             depth: self.depth,
             recorder: self.recorder.map(core::cell::RefCell::new),
-            null_hashes: compute_null_hashes::<H>(self.depth)
+            prefetcher: self.prefetcher,
+            fetcher: self.fetcher,
+            cache: self.cache,
+            verify_hashes: self.verify_hashes,
+            compression: self.compression,
+            null_hashes: self.null_hashes.unwrap_or_default(),
+            meter: self.meter.map(Meter::new),
         }
     }
 }
@@ -54,61 +157,605 @@ impl<'db, H: Hasher> TreeDBBuilder<'db, H> {
 /// `HashDBRef` database object.
 pub struct TreeDB<'a, H: Hasher> {
     db: &'a dyn HashDBRef<H, DBValue>,
-    root: &'a H::Out,
+    root: H::Out,
     depth: usize,
     recorder: Option<core::cell::RefCell<&'a mut dyn TreeRecorder<H>>>,
-    null_hashes: Vec<H::Out>,
+    prefetcher: Option<&'a dyn Prefetcher<H>>,
+    fetcher: Option<&'a dyn NodeFetcher<H>>,
+    cache: Option<&'a NodeCache<H>>,
+    verify_hashes: bool,
+    compression: Option<&'a dyn ValueCompression>,
+    null_hashes: Arc<NullHashes<H>>,
+    meter: Option<Meter<'a>>,
 }
 
 impl<'a, H: Hasher> TreeDB<'a, H> {
+    /// Open the tree at the root published as `version` in `versions`, failing with
+    /// `TreeError::UnknownVersion` if there is no such version.
+    ///
+    /// The version is not pinned, so callers pruning old state should read through
+    /// `VersionedRoots::snapshot_at` instead.
+    pub fn at_version(
+        db: &'a dyn HashDBRef<H, DBValue>,
+        versions: &VersionedRoots<H>,
+        version: u64,
+        depth: usize,
+    ) -> Result<Self, TreeError> {
+        let root = versions.root_at(version).ok_or(TreeError::UnknownVersion)?;
+        Ok(TreeDBBuilder::new(db, &root, depth).build())
+    }
+
     /// Get the backing database.
     pub fn db(&self) -> &dyn HashDBRef<H, DBValue> {
         self.db
     }
 
-    pub fn lookup(&self, key: &H::Out, depth: usize) -> Result<Node<H>, TreeError> {
-        let data = if let Some(value) = self.db.get(key, EMPTY_PREFIX) {
-            value
-        } else {
-            if depth == self.depth && key == &self.null_hashes[depth] {
-                return Ok(Node::Value(Value::Cached(DBValue::new())));
-            } else if key == &self.null_hashes[depth] {
-                let null_hash = self.null_hashes[depth + 1];
-                return Ok(Node::Inner(
-                    NodeHash::Hash(null_hash),
-                    NodeHash::Hash(null_hash),
-                ));
-            } else {
-                return Err(TreeError::UnexpectedError);
+    /// Walk every reachable node and check parent-child hash links, value node hashes and depth
+    /// consistency.
+    pub fn verify_integrity(&self) -> IntegrityReport<H> {
+        let null_hashes = self.null_hashes.ladder(self.depth);
+        integrity::verify(
+            self.db,
+            &self.root,
+            self.depth,
+            &null_hashes,
+            self.compression,
+        )
+    }
+
+    /// Check that the root is the latest root published in `versions` and the root of the tree
+    /// stored in the database: the node stored under it must hash to it and, for a tree deeper
+    /// than zero, be an inner node, and recomputing the root from the stored leaves, walking
+    /// every reachable node like `verify_integrity`, must yield the root again.
+    ///
+    /// Fails with `TreeError::StaleRoot` for a root other than the latest published one, such as
+    /// a root kept after another writer committed, with `TreeError::DataNotFound` for a missing
+    /// node and with `TreeError::HashMismatch` or `TreeError::UnexpectedNodeType` for a node
+    /// that does not match the hash it is referenced by or its level, such as a corrupt node.
+    pub fn assert_root_consistency(&self, versions: &VersionedRoots<H>) -> Result<(), TreeError> {
+        if versions.latest().1 != self.root {
+            return Err(TreeError::StaleRoot);
+        }
+
+        let node = self.lookup(&self.root, 0)?;
+        if node.hash() != self.root {
+            return Err(TreeError::HashMismatch);
+        }
+        if self.depth > 0 && node.get_left_child().is_err() {
+            return Err(TreeError::UnexpectedNodeType);
+        }
+        if self.recompute_hash(&self.root, 0, &mut HashMap::new())? != self.root {
+            return Err(TreeError::HashMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Recompute the hash of the subtree stored under `hash` at `level` from its leaves.
+    /// Subtrees referenced several times at the same level are walked once.
+    fn recompute_hash(
+        &self,
+        hash: &H::Out,
+        level: usize,
+        visited: &mut HashMap<(usize, H::Out), H::Out>,
+    ) -> Result<H::Out, TreeError> {
+        if hash == &self.null_hash(level) {
+            return Ok(*hash);
+        }
+        if let Some(recomputed) = visited.get(&(level, *hash)) {
+            return Ok(*recomputed);
+        }
+
+        let recomputed = match self.load(hash, level, false)? {
+            Node::Inner(left, right) if level < self.depth => Node::<H>::Inner(
+                NodeHash::Hash(self.recompute_hash(left.get_hash(), level + 1, visited)?),
+                NodeHash::Hash(self.recompute_hash(right.get_hash(), level + 1, visited)?),
+            )
+            .hash(),
+            node @ Node::Value(_) if level == self.depth => node.hash(),
+            _ => return Err(TreeError::UnexpectedNodeType),
+        };
+        visited.insert((level, *hash), recomputed);
+        Ok(recomputed)
+    }
+
+    /// Generate proofs for all populated leaves in a single traversal.  Returns the key, value and
+    /// proof of every populated leaf ordered by key.
+    pub fn prove_all(&self) -> Result<Vec<LeafProof<H>>, TreeError> {
+        self.iter_with_proofs().collect()
+    }
+
+    /// Export the subtree at `prefix` as a standalone proof: every stored node of the subtree
+    /// and the nodes on the path from the root to it.  The proof verifies against the root of
+    /// the tree and serves reads of any key under `prefix`, see `StorageProof::into_tree`.
+    pub fn prove_subtree(&self, prefix: &[u8]) -> Result<StorageProof, TreeError> {
+        Ok(self.prove_subtree_with_stubs(prefix, &[])?.into_parts().0)
+    }
+
+    /// Export the subtree at `prefix` like `prove_subtree`, omitting the non-empty subtrees at
+    /// the key prefixes `stubs`.  Each omitted subtree is exported as a stub, its prefix and
+    /// root hash, so the importer can expand it later, see `StubbedProof::import`.  Stubs
+    /// outside the subtree or at empty subtrees are ignored.
+    pub fn prove_subtree_with_stubs(
+        &self,
+        prefix: &[u8],
+        stubs: &[&[u8]],
+    ) -> Result<StubbedProof<H>, TreeError> {
+        if prefix.len() > self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut nodes = Vec::new();
+        let mut hash = self.root;
+        for (level, &bit) in prefix.iter().enumerate() {
+            // the path to an empty subtree ends at the first empty node
+            if hash == self.null_hash(level) {
+                return Ok(StubbedProof::new(StorageProof::new(nodes), Vec::new()));
+            }
+            let node = self
+                .lookup(&hash, level)
+                .map_err(|error| error.along(prefix))?;
+            hash = *node.get_child_bool(key_bit(bit)?)?.get_hash();
+            nodes.push(node.encode());
+        }
+
+        let mut omitted = Vec::new();
+        let mut stack = Vec::from([(hash, prefix.to_vec())]);
+        while let Some((hash, path)) = stack.pop() {
+            if hash == self.null_hash(path.len()) {
+                continue;
+            }
+            if stubs.contains(&path.as_slice()) {
+                omitted.push((path, hash));
+                continue;
+            }
+            let node = self.lookup(&hash, path.len())?;
+            if let Node::Inner(left, right) = &node {
+                stack.push((*right.get_hash(), [path.as_slice(), &[1]].concat()));
+                stack.push((*left.get_hash(), [path.as_slice(), &[0]].concat()));
+            }
+            nodes.push(node.encode());
+        }
+
+        Ok(StubbedProof::new(StorageProof::new(nodes), omitted))
+    }
+
+    /// Return a tree rooted at the node at `prefix`, with its depth reduced by the length of the
+    /// prefix.  Keys of the view are the suffixes of keys under `prefix` in this tree.
+    ///
+    /// The view shares the database, null hashes, prefetcher and value compression of this tree.
+    /// It does not record or meter its reads; use `prove_subtree` to capture a shard as a witness.
+    pub fn subtree_view(&self, prefix: &[u8]) -> Result<TreeDB<'a, H>, TreeError> {
+        let root = self.prefix_root(prefix)?;
+
+        Ok(TreeDB {
+            db: self.db,
+            root,
+            depth: self.depth - prefix.len(),
+            recorder: None,
+            prefetcher: self.prefetcher,
+            fetcher: self.fetcher,
+            cache: self.cache,
+            verify_hashes: self.verify_hashes,
+            compression: self.compression,
+            null_hashes: self.null_hashes.clone(),
+            meter: None,
+        })
+    }
+
+    /// Return the number of populated leaves with keys starting with `prefix`.  Only the subtree
+    /// under the prefix is read, and empty subtrees within it are skipped without a read.
+    pub fn count_prefix(&self, prefix: &[u8]) -> Result<usize, TreeError> {
+        self.count_leaves(self.prefix_root(prefix)?, prefix.len())
+    }
+
+    /// Return the number of populated leaves under each of the `2^k` key prefixes of length
+    /// `k`, indexed by the prefix read as a number, most significant bit first.  The subtrees
+    /// are counted in one traversal that skips empty subtrees, so balancing work across shards
+    /// costs a read per non-empty node.  Fails with `TreeError::IndexOutOfBounds` if `k`
+    /// exceeds the depth or `MAX_HISTOGRAM_DEPTH`.
+    pub fn prefix_histogram(&self, k: usize) -> Result<Vec<usize>, TreeError> {
+        if k > self.depth || k > MAX_HISTOGRAM_DEPTH {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut histogram = vec![0; 1 << k];
+        for node in self.iter_level(k)? {
+            let (prefix, hash) = node?;
+            let index = prefix
+                .iter()
+                .fold(0, |index, bit| (index << 1) | *bit as usize);
+            histogram[index] = self.count_leaves(hash, k)?;
+        }
+        Ok(histogram)
+    }
+
+    /// Count the populated leaves of the subtree with the given root at `level`.
+    fn count_leaves(&self, root: H::Out, level: usize) -> Result<usize, TreeError> {
+        let mut count = 0;
+        let mut stack = Vec::from([(root, level)]);
+        while let Some((hash, level)) = stack.pop() {
+            if hash == self.null_hash(level) {
+                continue;
+            }
+            if level == self.depth {
+                count += 1;
+                continue;
+            }
+            if let Node::Inner(left, right) = self.lookup(&hash, level)? {
+                stack.push((*left.get_hash(), level + 1));
+                stack.push((*right.get_hash(), level + 1));
             }
+        }
+
+        Ok(count)
+    }
+
+    /// Return the smallest populated key, or `None` for an empty tree.  Descends once from the
+    /// root preferring the left child, so the cost is one read per level.
+    pub fn first_key(&self) -> Result<Option<Vec<u8>>, TreeError> {
+        edge_key(
+            self.root,
+            self.depth,
+            &self.null_hashes,
+            false,
+            |hash, level| self.lookup(hash, level),
+        )
+    }
+
+    /// Return the largest populated key, or `None` for an empty tree.  Descends once from the
+    /// root preferring the right child, so the cost is one read per level.
+    pub fn last_key(&self) -> Result<Option<Vec<u8>>, TreeError> {
+        edge_key(
+            self.root,
+            self.depth,
+            &self.null_hashes,
+            true,
+            |hash, level| self.lookup(hash, level),
+        )
+    }
+
+    /// Iterate over all populated leaves in key order, yielding the key, value and proof of each.
+    /// The tree is traversed once and sibling hashes are shared along the traversal.
+    pub fn iter_with_proofs(&self) -> LeafProofIter<'_, 'a, H> {
+        LeafProofIter {
+            tree: self,
+            path: Vec::new(),
+            siblings: Vec::new(),
+            pending: Vec::from([(0, 0, self.root, self.root)]),
+        }
+    }
+
+    /// Iterate over the non-null nodes at `level` in key order, yielding the key prefix and hash
+    /// of each.  Level 0 is the root and level `depth` yields the leaves.  Empty subtrees are
+    /// skipped without a read, so only the nodes above the level and their children are read.
+    pub fn iter_level(&self, level: usize) -> Result<LevelIter<'_, 'a, H>, TreeError> {
+        if level > self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        Ok(LevelIter {
+            tree: self,
+            level,
+            pending: Vec::from([(Vec::new(), self.root)]),
+        })
+    }
+
+    /// Iterate over the populated leaves with keys greater than or equal to `start` in key order.
+    pub fn iter_from(&self, start: &[u8]) -> Result<LeafIter<'_, 'a, H>, TreeError> {
+        LeafIter::new(self, start)
+    }
+
+    /// Resume an iteration from a cursor.  Fails with `TreeError::StaleCursor` if the cursor was
+    /// created for a different root.
+    pub fn resume(&self, cursor: &Cursor<H>) -> Result<LeafIter<'_, 'a, H>, TreeError> {
+        if cursor.root() != &self.root {
+            return Err(TreeError::StaleCursor);
+        }
+        LeafIter::new(self, cursor.key())
+    }
+
+    /// Issue the hashes of the nodes on the paths to `keys` to the prefetcher, one batch per
+    /// level.  Each level is read to discover the next, so a backend that fetches a batch
+    /// concurrently pays the storage latency once per level instead of once per node.  Nodes
+    /// are read through the node cache and fetcher like other reads, but are not recorded or
+    /// metered.  Null nodes are not issued.  Returns the number of issued hashes.
+    pub fn prefetch(&self, keys: &[&[u8]]) -> Result<usize, TreeError> {
+        let prefetcher = match self.prefetcher {
+            Some(prefetcher) => prefetcher,
+            None => return Ok(0),
+        };
+        if keys.iter().any(|key| key.len() != self.depth) {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut issued = 0;
+        let mut level_nodes = Vec::from([(Vec::new(), self.root)]);
+        for level in 0..=self.depth {
+            level_nodes.retain(|(_, hash)| hash != &self.null_hash(level));
+            if level_nodes.is_empty() {
+                break;
+            }
+
+            let hashes: Vec<H::Out> = level_nodes.iter().map(|(_, hash)| *hash).collect();
+            prefetcher.prefetch(&hashes);
+            issued += hashes.len();
+            if level == self.depth {
+                break;
+            }
+
+            let mut next_nodes = Vec::new();
+            for (prefix, hash) in level_nodes {
+                let node = self
+                    .load(&hash, level, false)
+                    .map_err(|error| error.along(&prefix))?;
+                for bit in [false, true] {
+                    let mut child_prefix = prefix.clone();
+                    child_prefix.push(bit as u8);
+                    if keys.iter().any(|key| key.starts_with(&child_prefix)) {
+                        next_nodes.push((child_prefix, *node.get_child_bool(bit)?.get_hash()));
+                    }
+                }
+            }
+            level_nodes = next_nodes;
+        }
+
+        Ok(issued)
+    }
+
+    /// Return the hash of the node at the end of the path `prefix`.
+    fn prefix_root(&self, prefix: &[u8]) -> Result<H::Out, TreeError> {
+        match prefix.split_last() {
+            None => Ok(self.root),
+            Some(_) if prefix.len() > self.depth => Err(TreeError::IndexOutOfBounds),
+            Some((&bit, parent)) => Ok(*self.get(parent)?.get_child(bit)?.get_hash()),
+        }
+    }
+
+    /// Return a proof of the leaf at `key` revealing its hash instead of its value, for
+    /// disclosing inclusion to a party that must not see the value.
+    pub fn get_redacted_proof(&self, key: &[u8]) -> Result<RedactedProof<H>, TreeError> {
+        let (commitment, proof) = self.get_leaf_proof(key)?;
+        Ok(RedactedProof::new(commitment, proof))
+    }
+
+    /// Return the leaf hash at `key` and its proof without reading the value node, so proving a
+    /// leaf costs one read per level however large its value.  Check the proof with
+    /// `verify_with_siblings`.
+    pub fn get_leaf_proof(&self, key: &[u8]) -> Result<(H::Out, Proof<H>), TreeError> {
+        if key.len() != self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let path = self.inner_path(key)?;
+        let mut leaf = self.root;
+        let mut siblings = Vec::with_capacity(path.len());
+        for (&bit, (left, right)) in key.iter().zip(path) {
+            let (child, sibling) = if bit == 1 {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            leaf = child;
+            siblings.push(sibling);
+        }
+        siblings.reverse();
+
+        Ok((leaf, Proof::from_siblings(siblings)))
+    }
+
+    /// Read the inner nodes along `key_prefix` and return their child hashes from the root down.
+    /// The node at the end of the path is not read, so a walk to a leaf never fetches its value.
+    fn inner_path(&self, key_prefix: &[u8]) -> Result<Vec<ChildHashes<H>>, TreeError> {
+        let mut path = Vec::with_capacity(key_prefix.len());
+        let mut hash = self.root;
+        for (level, &bit) in key_prefix.iter().enumerate() {
+            let bit = key_bit(bit)?;
+            let (left, right) = match self
+                .lookup(&hash, level)
+                .map_err(|error| error.along(key_prefix))?
+            {
+                Node::Inner(left, right) => (*left.get_hash(), *right.get_hash()),
+                _ => return Err(TreeError::UnexpectedNodeType),
+            };
+            hash = if bit { right } else { left };
+            path.push((left, right));
+        }
+
+        Ok(path)
+    }
+
+    /// Return the compression of values at rest, if any.
+    pub(crate) fn compression(&self) -> Option<&'a dyn ValueCompression> {
+        self.compression
+    }
+
+    /// Return the null hash at the given level.
+    pub(crate) fn null_hash(&self, level: usize) -> H::Out {
+        self.null_hashes.at_level(level, self.depth)
+    }
+
+    pub fn lookup(&self, key: &H::Out, depth: usize) -> Result<Node<H>, TreeError> {
+        self.load(key, depth, true)
+    }
+
+    /// Read the node under `key` like `lookup`, recording and metering the read if `record`.
+    fn load(&self, key: &H::Out, depth: usize, record: bool) -> Result<Node<H>, TreeError> {
+        if let Some(data) = self.cache.and_then(|cache| cache.get(key)) {
+            return self.decode_node(key, data, record);
+        }
+
+        let (data, fetched) = match self.db.get(key, EMPTY_PREFIX) {
+            Some(value) => (value, false),
+            None => match null_node(&self.null_hashes, key, depth, self.depth) {
+                Some(node) => return Ok(node),
+                None => self
+                    .fetcher
+                    .and_then(|fetcher| fetcher.fetch(key))
+                    .map(|value| (value, true))
+                    .ok_or_else(|| TreeError::missing_node::<H>(key, depth))?,
+            },
         };
 
-        let node: Node<H> = data.try_into()?;
-        self.recorder
-            .as_ref()
-            .map(|r| r.borrow_mut().record(node.clone()));
+        let data = compression::decode_stored(self.compression, data)?;
+        if self.verify_hashes || fetched {
+            let node = Node::<H>::decode(&data)?;
+            if &node.hash() != key {
+                return Err(TreeError::HashMismatch);
+            }
+            if let Some(cache) = self.cache {
+                cache.insert_verified(*key, data.clone());
+            }
+        } else if let Some(cache) = self.cache {
+            // the cache is shared with trees that verify hashes, so check what enters it
+            cache.insert(*key, data.clone());
+        }
+
+        self.decode_node(key, data, record)
+    }
+
+    /// Decode the node read under `key`, recording and metering the read if `record`.
+    fn decode_node(&self, key: &H::Out, data: DBValue, record: bool) -> Result<Node<H>, TreeError> {
+        if record {
+            if let Some(recorder) = self.recorder.as_ref() {
+                recorder.borrow_mut().record(key, &data);
+            }
+            if let Some(meter) = self.meter.as_ref() {
+                meter.touch(data.len());
+            }
+        }
 
-        Ok(node)
+        data.try_into()
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Node<H>, TreeError> {
         // if index < 1 || (1 << self.depth) * 3 <= index {
         //     return Err(TreeError::IndexOutOfBounds);
         // }
-        let mut current_node = self.lookup(self.root, 0)?;
+        let mut current_node = self.lookup(&self.root, 0)?;
 
         for (depth, &bit) in key.iter().enumerate() {
-            let key = current_node.get_child(bit)?.get_hash();
-            current_node = self.lookup(key, depth + 1)?;
+            let hash = current_node.get_child_bool(key_bit(bit)?)?.get_hash();
+            current_node = self
+                .lookup(hash, depth + 1)
+                .map_err(|error| error.along(key))?;
         }
 
         Ok(current_node)
     }
 }
 
+/// The hashes of the left and right children of an inner node.
+type ChildHashes<H> = (<H as Hasher>::Out, <H as Hasher>::Out);
+
+/// A node's key prefix and hash.
+type PrefixedHash<H> = (Vec<u8>, <H as Hasher>::Out);
+
+/// An iterator over the populated leaves of a `TreeDB` and their proofs.
+///
+/// Created by `TreeDB::iter_with_proofs`.  Iteration stops after the first error.
+pub struct LeafProofIter<'t, 'a, H: Hasher> {
+    tree: &'t TreeDB<'a, H>,
+    path: Vec<u8>,
+    siblings: Vec<H::Out>,
+    /// Nodes left to visit as (level, branch bit, sibling hash, node hash).  The branch bit and
+    /// sibling are ignored for the root.
+    pending: Vec<(usize, u8, H::Out, H::Out)>,
+}
+
+impl<'t, 'a, H: Hasher> LeafProofIter<'t, 'a, H> {
+    fn step(&mut self) -> Result<Option<LeafProof<H>>, TreeError> {
+        while let Some((level, bit, sibling, hash)) = self.pending.pop() {
+            if level > 0 {
+                self.path.truncate(level - 1);
+                self.siblings.truncate(level - 1);
+                self.path.push(bit);
+                self.siblings.push(sibling);
+            }
+            if hash == self.tree.null_hash(level) {
+                continue;
+            }
+
+            let node = self.tree.lookup(&hash, level)?;
+            if level == self.tree.depth {
+                let proof = Proof::from_siblings(self.siblings.iter().rev().copied().collect());
+                let value = node.get_value()?.get().clone();
+                return Ok(Some((self.path.clone(), value, proof)));
+            }
+
+            let left = *node.get_left_child()?.get_hash();
+            let right = *node.get_right_child()?.get_hash();
+            self.pending.push((level + 1, 1, left, right));
+            self.pending.push((level + 1, 0, right, left));
+        }
+
+        Ok(None)
+    }
+}
+
+impl<'t, 'a, H: Hasher> Iterator for LeafProofIter<'t, 'a, H> {
+    type Item = Result<LeafProof<H>, TreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.step();
+        if result.is_err() {
+            self.pending.clear();
+        }
+        result.transpose()
+    }
+}
+
+/// An iterator over the non-null nodes at one level of a `TreeDB`.
+///
+/// Created by `TreeDB::iter_level`.  Iteration stops after the first error.
+pub struct LevelIter<'t, 'a, H: Hasher> {
+    tree: &'t TreeDB<'a, H>,
+    level: usize,
+    /// Nodes left to visit as (key prefix, hash), the next one last.
+    pending: Vec<PrefixedHash<H>>,
+}
+
+impl<'t, 'a, H: Hasher> LevelIter<'t, 'a, H> {
+    fn step(&mut self) -> Result<Option<PrefixedHash<H>>, TreeError> {
+        while let Some((prefix, hash)) = self.pending.pop() {
+            if hash == self.tree.null_hash(prefix.len()) {
+                continue;
+            }
+            if prefix.len() == self.level {
+                return Ok(Some((prefix, hash)));
+            }
+
+            let node = self
+                .tree
+                .lookup(&hash, prefix.len())
+                .map_err(|error| error.along(&prefix))?;
+            let left = *node.get_left_child()?.get_hash();
+            let right = *node.get_right_child()?.get_hash();
+            self.pending
+                .push(([prefix.as_slice(), &[1]].concat(), right));
+            self.pending
+                .push(([prefix.as_slice(), &[0]].concat(), left));
+        }
+
+        Ok(None)
+    }
+}
+
+impl<'t, 'a, H: Hasher> Iterator for LevelIter<'t, 'a, H> {
+    type Item = Result<PrefixedHash<H>, TreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.step();
+        if result.is_err() {
+            self.pending.clear();
+        }
+        result.transpose()
+    }
+}
+
 impl<'a, H: Hasher> Tree<H> for TreeDB<'a, H> {
     fn root(&self) -> &H::Out {
-        self.root
+        &self.root
     }
 
     fn depth(&self) -> usize {
@@ -120,9 +767,15 @@ impl<'a, H: Hasher> Tree<H> for TreeDB<'a, H> {
             return Err(TreeError::IndexOutOfBounds);
         }
 
+        if let Some(meter) = self.meter.as_ref() {
+            meter.start();
+        }
         let data = self
             .get(key)
-            .map(|node| node.get_value().map(|x| x.get().to_owned()))?;
+            .and_then(|node| node.get_value().map(|x| x.get().to_owned()));
+        if let Some(meter) = self.meter.as_ref() {
+            meter.charge();
+        }
 
         data
     }
@@ -132,47 +785,50 @@ impl<'a, H: Hasher> Tree<H> for TreeDB<'a, H> {
             return Err(TreeError::IndexOutOfBounds);
         }
 
-        let data = self.get(&key[..key.len() - 1]).map(|node| {
-            node.get_child(key[key.len() - 1])
-                .map(|x| x.get_hash().to_owned())
-        })?;
-
-        data
+        Ok(self.get_leaf_proof(key)?.0)
     }
 
+    /// Sibling hashes are read from the parent node, so the proof costs one read per level,
+    /// each depending on the previous one.  Use `prefetch` to batch the reads of many keys.
     fn get_proof(&self, key: &[u8]) -> Result<Vec<(usize, DBValue)>, TreeError> {
         if key.len() != self.depth {
             return Err(TreeError::IndexOutOfBounds);
         }
 
-        let mut proof = Vec::new();
+        let mut proof = Vec::with_capacity(2 * key.len() + 2);
         proof.push((1, self.root.as_ref().to_vec()));
 
-        let mut current_node = self.lookup(self.root, 0)?;
+        // the index of the current node, extended by one key bit per level
+        let mut index = 1;
+        let mut leaf = self.root;
+        for (&bit, (left, right)) in key.iter().zip(self.inner_path(key)?) {
+            let left_index = indices::child_index(index, false);
+            proof.extend_from_slice(&[
+                (left_index, left.as_ref().to_vec()),
+                (left_index + 1, right.as_ref().to_vec()),
+            ]);
+            leaf = if bit == 1 { right } else { left };
+            index = indices::child_index(index, bit == 1);
+        }
 
-        for (i, &bit) in key.iter().enumerate() {
-            let index = indices::compute_index(&key[..i + 1]);
-            let left_index = if index % 2 == 0 { index } else { index ^ 1 };
+        // the value is the only node read past the inner nodes
+        let value = self
+            .lookup(&leaf, self.depth)
+            .map_err(|error| error.along(key))?;
+        proof.push((0, value.get_value()?.get().clone()));
 
-            if let Node::Inner(left, right) = current_node {
-                let key = if bit == 0 {
-                    left.get_hash()
-                } else {
-                    right.get_hash()
-                };
-                current_node = self.lookup(key, i + 1)?;
+        Ok(proof)
+    }
 
-                proof.extend_from_slice(&[
-                    (left_index, left.get_hash().as_ref().to_vec()),
-                    (left_index + 1, right.get_hash().as_ref().to_vec()),
-                ]);
-            } else {
-                return Err(TreeError::UnexpectedNodeType);
-            }
+    fn get_node(&self, key_prefix: &[u8]) -> Result<NodeView<H>, TreeError> {
+        if key_prefix.len() > self.depth {
+            return Err(TreeError::IndexOutOfBounds);
         }
 
-        proof.push((0, current_node.get_value()?.get().clone()));
+        self.get(key_prefix).map(NodeView::from)
+    }
 
-        Ok(proof)
+    fn get_value_proof(&self, key: &[u8]) -> Result<(Option<DBValue>, Proof<H>), TreeError> {
+        value_proof(key, self.get_proof(key)?, self.null_hashes.default_leaf())
     }
 }
@@ -1,14 +1,34 @@
 use crate::{
-    indices, node::NodeHash, node::Value, rstd::HashMap, DBValue, Node, TreeError, TreeMut,
-    TreeRecorder, compute_null_hashes,
+    compression, frontier, indices, meter::Meter, node::edge_key, node::key_bit, node::null_node,
+    node::NodeHash, node::Value, overlay::Overlay, rstd::Arc, rstd::BTreeSet, rstd::HashSet,
+    split_expiry, value_proof, wal, with_expiry, AccessMeter, ConditionalWrite, DBValue, Frontier,
+    KeyDepth, Mutation, MutationSink, Node, NodeFetcher, NodeView, NullHashTable, NullHashes,
+    Proof, TreeError, TreeMetadata, TreeMut, TreeRecorder, ValueCompression,
 };
+use core::{hash::BuildHasher, marker::PhantomData};
 use hash_db::{HashDB, HashDBRef, Hasher, EMPTY_PREFIX};
+use std::{
+    collections::hash_map::RandomState,
+    time::{Duration, Instant},
+};
 
-pub struct TreeDBMutBuilder<'db, H: Hasher> {
+pub struct TreeDBMutBuilder<'db, H: Hasher, S = RandomState> {
     db: &'db mut dyn HashDB<H, DBValue>,
     root: &'db mut H::Out,
     depth: usize,
     recorder: Option<&'db mut dyn TreeRecorder<H>>,
+    audit_log: Option<&'db mut dyn MutationSink<H>>,
+    wal: Option<&'db mut dyn std::io::Write>,
+    verify_hashes: bool,
+    compression: Option<&'db dyn ValueCompression>,
+    fetcher: Option<&'db dyn NodeFetcher<H>>,
+    commit_progress: Option<CommitProgress<'db>>,
+    commit_summary: Option<SummaryCallback<'db, H>>,
+    spill: Option<(&'db mut dyn HashDB<H, DBValue>, usize)>,
+    arena: Option<usize>,
+    null_hashes: Option<Arc<NullHashes<H>>>,
+    meter: Option<&'db mut dyn AccessMeter>,
+    hasher: PhantomData<S>,
 }
 
 impl<'db, H: Hasher> TreeDBMutBuilder<'db, H> {
@@ -18,6 +38,52 @@ impl<'db, H: Hasher> TreeDBMutBuilder<'db, H> {
             root,
             depth,
             recorder: None,
+            audit_log: None,
+            wal: None,
+            verify_hashes: false,
+            compression: None,
+            fetcher: None,
+            commit_progress: None,
+            commit_summary: None,
+            spill: None,
+            arena: None,
+            null_hashes: None,
+            meter: None,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Create a builder for a tree keyed by `K`, deriving the depth from the key type.
+    pub fn for_key<K: KeyDepth>(
+        db: &'db mut dyn HashDB<H, DBValue>,
+        root: &'db mut H::Out,
+    ) -> Self {
+        Self::new(db, root, K::DEPTH)
+    }
+}
+
+impl<'db, H: Hasher, S: BuildHasher + Default> TreeDBMutBuilder<'db, H, S> {
+    /// Hash the keys of the in-memory overlay with `S2` instead of the default SipHash.  Node
+    /// hashes are uniformly distributed, so a cheap hasher such as `BuildFoldHasher`
+    /// suffices.
+    pub fn with_hasher<S2: BuildHasher + Default>(self) -> TreeDBMutBuilder<'db, H, S2> {
+        TreeDBMutBuilder {
+            db: self.db,
+            root: self.root,
+            depth: self.depth,
+            recorder: self.recorder,
+            audit_log: self.audit_log,
+            wal: self.wal,
+            verify_hashes: self.verify_hashes,
+            compression: self.compression,
+            fetcher: self.fetcher,
+            commit_progress: self.commit_progress,
+            commit_summary: self.commit_summary,
+            spill: self.spill,
+            arena: self.arena,
+            null_hashes: self.null_hashes,
+            meter: self.meter,
+            hasher: PhantomData,
         }
     }
 
@@ -34,37 +100,298 @@ impl<'db, H: Hasher> TreeDBMutBuilder<'db, H> {
         self
     }
 
-    pub fn build(self) -> TreeDBMut<'db, H> {
+    pub fn with_audit_log(mut self, audit_log: &'db mut dyn MutationSink<H>) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Record every insert to the given write-ahead log before it is applied.  Use
+    /// `replay_wal` to recover uncommitted inserts after a crash.
+    pub fn with_wal(mut self, wal: &'db mut dyn std::io::Write) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Invoke `callback` with the number of nodes and bytes written so far after every
+    /// `interval` nodes written by a commit and once the commit completes.  Returning false
+    /// cancels a `try_commit()`; `commit()` and the implicit commit of `root()` cannot be
+    /// cancelled and ignore the returned value.
+    pub fn with_commit_progress(
+        mut self,
+        interval: usize,
+        callback: &'db mut dyn FnMut(usize, usize) -> bool,
+    ) -> Self {
+        self.commit_progress = Some(CommitProgress {
+            interval: interval.max(1),
+            callback,
+        });
+        self
+    }
+
+    /// Invoke `callback` with a `CommitSummary` after every successful commit, including
+    /// commits with nothing to write.
+    pub fn with_commit_summary(mut self, callback: &'db mut dyn FnMut(&CommitSummary<H>)) -> Self {
+        self.commit_summary = Some(callback);
+        self
+    }
+
+    /// Move the least recently written uncommitted nodes to `backend` once they take more than
+    /// `budget` bytes in memory.  Spilled nodes are read back as needed and removed from
+    /// `backend` when the changes are committed.
+    pub fn with_spill(mut self, backend: &'db mut dyn HashDB<H, DBValue>, budget: usize) -> Self {
+        self.spill = Some((backend, budget));
+        self
+    }
+
+    /// Copy the values of uncommitted nodes into an arena of `chunk_size` byte chunks instead of
+    /// holding one allocation per value, and release the arena as a whole on commit.  Use it for
+    /// large batch imports creating many temporary nodes.
+    pub fn with_arena(mut self, chunk_size: usize) -> Self {
+        self.arena = Some(chunk_size);
+        self
+    }
+
+    /// Fetch nodes missing from the database from `fetcher` instead of failing, see
+    /// `NodeFetcher`.  Only changed nodes are written on commit, so unchanged fetched nodes are
+    /// fetched again on later reads.
+    pub fn with_node_fetcher(mut self, fetcher: &'db dyn NodeFetcher<H>) -> Self {
+        self.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Seed the null hashes from the hasher's precomputed table.  Heights beyond the table are
+    /// computed on demand.
+    pub fn with_null_hash_table(mut self) -> Self
+    where
+        H: NullHashTable,
+        H::Out: 'static,
+    {
+        self.null_hashes = Some(Arc::new(NullHashes::from_table()));
+        self
+    }
+
+    /// Share the given null-hash ladder instead of growing a new one for this tree.  Use a
+    /// ladder created with `NullHashes::with_default_leaf` for trees whose empty leaves hold
+    /// another value than the empty value.
+    pub fn with_null_hashes(mut self, null_hashes: Arc<NullHashes<H>>) -> Self {
+        self.null_hashes = Some(null_hashes);
+        self
+    }
+
+    /// Recompute the hash of every node fetched from the database and fail with
+    /// `TreeError::HashMismatch` if it does not match the key it was fetched with.
+    pub fn with_hash_verification(mut self) -> Self {
+        self.verify_hashes = true;
+        self
+    }
+
+    /// Charge `meter` for the nodes touched by every `get_value` and `insert`.
+    pub fn with_access_meter(mut self, meter: &'db mut dyn AccessMeter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Compress values at rest with `compression`, see `ValueCompression`.
+    pub fn with_value_compression(mut self, compression: &'db dyn ValueCompression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Check the tree against the `TreeMetadata` record stored under `metadata`.  Fails with
+    /// `TreeError::MetadataMismatch` if the record describes a different depth, arity or node
+    /// encoding, and with `TreeError::DataNotFound` if there is no such record.
+    pub fn with_metadata(self, metadata: &H::Out) -> Result<Self, TreeError> {
+        TreeMetadata::validate(&self.db, metadata, self.depth)?;
+        Ok(self)
+    }
+
+    pub fn build(self) -> TreeDBMut<'db, H, S> {
         let root_handle = NodeHash::Hash(*self.root);
         TreeDBMut {
             db: self.db,
-            storage: HashMap::new(),
+            storage: Overlay::new(self.spill, self.arena),
             root: self.root,
-            root_handle: root_handle,
+            root_handle,
             depth: self.depth,
             recorder: self.recorder.map(core::cell::RefCell::new),
-            null_hashes: compute_null_hashes::<H>(self.depth)
+            audit_log: self.audit_log,
+            wal: self.wal,
+            verify_hashes: self.verify_hashes,
+            compression: self.compression,
+            fetcher: self.fetcher,
+            commit_progress: self.commit_progress,
+            commit_summary: self.commit_summary,
+            watchers: Vec::new(),
+            touched: BTreeSet::new(),
+            next_watch_id: 0,
+            checkpoints: Vec::new(),
+            next_checkpoint_id: 0,
+            flushed: HashSet::new(),
+            wal_records: 0,
+            audit_records: 0,
+            commit_record_pending: false,
+            null_hashes: self.null_hashes.unwrap_or_default(),
+            meter: self.meter.map(Meter::new),
         }
     }
 }
 
+/// The number of nodes a commit wrote to the database and the number of duplicate references
+/// to already buffered nodes it skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommitStats {
+    pub written: usize,
+    pub skipped: usize,
+}
+
+/// A summary of a successful commit, passed to the callback set with
+/// `TreeDBMutBuilder::with_commit_summary`.
+#[derive(Debug)]
+pub struct CommitSummary<H: Hasher> {
+    /// The number of nodes written to the database.
+    pub written: usize,
+    /// The number of duplicate references to already buffered nodes.
+    pub skipped: usize,
+    /// The number of encoded bytes written to the database.
+    pub bytes: usize,
+    /// The root after the commit.
+    pub root: H::Out,
+    /// The time spent in the commit.
+    pub elapsed: Duration,
+}
+
+impl<H: Hasher> Clone for CommitSummary<H> {
+    fn clone(&self) -> Self {
+        Self {
+            written: self.written,
+            skipped: self.skipped,
+            bytes: self.bytes,
+            root: self.root,
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+/// Identifier of a watch registered with `TreeDBMut::watch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchId(usize);
+
+/// Identifier of a checkpoint taken with `TreeDBMut::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointId(usize);
+
+struct Checkpoint<H: Hasher> {
+    id: CheckpointId,
+    root_handle: NodeHash<H>,
+    touched: BTreeSet<Vec<u8>>,
+    wal_records: usize,
+    audit_records: usize,
+}
+
+type SummaryCallback<'a, H> = &'a mut dyn FnMut(&CommitSummary<H>);
+
+struct CommitProgress<'a> {
+    interval: usize,
+    callback: &'a mut dyn FnMut(usize, usize) -> bool,
+}
+
+struct Watcher<'a> {
+    id: WatchId,
+    prefix: Vec<u8>,
+    callback: &'a mut dyn FnMut(&[u8]),
+}
+
 /// A `TreeMut` implementation using a generic `HashDB` backing database.
 ///
 /// Use it as a `TreeMut` trait object.  You can use `db()` to get the backing
 /// database object.  Changes are not committed until `commit()` is called.
 ///
 /// Querying the root or dropping the `TreeDBMut` will `commit()` stored changes.
-pub struct TreeDBMut<'a, H: Hasher> {
+pub struct TreeDBMut<'a, H: Hasher, S = RandomState> {
     db: &'a mut dyn HashDB<H, DBValue>,
-    storage: HashMap<H::Out, Node<H>>,
+    storage: Overlay<'a, H, S>,
     root: &'a mut H::Out,
     root_handle: NodeHash<H>,
     depth: usize,
     recorder: Option<core::cell::RefCell<&'a mut dyn TreeRecorder<H>>>,
-    null_hashes: Vec<H::Out>
+    audit_log: Option<&'a mut dyn MutationSink<H>>,
+    wal: Option<&'a mut dyn std::io::Write>,
+    verify_hashes: bool,
+    compression: Option<&'a dyn ValueCompression>,
+    fetcher: Option<&'a dyn NodeFetcher<H>>,
+    commit_progress: Option<CommitProgress<'a>>,
+    commit_summary: Option<SummaryCallback<'a, H>>,
+    watchers: Vec<Watcher<'a>>,
+    touched: BTreeSet<Vec<u8>>,
+    next_watch_id: usize,
+    checkpoints: Vec<Checkpoint<H>>,
+    next_checkpoint_id: usize,
+    /// Nodes already written by a cancelled commit.
+    flushed: HashSet<H::Out>,
+    /// Insert records passed to the write-ahead log and the audit log since the last commit.
+    wal_records: usize,
+    audit_records: usize,
+    /// Set when the commit record of the last commit could not be written to the write-ahead
+    /// log.  It is written before any further record.
+    commit_record_pending: bool,
+    null_hashes: Arc<NullHashes<H>>,
+    meter: Option<Meter<'a>>,
 }
 
 impl<'a, H: Hasher> TreeDBMut<'a, H> {
+    /// Resume an append-only tree whose first `next_index` leaves are populated from the
+    /// siblings of its `Frontier`, without the nodes of those leaves.  The nodes along
+    /// the path to leaf `next_index` are rebuilt in memory, so leaves can be appended from
+    /// there on and `commit` writes the new root to `root`.  Reading the earlier leaves fails
+    /// unless their nodes are in `db`.
+    pub fn from_frontier(
+        db: &'a mut dyn HashDB<H, DBValue>,
+        root: &'a mut H::Out,
+        frontier: &[H::Out],
+        next_index: u64,
+        depth: usize,
+    ) -> Result<Self, TreeError> {
+        let capacity = frontier::capacity(depth);
+        if next_index as u128 > capacity {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut tree = TreeDBMutBuilder::new(db, root, depth).build();
+        if next_index as u128 == capacity {
+            // a full tree has no path to a next leaf, its frontier is the root
+            tree.root_handle = match frontier {
+                [root] => NodeHash::Hash(*root),
+                _ => return Err(TreeError::IndexOutOfBounds),
+            };
+            return Ok(tree);
+        }
+        if frontier.len() != next_index.count_ones() as usize {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut siblings = frontier.iter();
+        let mut handle = NodeHash::Hash(tree.null_hash(depth));
+        for level in (0..depth).rev() {
+            let height = depth - 1 - level;
+            let bit = next_index.checked_shr(height as u32).unwrap_or(0) & 1;
+            let node = if bit == 1 {
+                let left = *siblings.next().expect("one sibling per set bit");
+                Node::Inner(NodeHash::Hash(left), handle)
+            } else if handle.get_hash() == &tree.null_hash(level + 1) {
+                handle = NodeHash::Hash(tree.null_hash(level));
+                continue;
+            } else {
+                Node::Inner(handle, NodeHash::Hash(tree.null_hash(level + 1)))
+            };
+            handle = tree.store(node);
+        }
+        tree.root_handle = handle;
+
+        Ok(tree)
+    }
+}
+
+impl<'a, H: Hasher, S: BuildHasher + Default> TreeDBMut<'a, H, S> {
     pub fn db(&self) -> &dyn HashDB<H, DBValue> {
         self.db
     }
@@ -73,32 +400,149 @@ impl<'a, H: Hasher> TreeDBMut<'a, H> {
         self.db
     }
 
+    /// Return the number of uncommitted nodes currently held by the spill backend.
+    pub fn spilled_nodes(&self) -> usize {
+        self.storage.spilled()
+    }
+
+    /// Return the bytes reserved by the value arena, see `TreeDBMutBuilder::with_arena`.
+    pub fn arena_capacity(&self) -> usize {
+        self.storage.arena_capacity()
+    }
+
+    /// Register a callback invoked on `commit()` with every committed key under `prefix`.
+    pub fn watch(&mut self, prefix: &[u8], callback: &'a mut dyn FnMut(&[u8])) -> WatchId {
+        let id = WatchId(self.next_watch_id);
+        self.next_watch_id += 1;
+        self.watchers.push(Watcher {
+            id,
+            prefix: prefix.to_vec(),
+            callback,
+        });
+        id
+    }
+
+    /// Remove a previously registered watch.  Returns false if the watch does not exist.
+    pub fn unwatch(&mut self, id: WatchId) -> bool {
+        let len = self.watchers.len();
+        self.watchers.retain(|watcher| watcher.id != id);
+        self.watchers.len() != len
+    }
+
+    /// Snapshot the uncommitted changes, to be restored with `revert_to`.
+    ///
+    /// Uncommitted nodes are addressed by hash and never removed before commit, so a
+    /// checkpoint only holds the root handle and is cheap to take.  Committing drops all
+    /// checkpoints.
+    pub fn checkpoint(&mut self) -> CheckpointId {
+        let id = CheckpointId(self.next_checkpoint_id);
+        self.next_checkpoint_id += 1;
+        self.checkpoints.push(Checkpoint {
+            id,
+            root_handle: self.root_handle.clone(),
+            touched: self.touched.clone(),
+            wal_records: self.wal_records,
+            audit_records: self.audit_records,
+        });
+        id
+    }
+
+    /// Discard the changes made since the checkpoint was taken, without touching the database.
+    /// The checkpoint and those taken after it are dropped.  Fails with
+    /// `TreeError::UnknownCheckpoint` if the checkpoint was dropped or committed.
+    ///
+    /// The inserts made since the checkpoint are retracted with a revert record in the
+    /// write-ahead log, which `replay_wal` honours, and a `Mutation::Revert` in the audit log.
+    /// Fails with `TreeError::WalFailed`, reverting nothing, if the revert record cannot be
+    /// written.
+    pub fn revert_to(&mut self, id: CheckpointId) -> Result<(), TreeError> {
+        let position = self
+            .checkpoints
+            .iter()
+            .position(|checkpoint| checkpoint.id == id)
+            .ok_or(TreeError::UnknownCheckpoint)?;
+        let (wal_records, audit_records) = {
+            let checkpoint = &self.checkpoints[position];
+            (checkpoint.wal_records, checkpoint.audit_records)
+        };
+        self.write_pending_commit_record()?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal::append_revert(&mut **wal, wal_records)?;
+        }
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            audit_log.on_mutation(Mutation::Revert {
+                retained: audit_records,
+                root: *self.checkpoints[position].root_handle.get_hash(),
+            });
+        }
+        self.wal_records = wal_records;
+        self.audit_records = audit_records;
+
+        let checkpoint = self
+            .checkpoints
+            .drain(position..)
+            .next()
+            .expect("position is in bounds");
+        self.root_handle = checkpoint.root_handle;
+        self.touched = checkpoint.touched;
+        Ok(())
+    }
+
+    /// Drop the checkpoint, keeping the changes made since it was taken.  Fails with
+    /// `TreeError::UnknownCheckpoint` if the checkpoint was dropped or committed.
+    pub fn release_checkpoint(&mut self, id: CheckpointId) -> Result<(), TreeError> {
+        let position = self
+            .checkpoints
+            .iter()
+            .position(|checkpoint| checkpoint.id == id)
+            .ok_or(TreeError::UnknownCheckpoint)?;
+        self.checkpoints.remove(position);
+        Ok(())
+    }
+
+    fn notify_watchers(&mut self) {
+        let touched = core::mem::take(&mut self.touched);
+        for watcher in self.watchers.iter_mut() {
+            for key in touched
+                .iter()
+                .filter(|key| key.starts_with(&watcher.prefix))
+            {
+                (watcher.callback)(key);
+            }
+        }
+    }
 
     pub fn lookup(&self, key: &H::Out, depth: usize) -> Result<Node<H>, TreeError> {
         if let Some(node) = self.storage.get(key) {
-            return Ok(node.clone());
-        }
-
-        let data = if let Some(value) = self.db.get(key, EMPTY_PREFIX) {
-            value
-        } else {
-            if depth == self.depth && key == &self.null_hashes[depth] {
-                return Ok(Node::Value(Value::Cached(DBValue::new())));
-            } else if key == &self.null_hashes[depth] {
-                let null_hash = self.null_hashes[depth + 1];
-                return Ok(Node::Inner(
-                    NodeHash::Hash(null_hash),
-                    NodeHash::Hash(null_hash),
-                ));
-            } else {
-                return Err(TreeError::UnexpectedError);
+            if let Some(meter) = self.meter.as_ref() {
+                meter.touch(node.encoded_len());
             }
+            return Ok(node);
+        }
+
+        let (data, fetched) = match self.db.get(key, EMPTY_PREFIX) {
+            Some(value) => (value, false),
+            None => match null_node(&self.null_hashes, key, depth, self.depth) {
+                Some(node) => return Ok(node),
+                None => self
+                    .fetcher
+                    .and_then(|fetcher| fetcher.fetch(key))
+                    .map(|value| (value, true))
+                    .ok_or_else(|| TreeError::missing_node::<H>(key, depth))?,
+            },
         };
 
+        let data = compression::decode_stored(self.compression, data)?;
+        if let Some(recorder) = self.recorder.as_ref() {
+            recorder.borrow_mut().record(key, &data);
+        }
+        if let Some(meter) = self.meter.as_ref() {
+            meter.touch(data.len());
+        }
         let node: Node<H> = data.try_into()?;
-        self.recorder
-            .as_ref()
-            .map(|r| r.borrow_mut().record(node.clone()));
+        if (self.verify_hashes || fetched) && &node.hash() != key {
+            return Err(TreeError::HashMismatch);
+        }
 
         Ok(node)
     }
@@ -110,89 +554,533 @@ impl<'a, H: Hasher> TreeDBMut<'a, H> {
         let mut current_node = self.lookup(self.root_handle.get_hash(), 0)?;
 
         for (depth, &bit) in key.iter().enumerate() {
-            let key = current_node.get_child(bit)?.get_hash();
-            current_node = self.lookup(key, depth + 1)?;
+            let hash = current_node.get_child_bool(key_bit(bit)?)?.get_hash();
+            current_node = self
+                .lookup(hash, depth + 1)
+                .map_err(|error| error.along(key))?;
         }
 
         Ok(current_node)
     }
 
-    fn insert_at(
+    /// Return the smallest populated key, or `None` for an empty tree.  Descends once from the
+    /// root preferring the left child, so the cost is one read per level.
+    pub fn first_key(&self) -> Result<Option<Vec<u8>>, TreeError> {
+        edge_key(
+            *self.root_handle.get_hash(),
+            self.depth,
+            &self.null_hashes,
+            false,
+            |hash, level| self.lookup(hash, level),
+        )
+    }
+
+    /// Return the largest populated key, or `None` for an empty tree.  Descends once from the
+    /// root preferring the right child, so the cost is one read per level.
+    pub fn last_key(&self) -> Result<Option<Vec<u8>>, TreeError> {
+        edge_key(
+            *self.root_handle.get_hash(),
+            self.depth,
+            &self.null_hashes,
+            true,
+            |hash, level| self.lookup(hash, level),
+        )
+    }
+
+    /// Return the frontier of an append-only tree whose first `next_index` leaves are
+    /// populated.  Pass it to `from_frontier` to resume the tree without its leaves.
+    pub fn frontier(&self, next_index: u64) -> Result<Frontier<H>, TreeError> {
+        let capacity = frontier::capacity(self.depth);
+        if next_index as u128 > capacity {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+        if next_index as u128 == capacity {
+            return Ok(
+                Frontier::new(self.depth, next_index, Vec::from([self.peek_root()]))?
+                    .with_null_hashes(self.null_hashes.clone()),
+            );
+        }
+
+        let mut frontier = Vec::with_capacity(next_index.count_ones() as usize);
+        let mut hash = self.peek_root();
+        let mut key = Vec::with_capacity(self.depth);
+        for level in 0..self.depth {
+            let height = self.depth - 1 - level;
+            let bit = next_index.checked_shr(height as u32).unwrap_or(0) & 1 == 1;
+            let node = self
+                .lookup(&hash, level)
+                .map_err(|error| error.along(&key))?;
+            if bit {
+                frontier.push(*node.get_left_child()?.get_hash());
+            }
+            hash = *node.get_child_bool(bit)?.get_hash();
+            key.push(bit as u8);
+        }
+        frontier.reverse();
+
+        Ok(Frontier::new(self.depth, next_index, frontier)?
+            .with_null_hashes(self.null_hashes.clone()))
+    }
+
+    /// Remove the values at the specified keys in a single pass.
+    ///
+    /// Subtrees that become empty collapse to their null hash.  Returns the keys that held a
+    /// value before removal.
+    pub fn remove_batch(&mut self, keys: &[&[u8]]) -> Result<Vec<Vec<u8>>, TreeError> {
+        if keys.iter().any(|key| key.len() != self.depth) {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        let mut keys = keys.to_vec();
+        keys.sort();
+        keys.dedup();
+
+        // a removal is replayed as writing the default leaf
+        let null_hashes = self.null_hashes.clone();
+        for key in keys.iter() {
+            self.log_insert(key, null_hashes.default_leaf())?;
+        }
+
+        let mut removed = Vec::new();
+        let root_handle = self.root_handle.clone();
+        self.root_handle = self.remove_at(root_handle, 0, &keys, &mut removed)?;
+
+        for (key, old_value_hash) in removed.iter() {
+            self.on_write(key, *old_value_hash, self.null_hash(self.depth));
+        }
+
+        Ok(removed.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// Insert `value` annotated with the time it expires at, see `with_expiry`.  Returns the old
+    /// annotated value at the key.
+    pub fn insert_with_expiry(
         &mut self,
-        current_node: &mut Node<H>,
         key: &[u8],
-        value: DBValue,
-    ) -> Result<Node<H>, TreeError> {
-        if key.len() == 1 {
-            let old_leaf = current_node.get_child(key[0])?;
-            let old_value = self.lookup(&old_leaf.get_hash(), self.depth)?;
-            let new_node = Node::Value(Value::New(value));
-            current_node.set_child_hash(key[0], NodeHash::InMemory(new_node.hash()))?;
-            self.storage.insert(new_node.hash(), new_node);
-            Ok(old_value)
-        } else {
-            let child_key = current_node.get_child(key[0])?;
-            let mut child_node = self.lookup(child_key.get_hash(), self.depth - key.len() + 1)?;
-            let old_value = self.insert_at(&mut child_node, &key[1..], value)?;
-            current_node.set_child_hash(key[0], NodeHash::InMemory(child_node.hash()))?;
-            self.storage.insert(child_node.hash(), child_node);
-            Ok(old_value)
+        value: &[u8],
+        expires_at: u64,
+    ) -> Result<DBValue, TreeError> {
+        self.insert(key, with_expiry(value, expires_at))
+    }
+
+    /// Remove every value that expires at or before `now` in a single pass, for trees whose
+    /// values are all written with `insert_with_expiry`.  Returns the removed keys in order.
+    ///
+    /// Fails with `TreeError::MissingExpiry`, removing nothing, if a value carries no expiry.
+    pub fn sweep_expired(&mut self, now: u64) -> Result<Vec<Vec<u8>>, TreeError> {
+        let mut expired = Vec::new();
+        let mut stack = Vec::from([(*self.root_handle.get_hash(), Vec::new())]);
+        while let Some((hash, key)) = stack.pop() {
+            if hash == self.null_hash(key.len()) {
+                continue;
+            }
+            let node = self.lookup(&hash, key.len())?;
+            match node {
+                Node::Value(value) => {
+                    let (expires_at, _) = split_expiry(value.get())?;
+                    if expires_at <= now {
+                        expired.push(key);
+                    }
+                }
+                Node::Inner(left, right) => {
+                    for (bit, child) in [(1, right), (0, left)] {
+                        let mut child_key = key.clone();
+                        child_key.push(bit);
+                        stack.push((*child.get_hash(), child_key));
+                    }
+                }
+            }
         }
+
+        let keys: Vec<&[u8]> = expired.iter().map(|key| key.as_slice()).collect();
+        self.remove_batch(&keys)
     }
 
+    /// Move the value at `from` to `to` by relinking its leaf hash, leaving `from` empty.
+    /// Returns the hash of the leaf previously at `to`.
+    pub fn move_value(&mut self, from: &[u8], to: &[u8]) -> Result<H::Out, TreeError> {
+        if from.len() != self.depth || to.len() != self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        if self.wal.is_some() {
+            let value = self.get(from)?.get_value()?.get().clone();
+            let null_hashes = self.null_hashes.clone();
+            self.log_insert(to, &value)?;
+            self.log_insert(from, null_hashes.default_leaf())?;
+        }
+
+        let null_leaf = NodeHash::Hash(self.null_hash(self.depth));
+        let from_leaf = self.replace_leaf(from, null_leaf)?;
+        let from_hash = *from_leaf.get_hash();
+        let to_leaf = self.replace_leaf(to, from_leaf)?;
+
+        self.on_write(from, from_hash, self.null_hash(self.depth));
+        self.on_write(to, *to_leaf.get_hash(), from_hash);
+
+        Ok(*to_leaf.get_hash())
+    }
+
+    /// Swap the values at `key_a` and `key_b` by relinking their leaf hashes.
+    pub fn swap(&mut self, key_a: &[u8], key_b: &[u8]) -> Result<(), TreeError> {
+        if key_a.len() != self.depth || key_b.len() != self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        if self.wal.is_some() {
+            let value_a = self.get(key_a)?.get_value()?.get().clone();
+            let value_b = self.get(key_b)?.get_value()?.get().clone();
+            self.log_insert(key_a, &value_b)?;
+            self.log_insert(key_b, &value_a)?;
+        }
+
+        let null_leaf = NodeHash::Hash(self.null_hash(self.depth));
+        let leaf_a = self.replace_leaf(key_a, null_leaf)?;
+        let leaf_b = self.replace_leaf(key_b, leaf_a.clone())?;
+        self.replace_leaf(key_a, leaf_b.clone())?;
+
+        self.on_write(key_a, *leaf_a.get_hash(), *leaf_b.get_hash());
+        self.on_write(key_b, *leaf_b.get_hash(), *leaf_a.get_hash());
+
+        Ok(())
+    }
+
+    /// Grow the tree to `new_depth` by placing the existing tree under additional layers with
+    /// null siblings.  Existing keys keep their values under a prefix of `new_depth - depth`
+    /// zero bits.  Checkpoints taken before are dropped.
+    ///
+    /// Neither the write-ahead log, the audit log nor watchers can describe the change, so this
+    /// fails with `TreeError::HooksAttached` if any of them is attached.
+    pub fn extend_depth(&mut self, new_depth: usize) -> Result<(), TreeError> {
+        if new_depth < self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+        if self.has_hooks() {
+            return Err(TreeError::HooksAttached);
+        }
+
+        let extra = new_depth - self.depth;
+        self.depth = new_depth;
+        for level in (0..extra).rev() {
+            let sibling = NodeHash::Hash(self.null_hash(level + 1));
+            let node = Node::Inner(self.root_handle.clone(), sibling);
+            self.root_handle = self.store(node);
+        }
+
+        self.checkpoints.clear();
+
+        Ok(())
+    }
+
+    /// Return true if a write-ahead log, an audit log or a watcher observes the changes.
+    pub(crate) fn has_hooks(&self) -> bool {
+        self.wal.is_some() || self.audit_log.is_some() || !self.watchers.is_empty()
+    }
+
+    /// Return the compression of values at rest, if any.
+    pub(crate) fn compression(&self) -> Option<&'a dyn ValueCompression> {
+        self.compression
+    }
+
+    /// Return the null hash at the given level.
+    fn null_hash(&self, level: usize) -> H::Out {
+        self.null_hashes.at_level(level, self.depth)
+    }
+
+    pub(crate) fn root_handle(&self) -> &NodeHash<H> {
+        &self.root_handle
+    }
+
+    /// Return the root including uncommitted changes, without committing them.
+    ///
+    /// Reads and proofs are served from this root, so a proof taken before commit verifies
+    /// against `peek_root`, and against `root` once the changes are committed.
+    pub fn peek_root(&self) -> H::Out {
+        *self.root_handle.get_hash()
+    }
+
+    /// Store a node in the overlay, returning its handle.
+    pub(crate) fn store(&mut self, node: Node<H>) -> NodeHash<H> {
+        let hash = node.hash();
+        self.storage.insert(hash, node);
+        NodeHash::InMemory(hash)
+    }
+
+    fn replace_leaf(&mut self, key: &[u8], leaf: NodeHash<H>) -> Result<NodeHash<H>, TreeError> {
+        self.replace_subtree(key, leaf)
+    }
+
+    /// Replace the subtree at `prefix` with the given handle.  Returns the replaced handle.
+    pub(crate) fn replace_subtree(
+        &mut self,
+        prefix: &[u8],
+        handle: NodeHash<H>,
+    ) -> Result<NodeHash<H>, TreeError> {
+        if prefix.is_empty() {
+            return Ok(core::mem::replace(&mut self.root_handle, handle));
+        }
+
+        let mut root_data = self.lookup(self.root_handle.get_hash(), 0)?;
+        let old_handle = self.replace_subtree_at(&mut root_data, prefix, 0, handle)?;
+        let hash = root_data.hash();
+        self.storage.insert(hash, root_data);
+        self.root_handle = NodeHash::InMemory(hash);
+        Ok(old_handle)
+    }
+
+    fn replace_subtree_at(
+        &mut self,
+        node: &mut Node<H>,
+        prefix: &[u8],
+        level: usize,
+        handle: NodeHash<H>,
+    ) -> Result<NodeHash<H>, TreeError> {
+        let bit = key_bit(prefix[level])?;
+        if level + 1 == prefix.len() {
+            let old_handle = node.get_child_bool(bit)?.clone();
+            node.set_child_bool(bit, handle)?;
+            return Ok(old_handle);
+        }
+
+        let mut child = self.lookup(node.get_child_bool(bit)?.get_hash(), level + 1)?;
+        let old_handle = self.replace_subtree_at(&mut child, prefix, level + 1, handle)?;
+        let hash = child.hash();
+        self.storage.insert(hash, child);
+        node.set_child_bool(bit, NodeHash::InMemory(hash))?;
+        Ok(old_handle)
+    }
+
+    fn on_write(&mut self, key: &[u8], old_value_hash: H::Out, new_value_hash: H::Out) {
+        if !self.watchers.is_empty() {
+            self.touched.insert(key.to_vec());
+        }
+
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            audit_log.on_mutation(Mutation::Insert {
+                key: key.to_vec(),
+                old_value_hash,
+                new_value_hash,
+                root: *self.root_handle.get_hash(),
+            });
+            self.audit_records += 1;
+        }
+    }
+
+    /// Append an insert record to the write-ahead log, if any.
+    fn log_insert(&mut self, key: &[u8], value: &[u8]) -> Result<(), TreeError> {
+        self.write_pending_commit_record()?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal::append_insert(&mut **wal, key, value)?;
+            self.wal_records += 1;
+        }
+        Ok(())
+    }
+
+    /// Append the commit record of the last commit to the write-ahead log if it is missing.
+    fn write_pending_commit_record(&mut self) -> Result<(), TreeError> {
+        if !self.commit_record_pending {
+            return Ok(());
+        }
+        if let Some(wal) = self.wal.as_mut() {
+            wal::append_commit(&mut **wal, self.root.as_ref())?;
+        }
+        self.commit_record_pending = false;
+        Ok(())
+    }
+
+    fn remove_at(
+        &mut self,
+        handle: NodeHash<H>,
+        level: usize,
+        keys: &[&[u8]],
+        removed: &mut Vec<(Vec<u8>, H::Out)>,
+    ) -> Result<NodeHash<H>, TreeError> {
+        let hash = *handle.get_hash();
+        if keys.is_empty() || hash == self.null_hash(level) {
+            return Ok(handle);
+        }
+
+        if level == self.depth {
+            removed.push((keys[0].to_vec(), hash));
+            return Ok(NodeHash::Hash(self.null_hash(level)));
+        }
+
+        let (left, right) = match self.lookup(&hash, level)? {
+            Node::Inner(left, right) => (left, right),
+            Node::Value(_) => return Err(TreeError::UnexpectedNodeType),
+        };
+        let split = keys.partition_point(|key| key[level] == 0);
+        let left = self.remove_at(left, level + 1, &keys[..split], removed)?;
+        let right = self.remove_at(right, level + 1, &keys[split..], removed)?;
+
+        let null_child = self.null_hash(level + 1);
+        if left.get_hash() == &null_child && right.get_hash() == &null_child {
+            return Ok(NodeHash::Hash(self.null_hash(level)));
+        }
+
+        let node = Node::Inner(left, right);
+        let hash = node.hash();
+        self.storage.insert(hash, node);
+        Ok(NodeHash::InMemory(hash))
+    }
+
+    /// Commit stored changes.  The progress callback is invoked but cannot cancel the commit,
+    /// use `try_commit()` for a cancellable commit.
     pub fn commit(&mut self) {
+        match self.commit_nodes(false) {
+            // the commit record is written again before the next record
+            Ok(_) | Err(TreeError::WalFailed) => (),
+            Err(error) => unreachable!("an uncancellable commit failed: {:?}", error),
+        }
+    }
+
+    /// Commit stored changes, failing with `TreeError::CommitCancelled` if the progress
+    /// callback cancels the commit.
+    ///
+    /// Fails with `TreeError::WalFailed` if the commit record cannot be written to the
+    /// write-ahead log.  The changes are committed nonetheless and the commit record is written
+    /// again before the next record of the log, failing that record until it succeeds.
+    ///
+    /// The changed nodes are buffered and deduplicated by hash before being flushed to the
+    /// database, so a subtree referenced from several places is written once.
+    ///
+    /// Nodes are written in an order that only depends on the changes being committed: top-down
+    /// by path, each node followed by its changed left subtree and then its changed right
+    /// subtree.  Committing the same changes always issues the same sequence of writes, so
+    /// journals and replication logs of the database are reproducible.
+    ///
+    /// A cancelled commit leaves the nodes written so far in the database and all changes in
+    /// memory.  Committing again resumes it without writing those nodes twice.
+    pub fn try_commit(&mut self) -> Result<CommitStats, TreeError> {
+        self.commit_nodes(true)
+    }
+
+    /// Write the changed nodes to the database, letting the progress callback cancel the
+    /// commit if `cancellable` is set.
+    fn commit_nodes(&mut self, cancellable: bool) -> Result<CommitStats, TreeError> {
+        let started = Instant::now();
         let root_hash = match self.root_handle {
-            NodeHash::Hash(_) => return,
+            NodeHash::Hash(h) if &h == self.root => {
+                self.write_pending_commit_record()?;
+                self.summarize(CommitStats::default(), 0, started);
+                return Ok(CommitStats::default());
+            }
+            NodeHash::Hash(h) => h,
             NodeHash::InMemory(h) => h,
         };
 
-        match self.storage.remove(&root_hash) {
-            Some(node) => {
-                let encoded_node: Vec<u8> = node.clone().into();
-                self.db.emplace(root_hash, EMPTY_PREFIX, encoded_node);
-                self.commit_child(node);
-                *self.root = root_hash;
-                self.root_handle = NodeHash::Hash(*self.root)
+        // null subtrees are synthesized on lookup and never persisted
+        let mut nodes = Vec::new();
+        let mut skipped = 0;
+        if root_hash != self.null_hash(0) {
+            if let Some(node) = self.storage.remove(&root_hash) {
+                self.collect_uncommitted(root_hash, node, 0, &mut nodes, &mut skipped);
             }
-            None => return,
-        }
-    }
-
-    fn commit_child(&mut self, node: Node<H>) {
-        match node {
-            Node::Inner(left, right) => {
-                let hashes = vec![left, right];
-                for hash in hashes {
-                    match hash {
-                        NodeHash::Hash(_) => (),
-                        NodeHash::InMemory(hash) => match self.storage.remove(&hash) {
-                            Some(node) => {
-                                let encoded_node: Vec<u8> = node.clone().into();
-                                self.db.emplace(hash, EMPTY_PREFIX, encoded_node);
-
-                                if let &Node::Inner(_, _) = &node {
-                                    self.commit_child(node)
-                                }
-                            }
-                            None => (),
-                        },
-                    }
-                }
+        }
+
+        let node_count = nodes
+            .iter()
+            .filter(|(hash, _)| !self.flushed.contains(hash))
+            .count();
+        let mut written = 0;
+        let mut bytes_written = 0;
+        let mut cancelled_at = None;
+        for (position, (hash, node)) in nodes.iter().enumerate() {
+            if self.flushed.contains(hash) {
+                continue;
             }
-            Node::Value(value) => match value {
-                Value::Cached(_) => (),
-                Value::New(mut value) => {
-                    let hash = H::hash(&value);
-                    let mut encoded_node: Vec<u8> = vec![0];
-                    encoded_node.append(&mut value);
-                    self.db.emplace(hash, EMPTY_PREFIX, value);
+            let encoded_node = compression::encode_stored(self.compression, node);
+            bytes_written += encoded_node.len();
+            self.db.emplace(*hash, EMPTY_PREFIX, encoded_node);
+            written += 1;
+
+            if let Some(progress) = self.commit_progress.as_mut() {
+                if (written % progress.interval == 0 || written == node_count)
+                    && !(progress.callback)(written, bytes_written)
+                    && cancellable
+                {
+                    cancelled_at = Some(position);
+                    break;
                 }
-            },
+            }
+        }
+
+        // written nodes stay in memory as the paths to the unwritten ones
+        if let Some(position) = cancelled_at {
+            self.flushed
+                .extend(nodes[..=position].iter().map(|(hash, _)| *hash));
+            self.storage.extend(nodes);
+            return Err(TreeError::CommitCancelled);
+        }
+
+        // nodes left in the overlay are no longer reachable from the root
+        self.storage.clear();
+        self.flushed.clear();
+        self.checkpoints.clear();
+        self.wal_records = 0;
+        self.audit_records = 0;
+        *self.root = root_hash;
+        self.root_handle = NodeHash::Hash(*self.root);
+
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            audit_log.on_mutation(Mutation::Commit { root: root_hash });
+        }
+
+        self.commit_record_pending = self.wal.is_some();
+        let commit_record = self.write_pending_commit_record();
+
+        self.notify_watchers();
+        let stats = CommitStats {
+            written: node_count,
+            skipped,
+        };
+        self.summarize(stats, bytes_written, started);
+        commit_record.map(|_| stats)
+    }
+
+    /// Pass the summary of a successful commit to the summary callback.
+    fn summarize(&mut self, stats: CommitStats, bytes: usize, started: Instant) {
+        if let Some(callback) = self.commit_summary.as_mut() {
+            callback(&CommitSummary {
+                written: stats.written,
+                skipped: stats.skipped,
+                bytes,
+                root: *self.root,
+                elapsed: started.elapsed(),
+            });
+        }
+    }
+
+    /// Move the node and its uncommitted descendants from the overlay to `nodes`, top-down by
+    /// path.  Each node is moved once, further references to it are counted in `skipped`.
+    fn collect_uncommitted(
+        &mut self,
+        hash: H::Out,
+        node: Node<H>,
+        level: usize,
+        nodes: &mut Vec<(H::Out, Node<H>)>,
+        skipped: &mut usize,
+    ) {
+        let children = match &node {
+            Node::Inner(left, right) => Some([left.clone(), right.clone()]),
+            Node::Value(_) => None,
+        };
+        nodes.push((hash, node));
+
+        for child in children.into_iter().flatten() {
+            match child {
+                NodeHash::Hash(_) => (),
+                NodeHash::InMemory(hash) if hash == self.null_hash(level + 1) => (),
+                NodeHash::InMemory(hash) => match self.storage.remove(&hash) {
+                    Some(child) => self.collect_uncommitted(hash, child, level + 1, nodes, skipped),
+                    None => *skipped += 1,
+                },
+            }
         }
     }
 }
 
-impl<'a, H: Hasher> TreeMut<H> for TreeDBMut<'a, H> {
+impl<'a, H: Hasher, S: BuildHasher + Default> TreeMut<H> for TreeDBMut<'a, H, S> {
     fn root(&mut self) -> &H::Out {
         self.commit();
         self.root
@@ -207,9 +1095,15 @@ impl<'a, H: Hasher> TreeMut<H> for TreeDBMut<'a, H> {
             return Err(TreeError::IndexOutOfBounds);
         }
 
+        if let Some(meter) = self.meter.as_ref() {
+            meter.start();
+        }
         let data = self
             .get(key)
-            .map(|node| node.get_value().map(|x| x.get().to_owned()))?;
+            .and_then(|node| node.get_value().map(|x| x.get().to_owned()));
+        if let Some(meter) = self.meter.as_ref() {
+            meter.charge();
+        }
 
         data
     }
@@ -232,30 +1126,33 @@ impl<'a, H: Hasher> TreeMut<H> for TreeDBMut<'a, H> {
             return Err(TreeError::IndexOutOfBounds);
         }
 
+        // prove against the uncommitted root, which the nodes read below descend from
+        let root = self.peek_root();
         let mut proof = Vec::new();
-        proof.push((1, self.root.as_ref().to_vec()));
+        proof.push((1, root.as_ref().to_vec()));
 
-        let mut current_node = self.lookup(self.root_handle.get_hash(), 0)?;
+        let mut current_node = self.lookup(&root, 0)?;
 
-        for (i, &bit) in key.iter().enumerate() {
-            let index = indices::compute_index(&key[..i + 1]);
-            let left_index = if index % 2 == 0 { index } else { index ^ 1 };
-
-            if let Node::Inner(left, right) = current_node {
-                let key = if bit == 0 {
-                    left.get_hash()
-                } else {
-                    right.get_hash()
-                };
-                current_node = self.lookup(key, i + 1)?;
-
-                proof.extend_from_slice(&[
-                    (left_index, left.get_hash().as_ref().to_vec()),
-                    (left_index + 1, right.get_hash().as_ref().to_vec()),
-                ]);
-            } else {
-                return Err(TreeError::UnexpectedNodeType);
-            }
+        // the index of the current node, extended by one key bit per level
+        let mut index = 1;
+        for (level, &bit) in key.iter().enumerate() {
+            let bit = key_bit(bit)?;
+            let (left, right) = match current_node {
+                Node::Inner(left, right) => (left, right),
+                _ => return Err(TreeError::UnexpectedNodeType),
+            };
+
+            let left_index = indices::child_index(index, false);
+            proof.extend_from_slice(&[
+                (left_index, left.get_hash().as_ref().to_vec()),
+                (left_index + 1, right.get_hash().as_ref().to_vec()),
+            ]);
+
+            let child = if bit { right } else { left };
+            current_node = self
+                .lookup(child.get_hash(), level + 1)
+                .map_err(|error| error.along(key))?;
+            index = indices::child_index(index, bit);
         }
 
         proof.push((0, current_node.get_value()?.get().clone()));
@@ -268,14 +1165,112 @@ impl<'a, H: Hasher> TreeMut<H> for TreeDBMut<'a, H> {
             return Err(TreeError::IndexOutOfBounds);
         };
 
-        let mut root_data: Node<H> = self.lookup(&self.root_handle.get_hash(), 0)?;
+        if let Some(meter) = self.meter.as_ref() {
+            meter.start();
+        }
+        let old_value = self.write_value_if(key, value, |_| true);
+        if let Some(meter) = self.meter.as_ref() {
+            meter.charge();
+        }
+
+        old_value.map(|(old_value, _)| old_value)
+    }
+
+    fn insert_if_absent(
+        &mut self,
+        key: &[u8],
+        value: DBValue,
+    ) -> Result<ConditionalWrite, TreeError> {
+        let null_hashes = self.null_hashes.clone();
+        self.conditional_write(key, value, |current| {
+            current.as_slice() == null_hashes.default_leaf()
+        })
+    }
 
-        let old_value = self.insert_at(&mut root_data, key, value)?;
+    fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: &[u8],
+        new: DBValue,
+    ) -> Result<ConditionalWrite, TreeError> {
+        self.conditional_write(key, new, |current| current.as_slice() == expected)
+    }
 
-        self.storage.insert(root_data.hash(), root_data.clone());
+    fn get_node(&self, key_prefix: &[u8]) -> Result<NodeView<H>, TreeError> {
+        if key_prefix.len() > self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
 
-        self.root_handle = NodeHash::InMemory(root_data.hash());
+        self.get(key_prefix).map(NodeView::from)
+    }
 
-        old_value.get_value().map(|x| x.get().clone())
+    fn get_value_proof(&self, key: &[u8]) -> Result<(Option<DBValue>, Proof<H>), TreeError> {
+        value_proof(key, self.get_proof(key)?, self.null_hashes.default_leaf())
+    }
+}
+
+impl<'a, H: Hasher, S: BuildHasher + Default> TreeDBMut<'a, H, S> {
+    /// Write `value` at `key` if `check` accepts the current value, reading the path once.
+    /// Returns the current value and whether it was replaced.
+    fn write_value_if(
+        &mut self,
+        key: &[u8],
+        value: DBValue,
+        check: impl FnOnce(&DBValue) -> bool,
+    ) -> Result<(DBValue, bool), TreeError> {
+        let mut path = Vec::with_capacity(self.depth);
+        let mut node = self.lookup(self.root_handle.get_hash(), 0)?;
+        for (level, &bit) in key.iter().enumerate() {
+            let child = *node.get_child_bool(key_bit(bit)?)?.get_hash();
+            path.push(node);
+            node = self
+                .lookup(&child, level + 1)
+                .map_err(|error| error.along(key))?;
+        }
+        let old_value = node.get_value()?.get().clone();
+        if !check(&old_value) {
+            return Ok((old_value, false));
+        }
+
+        self.log_insert(key, &value)?;
+
+        let new_value_hash = H::hash(&value);
+        let mut child = self.store(Node::Value(Value::New(value)));
+        for (mut node, &bit) in path.into_iter().zip(key.iter()).rev() {
+            node.set_child_bool(key_bit(bit)?, child)?;
+            child = self.store(node);
+        }
+        self.root_handle = child;
+        self.on_write(key, H::hash(&old_value), new_value_hash);
+
+        Ok((old_value, true))
+    }
+
+    /// Write `value` at `key` if `check` accepts the current value, metering the operation.
+    fn conditional_write(
+        &mut self,
+        key: &[u8],
+        value: DBValue,
+        check: impl FnOnce(&DBValue) -> bool,
+    ) -> Result<ConditionalWrite, TreeError> {
+        if key.len() != self.depth {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+
+        if let Some(meter) = self.meter.as_ref() {
+            meter.start();
+        }
+        let result = self.write_value_if(key, value, check);
+        if let Some(meter) = self.meter.as_ref() {
+            meter.charge();
+        }
+
+        result.map(|(current, written)| {
+            if written {
+                ConditionalWrite::Written(current)
+            } else {
+                ConditionalWrite::Rejected(current)
+            }
+        })
     }
 }
@@ -0,0 +1,155 @@
+use crate::{
+    rstd::{Arc, HashMap, HashSet, Vec},
+    BuildFoldHasher, DBValue, HashDBRef, Hasher, Node, NodeView, NullHashes, Proof, StorageProof,
+    Tree, TreeDB, TreeDBBuilder, TreeError,
+};
+use hash_db::Prefix;
+
+/// A read-only database over the nodes of a `StorageProof`, checked against a root.
+///
+/// Every node is hashed once on construction, and the proof is rejected unless each node is
+/// linked to the root through the other nodes of the proof.  Lookups are then served from a map
+/// keyed by those hashes without hashing them again.
+#[derive(Debug)]
+pub struct VerifiedProofDb<H: Hasher> {
+    root: H::Out,
+    nodes: HashMap<H::Out, DBValue, BuildFoldHasher>,
+}
+
+impl<H: Hasher> VerifiedProofDb<H> {
+    /// Verify the proof against `root`.  Fails with `TreeError::NodeDeserializationFailed` if a
+    /// node is malformed and with `TreeError::UnlinkedProofNode` if a node is not reachable from
+    /// the root.  An empty proof is valid for any root.
+    pub fn new(proof: StorageProof, root: H::Out) -> Result<Self, TreeError> {
+        let mut nodes = HashMap::default();
+        for node in proof.into_nodes() {
+            let well_formed = match node.first() {
+                Some(0) => true,
+                Some(1) => node.len() == 1 + 2 * H::LENGTH,
+                _ => false,
+            };
+            if !well_formed {
+                return Err(TreeError::NodeDeserializationFailed);
+            }
+            nodes.insert(H::hash(&node[1..]), node);
+        }
+
+        // identical subtrees share nodes, so count each linked node once
+        let mut linked: HashSet<H::Out, BuildFoldHasher> = HashSet::default();
+        let mut pending = Vec::from([root]);
+        while let Some(hash) = pending.pop() {
+            let node: Node<H> = match nodes.get(&hash) {
+                Some(data) if linked.insert(hash) => Node::decode(data)?,
+                _ => continue,
+            };
+            if let Node::Inner(left, right) = node {
+                pending.extend([*left.get_hash(), *right.get_hash()]);
+            }
+        }
+        if linked.len() != nodes.len() {
+            return Err(TreeError::UnlinkedProofNode);
+        }
+
+        Ok(Self { root, nodes })
+    }
+
+    /// Return the root the proof was verified against.
+    pub fn root(&self) -> &H::Out {
+        &self.root
+    }
+
+    /// Return the number of nodes in the proof.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Return true if the proof holds no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add the nodes of another proof verified against the same root.
+    pub(crate) fn extend(&mut self, other: Self) {
+        debug_assert!(self.root == other.root);
+        self.nodes.extend(other.nodes);
+    }
+}
+
+impl<H: Hasher> HashDBRef<H, DBValue> for VerifiedProofDb<H> {
+    fn get(&self, key: &H::Out, _prefix: Prefix) -> Option<DBValue> {
+        self.nodes.get(key).cloned()
+    }
+
+    fn contains(&self, key: &H::Out, _prefix: Prefix) -> bool {
+        self.nodes.contains_key(key)
+    }
+}
+
+/// A read-only tree over a verified storage proof, returned by `StorageProof::into_tree`.
+///
+/// Reads outside the proof fail with `TreeError::DataNotFound`.
+pub struct ProofTree<H: Hasher> {
+    db: VerifiedProofDb<H>,
+    depth: usize,
+    null_hashes: Arc<NullHashes<H>>,
+}
+
+impl<H: Hasher> ProofTree<H> {
+    /// Verify the proof against `root` and open the tree of the given depth over it.
+    pub fn new(proof: StorageProof, root: H::Out, depth: usize) -> Result<Self, TreeError> {
+        Ok(Self {
+            db: VerifiedProofDb::new(proof, root)?,
+            depth,
+            null_hashes: Arc::default(),
+        })
+    }
+
+    /// Read the tree with the given null hashes, for trees whose empty leaves hold another value
+    /// than the empty value.
+    pub fn with_null_hashes(mut self, null_hashes: Arc<NullHashes<H>>) -> Self {
+        self.null_hashes = null_hashes;
+        self
+    }
+
+    /// Return the verified database backing the tree.
+    pub fn db(&self) -> &VerifiedProofDb<H> {
+        &self.db
+    }
+
+    /// Return a `TreeDB` over the proof, for the queries beyond the `Tree` trait.
+    pub fn tree(&self) -> TreeDB<'_, H> {
+        TreeDBBuilder::new(&self.db, self.db.root(), self.depth)
+            .with_null_hashes(self.null_hashes.clone())
+            .build()
+    }
+}
+
+impl<H: Hasher> Tree<H> for ProofTree<H> {
+    fn root(&self) -> &H::Out {
+        self.db.root()
+    }
+
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn get_value(&self, key: &[u8]) -> Result<DBValue, TreeError> {
+        self.tree().get_value(key)
+    }
+
+    fn get_leaf(&self, key: &[u8]) -> Result<H::Out, TreeError> {
+        self.tree().get_leaf(key)
+    }
+
+    fn get_proof(&self, key: &[u8]) -> Result<Vec<(usize, DBValue)>, TreeError> {
+        self.tree().get_proof(key)
+    }
+
+    fn get_node(&self, key_prefix: &[u8]) -> Result<NodeView<H>, TreeError> {
+        self.tree().get_node(key_prefix)
+    }
+
+    fn get_value_proof(&self, key: &[u8]) -> Result<(Option<DBValue>, Proof<H>), TreeError> {
+        self.tree().get_value_proof(key)
+    }
+}
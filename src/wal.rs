@@ -0,0 +1,90 @@
+use crate::{rstd::Vec, DBValue, Hasher, TreeError, TreeMut};
+use std::io::{Read, Write};
+
+const INSERT_RECORD: u8 = 0;
+const COMMIT_RECORD: u8 = 1;
+const REVERT_RECORD: u8 = 2;
+
+/// Append an insert record to the write-ahead log.
+pub(crate) fn append_insert(
+    wal: &mut dyn Write,
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), TreeError> {
+    bincode::serialize_into(&mut *wal, &(INSERT_RECORD, key, value))
+        .map_err(|_| TreeError::WalFailed)?;
+    wal.flush().map_err(|_| TreeError::WalFailed)
+}
+
+/// Append a commit record for the given root to the write-ahead log.
+pub(crate) fn append_commit(wal: &mut dyn Write, root: &[u8]) -> Result<(), TreeError> {
+    bincode::serialize_into(&mut *wal, &(COMMIT_RECORD, root, &[] as &[u8]))
+        .map_err(|_| TreeError::WalFailed)?;
+    wal.flush().map_err(|_| TreeError::WalFailed)
+}
+
+/// Append a revert record keeping only the first `retained` inserts after the last commit
+/// record.
+pub(crate) fn append_revert(wal: &mut dyn Write, retained: usize) -> Result<(), TreeError> {
+    let retained = (retained as u64).to_le_bytes();
+    bincode::serialize_into(&mut *wal, &(REVERT_RECORD, &retained[..], &[] as &[u8]))
+        .map_err(|_| TreeError::WalFailed)?;
+    wal.flush().map_err(|_| TreeError::WalFailed)
+}
+
+/// Replay the inserts recorded in a write-ahead log after its last commit record.
+///
+/// The inserts follow the root named by the last commit record, so this fails with
+/// `TreeError::WalRootMismatch`, replaying nothing, if `tree` is at another root.
+/// Inserts retracted by a revert record are skipped.  A truncated trailing record, as left
+/// behind by a crash during an append, is ignored.  Returns the number of replayed inserts.
+pub fn replay_wal<H: Hasher>(
+    reader: &mut dyn Read,
+    tree: &mut dyn TreeMut<H>,
+) -> Result<usize, TreeError> {
+    let mut pending: Vec<(Vec<u8>, DBValue)> = Vec::new();
+    let mut committed_root = None;
+
+    loop {
+        let record: (u8, Vec<u8>, DBValue) = match bincode::deserialize_from(&mut *reader) {
+            Ok(record) => record,
+            Err(err) => match *err {
+                bincode::ErrorKind::Io(ref io)
+                    if io.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                _ => return Err(TreeError::WalFailed),
+            },
+        };
+
+        match record {
+            (INSERT_RECORD, key, value) => pending.push((key, value)),
+            (COMMIT_RECORD, root, _) => {
+                pending.clear();
+                committed_root = Some(root);
+            }
+            (REVERT_RECORD, retained, _) => {
+                let retained = <[u8; 8]>::try_from(retained.as_slice())
+                    .map(u64::from_le_bytes)
+                    .map_err(|_| TreeError::WalFailed)?;
+                if retained > pending.len() as u64 {
+                    return Err(TreeError::WalFailed);
+                }
+                pending.truncate(retained as usize);
+            }
+            _ => return Err(TreeError::WalFailed),
+        }
+    }
+
+    if committed_root.is_some_and(|root| tree.root().as_ref() != root.as_slice()) {
+        return Err(TreeError::WalRootMismatch);
+    }
+
+    let replayed = pending.len();
+    for (key, value) in pending {
+        tree.insert(&key, value)?;
+    }
+
+    Ok(replayed)
+}
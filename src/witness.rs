@@ -0,0 +1,186 @@
+use crate::{
+    node::key_bit,
+    rstd::{Arc, Vec},
+    DBValue, Hasher, NullHashes, StorageProof, Tree, TreeDB, TreeDBBuilder, TreeDBMut,
+    TreeDBMutBuilder, TreeError, TreeMut,
+};
+
+/// Everything needed to re-execute a block statelessly: the pre-state root, the recorded
+/// witness, the keys the block accesses and the post-state root it must produce.
+#[derive(Debug)]
+pub struct WitnessBundle<H: Hasher> {
+    pub pre_state_root: H::Out,
+    pub proof: StorageProof,
+    pub access_list: Vec<Vec<u8>>,
+    pub post_state_root: H::Out,
+    pub depth: usize,
+}
+
+impl<H: Hasher> Clone for WitnessBundle<H> {
+    fn clone(&self) -> Self {
+        Self {
+            pre_state_root: self.pre_state_root,
+            proof: self.proof.clone(),
+            access_list: self.access_list.clone(),
+            post_state_root: self.post_state_root,
+            depth: self.depth,
+        }
+    }
+}
+
+impl<H: Hasher> PartialEq for WitnessBundle<H> {
+    fn eq(&self, other: &Self) -> bool {
+        self.pre_state_root == other.pre_state_root
+            && self.proof == other.proof
+            && self.access_list == other.access_list
+            && self.post_state_root == other.post_state_root
+            && self.depth == other.depth
+    }
+}
+
+impl<H: Hasher> Eq for WitnessBundle<H> {}
+
+/// Execute `f` against a tree backed by the bundle's witness at the pre-state root, then commit
+/// and check that the resulting root is the bundle's post-state root.
+///
+/// Only the witness nodes on the paths to the keys of the access list are available to `f`, so
+/// accessing a key outside the access list fails with `TreeError::DataNotFound` unless its path
+/// only crosses those nodes and empty subtrees.  Fails with `TreeError::DataNotFound` before
+/// running `f` if the witness lacks a node on the path to a key of the access list.  Returns
+/// the result of `f`, or `TreeError::PostStateRootMismatch` if the execution produced a
+/// different root.
+pub fn execute_against<H, R, F>(bundle: &WitnessBundle<H>, f: F) -> Result<R, TreeError>
+where
+    H: Hasher,
+    F: FnOnce(&mut TreeDBMut<H>) -> Result<R, TreeError>,
+{
+    execute_against_with_null_hashes(bundle, Arc::default(), f)
+}
+
+/// Like `execute_against`, for a tree whose empty leaves hold the default leaf of `null_hashes`.
+pub fn execute_against_with_null_hashes<H, R, F>(
+    bundle: &WitnessBundle<H>,
+    null_hashes: Arc<NullHashes<H>>,
+    f: F,
+) -> Result<R, TreeError>
+where
+    H: Hasher,
+    F: FnOnce(&mut TreeDBMut<H>) -> Result<R, TreeError>,
+{
+    let witness = bundle.proof.clone().into_memory_db::<H>();
+    let keys: Vec<&[u8]> = bundle
+        .access_list
+        .iter()
+        .map(|key| key.as_slice())
+        .collect();
+    let accessible = record_access_list(
+        &TreeDBBuilder::new(&witness, &bundle.pre_state_root, bundle.depth)
+            .with_null_hashes(null_hashes.clone())
+            .build(),
+        &keys,
+    )?;
+
+    let mut db = accessible.into_memory_db::<H>();
+    let mut root = bundle.pre_state_root;
+    let mut tree = TreeDBMutBuilder::new(&mut db, &mut root, bundle.depth)
+        .with_null_hashes(null_hashes)
+        .build();
+
+    let result = f(&mut tree)?;
+    tree.try_commit()?;
+    drop(tree);
+
+    if root != bundle.post_state_root {
+        return Err(TreeError::PostStateRootMismatch);
+    }
+    Ok(result)
+}
+
+/// Check that applying `updates` in order to the tree of the given depth at `old_root` yields
+/// `new_root`, using only the nodes of `multiproof`, such as a witness from `record_access_list`.
+///
+/// Fails with `TreeError::DataNotFound` if the multiproof lacks a node on the path to an updated
+/// key and with `TreeError::PostStateRootMismatch` if the updates produce a different root.
+pub fn verify_batch_transition<H: Hasher>(
+    old_root: &H::Out,
+    depth: usize,
+    multiproof: &StorageProof,
+    updates: &[(Vec<u8>, DBValue)],
+    new_root: &H::Out,
+) -> Result<(), TreeError> {
+    let bundle = WitnessBundle::<H> {
+        pre_state_root: *old_root,
+        proof: multiproof.clone(),
+        access_list: updates.iter().map(|(key, _)| key.clone()).collect(),
+        post_state_root: *new_root,
+        depth,
+    };
+
+    execute_against(&bundle, |tree| {
+        for (key, value) in updates {
+            tree.insert(key, value.clone())?;
+        }
+        Ok(())
+    })
+}
+
+/// Record the witness needed to read or write every key of `keys` against `tree`.
+///
+/// The paths to all keys are walked in a single pass and nodes shared between paths are visited
+/// once.  The witness holds the stored nodes along each path, including the leaf of populated
+/// keys, and stops at the empty subtree proving the absence of the others.
+pub fn record_access_list<H: Hasher>(
+    tree: &TreeDB<H>,
+    keys: &[&[u8]],
+) -> Result<StorageProof, TreeError> {
+    for key in keys {
+        if key.len() != tree.depth() {
+            return Err(TreeError::IndexOutOfBounds);
+        }
+        for bit in key.iter() {
+            key_bit(*bit)?;
+        }
+    }
+
+    let mut keys = keys.to_vec();
+    keys.sort();
+    keys.dedup();
+
+    let mut nodes = Vec::new();
+    if !keys.is_empty() {
+        record_subtree(tree, *tree.root(), 0, &keys, &mut nodes)?;
+    }
+    Ok(StorageProof::new(nodes))
+}
+
+/// Record the node at `hash` and the paths below it to the sorted `keys`, which all share the
+/// prefix of the node.
+fn record_subtree<H: Hasher>(
+    tree: &TreeDB<H>,
+    hash: H::Out,
+    level: usize,
+    keys: &[&[u8]],
+    nodes: &mut Vec<DBValue>,
+) -> Result<(), TreeError> {
+    if hash == tree.null_hash(level) {
+        return Ok(());
+    }
+
+    let node = tree
+        .lookup(&hash, level)
+        .map_err(|error| error.along(keys[0]))?;
+    if level == tree.depth() {
+        nodes.push(node.into());
+        return Ok(());
+    }
+
+    let split = keys.partition_point(|key| key[level] == 0);
+    for (bit, keys) in [(false, &keys[..split]), (true, &keys[split..])] {
+        if !keys.is_empty() {
+            let child = *node.get_child_bool(bit)?.get_hash();
+            record_subtree(tree, child, level + 1, keys, nodes)?;
+        }
+    }
+    nodes.push(node.into());
+    Ok(())
+}